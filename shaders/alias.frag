@@ -2,6 +2,7 @@
 
 layout(location = 0) in vec3 f_normal;
 layout(location = 1) in vec2 f_diffuse;
+layout(location = 2) in float f_light;
 
 // set 1: per-entity
 layout(set = 1, binding = 1) uniform sampler u_diffuse_sampler;
@@ -19,8 +20,8 @@ void main() {
     f_diffuse
   );
 
-  // TODO: get ambient light from uniform
-  light_attachment = vec4(0.25);
+  // the deferred pass doubles the sum of all four channels
+  light_attachment = vec4(f_light / 8.0);
 
   // rescale normal to [0, 1]
   normal_attachment = vec4(f_normal / 2.0 + 0.5, 1.0);
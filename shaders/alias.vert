@@ -1,17 +1,24 @@
 #version 450
 
 layout(location = 0) in vec3 a_position1;
-// layout(location = 1) in vec3 a_position2;
-layout(location = 2) in vec3 a_normal;
+layout(location = 1) in vec3 a_position2;
+layout(location = 2) in vec3 a_normal1;
 layout(location = 3) in vec2 a_diffuse;
+layout(location = 4) in vec3 a_normal2;
 
 layout(push_constant) uniform PushConstants {
   mat4 transform;
   mat4 model_view;
+  vec3 shade_vector;
+  float lerp;
+  float ambient_light;
+  float shade_light;
 } push_constants;
 
 layout(location = 0) out vec3 f_normal;
 layout(location = 1) out vec2 f_diffuse;
+layout(location = 2) out float f_light;
+layout(location = 3) out vec3 f_view_pos;
 
 // convert from Quake coordinates
 vec3 convert(vec3 from) {
@@ -19,7 +26,15 @@ vec3 convert(vec3 from) {
 }
 
 void main() {
-  f_normal = mat3(transpose(inverse(push_constants.model_view))) * convert(a_normal);
+  vec3 position = mix(a_position1, a_position2, push_constants.lerp);
+  vec3 normal = normalize(mix(a_normal1, a_normal2, push_constants.lerp));
+  f_normal = mat3(transpose(inverse(push_constants.model_view))) * convert(normal);
   f_diffuse = a_diffuse;
-  gl_Position = push_constants.transform * vec4(convert(a_position1), 1.0);
+
+  // convert from lightmap units, where 128 is normal brightness
+  float shade = max(dot(normal, push_constants.shade_vector), 0.0);
+  f_light = (push_constants.ambient_light + push_constants.shade_light * shade) / 128.0;
+
+  f_view_pos = (push_constants.model_view * vec4(convert(position), 1.0)).xyz;
+  gl_Position = push_constants.transform * vec4(convert(position), 1.0);
 }
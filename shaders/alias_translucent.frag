@@ -0,0 +1,41 @@
+#version 450
+
+layout(location = 0) in vec3 f_normal;
+layout(location = 1) in vec2 f_diffuse;
+layout(location = 2) in float f_light;
+layout(location = 3) in vec3 f_view_pos;
+
+layout(push_constant) uniform PushConstants {
+  layout(offset = 152) float alpha;
+  layout(offset = 160) vec4 fog;
+} push_constants;
+
+// set 1: per-entity
+layout(set = 1, binding = 1) uniform sampler u_diffuse_sampler;
+
+// set 2: per-texture chain
+layout(set = 2, binding = 0) uniform texture2D u_diffuse_texture;
+
+layout(location = 0) out vec4 color_attachment;
+
+// matches the fog applied to opaque surfaces in the deferred pass
+vec3 apply_fog(vec3 color) {
+  float density = push_constants.fog.w;
+  if (density <= 0.0) {
+    return color;
+  }
+
+  float fog_dist = density * length(f_view_pos);
+  float visibility = clamp(exp(-fog_dist * fog_dist), 0.0, 1.0);
+  return mix(push_constants.fog.rgb, color, visibility);
+}
+
+void main() {
+  vec4 diffuse = texture(
+    sampler2D(u_diffuse_texture, u_diffuse_sampler),
+    f_diffuse
+  );
+
+  // static lighting only, as for translucent brush surfaces
+  color_attachment = vec4(apply_fog(f_light * diffuse.rgb), push_constants.alpha);
+}
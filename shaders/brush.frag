@@ -9,10 +9,15 @@ const float WARP_AMPLITUDE = 0.15;
 const float WARP_FREQUENCY = 0.25;
 const float WARP_SCALE = 1.0;
 
+// scroll speeds of the sky layers in texels per second
+const float SKY_BACK_SPEED = 8.0;
+const float SKY_FRONT_SPEED = 16.0;
+
 layout(location = 0) in vec3 f_normal;
 layout(location = 1) in vec2 f_diffuse; // also used for fullbright
 layout(location = 2) in vec2 f_lightmap;
 flat layout(location = 3) in uvec4 f_lightmap_anim;
+layout(location = 4) in vec3 f_sky_dir;
 
 layout(push_constant) uniform PushConstants {
   layout(offset = 128) uint texture_kind;
@@ -50,6 +55,18 @@ float sample_texture_index(uint i) {
     return texture(sampler2D(u_lightmap_texture[i], u_lightmap_sampler), f_lightmap).r;
 }
 
+// project the view direction onto a flattened sphere around the camera
+vec2 sky_texcoord(float speed) {
+    vec3 dir = f_sky_dir;
+    dir.z *= 3.0;
+
+    // the coefficients here are magic taken from the Quake source
+    vec2 st = dir.xy * (6.0 * 63.0 / length(dir));
+
+    // wrap the scroll so that texcoords keep their precision as time grows
+    return (mod(speed * frame_uniforms.time, 128.0) + st) / 128.0;
+}
+
 vec4 calc_light() {
     vec4 light = vec4(0.0, 0.0, 0.0, 0.0);
     for (int i = 0; i < 4 && f_lightmap_anim[i] != LIGHTMAP_ANIM_END; i++) {
@@ -97,29 +114,22 @@ void main() {
             break;
 
         case TEXTURE_KIND_SKY:
-            vec2 base = mod(f_diffuse + frame_uniforms.time, 1.0);
-            vec2 cloud_texcoord = vec2(base.s * 0.5, base.t);
-            vec2 sky_texcoord = vec2(base.s * 0.5 + 0.5, base.t);
-
-            vec4 sky_color = texture(
+            // back layer in the diffuse texture, front layer in the fullbright
+            vec4 back_color = texture(
                 sampler2D(u_diffuse_texture, u_diffuse_sampler),
-                sky_texcoord
+                sky_texcoord(SKY_BACK_SPEED)
             );
-            vec4 cloud_color = texture(
-                sampler2D(u_diffuse_texture, u_diffuse_sampler),
-                cloud_texcoord
+            vec4 front_color = texture(
+                sampler2D(u_fullbright_texture, u_diffuse_sampler),
+                sky_texcoord(SKY_FRONT_SPEED)
             );
 
-            // 0.0 if black, 1.0 otherwise
-            float cloud_factor;
-            if (cloud_color.r + cloud_color.g + cloud_color.b == 0.0) {
-                cloud_factor = 0.0;
-            } else {
-                cloud_factor = 1.0;
-            }
-            diffuse_attachment = mix(sky_color, cloud_color, cloud_factor);
-            light_attachment = vec4(0.25);
-            break;
+            diffuse_attachment = vec4(mix(back_color.rgb, front_color.rgb, front_color.a), 1.0);
+            light_attachment = vec4(0.0);
+
+            // sky is unlit, so mark it to skip lighting in the deferred pass
+            normal_attachment = vec4(f_normal / 2.0 + 0.5, 0.0);
+            return;
 
         // not possible
         default:
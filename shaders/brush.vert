@@ -20,6 +20,8 @@ layout(location = 0) out vec3 f_normal;
 layout(location = 1) out vec2 f_diffuse;
 layout(location = 2) out vec2 f_lightmap;
 layout(location = 3) out uvec4 f_lightmap_anim;
+layout(location = 4) out vec3 f_sky_dir;
+layout(location = 5) out vec3 f_view_pos;
 
 layout(set = 0, binding = 0) uniform FrameUniforms {
     float light_anim_frames[64];
@@ -33,21 +35,13 @@ vec3 convert(vec3 from) {
 }
 
 void main() {
-    if (push_constants.texture_kind == TEXTURE_KIND_SKY) {
-        vec3 dir = a_position - frame_uniforms.camera_pos.xyz;
-        dir.z *= 3.0;
-
-        // the coefficients here are magic taken from the Quake source
-        float len = 6.0 * 63.0 / length(dir);
-        dir = vec3(dir.xy * len, dir.z);
-        f_diffuse = (mod(8.0 * frame_uniforms.time, 128.0) + dir.xy) / 128.0;
-    } else {
-        f_diffuse = a_diffuse;
-    }
+    // sky layers are projected per-fragment from the view direction
+    f_sky_dir = a_position - frame_uniforms.camera_pos.xyz;
+    f_diffuse = a_diffuse;
 
     f_normal = mat3(transpose(inverse(push_constants.model_view))) * convert(a_normal);
     f_lightmap = a_lightmap;
     f_lightmap_anim = a_lightmap_anim;
+    f_view_pos = (push_constants.model_view * vec4(convert(a_position), 1.0)).xyz;
     gl_Position = push_constants.transform * vec4(convert(a_position), 1.0);
-
 }
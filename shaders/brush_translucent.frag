@@ -0,0 +1,116 @@
+#version 450
+#define LIGHTMAP_ANIM_END (255)
+
+const uint TEXTURE_KIND_REGULAR = 0;
+const uint TEXTURE_KIND_WARP = 1;
+
+const float WARP_AMPLITUDE = 0.15;
+const float WARP_FREQUENCY = 0.25;
+const float WARP_SCALE = 1.0;
+
+// the deferred pass doubles and sums the four light channels, so a fullbright
+// light attachment of 0.25 comes out at this intensity
+const float FULLBRIGHT = 2.0;
+
+layout(location = 0) in vec3 f_normal;
+layout(location = 1) in vec2 f_diffuse; // also used for fullbright
+layout(location = 2) in vec2 f_lightmap;
+flat layout(location = 3) in uvec4 f_lightmap_anim;
+layout(location = 5) in vec3 f_view_pos;
+
+layout(push_constant) uniform PushConstants {
+  layout(offset = 128) uint texture_kind;
+  float alpha;
+  vec4 fog;
+} push_constants;
+
+// set 0: per-frame
+layout(set = 0, binding = 0) uniform FrameUniforms {
+    float light_anim_frames[64];
+    vec4 camera_pos;
+    float time;
+    bool r_lightmap;
+} frame_uniforms;
+
+// set 1: per-entity
+layout(set = 1, binding = 1) uniform sampler u_diffuse_sampler; // also used for fullbright
+layout(set = 1, binding = 2) uniform sampler u_lightmap_sampler;
+
+// set 2: per-texture
+layout(set = 2, binding = 0) uniform texture2D u_diffuse_texture;
+layout(set = 2, binding = 1) uniform texture2D u_fullbright_texture;
+
+// set 3: per-face
+layout(set = 3, binding = 0) uniform texture2D u_lightmap_texture[4];
+
+layout(location = 0) out vec4 color_attachment;
+
+float sample_texture_index(uint i) {
+    return texture(sampler2D(u_lightmap_texture[i], u_lightmap_sampler), f_lightmap).r;
+}
+
+// static lighting only; dynamic lights are applied in the deferred pass,
+// which translucent surfaces don't take part in
+float calc_light() {
+    float light = 0.0;
+    for (int i = 0; i < 4 && f_lightmap_anim[i] != LIGHTMAP_ANIM_END; i++) {
+        float style = frame_uniforms.light_anim_frames[f_lightmap_anim[i]];
+        light += 2.0 * clamp(sample_texture_index(i) * style, 0.0, 1.0);
+    }
+    return light;
+}
+
+// matches the fog applied to opaque surfaces in the deferred pass
+vec3 apply_fog(vec3 color) {
+    float density = push_constants.fog.w;
+    if (density <= 0.0) {
+        return color;
+    }
+
+    float fog_dist = density * length(f_view_pos);
+    float visibility = clamp(exp(-fog_dist * fog_dist), 0.0, 1.0);
+    return mix(push_constants.fog.rgb, color, visibility);
+}
+
+void main() {
+    vec4 diffuse;
+    float light;
+
+    switch (push_constants.texture_kind) {
+        case TEXTURE_KIND_REGULAR:
+            diffuse = texture(
+                sampler2D(u_diffuse_texture, u_diffuse_sampler),
+                f_diffuse
+            );
+
+            float fullbright = texture(
+                sampler2D(u_fullbright_texture, u_diffuse_sampler),
+                f_diffuse
+            ).r;
+
+            light = fullbright != 0.0 ? FULLBRIGHT : calc_light();
+            break;
+
+        case TEXTURE_KIND_WARP:
+            // note the texcoord transpose here
+            vec2 wave1 = 3.14159265359
+                * (WARP_SCALE * f_diffuse.ts
+                    + WARP_FREQUENCY * frame_uniforms.time);
+
+            vec2 warp_texcoord = f_diffuse.st + WARP_AMPLITUDE
+                * vec2(sin(wave1.s), sin(wave1.t));
+
+            diffuse = texture(
+                sampler2D(u_diffuse_texture, u_diffuse_sampler),
+                warp_texcoord
+            );
+            light = FULLBRIGHT;
+            break;
+
+        // sky is never translucent
+        default:
+            discard;
+    }
+
+    color_attachment = vec4(apply_fog(light * diffuse.rgb), push_constants.alpha);
+}
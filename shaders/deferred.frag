@@ -3,6 +3,9 @@
 // if this is changed, it must also be changed in client::entity
 const uint MAX_LIGHTS = 32;
 
+// if this is changed, it must also be changed in client::render::world
+const float VIEWMODEL_DEPTH_MAX = 0.3;
+
 layout(location = 0) in vec2 a_texcoord;
 
 layout(set = 0, binding = 0) uniform sampler u_sampler;
@@ -12,9 +15,11 @@ layout(set = 0, binding = 3) uniform texture2DMS u_light;
 layout(set = 0, binding = 4) uniform texture2DMS u_depth;
 layout(set = 0, binding = 5) uniform DeferredUniforms {
   mat4 inv_projection;
+  mat4 viewmodel_inv_projection;
   uint light_count;
   uint _pad1;
   uvec2 _pad2;
+  vec4 fog;
   vec4 lights[MAX_LIGHTS];
 } u_deferred;
 
@@ -28,11 +33,33 @@ float dlight_radius(vec4 dlight) {
   return dlight.w;
 }
 
+// exponential squared fog, applied to everything in the initial pass
+vec3 apply_fog(vec3 color, vec3 position) {
+  float density = u_deferred.fog.w;
+  if (density <= 0.0) {
+    return color;
+  }
+
+  float fog_dist = density * length(position);
+  float visibility = clamp(exp(-fog_dist * fog_dist), 0.0, 1.0);
+  return mix(u_deferred.fog.rgb, color, visibility);
+}
+
+// the viewmodel is drawn with its own projection into the front of the depth
+// range and everything else into the rest, so undo whichever remap applies
 vec3 reconstruct_position(float depth) {
   float x = a_texcoord.s * 2.0 - 1.0;
   float y = (1.0 - a_texcoord.t) * 2.0 - 1.0;
-  vec4 ndc = vec4(x, y, depth, 1.0);
-  vec4 view = u_deferred.inv_projection * ndc;
+
+  vec4 view;
+  if (depth < VIEWMODEL_DEPTH_MAX) {
+    vec4 ndc = vec4(x, y, depth / VIEWMODEL_DEPTH_MAX, 1.0);
+    view = u_deferred.viewmodel_inv_projection * ndc;
+  } else {
+    vec4 ndc = vec4(x, y, (depth - VIEWMODEL_DEPTH_MAX) / (1.0 - VIEWMODEL_DEPTH_MAX), 1.0);
+    view = u_deferred.inv_projection * ndc;
+  }
+
   return view.xyz / view.w;
 }
 
@@ -41,17 +68,28 @@ void main() {
   ivec2 texcoord = ivec2(vec2(dims) * a_texcoord);
   vec4 in_color = texelFetch(sampler2DMS(u_diffuse, u_sampler), texcoord, gl_SampleID);
 
+  vec4 normal_sample = texelFetch(sampler2DMS(u_normal, u_sampler), texcoord, gl_SampleID);
+
+  float in_depth = texelFetch(sampler2DMS(u_depth, u_sampler), texcoord, gl_SampleID).x;
+  vec3 position = reconstruct_position(in_depth);
+
+  // unlit surfaces (e.g. sky) are marked with zero normal alpha and ignore
+  // both static and dynamic lights
+  if (normal_sample.a == 0.0) {
+    color_attachment = vec4(apply_fog(in_color.rgb, position), 1.0);
+    return;
+  }
+
   // scale from [0, 1] to [-1, 1]
-  vec3 in_normal = 2.0
-    * texelFetch(sampler2DMS(u_normal, u_sampler), texcoord, gl_SampleID).xyz
-    - 1.0;
+  vec3 in_normal = 2.0 * normal_sample.xyz - 1.0;
+
+  // surfaces without a facing (e.g. particles) write a zero normal and only
+  // take static light
+  bool has_normal = length(in_normal) > 0.5;
 
   // Double to restore overbright values.
   vec4 in_light = 2.0 * texelFetch(sampler2DMS(u_light, u_sampler), texcoord, gl_SampleID);
 
-  float in_depth = texelFetch(sampler2DMS(u_depth, u_sampler), texcoord, gl_SampleID).x;
-  vec3 position = reconstruct_position(in_depth);
-
   vec4 out_color = in_color;
 
   float light = in_light.x + in_light.y + in_light.z + in_light.w;
@@ -61,11 +99,11 @@ void main() {
     float dist = abs(distance(dlight_origin(dlight), position));
     float radius = dlight_radius(dlight);
 
-    if (dist < radius && dot(dir, in_normal) < 0.0) {
+    if (has_normal && dist < radius && dot(dir, in_normal) < 0.0) {
       // linear attenuation
       light += (radius - dist) / radius;
     }
   }
 
-  color_attachment = vec4(light * out_color.rgb, 1.0);
+  color_attachment = vec4(apply_fog(light * out_color.rgb, position), 1.0);
 }
@@ -10,7 +10,7 @@ layout(set = 0, binding = 0) uniform sampler u_sampler;
 layout(set = 0, binding = 1) uniform texture2D u_texture[256];
 
 layout(location = 0) out vec4 diffuse_attachment;
-// layout(location = 1) out vec4 normal_attachment;
+layout(location = 1) out vec4 normal_attachment;
 layout(location = 2) out vec4 light_attachment;
 
 void main() {
@@ -25,4 +25,8 @@ void main() {
 
   diffuse_attachment = tex_color;
   light_attachment = vec4(0.25);
+
+  // particles have no facing, so write a zero normal to keep dynamic lights
+  // off them and a nonzero alpha so they aren't drawn unlit in front of sky
+  normal_attachment = vec4(0.5, 0.5, 0.5, 1.0);
 }
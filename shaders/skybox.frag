@@ -0,0 +1,24 @@
+#version 450
+
+layout(location = 0) in vec3 f_normal;
+layout(location = 1) in vec3 f_dir;
+
+// set 1: per-entity
+layout(set = 1, binding = 1) uniform sampler u_diffuse_sampler;
+
+// set 2: per-skybox
+layout(set = 2, binding = 0) uniform textureCube u_skybox;
+
+layout(location = 0) out vec4 diffuse_attachment;
+layout(location = 1) out vec4 normal_attachment;
+layout(location = 2) out vec4 light_attachment;
+
+void main() {
+    // cubemap faces are laid out with Quake's Y and Z axes swapped
+    vec3 dir = vec3(f_dir.x, f_dir.z, f_dir.y);
+    diffuse_attachment = vec4(texture(samplerCube(u_skybox, u_diffuse_sampler), dir).rgb, 1.0);
+    light_attachment = vec4(0.0);
+
+    // sky is unlit, so mark it to skip lighting in the deferred pass
+    normal_attachment = vec4(f_normal / 2.0 + 0.5, 0.0);
+}
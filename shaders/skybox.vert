@@ -0,0 +1,30 @@
+#version 450
+
+layout(location = 0) in vec3 a_position;
+layout(location = 1) in vec3 a_normal;
+
+layout(push_constant) uniform PushConstants {
+  mat4 transform;
+  mat4 model_view;
+} push_constants;
+
+layout(location = 0) out vec3 f_normal;
+layout(location = 1) out vec3 f_dir;
+
+layout(set = 0, binding = 0) uniform FrameUniforms {
+    float light_anim_frames[64];
+    vec4 camera_pos;
+    float time;
+} frame_uniforms;
+
+// convert from Quake coordinates
+vec3 convert(vec3 from) {
+  return vec3(-from.y, from.z, -from.x);
+}
+
+void main() {
+    // the cubemap is sampled along the view ray, so the sky stays at infinity
+    f_dir = a_position - frame_uniforms.camera_pos.xyz;
+    f_normal = mat3(transpose(inverse(push_constants.model_view))) * convert(a_normal);
+    gl_Position = push_constants.transform * vec4(convert(a_position), 1.0);
+}
@@ -0,0 +1,24 @@
+#version 450
+
+layout(location = 0) in vec3 a_position;
+layout(location = 1) in vec3 a_normal;
+layout(location = 2) in vec2 a_diffuse;
+
+layout(push_constant) uniform PushConstants {
+  mat4 transform;
+  mat4 model_view;
+} push_constants;
+
+layout(location = 1) out vec2 f_diffuse;
+layout(location = 2) out vec3 f_view_pos;
+
+// convert from Quake coordinates
+vec3 convert(vec3 from) {
+  return vec3(-from.y, from.z, -from.x);
+}
+
+void main() {
+  f_diffuse = a_diffuse;
+  f_view_pos = (push_constants.model_view * vec4(convert(a_position), 1.0)).xyz;
+  gl_Position = push_constants.transform * vec4(convert(a_position), 1.0);
+}
@@ -0,0 +1,125 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+extern crate richter;
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    path::PathBuf,
+    process::exit,
+};
+
+use richter::{
+    client::{
+        demo::DemoServer,
+        demoinfo::{DemoInfo, DEFAULT_SAMPLE_INTERVAL_SECS},
+    },
+    common::{engine, vfs::VirtualFile},
+};
+
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// Seconds between samples of player positions.
+    #[structopt(long, default_value = "1.0")]
+    sample_interval: f32,
+
+    /// Write indented JSON.
+    #[structopt(long)]
+    pretty: bool,
+
+    /// The file to write to. Defaults to standard output.
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    #[structopt(long)]
+    version: bool,
+
+    #[structopt(name = "DEMO", parse(from_os_str), required_unless = "version")]
+    demo: Option<PathBuf>,
+}
+
+const VERSION: &str = "
+demoinfo 0.1
+Copyright © 2020 Cormac O'Brien
+Released under the terms of the MIT License
+";
+
+fn main() {
+    let opt = Opt::from_args();
+
+    if opt.version {
+        println!("{}", VERSION);
+        exit(0);
+    }
+
+    // required_unless guarantees this is present
+    let demo = opt.demo.unwrap();
+    let mut demo_file = match File::open(&demo) {
+        Ok(f) => VirtualFile::FileBacked(BufReader::new(f)),
+        Err(why) => {
+            eprintln!("Couldn't open {}: {}", demo.display(), why);
+            exit(1);
+        }
+    };
+
+    let mut server = match DemoServer::new(&mut demo_file) {
+        Ok(s) => s,
+        Err(why) => {
+            eprintln!("Couldn't read {}: {}", demo.display(), why);
+            exit(1);
+        }
+    };
+
+    let sample_interval = if opt.sample_interval > 0.0 {
+        opt.sample_interval
+    } else {
+        DEFAULT_SAMPLE_INTERVAL_SECS
+    };
+
+    let info = match DemoInfo::analyze(&mut server, engine::duration_from_f32(sample_interval)) {
+        Ok(i) => i,
+        Err(why) => {
+            eprintln!("Couldn't analyze {}: {}", demo.display(), why);
+            exit(1);
+        }
+    };
+
+    let writer: Box<dyn Write> = match opt.output {
+        Some(ref path) => match File::create(path) {
+            Ok(f) => Box::new(BufWriter::new(f)),
+            Err(why) => {
+                eprintln!("Couldn't create {}: {}", path.display(), why);
+                exit(1);
+            }
+        },
+        None => Box::new(io::stdout()),
+    };
+
+    let result = if opt.pretty {
+        serde_json::to_writer_pretty(writer, &info)
+    } else {
+        serde_json::to_writer(writer, &info)
+    };
+
+    if let Err(why) = result {
+        eprintln!("Couldn't write demo info: {}", why);
+        exit(1);
+    }
+}
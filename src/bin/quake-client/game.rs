@@ -94,7 +94,7 @@ impl Game {
     pub fn frame(&mut self, gfx_state: &GraphicsState, frame_duration: Duration) {
         use ClientError::*;
 
-        match self.client.frame(frame_duration, gfx_state) {
+        match self.client.frame(frame_duration, Some(gfx_state)) {
             Ok(()) => (),
             Err(e) => match e {
                 Cvar(_)
@@ -161,6 +161,11 @@ impl Game {
             )
             .unwrap();
 
+        // a captured demo frame is written like a screenshot
+        if let Some(path) = self.client.take_capture_frame() {
+            self.screenshot_path.replace(Some(path));
+        }
+
         // screenshot setup
         let capture = self.screenshot_path.borrow().as_ref().map(|_| {
             let cap = Capture::new(gfx_state.device(), Extent2d { width, height });
@@ -42,7 +42,9 @@ use richter::{
         demo::DemoServer,
         input::{Input, InputFocus},
         menu::Menu,
-        render::{self, Extent2d, GraphicsState, UiRenderer, DIFFUSE_ATTACHMENT_FORMAT},
+        render::{
+            self, Extent2d, GraphicsState, TextureFiltering, UiRenderer, DIFFUSE_ATTACHMENT_FORMAT,
+        },
         Client,
     },
     common::{
@@ -76,6 +78,9 @@ struct ClientProgram {
 
     game: Game,
     input: Rc<RefCell<Input>>,
+
+    // last valid value of gl_texturemode, restored if an invalid one is set
+    texture_mode: String,
 }
 
 impl ClientProgram {
@@ -202,6 +207,7 @@ impl ClientProgram {
         );
 
         let game = Game::new(cvars.clone(), cmds.clone(), input.clone(), client).unwrap();
+        let texture_mode = cvars.borrow().get("gl_texturemode").unwrap();
 
         ClientProgram {
             vfs,
@@ -216,6 +222,7 @@ impl ClientProgram {
             ui_renderer,
             game,
             input,
+            texture_mode,
         }
     }
 
@@ -287,8 +294,41 @@ impl Program for ClientProgram {
             sample_count = 2;
         }
 
+        let filtering = {
+            let cvars = self.cvars.borrow();
+            let mode = cvars.get("gl_texturemode").unwrap_or_default();
+            let anisotropy = cvars.get_value("gl_texture_anisotropy").unwrap_or(1.0) as u16;
+            match TextureFiltering::from_texture_mode(&mode, anisotropy) {
+                Some(filtering) => {
+                    self.texture_mode = mode;
+                    filtering
+                }
+
+                // reject the new value and keep filtering with the last valid one
+                None => {
+                    self.console
+                        .borrow()
+                        .println(format!("bad filter name: {}", mode));
+                    cvars
+                        .set("gl_texturemode", self.texture_mode.as_str())
+                        .unwrap();
+                    TextureFiltering::from_texture_mode(&self.texture_mode, anisotropy)
+                        .unwrap_or_default()
+                }
+            }
+        };
+
         // recreate attachments and rebuild pipelines if necessary
-        self.gfx_state.borrow_mut().update(size, sample_count);
+        self.gfx_state
+            .borrow_mut()
+            .update(size, sample_count, filtering);
+
+        // captured demos advance at a fixed rate however long frames take
+        let frame_duration = self
+            .game
+            .client
+            .capture_frame_time()
+            .unwrap_or(frame_duration);
         self.game.frame(&self.gfx_state.borrow(), frame_duration);
 
         match self.input.borrow().focus() {
@@ -320,6 +360,10 @@ impl Program for ClientProgram {
         // TODO: do cleanup things here
     }
 
+    fn limit_frame_rate(&self) -> bool {
+        !self.game.client.timedemo_running() && !self.game.client.capture_running()
+    }
+
     fn cvars(&self) -> Ref<CvarRegistry> {
         self.cvars.borrow()
     }
@@ -327,6 +371,10 @@ impl Program for ClientProgram {
     fn cvars_mut(&self) -> RefMut<CvarRegistry> {
         self.cvars.borrow_mut()
     }
+
+    fn cmds_mut(&self) -> RefMut<CmdRegistry> {
+        self.cmds.borrow_mut()
+    }
 }
 
 #[derive(StructOpt, Debug)]
@@ -0,0 +1,839 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! A library interface for driving a client from Rust.
+//!
+//! A [`Bot`] is a [`HeadlessClient`] whose moves come from code rather than
+//! from the input system. Each call to [`Bot::tick`] runs one client frame and
+//! sends a single [`ClientCmd::Move`] built from a [`BotCmd`]. Every message
+//! received from the server is kept until taken with
+//! [`Bot::take_server_cmds`], so tests can assert on what the server sent in
+//! response.
+//!
+//! ```no_run
+//! # use std::rc::Rc;
+//! # use chrono::Duration;
+//! # use richter::{client::bot::{Bot, BotCmd}, common::{self, vfs::Vfs}};
+//! let vfs = Vfs::with_base_dir(common::default_base_dir());
+//! let mut bot = Bot::new(Rc::new(vfs)).unwrap();
+//! bot.connect("127.0.0.1:26000").unwrap();
+//! bot.wait_for_signon(Duration::seconds(10)).unwrap();
+//!
+//! // run forward for a second, then fire
+//! let fwd = BotCmd {
+//!     forward: 200,
+//!     ..Default::default()
+//! };
+//! for _ in 0..60 {
+//!     bot.tick(&fwd).unwrap();
+//! }
+//! bot.tick(&BotCmd {
+//!     attack: true,
+//!     ..Default::default()
+//! })
+//! .unwrap();
+//!
+//! let cmds = bot.take_server_cmds().unwrap();
+//! ```
+
+use std::{io::BufReader, net::ToSocketAddrs, rc::Rc, thread};
+
+use crate::{
+    client::{headless::HeadlessClient, ClientError},
+    common::{
+        net::{ButtonFlags, ClientCmd, ClientStat, ItemFlags, NetError, ServerCmd},
+        vfs::Vfs,
+    },
+};
+
+use cgmath::{Deg, Vector3, Zero as _};
+use chrono::Duration;
+use thiserror::Error;
+
+/// The default length of a bot frame (72 frames per second, the rate limit of
+/// the original client).
+pub const DEFAULT_BOT_FRAME_TIME_MS: i64 = 1000 / 72;
+
+#[derive(Error, Debug)]
+pub enum BotError {
+    #[error("Client error: {0}")]
+    Client(#[from] ClientError),
+    #[error("Network error: {0}")]
+    Net(#[from] NetError),
+    #[error("Bot is not connected")]
+    NotConnected,
+    #[error("Timed out waiting for signon")]
+    SignOnTimeout,
+}
+
+/// The movement and actions of a bot for a single frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BotCmd {
+    /// The direction to face, as (pitch, yaw, roll).
+    pub angles: Vector3<Deg<f32>>,
+
+    /// Forward speed in units per second. Negative values move backward.
+    pub forward: i16,
+
+    /// Sideways speed in units per second. Negative values move left.
+    pub side: i16,
+
+    /// Vertical speed in units per second, for swimming and flying.
+    pub up: i16,
+
+    pub attack: bool,
+    pub jump: bool,
+
+    /// Impulse to send, e.g. to change weapons. 0 is no impulse.
+    pub impulse: u8,
+}
+
+impl BotCmd {
+    fn to_move(self, send_time: Duration) -> ClientCmd {
+        let mut button_flags = ButtonFlags::empty();
+        button_flags.set(ButtonFlags::ATTACK, self.attack);
+        button_flags.set(ButtonFlags::JUMP, self.jump);
+
+        ClientCmd::Move {
+            send_time,
+            angles: self.angles,
+            fwd_move: self.forward,
+            side_move: self.side,
+            up_move: self.up,
+            button_flags,
+            impulse: self.impulse,
+        }
+    }
+}
+
+impl Default for BotCmd {
+    fn default() -> BotCmd {
+        BotCmd {
+            angles: Vector3::new(Deg::zero(), Deg::zero(), Deg::zero()),
+            forward: 0,
+            side: 0,
+            up: 0,
+            attack: false,
+            jump: false,
+            impulse: 0,
+        }
+    }
+}
+
+/// A snapshot of an entity visible to the bot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BotEntity {
+    pub id: usize,
+
+    /// The name of the entity's model, e.g. `progs/player.mdl`.
+    pub model: String,
+
+    pub origin: Vector3<f32>,
+    pub angles: Vector3<Deg<f32>>,
+    pub frame_id: usize,
+}
+
+pub struct Bot {
+    client: HeadlessClient,
+    frame_time: Duration,
+    real_time: bool,
+}
+
+impl Bot {
+    /// Create a new, disconnected bot.
+    pub fn new(vfs: Rc<Vfs>) -> Result<Bot, BotError> {
+        let mut client = HeadlessClient::new(vfs)?;
+        client.client_mut().set_capture_server_msgs(true);
+
+        Ok(Bot {
+            client,
+            frame_time: Duration::milliseconds(DEFAULT_BOT_FRAME_TIME_MS),
+            real_time: true,
+        })
+    }
+
+    /// Set the length of each frame run by [`Bot::tick`].
+    pub fn set_frame_time(&mut self, frame_time: Duration) {
+        self.frame_time = frame_time;
+    }
+
+    /// Choose whether frames take real time.
+    ///
+    /// This is on by default. Turn it off when the caller steps the server
+    /// itself between frames.
+    pub fn set_real_time(&mut self, real_time: bool) {
+        self.real_time = real_time;
+    }
+
+    /// Connect to a server.
+    ///
+    /// This only performs the connection handshake. Use
+    /// [`Bot::wait_for_signon`] to wait until the bot is in the game.
+    pub fn connect<A>(&mut self, server_addrs: A) -> Result<(), BotError>
+    where
+        A: ToSocketAddrs,
+    {
+        self.client.client_mut().connect(server_addrs)?;
+        Ok(())
+    }
+
+    /// Run frames without moving until the bot has finished signing on.
+    pub fn wait_for_signon(&mut self, timeout: Duration) -> Result<(), BotError> {
+        let mut waited = Duration::zero();
+        while !self.client.client().signed_on() {
+            if waited >= timeout {
+                return Err(BotError::SignOnTimeout);
+            }
+
+            self.frame()?;
+            waited += self.frame_time;
+        }
+
+        Ok(())
+    }
+
+    /// Run one frame and send `cmd` to the server.
+    ///
+    /// Unless disabled with [`Bot::set_real_time`], frames take real time so
+    /// that the bot doesn't outrun the server.
+    pub fn tick(&mut self, cmd: &BotCmd) -> Result<(), BotError> {
+        self.frame()?;
+
+        let send_time = match self.client.client().state() {
+            Some(state) => state.time(),
+            None => return Err(BotError::NotConnected),
+        };
+
+        self.client
+            .client()
+            .send_unreliable(&cmd.to_move(send_time))?;
+
+        Ok(())
+    }
+
+    fn frame(&mut self) -> Result<(), BotError> {
+        if self.real_time {
+            if let Ok(t) = self.frame_time.to_std() {
+                thread::sleep(t);
+            }
+        }
+
+        // moves come from tick(), so skip the input system
+        self.client.console().borrow().execute();
+        self.client.client_mut().frame(self.frame_time, None)?;
+
+        if self.client.client().state().is_none() {
+            return Err(BotError::NotConnected);
+        }
+
+        Ok(())
+    }
+
+    /// Send a console command to the server, e.g. `say hello`.
+    ///
+    /// The command is sent reliably at the end of the next frame.
+    pub fn server_cmd<S>(&self, cmd: S) -> Result<(), BotError>
+    where
+        S: AsRef<str>,
+    {
+        self.client.client().send_reliable(&ClientCmd::StringCmd {
+            cmd: cmd.as_ref().to_owned(),
+        })?;
+        Ok(())
+    }
+
+    /// Returns `true` if the bot has finished signing on.
+    pub fn signed_on(&self) -> bool {
+        self.client.client().signed_on()
+    }
+
+    /// The position of the bot's view entity.
+    pub fn origin(&self) -> Option<Vector3<f32>> {
+        let state = self.client.client().state()?;
+        state
+            .entities
+            .get(state.view_entity_id())
+            .map(|e| e.get_origin())
+    }
+
+    /// The bot's velocity as last reported by the server.
+    pub fn velocity(&self) -> Option<Vector3<f32>> {
+        Some(self.client.client().state()?.velocity)
+    }
+
+    pub fn stat(&self, stat: ClientStat) -> Option<i32> {
+        Some(self.client.client().state()?.stats()[stat as usize])
+    }
+
+    pub fn items(&self) -> Option<ItemFlags> {
+        Some(self.client.client().state()?.items())
+    }
+
+    /// The networked entities that were visible to the bot on the last frame.
+    ///
+    /// The bot's own entity is not included.
+    pub fn visible_entities(&self) -> Vec<BotEntity> {
+        let state = match self.client.client().state() {
+            Some(s) => s,
+            None => return Vec::new(),
+        };
+
+        state
+            .visible_entity_ids
+            .iter()
+            .map(|&id| {
+                let ent = &state.entities[id];
+                BotEntity {
+                    id,
+                    model: state
+                        .models
+                        .get(ent.model_id())
+                        .map(|m| m.name().to_owned())
+                        .unwrap_or_default(),
+                    origin: ent.get_origin(),
+                    angles: ent.get_angles(),
+                    frame_id: ent.frame_id(),
+                }
+            })
+            .collect()
+    }
+
+    /// Take the commands received from the server since the last call.
+    pub fn take_server_cmds(&mut self) -> Result<Vec<ServerCmd>, BotError> {
+        Ok(decode_msgs(&self.client.client_mut().take_server_msgs())?)
+    }
+
+    pub fn client(&self) -> &HeadlessClient {
+        &self.client
+    }
+
+    pub fn client_mut(&mut self) -> &mut HeadlessClient {
+        &mut self.client
+    }
+}
+
+fn decode_msgs(msgs: &[Vec<u8>]) -> Result<Vec<ServerCmd>, NetError> {
+    let mut cmds = Vec::new();
+    for msg in msgs {
+        let mut reader = BufReader::new(msg.as_slice());
+        while let Some(cmd) = ServerCmd::deserialize(&mut reader)? {
+            cmds.push(cmd);
+        }
+    }
+
+    Ok(cmds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        collections::VecDeque,
+        fs,
+        io::{BufRead as _, Cursor},
+        net::UdpSocket,
+        path::Path,
+    };
+
+    use crate::{
+        client::sound::WavWriter,
+        common::{
+            engine,
+            net::{
+                self,
+                connect::{ConnectListener, ListenerPacket, Request, Response, ResponseAccept},
+                BlockingMode, EntityUpdate, GameType, QSocket, SignOnStage,
+            },
+        },
+    };
+
+    use byteorder::{LittleEndian, WriteBytesExt as _};
+    use cgmath::{InnerSpace as _, Rad};
+
+    const SOUND_NAMES: &[&str] = &[
+        "misc/null.wav",
+        "hknight/hit.wav",
+        "weapons/r_exp3.wav",
+        "weapons/ric1.wav",
+        "weapons/ric2.wav",
+        "weapons/ric3.wav",
+        "weapons/tink1.wav",
+        "wizard/hit.wav",
+    ];
+
+    /// Build a world with no faces: a single plane at the bottom of the map
+    /// with solid space below it and empty space above.
+    fn empty_world_bsp() -> Vec<u8> {
+        let mut sections: Vec<Vec<u8>> = vec![Vec::new(); 15];
+
+        sections[0] = b"{\n\"classname\" \"worldspawn\"\n}\n\0".to_vec();
+
+        // planes: normal, distance, axis
+        let planes = &mut sections[1];
+        for &x in &[0.0, 0.0, 1.0, -1024.0] {
+            planes.write_f32::<LittleEndian>(x).unwrap();
+        }
+        planes.write_i32::<LittleEndian>(2).unwrap();
+
+        // textures: count
+        sections[2].write_i32::<LittleEndian>(0).unwrap();
+
+        // render nodes: plane, front (leaf 1), back (leaf 0), bounds, faces
+        let nodes = &mut sections[5];
+        nodes.write_i32::<LittleEndian>(0).unwrap();
+        for &x in &[!1, !0, 0, 0, 0, 0, 0, 0, 0, 0] {
+            nodes.write_i16::<LittleEndian>(x).unwrap();
+        }
+
+        // leaves: contents, visibility offset, bounds, faces, ambient sounds
+        let leaves = &mut sections[10];
+        for &contents in &[-2, -1] {
+            leaves.write_i32::<LittleEndian>(contents).unwrap();
+            leaves.write_i32::<LittleEndian>(-1).unwrap();
+            leaves.extend_from_slice(&[0; 20]);
+        }
+
+        // models: bounds, origin, hull roots, leaf count, faces
+        let models = &mut sections[14];
+        for &x in &[
+            -1024.0, -1024.0, -1024.0, 1024.0, 1024.0, 1024.0, 0.0, 0.0, 0.0,
+        ] {
+            models.write_f32::<LittleEndian>(x).unwrap();
+        }
+        for &x in &[0, 0, 0, 0, 2, 0, 0] {
+            models.write_i32::<LittleEndian>(x).unwrap();
+        }
+
+        let mut bsp = Vec::new();
+        bsp.write_i32::<LittleEndian>(29).unwrap();
+        let mut offset = 4 + 8 * sections.len();
+        for section in &sections {
+            bsp.write_i32::<LittleEndian>(offset as i32).unwrap();
+            bsp.write_i32::<LittleEndian>(section.len() as i32).unwrap();
+            offset += section.len();
+        }
+        for section in &sections {
+            bsp.extend_from_slice(section);
+        }
+
+        bsp
+    }
+
+    /// Write the world, the sounds every client loads and a 1x1 sprite for the
+    /// player.
+    fn write_game_data(dir: &Path) {
+        fs::create_dir_all(dir.join("maps")).unwrap();
+        fs::write(dir.join("maps/empty.bsp"), empty_world_bsp()).unwrap();
+
+        for name in SOUND_NAMES {
+            let path = dir.join("sound").join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let mut wav = WavWriter::new(Cursor::new(Vec::new()), 1, 11025).unwrap();
+            wav.write_samples(&[0.0; 16]).unwrap();
+            fs::write(path, wav.finish().unwrap().into_inner()).unwrap();
+        }
+
+        let mut spr = Vec::new();
+        spr.extend_from_slice(b"IDSP");
+        // version, kind
+        for &x in &[1, 0] {
+            spr.write_i32::<LittleEndian>(x).unwrap();
+        }
+        spr.write_f32::<LittleEndian>(1.0).unwrap();
+        // max width, max height, frame count, beam length, sync type, then a
+        // single static frame: kind, origin x, origin z, width, height
+        for &x in &[1, 1, 1, 0, 0, 0, 0, 0, 1, 1] {
+            spr.write_i32::<LittleEndian>(x).unwrap();
+        }
+        spr.push(0);
+        fs::create_dir_all(dir.join("progs")).unwrap();
+        fs::write(dir.join("progs/bot.spr"), spr).unwrap();
+    }
+
+    fn server_msg(cmds: &[ServerCmd]) -> Vec<u8> {
+        let mut msg = Vec::new();
+        for cmd in cmds {
+            cmd.serialize(&mut msg).unwrap();
+        }
+        msg
+    }
+
+    /// Accept the connection request from a single client.
+    ///
+    /// The client blocks in `connect` until it's accepted, so this runs on its
+    /// own thread. Returns the socket for the new connection.
+    fn accept_one_client(listener: ConnectListener) -> thread::JoinHandle<QSocket> {
+        thread::spawn(move || {
+            let (packet, remote) = listener.recv_packet().unwrap();
+            assert!(matches!(
+                packet,
+                ListenerPacket::Request(Request::Connect(_))
+            ));
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let port = socket.local_addr().unwrap().port() as i32;
+            listener
+                .send_response(Response::Accept(ResponseAccept { port }), remote)
+                .unwrap();
+            QSocket::new(socket, remote)
+        })
+    }
+
+    /// A server for a single client with no world to collide with.
+    ///
+    /// The server is stepped by the test in lockstep with the bot, using the
+    /// same frame time. Every `Move` moves the client's entity and firing uses
+    /// up one unit of ammo.
+    struct TestServer {
+        qsock: QSocket,
+        frame_time: Duration,
+        time: Duration,
+        origin: Vector3<f32>,
+        ammo: i32,
+        spawned: bool,
+        reliable: VecDeque<Vec<u8>>,
+
+        /// Every command received from the client.
+        received: Vec<ClientCmd>,
+    }
+
+    impl TestServer {
+        fn new(qsock: QSocket, frame_time: Duration) -> TestServer {
+            let mut reliable = VecDeque::new();
+            reliable.push_back(server_msg(&[
+                ServerCmd::ServerInfo {
+                    protocol_version: net::PROTOCOL_VERSION as i32,
+                    max_clients: 1,
+                    game_type: GameType::CoOp,
+                    message: String::from("bot test"),
+                    model_precache: vec![
+                        String::from("maps/empty.bsp"),
+                        String::from("progs/bot.spr"),
+                    ],
+                    sound_precache: Vec::new(),
+                },
+                ServerCmd::SignOnStage {
+                    stage: SignOnStage::Prespawn,
+                },
+            ]));
+
+            TestServer {
+                qsock,
+                frame_time,
+                time: Duration::zero(),
+                origin: Vector3::zero(),
+                ammo: 25,
+                spawned: false,
+                reliable,
+                received: Vec::new(),
+            }
+        }
+
+        /// Handle everything the client has sent, then send it one message.
+        ///
+        /// The client reads one message per frame and waits for one while
+        /// signing on, so exactly one is sent per step.
+        fn step(&mut self) {
+            self.time = self.time + self.frame_time;
+
+            loop {
+                let msg = self.qsock.recv_msg(BlockingMode::NonBlocking).unwrap();
+                if msg.is_empty() {
+                    break;
+                }
+
+                let mut reader = BufReader::new(msg.as_slice());
+                while !reader.fill_buf().unwrap().is_empty() {
+                    let cmd = ClientCmd::deserialize(&mut reader).unwrap();
+                    self.handle(&cmd);
+                    self.received.push(cmd);
+                }
+            }
+
+            if self.qsock.can_send() && !self.reliable.is_empty() {
+                let msg = self.reliable.pop_front().unwrap();
+                self.qsock.begin_send_msg(&msg).unwrap();
+            } else if self.spawned {
+                self.send_update();
+            } else {
+                self.qsock
+                    .send_msg_unreliable(&server_msg(&[ServerCmd::NoOp]))
+                    .unwrap();
+            }
+        }
+
+        fn handle(&mut self, cmd: &ClientCmd) {
+            match *cmd {
+                ClientCmd::StringCmd { ref cmd } if cmd == "prespawn" => {
+                    self.reliable
+                        .push_back(server_msg(&[ServerCmd::SignOnStage {
+                            stage: SignOnStage::ClientInfo,
+                        }]));
+                }
+                ClientCmd::StringCmd { ref cmd } if cmd.starts_with("spawn") => {
+                    self.reliable.push_back(server_msg(&[
+                        ServerCmd::SetView { ent_id: 1 },
+                        ServerCmd::UpdateStat {
+                            stat: ClientStat::Ammo,
+                            value: self.ammo,
+                        },
+                        ServerCmd::SignOnStage {
+                            stage: SignOnStage::Begin,
+                        },
+                    ]));
+                }
+                // the first update finishes signon
+                ClientCmd::StringCmd { ref cmd } if cmd == "begin" => self.spawned = true,
+                ClientCmd::Move {
+                    angles,
+                    fwd_move,
+                    button_flags,
+                    ..
+                } if self.spawned => {
+                    let yaw = Rad::from(angles.y).0;
+                    let secs = self.frame_time.num_milliseconds() as f32 / 1000.0;
+                    self.origin += Vector3::new(yaw.cos(), yaw.sin(), 0.0) * fwd_move as f32 * secs;
+                    if button_flags.contains(ButtonFlags::ATTACK) {
+                        self.ammo -= 1;
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        fn send_update(&mut self) {
+            self.qsock
+                .send_msg_unreliable(&server_msg(&[
+                    ServerCmd::Time {
+                        time: engine::duration_to_f32(self.time),
+                    },
+                    ServerCmd::UpdateStat {
+                        stat: ClientStat::Ammo,
+                        value: self.ammo,
+                    },
+                    ServerCmd::FastUpdate(EntityUpdate {
+                        ent_id: 1,
+                        model_id: Some(2),
+                        frame_id: None,
+                        colormap: None,
+                        skin_id: None,
+                        effects: None,
+                        origin_x: Some(self.origin.x),
+                        pitch: None,
+                        origin_y: Some(self.origin.y),
+                        yaw: None,
+                        origin_z: Some(self.origin.z),
+                        roll: None,
+                        no_lerp: false,
+                    }),
+                ]))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_bot_walk_and_fire() {
+        let dir = std::env::temp_dir().join(format!("richter-bot-{}", std::process::id()));
+        write_game_data(&dir);
+        let mut vfs = Vfs::new();
+        vfs.add_directory(&dir).unwrap();
+
+        let listener = ConnectListener::bind("127.0.0.1:0").unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let accept = accept_one_client(listener);
+
+        let frame_time = Duration::milliseconds(DEFAULT_BOT_FRAME_TIME_MS);
+        let mut bot = Bot::new(Rc::new(vfs)).unwrap();
+        bot.set_frame_time(frame_time);
+        bot.set_real_time(false);
+        bot.connect(server_addr).unwrap();
+        let mut server = TestServer::new(accept.join().unwrap(), frame_time);
+
+        // each bot frame reads what the server sent in the step before it
+        let mut tick = |bot: &mut Bot, cmd: Option<&BotCmd>| {
+            server.step();
+            match cmd {
+                Some(cmd) => bot.tick(cmd).unwrap(),
+                None => bot.frame().unwrap(),
+            }
+        };
+
+        for _ in 0..20 {
+            if bot.signed_on() {
+                break;
+            }
+            tick(&mut bot, None);
+        }
+        assert!(bot.signed_on());
+        assert_eq!(bot.stat(ClientStat::Ammo), Some(25));
+
+        // walk to the target, turning towards it every frame
+        let target = Vector3::new(96.0, 48.0, 0.0);
+        let mut reached = false;
+        for _ in 0..500 {
+            let delta = target - bot.origin().unwrap();
+            if delta.magnitude() < 8.0 {
+                reached = true;
+                break;
+            }
+
+            let yaw = Deg::from(Rad(delta.y.atan2(delta.x)));
+            let cmd = BotCmd {
+                angles: Vector3::new(Deg(0.0), yaw, Deg(0.0)),
+                forward: 200,
+                ..Default::default()
+            };
+            tick(&mut bot, Some(&cmd));
+        }
+        assert!(reached, "bot stopped at {:?}", bot.origin());
+
+        let walk_cmds = bot.take_server_cmds().unwrap();
+        assert!(walk_cmds.iter().any(|cmd| matches!(
+            cmd,
+            ServerCmd::FastUpdate(EntityUpdate {
+                ent_id: 1,
+                origin_x: Some(x),
+                ..
+            }) if *x > 0.0
+        )));
+
+        // fire; the server handles the move in its next step and the bot sees
+        // the result in the frame after that
+        let fire = BotCmd {
+            attack: true,
+            ..Default::default()
+        };
+        tick(&mut bot, Some(&fire));
+        tick(&mut bot, Some(&BotCmd::default()));
+        assert_eq!(bot.stat(ClientStat::Ammo), Some(24));
+        assert!(bot
+            .take_server_cmds()
+            .unwrap()
+            .contains(&ServerCmd::UpdateStat {
+                stat: ClientStat::Ammo,
+                value: 24,
+            }));
+
+        server.step();
+        let string_cmds: Vec<_> = server
+            .received
+            .iter()
+            .filter_map(|cmd| match cmd {
+                ClientCmd::StringCmd { cmd } => Some(cmd.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(string_cmds.first(), Some(&"prespawn"));
+        assert_eq!(string_cmds.last(), Some(&"begin"));
+
+        // the bot ran forward, fired once and then stood still
+        let moves: Vec<_> = server
+            .received
+            .iter()
+            .filter_map(|cmd| match *cmd {
+                ClientCmd::Move {
+                    fwd_move,
+                    button_flags,
+                    ..
+                } => Some((fwd_move, button_flags.contains(ButtonFlags::ATTACK))),
+                _ => None,
+            })
+            .collect();
+        let fire = moves.iter().position(|&(_, attack)| attack).unwrap();
+        assert!(fire > 0);
+        assert!(moves[..fire].iter().all(|&m| m == (200, false)));
+        assert_eq!(&moves[fire + 1..], &[(0, false)]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_bot_cmd_to_move() {
+        let cmd = BotCmd {
+            angles: Vector3::new(Deg(10.0), Deg(90.0), Deg(0.0)),
+            forward: 200,
+            side: -100,
+            attack: true,
+            impulse: 7,
+            ..Default::default()
+        };
+
+        let mut msg = Vec::new();
+        cmd.to_move(Duration::milliseconds(500))
+            .serialize(&mut msg)
+            .unwrap();
+        match ClientCmd::deserialize(&mut BufReader::new(msg.as_slice())).unwrap() {
+            ClientCmd::Move {
+                fwd_move,
+                side_move,
+                up_move,
+                button_flags,
+                impulse,
+                ..
+            } => {
+                assert_eq!((fwd_move, side_move, up_move), (200, -100, 0));
+                assert_eq!(button_flags, ButtonFlags::ATTACK);
+                assert_eq!(impulse, 7);
+            }
+            other => panic!("expected Move, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decode_msgs() {
+        let mut first = Vec::new();
+        ServerCmd::Time { time: 1.5 }.serialize(&mut first).unwrap();
+        ServerCmd::SetView { ent_id: 1 }
+            .serialize(&mut first)
+            .unwrap();
+        let mut second = Vec::new();
+        ServerCmd::Print {
+            text: "hello\n".to_owned(),
+        }
+        .serialize(&mut second)
+        .unwrap();
+
+        assert_eq!(
+            decode_msgs(&[first, second]).unwrap(),
+            vec![
+                ServerCmd::Time { time: 1.5 },
+                ServerCmd::SetView { ent_id: 1 },
+                ServerCmd::Print {
+                    text: "hello\n".to_owned()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bot_disconnected() {
+        let mut bot = Bot::new(Rc::new(Vfs::new())).unwrap();
+        bot.set_frame_time(Duration::zero());
+
+        assert!(!bot.signed_on());
+        assert_eq!(bot.origin(), None);
+        assert!(bot.visible_entities().is_empty());
+        assert!(matches!(
+            bot.tick(&BotCmd::default()),
+            Err(BotError::NotConnected)
+        ));
+        assert!(bot.server_cmd("say hello").is_err());
+    }
+}
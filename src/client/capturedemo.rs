@@ -0,0 +1,180 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Demo capture to image sequences and audio.
+//!
+//! A captured demo is played back at a fixed frame rate regardless of how long
+//! each frame takes to render. Sound is mixed offline, and exactly as many
+//! samples are written per frame as the frame lasts, so the frames and audio
+//! stay in sync however long the capture runs.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use crate::client::sound::{OfflineMixer, SoundOutput, WavWriter};
+
+use chrono::Duration;
+
+/// The default capture frame rate.
+pub const DEFAULT_CAPTURE_FPS: u32 = 30;
+
+/// The sample rate of captured audio.
+pub const CAPTURE_SAMPLE_RATE: u32 = 44100;
+
+/// The number of channels in captured audio.
+pub const CAPTURE_CHANNELS: u16 = 2;
+
+/// The name of the captured audio file within the capture directory.
+pub const CAPTURE_AUDIO_FILE: &str = "audio.wav";
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// A demo being captured to a directory.
+pub struct DemoCapture {
+    dir: PathBuf,
+    fps: u32,
+
+    // number of frames captured so far
+    frames: u64,
+
+    mixer: OfflineMixer,
+    wav: WavWriter<BufWriter<File>>,
+    samples: Vec<f32>,
+
+    // the image for the most recent frame, if it hasn't been written yet
+    pending_frame: Option<PathBuf>,
+}
+
+impl DemoCapture {
+    /// Begin a capture into `dir`, creating it if necessary.
+    ///
+    /// Returns the capture along with the output that sounds should be played
+    /// on to be captured.
+    pub fn new<P>(dir: P, fps: u32) -> Result<(DemoCapture, SoundOutput), io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = dir.as_ref().to_owned();
+        fs::create_dir_all(&dir)?;
+
+        let (mixer, output) = OfflineMixer::new(CAPTURE_CHANNELS, CAPTURE_SAMPLE_RATE);
+        let wav = WavWriter::new(
+            BufWriter::new(File::create(dir.join(CAPTURE_AUDIO_FILE))?),
+            CAPTURE_CHANNELS,
+            CAPTURE_SAMPLE_RATE,
+        )?;
+
+        Ok((
+            DemoCapture {
+                dir,
+                fps,
+                frames: 0,
+                mixer,
+                wav,
+                samples: Vec::new(),
+                pending_frame: None,
+            },
+            output,
+        ))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Returns the simulated duration of the next frame.
+    ///
+    /// Frame times are rounded to the nanosecond, but the rounding never
+    /// accumulates: after `n` frames exactly `n / fps` seconds have passed.
+    pub fn frame_time(&self) -> Duration {
+        let nanos =
+            frame_start_nanos(self.frames + 1, self.fps) - frame_start_nanos(self.frames, self.fps);
+        Duration::nanoseconds(nanos as i64)
+    }
+
+    /// Capture the audio for the frame just simulated.
+    ///
+    /// The frame's image should then be written to the path returned by
+    /// [`take_pending_frame`](DemoCapture::take_pending_frame).
+    pub fn capture_frame(&mut self) -> Result<(), io::Error> {
+        let sample_frames = frame_start_sample(self.frames + 1, self.fps)
+            - frame_start_sample(self.frames, self.fps);
+
+        self.samples.clear();
+        self.mixer.mix(sample_frames as usize, &mut self.samples);
+        self.wav.write_samples(&self.samples)?;
+
+        self.pending_frame = Some(self.dir.join(format!("frame{:06}.png", self.frames)));
+        self.frames += 1;
+
+        Ok(())
+    }
+
+    /// Returns the path the latest frame's image should be written to, if it
+    /// hasn't been taken already.
+    pub fn take_pending_frame(&mut self) -> Option<PathBuf> {
+        self.pending_frame.take()
+    }
+
+    /// Finish writing the captured audio.
+    pub fn finish(self) -> Result<(), io::Error> {
+        self.wav.finish()?;
+        Ok(())
+    }
+}
+
+// the time at which frame `n` begins, in nanoseconds
+fn frame_start_nanos(n: u64, fps: u32) -> u64 {
+    n * NANOS_PER_SEC / fps as u64
+}
+
+// the first audio sample frame played during frame `n`
+fn frame_start_sample(n: u64, fps: u32) -> u64 {
+    n * CAPTURE_SAMPLE_RATE as u64 / fps as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_timing_does_not_drift() {
+        for &fps in [24, 30, 60, 144].iter() {
+            let n = fps as u64 * 3600;
+
+            let nanos: u64 = (0..n)
+                .map(|i| frame_start_nanos(i + 1, fps) - frame_start_nanos(i, fps))
+                .sum();
+            assert_eq!(nanos, 3600 * NANOS_PER_SEC);
+
+            let samples: u64 = (0..n)
+                .map(|i| frame_start_sample(i + 1, fps) - frame_start_sample(i, fps))
+                .sum();
+            assert_eq!(samples, 3600 * CAPTURE_SAMPLE_RATE as u64);
+        }
+    }
+}
@@ -30,6 +30,7 @@ pub fn register_cvars(cvars: &CvarRegistry) -> Result<(), ConsoleError> {
     cvars.register("cl_crossx", "0")?;
     cvars.register("cl_crossy", "0")?;
     cvars.register_archive("cl_forwardspeed", "400")?;
+    cvars.register_archive("cl_gun_offset", "0 0 0")?;
     cvars.register("cl_movespeedkey", "2.0")?;
     cvars.register_archive("_cl_name", "player")?;
     cvars.register("cl_nolerp", "0")?;
@@ -40,10 +41,16 @@ pub fn register_cvars(cvars: &CvarRegistry) -> Result<(), ConsoleError> {
     cvars.register("cl_sidespeed", "350")?;
     cvars.register("cl_upspeed", "200")?;
     cvars.register("cl_yawspeed", "140")?;
+    cvars.register("demo_speed", "1")?;
     cvars.register("fov", "90")?;
     cvars.register_archive("m_pitch", "0.022")?;
     cvars.register_archive("m_yaw", "0.022")?;
+    cvars.register_archive("net_master", "dpmaster.deathmask.net")?;
+    cvars.register("rcon_address", "")?;
+    cvars.register("rcon_password", "")?;
     cvars.register_archive("sensitivity", "3")?;
+    cvars.register_archive("slist_port_max", "26000")?;
+    cvars.register_archive("slist_port_min", "26000")?;
     cvars.register("v_idlescale", "0")?;
     cvars.register("v_ipitch_cycle", "1")?;
     cvars.register("v_ipitch_level", "0.3")?;
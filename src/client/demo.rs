@@ -1,15 +1,26 @@
-use std::{io, ops::Range};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    ops::Range,
+    path::Path,
+};
 
-use crate::common::{
-    net::{self, NetError},
-    util::read_f32_3,
-    vfs::VirtualFile,
+use crate::{
+    client::qwdemo::{DemoFormat, QwDemo},
+    common::{
+        engine,
+        net::{self, ClientStat, NetError, PlayerColor, ServerCmd},
+        util::read_f32_3,
+        vfs::VirtualFile,
+    },
 };
 
 use arrayvec::ArrayVec;
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use cgmath::{Deg, Vector3};
-use io::BufReader;
+use chrono::Duration;
+use num::FromPrimitive;
 use thiserror::Error;
 
 /// An error returned by a demo server.
@@ -25,11 +36,40 @@ pub enum DemoServerError {
     Io(#[from] io::Error),
     #[error("Network error: {0}")]
     Net(#[from] NetError),
+    #[error("Invalid demo block type: {0}")]
+    InvalidBlock(u8),
+    #[error("Unsupported QuakeWorld protocol version: {0}")]
+    QwProtocol(i32),
+    #[error("Unsupported QuakeWorld protocol extension {0:#010x} (flags {1:#010x})")]
+    QwExtension(u32, u32),
+    #[error("Demo is not a QuakeWorld demo")]
+    NotQuakeWorld,
+    #[error("Demo is not a multi-view demo")]
+    NotMultiView,
+    #[error("No such player in demo: {0}")]
+    NoSuchPlayer(u8),
+}
+
+/// Returns the file name for the demo `name`.
+///
+/// Demos are `.dem` files unless another extension is given, so QuakeWorld
+/// demos can be played by naming the `.qwd` or `.mvd` file.
+pub fn demo_file_name(name: &str) -> String {
+    match Path::new(name).extension() {
+        Some(_) => name.to_owned(),
+        None => format!("{}.dem", name),
+    }
 }
 
+/// The minimum playback time between seek keyframes.
+pub const KEYFRAME_INTERVAL_SECS: i64 = 10;
+
 struct DemoMessage {
     view_angles: Vector3<Deg<f32>>,
     msg_range: Range<usize>,
+
+    // playback time as of the last `Time` command in or before this message
+    elapsed: Duration,
 }
 
 /// A view of a server message from a demo.
@@ -50,10 +90,149 @@ impl<'a> DemoMessageView<'a> {
     }
 }
 
+/// A point in the demo that playback can be restarted from.
+///
+/// Entity state is sent in full every server frame, so the client state at a
+/// keyframe can be rebuilt from the level's signon messages plus the few
+/// pieces of state that are only sent when they change.
+struct Keyframe {
+    elapsed: Duration,
+
+    // the message to continue playback from
+    message_id: usize,
+
+    // the signon messages for the level being played
+    signon: Range<usize>,
+
+    // commands restoring light styles, scores, stats and music
+    state_msg: Vec<u8>,
+}
+
+#[derive(Default)]
+struct DemoPlayerInfo {
+    name: String,
+    frags: i16,
+    colors: Option<PlayerColor>,
+}
+
+// State that is only sent when it changes, tracked while indexing the demo.
+#[derive(Default)]
+struct PersistentState {
+    cd_track: Option<(u8, u8)>,
+    light_styles: BTreeMap<u8, String>,
+    stats: BTreeMap<u8, i32>,
+    players: BTreeMap<u8, DemoPlayerInfo>,
+}
+
+impl PersistentState {
+    fn update(&mut self, cmd: &ServerCmd) {
+        match *cmd {
+            ServerCmd::CdTrack { track, loop_ } => self.cd_track = Some((track, loop_)),
+            ServerCmd::LightStyle { id, ref value } => {
+                self.light_styles.insert(id, value.clone());
+            }
+            ServerCmd::UpdateStat { stat, value } => {
+                self.stats.insert(stat as u8, value);
+            }
+            ServerCmd::KilledMonster => {
+                *self
+                    .stats
+                    .entry(ClientStat::KilledMonsters as u8)
+                    .or_insert(0) += 1
+            }
+            ServerCmd::FoundSecret => {
+                *self
+                    .stats
+                    .entry(ClientStat::FoundSecrets as u8)
+                    .or_insert(0) += 1
+            }
+            ServerCmd::UpdateName {
+                player_id,
+                ref new_name,
+            } => self.players.entry(player_id).or_default().name = new_name.clone(),
+            ServerCmd::UpdateFrags {
+                player_id,
+                new_frags,
+            } => self.players.entry(player_id).or_default().frags = new_frags,
+            ServerCmd::UpdateColors {
+                player_id,
+                new_colors,
+            } => self.players.entry(player_id).or_default().colors = Some(new_colors),
+            _ => (),
+        }
+    }
+
+    fn to_msg(&self) -> Result<Vec<u8>, NetError> {
+        let mut msg = Vec::new();
+
+        if let Some((track, loop_)) = self.cd_track {
+            ServerCmd::CdTrack { track, loop_ }.serialize(&mut msg)?;
+        }
+
+        for (&id, value) in self.light_styles.iter() {
+            ServerCmd::LightStyle {
+                id,
+                value: value.clone(),
+            }
+            .serialize(&mut msg)?;
+        }
+
+        for (&stat, &value) in self.stats.iter() {
+            if let Some(stat) = ClientStat::from_u8(stat) {
+                ServerCmd::UpdateStat { stat, value }.serialize(&mut msg)?;
+            }
+        }
+
+        for (&player_id, info) in self.players.iter() {
+            // a player only exists once they have a name
+            if info.name.is_empty() {
+                continue;
+            }
+
+            ServerCmd::UpdateName {
+                player_id,
+                new_name: info.name.clone(),
+            }
+            .serialize(&mut msg)?;
+            ServerCmd::UpdateFrags {
+                player_id,
+                new_frags: info.frags,
+            }
+            .serialize(&mut msg)?;
+            if let Some(new_colors) = info.colors {
+                ServerCmd::UpdateColors {
+                    player_id,
+                    new_colors,
+                }
+                .serialize(&mut msg)?;
+            }
+        }
+
+        Ok(msg)
+    }
+}
+
+// Where the next message comes from while restoring a keyframe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Restore {
+    Signon { keyframe: usize, message_id: usize },
+    State { keyframe: usize },
+}
+
+// A QuakeWorld demo and the point of view its messages were translated from.
+struct QwPlayback {
+    demo: QwDemo,
+    tracked: Option<u8>,
+    players: Vec<u8>,
+}
+
 /// A server that yields commands from a demo file.
 pub struct DemoServer {
     track_override: Option<u32>,
 
+    // the source of the messages if this is a QuakeWorld demo
+    qw: Option<QwPlayback>,
+
     // id of next message to "send"
     message_id: usize,
 
@@ -61,80 +240,57 @@ pub struct DemoServer {
 
     // all message data
     message_data: Vec<u8>,
+
+    keyframes: Vec<Keyframe>,
+    restore: Option<Restore>,
+    seek_target: Option<Duration>,
+    elapsed: Duration,
+    paused: bool,
+    timedemo: bool,
 }
 
 impl DemoServer {
     /// Construct a new `DemoServer` from the specified demo file.
+    ///
+    /// All messages are read up front and indexed by time so that playback
+    /// can be repositioned with [`seek`](DemoServer::seek).
     pub fn new(file: &mut VirtualFile) -> Result<DemoServer, DemoServerError> {
-        let mut dem_reader = BufReader::new(file);
-
-        let mut buf = ArrayVec::<u8, 3>::new();
-        // copy CD track number (terminated by newline) into buffer
-        for i in 0..buf.capacity() {
-            match dem_reader.read_u8()? {
-                b'\n' => break,
-                // cannot panic because we won't exceed capacity with a loop this small
-                b => buf.push(b),
-            }
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
 
-            if i >= buf.capacity() - 1 {
-                // CD track would be more than 2 digits long, which is impossible
-                Err(DemoServerError::InvalidCdTrack)?;
+        let (track_override, mut messages, message_data, qw) = match DemoFormat::detect(&data) {
+            DemoFormat::NetQuake => {
+                let (track_override, messages, message_data) = read_netquake(&data)?;
+                (track_override, messages, message_data, None)
             }
-        }
 
-        let track_override = {
-            let track_str = match std::str::from_utf8(&buf) {
-                Ok(s) => s,
-                Err(_) => Err(DemoServerError::InvalidCdTrack)?,
-            };
-
-            match track_str {
-                // if track is empty, default to track 0
-                "" => Some(0),
-                s => match s.parse::<i32>() {
-                    Ok(track) => match track {
-                        // if track is -1, allow demo to specify tracks in messages
-                        -1 => None,
-                        t if t < -1 => Err(DemoServerError::InvalidCdTrack)?,
-                        _ => Some(track as u32),
-                    },
-                    Err(_) => Err(DemoServerError::InvalidCdTrack)?,
-                },
+            format => {
+                let demo = QwDemo::parse(format, data)?;
+                let translation = demo.translate(None)?;
+                let (messages, message_data) = translated_messages(translation.messages);
+                let qw = QwPlayback {
+                    demo,
+                    tracked: translation.tracked,
+                    players: translation.players,
+                };
+                (None, messages, message_data, Some(qw))
             }
         };
 
-        let mut message_data = Vec::new();
-        let mut messages = Vec::new();
-
-        // read all messages
-        while let Ok(msg_len) = dem_reader.read_u32::<LittleEndian>() {
-            // get view angles
-            let view_angles_f32 = read_f32_3(&mut dem_reader)?;
-            let view_angles = Vector3::new(
-                Deg(view_angles_f32[0]),
-                Deg(view_angles_f32[1]),
-                Deg(view_angles_f32[2]),
-            );
-
-            // read next message
-            let msg_start = message_data.len();
-            for _ in 0..msg_len {
-                message_data.push(dem_reader.read_u8()?);
-            }
-            let msg_end = message_data.len();
-
-            messages.push(DemoMessage {
-                view_angles,
-                msg_range: msg_start..msg_end,
-            });
-        }
+        let keyframes = index_messages(&mut messages, &message_data)?;
 
         Ok(DemoServer {
             track_override,
+            qw,
             message_id: 0,
             messages,
             message_data,
+            keyframes,
+            restore: None,
+            seek_target: None,
+            elapsed: Duration::zero(),
+            paused: false,
+            timedemo: false,
         })
     }
 
@@ -142,12 +298,57 @@ impl DemoServer {
     ///
     /// If this returns `None`, the demo is complete.
     pub fn next(&mut self) -> Option<DemoMessageView> {
+        match self.restore {
+            Some(Restore::Signon {
+                keyframe,
+                message_id,
+            }) => {
+                let kf = &self.keyframes[keyframe];
+                self.restore = if message_id + 1 < kf.signon.end {
+                    Some(Restore::Signon {
+                        keyframe,
+                        message_id: message_id + 1,
+                    })
+                } else {
+                    Some(Restore::State { keyframe })
+                };
+
+                let msg = &self.messages[message_id];
+                return Some(DemoMessageView {
+                    view_angles: msg.view_angles,
+                    message: &self.message_data[msg.msg_range.clone()],
+                });
+            }
+
+            Some(Restore::State { keyframe }) => {
+                self.restore = None;
+
+                let kf = &self.keyframes[keyframe];
+                self.message_id = kf.message_id;
+                self.elapsed = kf.elapsed;
+                return Some(DemoMessageView {
+                    view_angles: self.messages[kf.message_id].view_angles,
+                    message: &kf.state_msg,
+                });
+            }
+
+            None => (),
+        }
+
         if self.message_id >= self.messages.len() {
+            self.seek_target = None;
             return None;
         }
 
         let msg = &self.messages[self.message_id];
         self.message_id += 1;
+        self.elapsed = msg.elapsed;
+
+        if let Some(target) = self.seek_target {
+            if msg.elapsed >= target {
+                self.seek_target = None;
+            }
+        }
 
         Some(DemoMessageView {
             view_angles: msg.view_angles,
@@ -163,4 +364,668 @@ impl DemoServer {
     pub fn track_override(&self) -> Option<u32> {
         self.track_override
     }
+
+    /// Returns the playback time of the most recent message.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Returns the total playback time of the demo.
+    pub fn duration(&self) -> Duration {
+        self.messages
+            .last()
+            .map(|m| m.elapsed)
+            .unwrap_or_else(Duration::zero)
+    }
+
+    /// Reposition playback at the given time.
+    ///
+    /// Messages up to `target` should then be played back without waiting,
+    /// until [`seeking`](DemoServer::seeking) returns false. If this returns
+    /// `true`, playback restarts from a keyframe and the client state must be
+    /// rebuilt from the upcoming signon messages.
+    pub fn seek(&mut self, target: Duration) -> bool {
+        let target = target.max(Duration::zero()).min(self.duration());
+        self.seek_target = Some(target);
+
+        let keyframe = self
+            .keyframes
+            .iter()
+            .rposition(|kf| kf.elapsed <= target)
+            .unwrap_or(0);
+
+        // if we're already between the keyframe and the target, just keep going
+        if self.restore.is_none()
+            && target >= self.elapsed
+            && self.keyframes[keyframe].message_id <= self.message_id
+        {
+            return false;
+        }
+
+        let kf = &self.keyframes[keyframe];
+        self.restore = if kf.signon.is_empty() {
+            Some(Restore::State { keyframe })
+        } else {
+            Some(Restore::Signon {
+                keyframe,
+                message_id: kf.signon.start,
+            })
+        };
+
+        true
+    }
+
+    /// Returns true if playback has not yet reached the target of a seek.
+    pub fn seeking(&self) -> bool {
+        self.seek_target.is_some()
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Returns true if this demo is being benchmarked.
+    ///
+    /// In a timedemo, one message is played back per frame regardless of the
+    /// time recorded in the demo.
+    pub fn timedemo(&self) -> bool {
+        self.timedemo
+    }
+
+    pub fn set_timedemo(&mut self, timedemo: bool) {
+        self.timedemo = timedemo;
+    }
+
+    /// Returns the format of the demo file being played.
+    pub fn format(&self) -> DemoFormat {
+        match self.qw {
+            Some(ref qw) => qw.demo.format(),
+            None => DemoFormat::NetQuake,
+        }
+    }
+
+    /// Returns the players whose point of view can be played back, in order.
+    ///
+    /// This is empty unless the demo is a multi-view demo.
+    pub fn trackable_players(&self) -> &[u8] {
+        match self.qw {
+            Some(ref qw) if qw.demo.format() == DemoFormat::MultiView => &qw.players,
+            _ => &[],
+        }
+    }
+
+    /// Returns the player whose point of view is being played back, if known.
+    pub fn tracked_player(&self) -> Option<u8> {
+        self.qw.as_ref().and_then(|qw| qw.tracked)
+    }
+
+    /// Play a multi-view demo back from the point of view of another player.
+    ///
+    /// Playback continues from the current time, but the client state must be
+    /// rebuilt from the upcoming signon messages as it would be after a
+    /// [`seek`](DemoServer::seek).
+    pub fn track_player(&mut self, player_id: u8) -> Result<(), DemoServerError> {
+        let qw = match self.qw {
+            Some(ref mut qw) if qw.demo.format() == DemoFormat::MultiView => qw,
+            _ => Err(DemoServerError::NotMultiView)?,
+        };
+
+        if !qw.players.contains(&player_id) {
+            Err(DemoServerError::NoSuchPlayer(player_id))?;
+        }
+
+        let translation = qw.demo.translate(Some(player_id))?;
+        qw.tracked = translation.tracked;
+        let (mut messages, message_data) = translated_messages(translation.messages);
+        self.keyframes = index_messages(&mut messages, &message_data)?;
+        self.messages = messages;
+        self.message_data = message_data;
+
+        // restart from the beginning, then catch up to where we were
+        let elapsed = self.elapsed;
+        self.message_id = 0;
+        self.elapsed = Duration::zero();
+        self.restore = None;
+        self.seek(elapsed);
+
+        Ok(())
+    }
+}
+
+// Read the CD track and messages of a NetQuake demo.
+fn read_netquake(data: &[u8]) -> Result<(Option<u32>, Vec<DemoMessage>, Vec<u8>), DemoServerError> {
+    let mut dem_reader = data;
+
+    let mut buf = ArrayVec::<u8, 3>::new();
+    // copy CD track number (terminated by newline) into buffer
+    for i in 0..buf.capacity() {
+        match dem_reader.read_u8()? {
+            b'\n' => break,
+            // cannot panic because we won't exceed capacity with a loop this small
+            b => buf.push(b),
+        }
+
+        if i >= buf.capacity() - 1 {
+            // CD track would be more than 2 digits long, which is impossible
+            Err(DemoServerError::InvalidCdTrack)?;
+        }
+    }
+
+    let track_override = {
+        let track_str = match std::str::from_utf8(&buf) {
+            Ok(s) => s,
+            Err(_) => Err(DemoServerError::InvalidCdTrack)?,
+        };
+
+        match track_str {
+            // if track is empty, default to track 0
+            "" => Some(0),
+            s => match s.parse::<i32>() {
+                Ok(track) => match track {
+                    // if track is -1, allow demo to specify tracks in messages
+                    -1 => None,
+                    t if t < -1 => Err(DemoServerError::InvalidCdTrack)?,
+                    _ => Some(track as u32),
+                },
+                Err(_) => Err(DemoServerError::InvalidCdTrack)?,
+            },
+        }
+    };
+
+    let mut message_data = Vec::new();
+    let mut messages = Vec::new();
+
+    // read all messages
+    while let Ok(msg_len) = dem_reader.read_u32::<LittleEndian>() {
+        // get view angles
+        let view_angles_f32 = read_f32_3(&mut dem_reader)?;
+        let view_angles = Vector3::new(
+            Deg(view_angles_f32[0]),
+            Deg(view_angles_f32[1]),
+            Deg(view_angles_f32[2]),
+        );
+
+        // read next message
+        if msg_len as usize > dem_reader.len() {
+            Err(io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        }
+        let msg_start = message_data.len();
+        let (msg, rest) = dem_reader.split_at(msg_len as usize);
+        message_data.extend_from_slice(msg);
+        dem_reader = rest;
+        let msg_end = message_data.len();
+
+        messages.push(DemoMessage {
+            view_angles,
+            msg_range: msg_start..msg_end,
+            elapsed: Duration::zero(),
+        });
+    }
+
+    Ok((track_override, messages, message_data))
+}
+
+// Lay out messages translated from a QuakeWorld demo like those read from a
+// NetQuake demo.
+fn translated_messages(
+    translated: Vec<(Vector3<Deg<f32>>, Vec<u8>)>,
+) -> (Vec<DemoMessage>, Vec<u8>) {
+    let mut message_data = Vec::new();
+    let messages = translated
+        .into_iter()
+        .map(|(view_angles, msg)| {
+            let msg_start = message_data.len();
+            message_data.extend_from_slice(&msg);
+            DemoMessage {
+                view_angles,
+                msg_range: msg_start..message_data.len(),
+                elapsed: Duration::zero(),
+            }
+        })
+        .collect();
+
+    (messages, message_data)
+}
+
+// Compute the playback time of each message and build the seek keyframes.
+fn index_messages(
+    messages: &mut [DemoMessage],
+    message_data: &[u8],
+) -> Result<Vec<Keyframe>, DemoServerError> {
+    let interval = Duration::seconds(KEYFRAME_INTERVAL_SECS);
+
+    // playing from the first message is always possible
+    let mut keyframes = vec![Keyframe {
+        elapsed: Duration::zero(),
+        message_id: 0,
+        signon: 0..0,
+        state_msg: Vec::new(),
+    }];
+
+    let mut state = PersistentState::default();
+    let mut elapsed = Duration::zero();
+    let mut last_time: Option<f32> = None;
+    let mut signon_start = None;
+    let mut signon: Option<Range<usize>> = None;
+
+    for (message_id, msg) in messages.iter_mut().enumerate() {
+        let mut reader = &message_data[msg.msg_range.clone()];
+        let mut cmds = Vec::new();
+        loop {
+            match ServerCmd::deserialize(&mut reader) {
+                Ok(Some(cmd)) => cmds.push(cmd),
+                Ok(None) => break,
+                Err(e) => {
+                    // the client will report this when it gets here
+                    debug!("Failed to index demo message {}: {}", message_id, e);
+                    break;
+                }
+            }
+        }
+
+        for cmd in cmds.iter() {
+            match *cmd {
+                // a new level begins
+                ServerCmd::ServerInfo { .. } => {
+                    state = PersistentState::default();
+                    last_time = None;
+                    signon_start = Some(message_id);
+                    signon = None;
+                }
+
+                // the first entity update ends the signon
+                ServerCmd::FastUpdate(_) if signon.is_none() => {
+                    if let Some(start) = signon_start {
+                        signon = Some(start..message_id);
+                    }
+                }
+
+                _ => (),
+            }
+        }
+
+        if let Some(time) = cmds.iter().find_map(|cmd| match *cmd {
+            ServerCmd::Time { time } => Some(time),
+            _ => None,
+        }) {
+            if let Some(ref signon) = signon {
+                // keyframe state is the state before this message
+                let last = keyframes.last().unwrap();
+                if message_id > signon.end && elapsed - last.elapsed >= interval {
+                    keyframes.push(Keyframe {
+                        elapsed,
+                        message_id,
+                        signon: signon.clone(),
+                        state_msg: state.to_msg()?,
+                    });
+                }
+            }
+
+            if let Some(last) = last_time {
+                if time > last {
+                    elapsed += engine::duration_from_f32(time - last);
+                }
+            }
+            last_time = Some(time);
+        }
+
+        for cmd in cmds.iter() {
+            state.update(cmd);
+        }
+
+        msg.elapsed = elapsed;
+    }
+
+    Ok(keyframes)
+}
+
+/// Records server messages to a demo file.
+///
+/// The output uses the same layout read by [`DemoServer::new`]: a CD track
+/// number terminated by a newline, followed by any number of messages, each
+/// consisting of its length, the view angles at the time it was received and
+/// the message itself.
+pub struct DemoRecorder {
+    writer: BufWriter<File>,
+    message_count: usize,
+}
+
+impl DemoRecorder {
+    /// Create a new demo file at the given path, truncating any existing file.
+    ///
+    /// If `track_override` is `None`, the demo will play the CD tracks specified
+    /// by the server.
+    pub fn create<P>(path: P, track_override: Option<u32>) -> Result<DemoRecorder, DemoServerError>
+    where
+        P: AsRef<Path>,
+    {
+        let mut writer = BufWriter::new(File::create(path)?);
+        match track_override {
+            Some(t) => writeln!(writer, "{}", t)?,
+            None => writeln!(writer, "-1")?,
+        }
+
+        Ok(DemoRecorder {
+            writer,
+            message_count: 0,
+        })
+    }
+
+    /// Returns the number of messages written so far.
+    pub fn message_count(&self) -> usize {
+        self.message_count
+    }
+
+    /// Write a single server message along with the current view angles.
+    pub fn write_message(
+        &mut self,
+        view_angles: Vector3<Deg<f32>>,
+        msg: &[u8],
+    ) -> Result<(), DemoServerError> {
+        if msg.len() > net::MAX_MESSAGE {
+            return Err(DemoServerError::MessageTooLong(msg.len() as u32));
+        }
+
+        self.writer.write_u32::<LittleEndian>(msg.len() as u32)?;
+        for angle in [view_angles.x, view_angles.y, view_angles.z] {
+            self.writer.write_f32::<LittleEndian>(angle.0)?;
+        }
+        self.writer.write_all(msg)?;
+        self.message_count += 1;
+
+        Ok(())
+    }
+
+    /// Serialize `cmds` and write them as one or more messages.
+    ///
+    /// Commands are packed into as few messages as possible without exceeding
+    /// the maximum message size.
+    pub fn write_cmds(
+        &mut self,
+        view_angles: Vector3<Deg<f32>>,
+        cmds: &[ServerCmd],
+    ) -> Result<(), DemoServerError> {
+        let mut msg = Vec::new();
+        let mut cmd_buf = Vec::new();
+
+        for cmd in cmds {
+            cmd_buf.clear();
+            cmd.serialize(&mut cmd_buf)?;
+
+            if msg.len() + cmd_buf.len() > net::MAX_MESSAGE {
+                self.write_message(view_angles, &msg)?;
+                msg.clear();
+            }
+
+            msg.extend_from_slice(&cmd_buf);
+        }
+
+        if !msg.is_empty() {
+            self.write_message(view_angles, &msg)?;
+        }
+
+        Ok(())
+    }
+
+    /// End the recording.
+    ///
+    /// A final `Disconnect` message is written so that playback ends cleanly.
+    pub fn finish(mut self, view_angles: Vector3<Deg<f32>>) -> Result<(), DemoServerError> {
+        self.write_cmds(view_angles, &[ServerCmd::Disconnect])?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use crate::common::net::{GameType, SignOnStage};
+
+    // an entity update for entity 1 with no fields set
+    const ENTITY_UPDATE: [u8; 2] = [0x80, 0x01];
+
+    // A demo of a single level with a message every half second for 30 seconds.
+    // Light style 0 changes from "a" to "z" 15 seconds in.
+    fn seek_test_demo() -> DemoServer {
+        let path =
+            std::env::temp_dir().join(format!("richter-demo-seek-{}.dem", std::process::id()));
+        let angles = Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0));
+
+        let mut recorder = DemoRecorder::create(&path, None).unwrap();
+        recorder
+            .write_cmds(
+                angles,
+                &[
+                    ServerCmd::ServerInfo {
+                        protocol_version: net::PROTOCOL_VERSION as i32,
+                        max_clients: 1,
+                        game_type: GameType::CoOp,
+                        message: String::from("test"),
+                        model_precache: vec![String::from("maps/test.bsp")],
+                        sound_precache: Vec::new(),
+                    },
+                    ServerCmd::SignOnStage {
+                        stage: SignOnStage::Prespawn,
+                    },
+                ],
+            )
+            .unwrap();
+        recorder
+            .write_cmds(
+                angles,
+                &[ServerCmd::LightStyle {
+                    id: 0,
+                    value: String::from("a"),
+                }],
+            )
+            .unwrap();
+
+        for i in 0..60 {
+            let mut msg = Vec::new();
+            ServerCmd::Time {
+                time: 1.0 + i as f32 * 0.5,
+            }
+            .serialize(&mut msg)
+            .unwrap();
+            msg.extend_from_slice(&ENTITY_UPDATE);
+            if i == 30 {
+                ServerCmd::LightStyle {
+                    id: 0,
+                    value: String::from("z"),
+                }
+                .serialize(&mut msg)
+                .unwrap();
+            }
+            recorder.write_message(angles, &msg).unwrap();
+        }
+        drop(recorder);
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        DemoServer::new(&mut VirtualFile::PakBacked(Cursor::new(&data))).unwrap()
+    }
+
+    fn first_cmd(msg: &DemoMessageView) -> Option<ServerCmd> {
+        let mut reader = msg.message();
+        ServerCmd::deserialize(&mut reader).unwrap()
+    }
+
+    #[test]
+    fn test_demo_index() {
+        let demo = seek_test_demo();
+        assert_eq!(demo.duration(), Duration::milliseconds(29_500));
+
+        // the first keyframe is the start of the demo
+        assert_eq!(demo.keyframes[0].message_id, 0);
+        for pair in demo.keyframes.windows(2) {
+            assert!(pair[1].elapsed - pair[0].elapsed >= Duration::seconds(KEYFRAME_INTERVAL_SECS));
+        }
+        assert!(demo.keyframes.len() >= 3);
+
+        // keyframes after the signon replay it
+        assert_eq!(demo.keyframes[1].signon, 0..2);
+    }
+
+    #[test]
+    fn test_demo_seek_backwards() {
+        let mut demo = seek_test_demo();
+
+        // play to the end
+        while demo.next().is_some() {}
+        assert_eq!(demo.elapsed(), Duration::milliseconds(29_500));
+
+        assert!(demo.seek(Duration::seconds(25)));
+        assert!(demo.seeking());
+
+        // the level's signon is replayed first...
+        match first_cmd(&demo.next().unwrap()) {
+            Some(ServerCmd::ServerInfo { .. }) => (),
+            c => panic!("expected ServerInfo, got {:?}", c),
+        }
+        match first_cmd(&demo.next().unwrap()) {
+            Some(ServerCmd::LightStyle { value, .. }) => assert_eq!(value, "a"),
+            c => panic!("expected LightStyle, got {:?}", c),
+        }
+
+        // ...followed by the state at the keyframe
+        match first_cmd(&demo.next().unwrap()) {
+            Some(ServerCmd::LightStyle { value, .. }) => assert_eq!(value, "z"),
+            c => panic!("expected LightStyle, got {:?}", c),
+        }
+
+        // then playback continues from the keyframe until the target
+        let mut count = 0;
+        while demo.seeking() {
+            demo.next().unwrap();
+            count += 1;
+        }
+        assert!(count <= 2 * KEYFRAME_INTERVAL_SECS as usize + 1);
+        assert_eq!(demo.elapsed(), Duration::seconds(25));
+    }
+
+    #[test]
+    fn test_demo_seek_forward() {
+        let mut demo = seek_test_demo();
+        for _ in 0..10 {
+            demo.next().unwrap();
+        }
+
+        // seeking forward within a keyframe interval doesn't restart playback
+        assert!(!demo.seek(Duration::seconds(5)));
+        while demo.seeking() {
+            demo.next().unwrap();
+        }
+        assert_eq!(demo.elapsed(), Duration::seconds(5));
+    }
+
+    #[test]
+    fn test_demo_recorder_round_trip() {
+        let path = std::env::temp_dir().join(format!("richter-demo-{}.dem", std::process::id()));
+        let angles = Vector3::new(Deg(10.0), Deg(90.0), Deg(0.0));
+
+        let mut recorder = DemoRecorder::create(&path, Some(2)).unwrap();
+        recorder.write_message(angles, &[1, 2, 3]).unwrap();
+        recorder
+            .write_cmds(
+                angles,
+                &[
+                    ServerCmd::Print {
+                        text: String::from("hello"),
+                    },
+                    ServerCmd::NoOp,
+                ],
+            )
+            .unwrap();
+        assert_eq!(recorder.message_count(), 2);
+        recorder.finish(angles).unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut file = VirtualFile::PakBacked(Cursor::new(&data));
+        let mut server = DemoServer::new(&mut file).unwrap();
+        assert_eq!(server.track_override(), Some(2));
+
+        let msg = server.next().unwrap();
+        assert_eq!(msg.view_angles(), angles);
+        assert_eq!(msg.message(), &[1, 2, 3]);
+
+        let msg = server.next().unwrap();
+        let mut reader = msg.message();
+        assert_eq!(
+            ServerCmd::deserialize(&mut reader).unwrap(),
+            Some(ServerCmd::Print {
+                text: String::from("hello")
+            })
+        );
+        assert_eq!(
+            ServerCmd::deserialize(&mut reader).unwrap(),
+            Some(ServerCmd::NoOp)
+        );
+
+        let msg = server.next().unwrap();
+        let mut reader = msg.message();
+        assert_eq!(
+            ServerCmd::deserialize(&mut reader).unwrap(),
+            Some(ServerCmd::Disconnect)
+        );
+        assert!(server.next().is_none());
+    }
+
+    #[test]
+    fn test_demo_recorder_splits_long_messages() {
+        let path =
+            std::env::temp_dir().join(format!("richter-demo-split-{}.dem", std::process::id()));
+        let angles = Vector3::new(Deg(-15.0), Deg(270.0), Deg(5.0));
+
+        let cmds: Vec<ServerCmd> = (0..=255)
+            .map(|id| ServerCmd::LightStyle {
+                id,
+                value: "m".repeat(63),
+            })
+            .collect();
+        let mut expected = Vec::new();
+        for cmd in cmds.iter() {
+            cmd.serialize(&mut expected).unwrap();
+        }
+        assert!(expected.len() > net::MAX_MESSAGE);
+
+        let mut recorder = DemoRecorder::create(&path, None).unwrap();
+        recorder.write_cmds(angles, &cmds).unwrap();
+        let message_count = recorder.message_count();
+        assert!(message_count > 1);
+        drop(recorder);
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut server = DemoServer::new(&mut VirtualFile::PakBacked(Cursor::new(&data))).unwrap();
+
+        // every message splits between commands and carries the view angles
+        let mut received = Vec::new();
+        let mut received_cmds = Vec::new();
+        for _ in 0..message_count {
+            let msg = server.next().unwrap();
+            assert_eq!(msg.view_angles(), angles);
+            assert!(msg.message().len() <= net::MAX_MESSAGE);
+            received.extend_from_slice(msg.message());
+
+            let mut reader = msg.message();
+            while let Some(cmd) = ServerCmd::deserialize(&mut reader).unwrap() {
+                received_cmds.push(cmd);
+            }
+        }
+        assert!(server.next().is_none());
+
+        assert_eq!(received, expected);
+        assert_eq!(received_cmds, cmds);
+    }
 }
@@ -0,0 +1,606 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Match statistics extracted from demos.
+//!
+//! Demos are played back through a [`ClientState`] without rendering or
+//! loading any game data. Player names, scores, stats and positions are read
+//! from the state, and events like obituaries and damage are collected from
+//! the server commands as they're applied.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    client::{
+        demo::DemoServer,
+        sound::SoundOutput,
+        state::{ClientState, PlayerInfo},
+        ClientError,
+    },
+    common::{
+        engine,
+        net::{ClientStat, ServerCmd},
+    },
+};
+
+use chrono::Duration;
+use serde::Serialize;
+
+/// The default time between samples of player positions.
+pub const DEFAULT_SAMPLE_INTERVAL_SECS: f32 = 1.0;
+
+/// A player who appeared in the demo.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PlayerSummary {
+    pub slot: u8,
+    pub name: String,
+    pub top_color: u8,
+    pub bottom_color: u8,
+
+    /// The player's score at the end of the demo.
+    pub frags: i16,
+
+    pub positions: Vec<PositionSample>,
+}
+
+/// A player's position at a point in the demo.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PositionSample {
+    pub time: f32,
+    pub origin: [f32; 3],
+}
+
+/// A change to a player's score.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FragEvent {
+    pub time: f32,
+    pub player: String,
+    pub frags: i16,
+    pub change: i16,
+}
+
+/// A death announced by the server.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Obituary {
+    pub time: f32,
+    pub victim: String,
+
+    /// The player responsible, or `None` for suicides and deaths to the world.
+    pub attacker: Option<String>,
+
+    /// The obituary message with the player names removed.
+    pub message: String,
+}
+
+/// An item picked up by the player whose point of view was recorded.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ItemPickup {
+    pub time: f32,
+    pub player: Option<String>,
+    pub item: String,
+}
+
+/// Damage taken by the player whose point of view was recorded.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DamageEvent {
+    pub time: f32,
+    pub player: Option<String>,
+    pub armor: u8,
+    pub health: u8,
+    pub source: [f32; 3],
+}
+
+/// A timeline of the match recorded in a demo.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct DemoInfo {
+    /// The name of the map, e.g. `e1m1`.
+    pub map: String,
+
+    /// The level's title as shown on the scoreboard.
+    pub title: String,
+
+    pub duration: f32,
+    pub players: Vec<PlayerSummary>,
+    pub frags: Vec<FragEvent>,
+    pub obituaries: Vec<Obituary>,
+    pub pickups: Vec<ItemPickup>,
+    pub damage: Vec<DamageEvent>,
+
+    /// Monsters killed and secrets found on the last level, as shown on the
+    /// scoreboard.
+    pub monsters_killed: i32,
+    pub secrets_found: i32,
+}
+
+impl DemoInfo {
+    /// Play back a demo and collect its timeline.
+    ///
+    /// Player positions are sampled at most once per `sample_interval`.
+    pub fn analyze(
+        server: &mut DemoServer,
+        sample_interval: Duration,
+    ) -> Result<DemoInfo, ClientError> {
+        let mut analyzer = DemoAnalyzer::new(sample_interval);
+
+        while let Some(msg) = server.next() {
+            let mut reader = msg.message();
+            let mut cmds = Vec::new();
+            while let Some(cmd) = ServerCmd::deserialize(&mut reader)? {
+                cmds.push(cmd);
+            }
+
+            analyzer.update(server.elapsed(), cmds)?;
+        }
+
+        Ok(analyzer.finish(server.duration()))
+    }
+}
+
+// Match state collected from server commands.
+//
+// Commands are applied to a `ClientState` the same way the client applies
+// them, and names, scores and positions are read back from it.
+struct DemoAnalyzer {
+    info: DemoInfo,
+    sample_interval: Duration,
+    next_sample: Duration,
+
+    state: ClientState,
+    positions: BTreeMap<usize, Vec<PositionSample>>,
+}
+
+impl DemoAnalyzer {
+    fn new(sample_interval: Duration) -> DemoAnalyzer {
+        DemoAnalyzer {
+            info: DemoInfo::default(),
+            sample_interval,
+            next_sample: Duration::zero(),
+            state: ClientState::new(SoundOutput::Null),
+            positions: BTreeMap::new(),
+        }
+    }
+
+    fn player(&self, player_id: usize) -> Option<&PlayerInfo> {
+        self.state
+            .player_info
+            .get(player_id)?
+            .as_ref()
+            .filter(|p| !p.name.is_empty())
+    }
+
+    fn player_name(&self, ent_id: usize) -> Option<String> {
+        match ent_id {
+            0 => None,
+            id => self.player(id - 1).map(|p| p.name.clone()),
+        }
+    }
+
+    // Apply the commands from one message, received at `elapsed`.
+    fn update(&mut self, elapsed: Duration, cmds: Vec<ServerCmd>) -> Result<(), ClientError> {
+        let time = engine::duration_to_f32(elapsed);
+        let mut updated = Vec::new();
+
+        for cmd in cmds {
+            match cmd {
+                ServerCmd::ServerInfo {
+                    max_clients,
+                    ref message,
+                    ref model_precache,
+                    ..
+                } => {
+                    // the world model is always first, e.g. maps/e1m1.bsp
+                    if let Some(world) = model_precache.first() {
+                        self.info.map = world
+                            .trim_start_matches("maps/")
+                            .trim_end_matches(".bsp")
+                            .to_owned();
+                    }
+                    self.info.title = message.clone();
+                    self.state = ClientState::without_game_data(max_clients, model_precache);
+                }
+
+                ServerCmd::UpdateFrags {
+                    player_id,
+                    new_frags,
+                } => {
+                    let old = self
+                        .player(player_id as usize)
+                        .map(|p| (p.name.clone(), p.frags));
+                    self.state.handle_server_cmd(cmd)?;
+
+                    // scores are restated at signon and for new players
+                    if let Some((player, frags)) = old {
+                        let change = new_frags - frags as i16;
+                        if change != 0 {
+                            self.info.frags.push(FragEvent {
+                                time,
+                                player,
+                                frags: new_frags,
+                                change,
+                            });
+                        }
+                    }
+                }
+
+                ServerCmd::Print { ref text } => self.print(time, text),
+
+                ServerCmd::Damage {
+                    armor,
+                    blood,
+                    source,
+                } => self.info.damage.push(DamageEvent {
+                    time,
+                    player: self.player_name(self.state.view_entity_id()),
+                    armor,
+                    health: blood,
+                    source: source.into(),
+                }),
+
+                ServerCmd::FastUpdate(update) => {
+                    let ent_id = update.ent_id as usize;
+                    self.state.update_entity(ent_id, update)?;
+                    updated.push(ent_id);
+                }
+
+                // everything else is either handled by the state or ignored
+                cmd => {
+                    self.state.handle_server_cmd(cmd)?;
+                }
+            }
+        }
+
+        // only frames with entity updates count as samples
+        if elapsed >= self.next_sample && !updated.is_empty() {
+            for ent_id in updated {
+                if ent_id == 0 || ent_id > self.state.max_players {
+                    continue;
+                }
+
+                if self.player(ent_id - 1).is_some() {
+                    let origin = self.state.entities[ent_id].msg_origins[0];
+                    self.positions
+                        .entry(ent_id - 1)
+                        .or_default()
+                        .push(PositionSample {
+                            time,
+                            origin: origin.into(),
+                        });
+                }
+            }
+
+            while self.next_sample <= elapsed {
+                self.next_sample += self.sample_interval;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn print(&mut self, time: f32, text: &str) {
+        for line in text.lines() {
+            if let Some(item) = parse_pickup(line) {
+                self.info.pickups.push(ItemPickup {
+                    time,
+                    player: self.player_name(self.state.view_entity_id()),
+                    item: item.to_owned(),
+                });
+            } else if let Some(obituary) = self.parse_obituary(time, line) {
+                self.info.obituaries.push(obituary);
+            }
+        }
+    }
+
+    // Obituaries are a victim's name followed by a description of their death
+    // which may include the name of the killer.
+    fn parse_obituary(&self, time: f32, line: &str) -> Option<Obituary> {
+        const NOT_OBITUARIES: [&str; 4] = [
+            "entered the game",
+            "left the game",
+            "changed name to",
+            "has joined the",
+        ];
+
+        let names: Vec<&str> = (0..self.state.max_players)
+            .filter_map(|id| self.player(id))
+            .map(|p| p.name.as_str())
+            .collect();
+
+        // prefer longer names in case one is a prefix of another
+        let victim = names
+            .iter()
+            .filter(|&&n| {
+                line.starts_with(n)
+                    && line[n.len()..].starts_with(' ')
+                    && !line[n.len()..].starts_with(" :")
+            })
+            .max_by_key(|n| n.len())?;
+        let rest = line[victim.len()..].trim();
+        if rest.is_empty() || NOT_OBITUARIES.iter().any(|s| rest.contains(s)) {
+            return None;
+        }
+
+        let attacker = names
+            .iter()
+            .filter_map(|&n| {
+                let at = rest.find(n)?;
+                let before = rest[..at].chars().last();
+                let after = rest[at + n.len()..].chars().next();
+                match (before, after) {
+                    (Some(' '), None | Some(' ') | Some('\'') | Some('.') | Some('!')) => {
+                        Some((n, at))
+                    }
+                    _ => None,
+                }
+            })
+            .max_by_key(|(n, _)| n.len());
+
+        let message = match attacker {
+            Some((n, at)) => format!("{}{}", &rest[..at], &rest[at + n.len()..]),
+            None => rest.to_owned(),
+        };
+
+        Some(Obituary {
+            time,
+            victim: victim.to_string(),
+            attacker: attacker.map(|(n, _)| n.to_owned()),
+            message: message.trim().to_owned(),
+        })
+    }
+
+    fn finish(mut self, duration: Duration) -> DemoInfo {
+        let mut info = self.info;
+        info.duration = engine::duration_to_f32(duration);
+        info.monsters_killed = self.state.stats[ClientStat::KilledMonsters as usize];
+        info.secrets_found = self.state.stats[ClientStat::FoundSecrets as usize];
+
+        let positions = &mut self.positions;
+        info.players = self
+            .state
+            .player_info
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, p)| Some((slot, p.as_ref()?)))
+            .filter(|(_, p)| !p.name.is_empty())
+            .map(|(slot, p)| PlayerSummary {
+                slot: slot as u8,
+                name: p.name.clone(),
+                top_color: p.colors.bits() >> 4,
+                bottom_color: p.colors.bits() & 0x0F,
+                frags: p.frags as i16,
+                positions: positions.remove(&slot).unwrap_or_default(),
+            })
+            .collect();
+        info
+    }
+}
+
+// Item pickup messages are only sent to the player picking the item up.
+fn parse_pickup(line: &str) -> Option<&str> {
+    ["You got the ", "You got ", "You get ", "You receive "]
+        .iter()
+        .find_map(|prefix| line.strip_prefix(prefix))
+        .map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::net::{EntityUpdate, GameType, PlayerColor};
+
+    use cgmath::Vector3;
+
+    fn secs(s: i64) -> Duration {
+        Duration::seconds(s)
+    }
+
+    fn analyzer() -> DemoAnalyzer {
+        let mut analyzer = DemoAnalyzer::new(secs(1));
+        let mut cmds = vec![
+            ServerCmd::ServerInfo {
+                protocol_version: 15,
+                max_clients: 4,
+                game_type: GameType::Deathmatch,
+                message: String::from("The Slipgate Complex"),
+                model_precache: vec![String::from("maps/e1m1.bsp")],
+                sound_precache: Vec::new(),
+            },
+            ServerCmd::SpawnBaseline {
+                ent_id: 2,
+                model_id: 0,
+                frame_id: 0,
+                colormap: 0,
+                skin_id: 0,
+                origin: Vector3::new(16.0, 32.0, 48.0),
+                angles: Vector3::new(cgmath::Deg(0.0), cgmath::Deg(0.0), cgmath::Deg(0.0)),
+            },
+            ServerCmd::SetView { ent_id: 1 },
+        ];
+        for (player_id, name) in ["ranger", "ranger2"].iter().enumerate() {
+            cmds.push(ServerCmd::UpdateName {
+                player_id: player_id as u8,
+                new_name: name.to_string(),
+            });
+            cmds.push(ServerCmd::UpdateColors {
+                player_id: player_id as u8,
+                new_colors: PlayerColor::new(player_id as u8, 4),
+            });
+        }
+        analyzer.update(Duration::zero(), cmds).unwrap();
+        analyzer
+    }
+
+    #[test]
+    fn test_obituaries() {
+        let mut analyzer = analyzer();
+        analyzer
+            .update(
+                secs(1),
+                vec![
+                    ServerCmd::Print {
+                        text: String::from("ranger2 rides ranger's rocket\n"),
+                    },
+                    ServerCmd::UpdateFrags {
+                        player_id: 0,
+                        new_frags: 1,
+                    },
+                    ServerCmd::Print {
+                        text: String::from("ranger becomes bored with life\n"),
+                    },
+                    ServerCmd::Print {
+                        text: String::from("\u{1}ranger: gg\n"),
+                    },
+                    ServerCmd::Print {
+                        text: String::from("ranger2 left the game with 0 frags\n"),
+                    },
+                ],
+            )
+            .unwrap();
+
+        let info = analyzer.finish(secs(1));
+        assert_eq!(
+            info.obituaries,
+            vec![
+                Obituary {
+                    time: 1.0,
+                    victim: String::from("ranger2"),
+                    attacker: Some(String::from("ranger")),
+                    message: String::from("rides 's rocket"),
+                },
+                Obituary {
+                    time: 1.0,
+                    victim: String::from("ranger"),
+                    attacker: None,
+                    message: String::from("becomes bored with life"),
+                },
+            ]
+        );
+        assert_eq!(
+            info.frags,
+            vec![FragEvent {
+                time: 1.0,
+                player: String::from("ranger"),
+                frags: 1,
+                change: 1,
+            }]
+        );
+        assert_eq!(info.players[0].frags, 1);
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut analyzer = analyzer();
+        analyzer
+            .update(
+                secs(1),
+                vec![
+                    ServerCmd::KilledMonster,
+                    ServerCmd::FoundSecret,
+                    ServerCmd::KilledMonster,
+                ],
+            )
+            .unwrap();
+
+        let info = analyzer.finish(secs(1));
+        assert_eq!(info.monsters_killed, 2);
+        assert_eq!(info.secrets_found, 1);
+    }
+
+    #[test]
+    fn test_pickups_and_damage() {
+        let mut analyzer = analyzer();
+        analyzer
+            .update(
+                secs(2),
+                vec![
+                    ServerCmd::Print {
+                        text: String::from("You got the Rocket Launcher\n"),
+                    },
+                    ServerCmd::Print {
+                        text: String::from("You receive 25 health\n"),
+                    },
+                    ServerCmd::Damage {
+                        armor: 5,
+                        blood: 10,
+                        source: Vector3::new(1.0, 2.0, 3.0),
+                    },
+                ],
+            )
+            .unwrap();
+
+        let info = analyzer.finish(secs(2));
+        let items: Vec<&str> = info.pickups.iter().map(|p| p.item.as_str()).collect();
+        assert_eq!(items, vec!["Rocket Launcher", "25 health"]);
+        assert_eq!(info.pickups[0].player.as_deref(), Some("ranger"));
+        assert_eq!(info.damage[0].health, 10);
+        assert_eq!(info.damage[0].source, [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_position_samples() {
+        let mut analyzer = analyzer();
+        let update = |x| {
+            ServerCmd::FastUpdate(EntityUpdate {
+                ent_id: 2,
+                model_id: None,
+                frame_id: None,
+                colormap: None,
+                skin_id: None,
+                effects: None,
+                origin_x: Some(x),
+                pitch: None,
+                origin_y: None,
+                yaw: None,
+                origin_z: None,
+                roll: None,
+                no_lerp: false,
+            })
+        };
+
+        // the second update comes before the next sample is due
+        analyzer
+            .update(Duration::milliseconds(100), vec![update(1.0)])
+            .unwrap();
+        analyzer
+            .update(Duration::milliseconds(600), vec![update(2.0)])
+            .unwrap();
+        analyzer
+            .update(Duration::milliseconds(1100), vec![update(3.0)])
+            .unwrap();
+
+        let info = analyzer.finish(secs(2));
+        assert_eq!(info.map, "e1m1");
+        assert_eq!(info.players[1].name, "ranger2");
+        assert_eq!(info.players[1].top_color, 1);
+        assert_eq!(
+            info.players[1].positions,
+            vec![
+                PositionSample {
+                    time: 0.1,
+                    origin: [1.0, 32.0, 48.0],
+                },
+                PositionSample {
+                    time: 1.1,
+                    origin: [3.0, 32.0, 48.0],
+                },
+            ]
+        );
+    }
+}
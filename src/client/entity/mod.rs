@@ -23,7 +23,7 @@ pub mod particle;
 use crate::common::{
     alloc::LinkedSlab,
     engine,
-    net::{EntityEffects, EntityState, EntityUpdate},
+    net::{EntityEffects, EntityState, EntityUpdate, PlayerColor},
 };
 
 use cgmath::{Deg, Vector3};
@@ -35,6 +35,10 @@ pub const MAX_BEAMS: usize = 24;
 pub const MAX_TEMP_ENTITIES: usize = 64;
 pub const MAX_STATIC_ENTITIES: usize = 128;
 
+// time taken to blend from one keyframe to the next. server-side animations run
+// at 10 Hz, so this matches the interval between frame changes.
+const FRAME_LERP_INTERVAL_MS: i64 = 100;
+
 #[derive(Debug)]
 pub struct ClientEntity {
     pub force_link: bool,
@@ -47,8 +51,11 @@ pub struct ClientEntity {
     pub model_id: usize,
     model_changed: bool,
     pub frame_id: usize,
+    prev_frame_id: usize,
+    frame_time: Duration,
     pub skin_id: usize,
     colormap: Option<u8>,
+    colors: Option<PlayerColor>,
     pub sync_base: Duration,
     pub effects: EntityEffects,
     pub light_id: Option<usize>,
@@ -71,8 +78,14 @@ impl ClientEntity {
             model_id: baseline.model_id,
             model_changed: false,
             frame_id: baseline.frame_id,
+            prev_frame_id: baseline.frame_id,
+            frame_time: Duration::zero(),
             skin_id: baseline.skin_id,
-            colormap: None,
+            colormap: match baseline.colormap {
+                0 => None,
+                c => Some(c),
+            },
+            colors: None,
             sync_base: Duration::zero(),
             effects: baseline.effects,
             light_id: None,
@@ -94,8 +107,11 @@ impl ClientEntity {
             model_id: 0,
             model_changed: false,
             frame_id: 0,
+            prev_frame_id: 0,
+            frame_time: Duration::zero(),
             skin_id: 0,
             colormap: None,
+            colors: None,
             sync_base: Duration::zero(),
             effects: EntityEffects::empty(),
             light_id: None,
@@ -130,16 +146,41 @@ impl ClientEntity {
             self.model_id = new_state.model_id;
         }
 
-        self.frame_id = new_state.frame_id;
+        self.set_frame(msg_times[0], new_state.frame_id);
         self.skin_id = new_state.skin_id;
         self.effects = new_state.effects;
-        self.colormap = update.colormap;
+        self.colormap = match new_state.colormap {
+            0 => None,
+            c => Some(c),
+        };
 
         if self.force_link {
             self.msg_origins[1] = self.msg_origins[0];
             self.origin = self.msg_origins[0];
             self.msg_angles[1] = self.msg_angles[0];
             self.angles = self.msg_angles[0];
+            self.prev_frame_id = self.frame_id;
+        }
+    }
+
+    /// Switches the entity to a new model.
+    ///
+    /// Keyframes don't carry over between models, so this snaps to `frame_id`
+    /// rather than blending from the previous pose.
+    pub fn set_model(&mut self, model_id: usize, frame_id: usize) {
+        self.model_id = model_id;
+        self.model_changed = true;
+        self.frame_id = frame_id;
+        self.prev_frame_id = frame_id;
+    }
+
+    /// Sets the entity's keyframe, blending from the previous keyframe starting
+    /// at `time` if it changed.
+    pub fn set_frame(&mut self, time: Duration, frame_id: usize) {
+        if self.frame_id != frame_id {
+            self.prev_frame_id = self.frame_id;
+            self.frame_id = frame_id;
+            self.frame_time = time;
         }
     }
 
@@ -171,10 +212,26 @@ impl ClientEntity {
         self.model_changed
     }
 
+    /// Returns the entity's colormap, which is the number of the player whose
+    /// colors it wears, starting at 1.
     pub fn colormap(&self) -> Option<u8> {
         self.colormap
     }
 
+    /// Returns the shirt and pants colors the entity is drawn with, if any.
+    pub fn colors(&self) -> Option<PlayerColor> {
+        self.colors
+    }
+
+    pub fn set_colors(&mut self, colors: Option<PlayerColor>) {
+        self.colors = colors;
+    }
+
+    /// Returns the entity's opacity from its baseline.
+    pub fn alpha(&self) -> f32 {
+        self.baseline.alpha
+    }
+
     pub fn get_origin(&self) -> Vector3<f32> {
         self.origin
     }
@@ -191,6 +248,22 @@ impl ClientEntity {
         self.frame_id
     }
 
+    /// Returns the keyframe the entity is animating from.
+    pub fn prev_frame_id(&self) -> usize {
+        self.prev_frame_id
+    }
+
+    /// Returns how far the entity has blended from its previous keyframe to
+    /// its current one at `time`, in the range `[0, 1]`.
+    pub fn frame_lerp(&self, time: Duration) -> f32 {
+        if self.prev_frame_id == self.frame_id {
+            return 1.0;
+        }
+
+        let elapsed_ms = (time - self.frame_time).num_milliseconds();
+        (elapsed_ms as f32 / FRAME_LERP_INTERVAL_MS as f32).clamp(0.0, 1.0)
+    }
+
     pub fn skin_id(&self) -> usize {
         self.skin_id
     }
@@ -330,3 +403,47 @@ pub struct Beam {
     pub start: Vector3<f32>,
     pub end: Vector3<f32>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_update(frame_id: u8) -> EntityUpdate {
+        EntityUpdate {
+            ent_id: 1,
+            model_id: None,
+            frame_id: Some(frame_id),
+            colormap: None,
+            skin_id: None,
+            effects: None,
+            origin_x: None,
+            pitch: None,
+            origin_y: None,
+            yaw: None,
+            origin_z: None,
+            roll: None,
+            no_lerp: false,
+        }
+    }
+
+    #[test]
+    fn test_frame_lerp() {
+        let mut ent = ClientEntity::uninitialized();
+        let t = |ms| Duration::milliseconds(ms);
+
+        ent.update([t(100), t(0)], frame_update(0));
+        assert_eq!(ent.frame_lerp(t(100)), 1.0);
+
+        ent.update([t(200), t(100)], frame_update(3));
+        assert_eq!(ent.prev_frame_id(), 0);
+        assert_eq!(ent.frame_id(), 3);
+        assert_eq!(ent.frame_lerp(t(200)), 0.0);
+        assert_eq!(ent.frame_lerp(t(250)), 0.5);
+        assert_eq!(ent.frame_lerp(t(400)), 1.0);
+
+        // a skipped message forces a link, which snaps to the new frame
+        ent.update([t(500), t(400)], frame_update(4));
+        assert_eq!(ent.prev_frame_id(), 4);
+        assert_eq!(ent.frame_lerp(t(500)), 1.0);
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Distance fog.
+//!
+//! Fog is set per map by the worldspawn `fog` key, by the server with
+//! `svc_fog` or by the `fog` console command. Changes can fade in over time.
+
+use cgmath::{Vector3, VectorSpace as _};
+use chrono::Duration;
+
+use crate::common::engine;
+
+/// Color of fog set without an explicit color, as in FitzQuake.
+const DEFAULT_COLOR: Vector3<f32> = Vector3::new(0.3, 0.3, 0.3);
+
+/// Fog density and color.
+///
+/// A density of zero disables fog.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FogParams {
+    pub density: f32,
+    pub color: Vector3<f32>,
+}
+
+impl FogParams {
+    pub fn none() -> FogParams {
+        FogParams {
+            density: 0.0,
+            color: DEFAULT_COLOR,
+        }
+    }
+
+    /// Parses a worldspawn `fog` value of the form `<density> <r> <g> <b>`.
+    ///
+    /// The color may be omitted.
+    pub fn parse<S>(value: S) -> Option<FogParams>
+    where
+        S: AsRef<str>,
+    {
+        let values = value
+            .as_ref()
+            .split_whitespace()
+            .map(|v| v.parse::<f32>().ok())
+            .collect::<Option<Vec<_>>>()?;
+
+        match values.as_slice() {
+            [density] => Some(FogParams {
+                density: *density,
+                color: DEFAULT_COLOR,
+            }),
+            [density, r, g, b] => Some(FogParams {
+                density: *density,
+                color: Vector3::new(*r, *g, *b),
+            }),
+            _ => None,
+        }
+    }
+
+    fn lerp(&self, other: FogParams, factor: f32) -> FogParams {
+        FogParams {
+            density: self.density + (other.density - self.density) * factor,
+            color: self.color.lerp(other.color, factor),
+        }
+    }
+}
+
+/// Current fog settings, possibly fading between two values.
+#[derive(Clone, Debug)]
+pub struct Fog {
+    from: FogParams,
+    to: FogParams,
+    fade_start: Duration,
+    fade_time: Duration,
+}
+
+impl Fog {
+    pub fn new(params: FogParams) -> Fog {
+        Fog {
+            from: params,
+            to: params,
+            fade_start: Duration::zero(),
+            fade_time: Duration::zero(),
+        }
+    }
+
+    /// Starts fading to `params` at `time`, reaching them after `fade_time`.
+    pub fn set(&mut self, params: FogParams, time: Duration, fade_time: Duration) {
+        self.from = self.params(time);
+        self.to = params;
+        self.fade_start = time;
+        self.fade_time = fade_time;
+    }
+
+    /// Returns the fog settings that should be applied at `time`.
+    pub fn params(&self, time: Duration) -> FogParams {
+        if self.fade_time <= Duration::zero() {
+            return self.to;
+        }
+
+        let elapsed = engine::duration_to_f32(time - self.fade_start);
+        let factor = (elapsed / engine::duration_to_f32(self.fade_time)).clamp(0.0, 1.0);
+        self.from.lerp(self.to, factor)
+    }
+
+    /// Returns the settings this fog is fading towards.
+    pub fn target(&self) -> FogParams {
+        self.to
+    }
+}
+
+impl std::default::Default for Fog {
+    fn default() -> Self {
+        Fog::new(FogParams::none())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fog_params_parse() {
+        assert_eq!(
+            FogParams::parse("0.05 0.2 0.3 0.4"),
+            Some(FogParams {
+                density: 0.05,
+                color: Vector3::new(0.2, 0.3, 0.4),
+            })
+        );
+        assert_eq!(
+            FogParams::parse(" 0.1 "),
+            Some(FogParams {
+                density: 0.1,
+                color: DEFAULT_COLOR,
+            })
+        );
+        assert_eq!(FogParams::parse("0.1 0.2"), None);
+        assert_eq!(FogParams::parse("thick"), None);
+    }
+
+    #[test]
+    fn test_fog_fade() {
+        let mut fog = Fog::default();
+        let target = FogParams {
+            density: 1.0,
+            color: Vector3::new(1.0, 0.0, 0.0),
+        };
+        fog.set(target, Duration::seconds(10), Duration::seconds(2));
+
+        assert_eq!(fog.params(Duration::seconds(10)), FogParams::none());
+        assert_eq!(fog.params(Duration::seconds(11)).density, 0.5);
+        assert_eq!(fog.params(Duration::seconds(12)), target);
+        assert_eq!(fog.params(Duration::seconds(20)), target);
+
+        // a new fade starts from wherever the previous one got to
+        fog.set(FogParams::none(), Duration::seconds(11), Duration::zero());
+        assert_eq!(fog.params(Duration::seconds(11)), FogParams::none());
+    }
+}
@@ -0,0 +1,297 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Clients that run without a window or an audio device.
+//!
+//! A [`HeadlessClient`] owns everything the windowed client normally sets up
+//! around a [`Client`]: the cvar and command registries, the console and the
+//! input state. Commands are driven through the console, so a simulated player
+//! can connect, move and record demos with the same commands a real one would
+//! type (`connect`, `+forward`, `record` and so on).
+
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    client::{
+        self,
+        input::{Input, InputFocus},
+        menu::{MenuBodyView, MenuBuilder, MenuView},
+        render, Client, ClientError,
+    },
+    common::{
+        console::{CmdRegistry, Console, CvarRegistry},
+        vfs::Vfs,
+    },
+};
+
+use chrono::Duration;
+
+pub struct HeadlessClient {
+    cvars: Rc<RefCell<CvarRegistry>>,
+    cmds: Rc<RefCell<CmdRegistry>>,
+    console: Rc<RefCell<Console>>,
+    input: Rc<RefCell<Input>>,
+    client: Client,
+}
+
+impl HeadlessClient {
+    /// Create a new headless client.
+    ///
+    /// Clients only read from the virtual filesystem, so many clients can
+    /// share a single `Vfs`.
+    pub fn new(vfs: Rc<Vfs>) -> Result<HeadlessClient, ClientError> {
+        let con_names = Rc::new(RefCell::new(Vec::new()));
+
+        let cvars = Rc::new(RefCell::new(CvarRegistry::new(con_names.clone())));
+        client::register_cvars(&cvars.borrow()).map_err(ClientError::Cvar)?;
+
+        // some of these are read outside the renderer (e.g. fov)
+        render::register_cvars(&cvars.borrow());
+
+        let cmds = Rc::new(RefCell::new(CmdRegistry::new(con_names)));
+        let console = Rc::new(RefCell::new(Console::new(cmds.clone(), cvars.clone())));
+
+        // the menu is never shown, but input focus can still be switched to it
+        let menu = Rc::new(RefCell::new(MenuBuilder::new().build(MenuView {
+            draw_plaque: false,
+            title_path: String::new(),
+            body: MenuBodyView::Dynamic,
+        })));
+
+        let input = Rc::new(RefCell::new(Input::new(
+            InputFocus::Console,
+            console.clone(),
+            menu,
+        )));
+        input.borrow().register_cmds(&mut cmds.borrow_mut());
+
+        let client = Client::headless(
+            vfs,
+            cvars.clone(),
+            cmds.clone(),
+            console.clone(),
+            input.clone(),
+        );
+
+        Ok(HeadlessClient {
+            cvars,
+            cmds,
+            console,
+            input,
+            client,
+        })
+    }
+
+    /// Queue console text to be executed at the start of the next frame.
+    pub fn stuff_text<S>(&self, text: S)
+    where
+        S: AsRef<str>,
+    {
+        self.console.borrow().stuff_text(text);
+    }
+
+    /// Run one frame of the client.
+    ///
+    /// This executes any queued console commands, processes server messages
+    /// and, if the client is in game, sends a move command built from the
+    /// current input state.
+    ///
+    /// Errors are returned as-is; it's up to the caller whether to disconnect.
+    pub fn frame(&mut self, frame_time: Duration) -> Result<(), ClientError> {
+        self.console.borrow().execute();
+
+        self.client.frame(frame_time, None)?;
+
+        if let Some(game_input) = self.input.borrow_mut().game_input_mut() {
+            self.client.handle_input(game_input, frame_time)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn client_mut(&mut self) -> &mut Client {
+        &mut self.client
+    }
+
+    pub fn cvars(&self) -> &Rc<RefCell<CvarRegistry>> {
+        &self.cvars
+    }
+
+    pub fn cmds(&self) -> &Rc<RefCell<CmdRegistry>> {
+        &self.cmds
+    }
+
+    pub fn console(&self) -> &Rc<RefCell<Console>> {
+        &self.console
+    }
+
+    pub fn input(&self) -> &Rc<RefCell<Input>> {
+        &self.input
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        client::demo::DemoRecorder,
+        common::net::{ClientStat, ServerCmd},
+    };
+
+    use cgmath::{Deg, Vector3};
+
+    fn console_text(client: &HeadlessClient) -> Vec<String> {
+        client
+            .console()
+            .borrow()
+            .output()
+            .lines()
+            .map(|l| l.iter().collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_headless_commands() {
+        let vfs = Rc::new(Vfs::new());
+        let mut clients: Vec<_> = (0..4)
+            .map(|_| HeadlessClient::new(vfs.clone()).unwrap())
+            .collect();
+
+        for (i, client) in clients.iter_mut().enumerate() {
+            client.stuff_text(format!("cl_forwardspeed {}", 100 * (i + 1)));
+            client.stuff_text("+forward");
+            client.stuff_text("playdemo nonexistent");
+            client.frame(Duration::milliseconds(16)).unwrap();
+        }
+
+        for (i, client) in clients.iter().enumerate() {
+            assert_eq!(
+                client.client().cvar_value("cl_forwardspeed").unwrap(),
+                100.0 * (i + 1) as f32
+            );
+
+            // the failed demo leaves the client disconnected
+            assert!(!console_text(client).is_empty());
+            assert_eq!(client.input().borrow().focus(), InputFocus::Console);
+        }
+    }
+
+    #[test]
+    fn test_headless_demo_messages() {
+        let dir = std::env::temp_dir().join(format!("richter-headless-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let angles = Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0));
+        let mut recorder = DemoRecorder::create(dir.join("messages.dem"), None).unwrap();
+        recorder
+            .write_cmds(
+                angles,
+                &[
+                    ServerCmd::Time { time: 1.0 },
+                    ServerCmd::Print {
+                        text: String::from("hello from the server\n"),
+                    },
+                    ServerCmd::StuffText {
+                        text: String::from("cl_forwardspeed 123\n"),
+                    },
+                    ServerCmd::UpdateStat {
+                        stat: ClientStat::Health,
+                        value: 42,
+                    },
+                ],
+            )
+            .unwrap();
+        recorder.finish(angles).unwrap();
+
+        let mut vfs = Vfs::new();
+        vfs.add_directory(&dir).unwrap();
+        let mut client = HeadlessClient::new(Rc::new(vfs)).unwrap();
+
+        // the first frame starts playback and handles the first message
+        client.stuff_text("playdemo messages");
+        client.frame(Duration::milliseconds(16)).unwrap();
+        assert_eq!(
+            client.client().state().unwrap().stats()[ClientStat::Health as usize],
+            42
+        );
+        assert!(console_text(&client)
+            .iter()
+            .any(|l| l == "hello from the server"));
+
+        // the server's stuffed text runs on the next frame, and the demo's
+        // final message disconnects the client once its time is reached
+        client.frame(Duration::seconds(1)).unwrap();
+        assert_eq!(
+            client.client().cvar_value("cl_forwardspeed").unwrap(),
+            123.0
+        );
+        assert!(client.client().state().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_headless_record_map() {
+        let dir = std::env::temp_dir().join(format!("richter-record-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let angles = Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0));
+        let mut recorder = DemoRecorder::create(dir.join("playing.dem"), None).unwrap();
+        recorder
+            .write_cmds(angles, &[ServerCmd::Time { time: 10.0 }])
+            .unwrap();
+        recorder.finish(angles).unwrap();
+
+        // an empty demo, as recorded from a disconnected client
+        DemoRecorder::create(dir.join("empty.dem"), None)
+            .unwrap()
+            .finish(angles)
+            .unwrap();
+
+        let mut vfs = Vfs::new();
+        vfs.add_directory(&dir).unwrap();
+        let mut client = HeadlessClient::new(Rc::new(vfs)).unwrap();
+        client.stuff_text("playdemo playing");
+        client.frame(Duration::milliseconds(16)).unwrap();
+        assert!(client.client().state().is_some());
+
+        // naming a map disconnects so that recording starts with its signon
+        client.stuff_text("record recorded start");
+        client.frame(Duration::milliseconds(16)).unwrap();
+        assert!(client.client().state().is_none());
+        assert!(console_text(&client)
+            .iter()
+            .any(|l| l == "recording to recorded.dem"));
+
+        client.stuff_text("stop");
+        client.frame(Duration::milliseconds(16)).unwrap();
+        assert_eq!(
+            std::fs::read(dir.join("recorded.dem")).unwrap(),
+            std::fs::read(dir.join("empty.dem")).unwrap()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -18,34 +18,52 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+pub mod bot;
+pub mod capturedemo;
 mod cvars;
 pub mod demo;
+pub mod demoinfo;
 pub mod entity;
+pub mod fog;
+pub mod headless;
 pub mod input;
 pub mod menu;
+pub mod netgraph;
+pub mod netlog;
+pub mod qwdemo;
 pub mod render;
 pub mod sound;
+pub mod spectator;
 pub mod state;
+pub mod timedemo;
 pub mod trace;
 pub mod view;
 
 pub use self::cvars::register_cvars;
 
 use std::{
-    cell::RefCell,
+    cell::{Ref, RefCell},
     collections::{HashMap, VecDeque},
     io::BufReader,
     net::ToSocketAddrs,
+    path::PathBuf,
     rc::Rc,
+    sync::mpsc::TryRecvError,
 };
 
 use crate::{
     client::{
-        demo::{DemoServer, DemoServerError},
+        capturedemo::{DemoCapture, DEFAULT_CAPTURE_FPS},
+        demo::{demo_file_name, DemoRecorder, DemoServer, DemoServerError},
         entity::{ClientEntity, MAX_STATIC_ENTITIES},
+        fog::FogParams,
         input::{game::GameInput, Input},
-        sound::{MusicPlayer, StaticSound},
-        state::{ClientState, PlayerInfo},
+        netgraph::NetGraph,
+        netlog::NetLog,
+        sound::{MusicPlayer, SoundOutput, StaticSound},
+        spectator::DemoCamera,
+        state::ClientState,
+        timedemo::TimeDemo,
         trace::{TraceEntity, TraceFrame},
         view::{IdleVars, KickVars, MouseVars, RollVars},
     },
@@ -56,19 +74,23 @@ use crate::{
         net::{
             self,
             connect::{ConnectSocket, Request, Response, CONNECT_PROTOCOL_VERSION},
+            master,
+            rcon::RconClient,
+            slist::{self, ServerQuery},
             BlockingMode, ClientCmd, ClientStat, ColorShift, EntityEffects, EntityState, GameType,
-            NetError, PlayerColor, QSocket, ServerCmd, SignOnStage,
+            NetError, QSocket, ServerCmd, ServerCmdCode, SignOnStage,
         },
         vfs::{Vfs, VfsError},
     },
 };
 
-use cgmath::Deg;
+use cgmath::{Deg, Vector3};
 use chrono::Duration;
 use input::InputFocus;
 use menu::Menu;
+use num::FromPrimitive as _;
 use render::{ClientRenderer, GraphicsState, WorldRenderer};
-use rodio::{OutputStream, OutputStreamHandle};
+use rodio::OutputStream;
 use sound::SoundError;
 use thiserror::Error;
 use view::BobVars;
@@ -160,8 +182,11 @@ enum ColorShiftCode {
 }
 
 struct ServerInfo {
-    _max_clients: u8,
-    _game_type: GameType,
+    max_clients: u8,
+    game_type: GameType,
+    message: String,
+    model_precache: Vec<String>,
+    sound_precache: Vec<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -190,7 +215,9 @@ enum ConnectionState {
     SignOn(SignOnStage),
 
     /// The client is fully connected.
-    Connected(WorldRenderer),
+    ///
+    /// Headless clients have no world renderer.
+    Connected(Option<WorldRenderer>),
 }
 
 /// Possible targets that a client can be connected to.
@@ -215,13 +242,20 @@ pub struct Connection {
     state: ClientState,
     conn_state: ConnectionState,
     kind: ConnectionKind,
+    net_graph: NetGraph,
+
+    // kept for the signon of demos recorded mid-game
+    server_info: Option<ServerInfo>,
+
+    // point of view for demo playback
+    demo_camera: DemoCamera,
 }
 
 impl Connection {
     fn handle_signon(
         &mut self,
         new_stage: SignOnStage,
-        gfx_state: &GraphicsState,
+        gfx_state: Option<&GraphicsState>,
     ) -> Result<(), ClientError> {
         use SignOnStage::*;
 
@@ -277,11 +311,9 @@ impl Connection {
                     Prespawn | ClientInfo | Begin => ConnectionState::SignOn(new_stage),
 
                     // finished signing on, build world renderer
-                    Done => ConnectionState::Connected(WorldRenderer::new(
-                        gfx_state,
-                        self.state.models(),
-                        1,
-                    )),
+                    Done => ConnectionState::Connected(
+                        gfx_state.map(|gfx| WorldRenderer::new(gfx, self.state.models(), 1)),
+                    ),
                 }
             }
 
@@ -297,11 +329,15 @@ impl Connection {
     fn parse_server_msg(
         &mut self,
         vfs: &Vfs,
-        gfx_state: &GraphicsState,
+        gfx_state: Option<&GraphicsState>,
         cmds: &mut CmdRegistry,
         console: &mut Console,
         music_player: &mut MusicPlayer,
         kick_vars: KickVars,
+        cl_shownet: f32,
+        net_log: &mut Option<NetLog>,
+        server_msgs: &mut Option<Vec<Vec<u8>>>,
+        demo_recorder: &mut Option<DemoRecorder>,
     ) -> Result<ConnectionStatus, ClientError> {
         use ConnectionStatus::*;
 
@@ -354,9 +390,56 @@ impl Connection {
             return Ok(Maintain);
         }
 
+        if let Some(log) = net_log {
+            if let Err(e) = log.log_server_msg(&msg) {
+                console.println(format!("Couldn't write to net log: {}", e));
+                *net_log = None;
+            }
+        }
+
+        if let Some(msgs) = server_msgs {
+            msgs.push(msg.clone());
+        }
+
+        if let (ConnectionKind::Server { .. }, Some(recorder)) = (&self.kind, &mut *demo_recorder) {
+            if let Err(e) = recorder.write_message(self.demo_view_angles(), &msg) {
+                console.println(format!("Couldn't write to demo: {}", e));
+                *demo_recorder = None;
+            }
+        }
+
+        match cl_shownet as i32 {
+            1 => console.print(format!("{} ", msg.len())),
+            2 => console.println("------------------"),
+            _ => (),
+        }
+
         let mut reader = BufReader::new(msg.as_slice());
 
-        while let Some(cmd) = ServerCmd::deserialize(&mut reader)? {
+        loop {
+            // offset of the next command, for cl_shownet 2
+            let offset = msg.len() - reader.get_ref().len() - reader.buffer().len();
+
+            let cmd = match ServerCmd::deserialize(&mut reader)? {
+                Some(c) => c,
+                None => break,
+            };
+
+            if cl_shownet as i32 == 2 {
+                let name = match cmd {
+                    // fast updates don't have a command code
+                    ServerCmd::FastUpdate(_) => "FastUpdate".to_owned(),
+                    _ => format!("{:?}", ServerCmdCode::from_u8(cmd.code()).unwrap()),
+                };
+                console.println(format!("{:3}:{}", offset, name));
+            }
+
+            // commands that only update the client state
+            let cmd = match self.state.handle_server_cmd(cmd)? {
+                Some(c) => c,
+                None => continue,
+            };
+
             match cmd {
                 // TODO: have an error for this instead of panicking
                 // once all other commands have placeholder handlers, just error
@@ -380,13 +463,27 @@ impl Connection {
                     println!("{}", text);
                 }
 
-                ServerCmd::PlayerData(player_data) => self.state.update_player(player_data),
-
                 ServerCmd::Cutscene { text } => {
                     self.state.intermission = Some(IntermissionKind::Cutscene { text });
                     self.state.completion_time = Some(self.state.time);
                 }
 
+                ServerCmd::SkyBox { name } => {
+                    // an empty name reverts to the map's own sky
+                    self.state.skybox = Some(name).filter(|n| !n.is_empty());
+                }
+
+                ServerCmd::Fog {
+                    density,
+                    color,
+                    fade_time,
+                } => {
+                    let time = self.state.time;
+                    self.state
+                        .fog
+                        .set(FogParams { density, color }, time, fade_time);
+                }
+
                 ServerCmd::Damage {
                     armor,
                     blood,
@@ -420,15 +517,6 @@ impl Connection {
                     self.state.completion_time = Some(self.state.time);
                 }
 
-                ServerCmd::FoundSecret => self.state.stats[ClientStat::FoundSecrets as usize] += 1,
-                ServerCmd::Intermission => {
-                    self.state.intermission = Some(IntermissionKind::Intermission);
-                    self.state.completion_time = Some(self.state.time);
-                }
-                ServerCmd::KilledMonster => {
-                    self.state.stats[ClientStat::KilledMonsters as usize] += 1
-                }
-
                 ServerCmd::LightStyle { id, value } => {
                     trace!("Inserting light style {} with value {}", id, &value);
                     let _ = self.state.light_styles.insert(id, value);
@@ -474,21 +562,24 @@ impl Connection {
                     }
 
                     console.println(CONSOLE_DIVIDER);
-                    console.println(message);
+                    console.println(&message);
                     console.println(CONSOLE_DIVIDER);
 
-                    let _server_info = ServerInfo {
-                        _max_clients: max_clients,
-                        _game_type: game_type,
-                    };
-
                     self.state = ClientState::from_server_info(
                         vfs,
                         self.state.mixer.stream(),
                         max_clients,
+                        model_precache.clone(),
+                        sound_precache.clone(),
+                    )?;
+
+                    self.server_info = Some(ServerInfo {
+                        max_clients,
+                        game_type,
+                        message,
                         model_precache,
                         sound_precache,
-                    )?;
+                    });
 
                     let bonus_cshift =
                         self.state.color_shifts[ColorShiftCode::Bonus as usize].clone();
@@ -507,14 +598,6 @@ impl Connection {
 
                 ServerCmd::SetAngle { angles } => self.state.set_view_angles(angles),
 
-                ServerCmd::SetView { ent_id } => {
-                    if ent_id <= 0 {
-                        Err(ClientError::InvalidViewEntity(ent_id as usize))?;
-                    }
-
-                    self.state.set_view_entity(ent_id as usize)?;
-                }
-
                 ServerCmd::SignOnStage { stage } => self.handle_signon(stage, gfx_state)?,
 
                 ServerCmd::Sound {
@@ -532,6 +615,13 @@ impl Connection {
                         channel
                     );
 
+                    // don't play every sound skipped over by a seek at once
+                    if let ConnectionKind::Demo(ref demo_srv) = self.kind {
+                        if demo_srv.seeking() {
+                            continue;
+                        }
+                    }
+
                     if entity_id as usize >= self.state.entities.len() {
                         warn!(
                             "server tried to start sound on nonexistent entity {}",
@@ -555,29 +645,6 @@ impl Connection {
                     );
                 }
 
-                ServerCmd::SpawnBaseline {
-                    ent_id,
-                    model_id,
-                    frame_id,
-                    colormap,
-                    skin_id,
-                    origin,
-                    angles,
-                } => {
-                    self.state.spawn_entities(
-                        ent_id as usize,
-                        EntityState {
-                            model_id: model_id as usize,
-                            frame_id: frame_id as usize,
-                            colormap,
-                            skin_id: skin_id as usize,
-                            origin,
-                            angles,
-                            effects: EntityEffects::empty(),
-                        },
-                    )?;
-                }
-
                 ServerCmd::SpawnStatic {
                     model_id,
                     frame_id,
@@ -599,6 +666,7 @@ impl Connection {
                             colormap,
                             skin_id: skin_id as usize,
                             effects: EntityEffects::empty(),
+                            alpha: 1.0,
                         }));
                 }
 
@@ -622,96 +690,6 @@ impl Connection {
 
                 ServerCmd::StuffText { text } => console.stuff_text(text),
 
-                ServerCmd::Time { time } => {
-                    self.state.msg_times[1] = self.state.msg_times[0];
-                    self.state.msg_times[0] = engine::duration_from_f32(time);
-                }
-
-                ServerCmd::UpdateColors {
-                    player_id,
-                    new_colors,
-                } => {
-                    let player_id = player_id as usize;
-                    self.state.check_player_id(player_id)?;
-
-                    match self.state.player_info[player_id] {
-                        Some(ref mut info) => {
-                            trace!(
-                                "Player {} (ID {}) colors: {:?} -> {:?}",
-                                info.name,
-                                player_id,
-                                info.colors,
-                                new_colors,
-                            );
-                            info.colors = new_colors;
-                        }
-
-                        None => {
-                            error!(
-                                "Attempted to set colors on nonexistent player with ID {}",
-                                player_id
-                            );
-                        }
-                    }
-                }
-
-                ServerCmd::UpdateFrags {
-                    player_id,
-                    new_frags,
-                } => {
-                    let player_id = player_id as usize;
-                    self.state.check_player_id(player_id)?;
-
-                    match self.state.player_info[player_id] {
-                        Some(ref mut info) => {
-                            trace!(
-                                "Player {} (ID {}) frags: {} -> {}",
-                                &info.name,
-                                player_id,
-                                info.frags,
-                                new_frags
-                            );
-                            info.frags = new_frags as i32;
-                        }
-                        None => {
-                            error!(
-                                "Attempted to set frags on nonexistent player with ID {}",
-                                player_id
-                            );
-                        }
-                    }
-                }
-
-                ServerCmd::UpdateName {
-                    player_id,
-                    new_name,
-                } => {
-                    let player_id = player_id as usize;
-                    self.state.check_player_id(player_id)?;
-
-                    if let Some(ref mut info) = self.state.player_info[player_id] {
-                        // if this player is already connected, it's a name change
-                        debug!("Player {} has changed name to {}", &info.name, &new_name);
-                        info.name = new_name.to_owned();
-                    } else {
-                        // if this player is not connected, it's a join
-                        debug!("Player {} with ID {} has joined", &new_name, player_id);
-                        self.state.player_info[player_id] = Some(PlayerInfo {
-                            name: new_name.to_owned(),
-                            colors: PlayerColor::new(0, 0),
-                            frags: 0,
-                        });
-                    }
-                }
-
-                ServerCmd::UpdateStat { stat, value } => {
-                    debug!(
-                        "{:?}: {} -> {}",
-                        stat, self.state.stats[stat as usize], value
-                    );
-                    self.state.stats[stat as usize] = value;
-                }
-
                 ServerCmd::Version { version } => {
                     if version != net::PROTOCOL_VERSION as i32 {
                         // TODO: handle with an error
@@ -734,11 +712,158 @@ impl Connection {
         Ok(Maintain)
     }
 
+    /// Returns the time to advance the client state by this frame.
+    ///
+    /// Demo playback may be paused or sped up; connections to a server always
+    /// run in real time.
+    fn playback_frame_time(&self, frame_time: Duration, demo_speed: f32) -> Duration {
+        match self.kind {
+            ConnectionKind::Demo(ref demo_srv) if demo_srv.paused() => Duration::zero(),
+            ConnectionKind::Demo(_) => {
+                engine::duration_from_f32(engine::duration_to_f32(frame_time) * demo_speed.max(0.0))
+            }
+            ConnectionKind::Server { .. } => frame_time,
+        }
+    }
+
+    /// Returns the view angles to record alongside server messages in demos.
+    fn demo_view_angles(&self) -> Vector3<Deg<f32>> {
+        let angles = self.state.view.input_angles();
+
+        // roll is inverted on playback (see parse_server_msg)
+        Vector3::new(angles.pitch, angles.yaw, -angles.roll)
+    }
+
+    /// Write a signon recreating the current game state to `recorder`.
+    ///
+    /// This allows a demo recorded mid-game to be played back from its start.
+    fn write_demo_signon(&self, recorder: &mut DemoRecorder) -> Result<(), ClientError> {
+        let server_info = match (&self.conn_state, &self.server_info) {
+            (ConnectionState::Connected(_), Some(info)) => info,
+            _ => return Err(ClientError::NotConnected),
+        };
+
+        let angles = self.demo_view_angles();
+
+        recorder.write_cmds(
+            angles,
+            &[
+                ServerCmd::ServerInfo {
+                    protocol_version: net::PROTOCOL_VERSION as i32,
+                    max_clients: server_info.max_clients,
+                    game_type: server_info.game_type,
+                    message: server_info.message.clone(),
+                    model_precache: server_info.model_precache.clone(),
+                    sound_precache: server_info.sound_precache.clone(),
+                },
+                ServerCmd::SetView {
+                    ent_id: self.state.view_entity_id() as i16,
+                },
+                ServerCmd::SignOnStage {
+                    stage: SignOnStage::Prespawn,
+                },
+            ],
+        )?;
+
+        let mut cmds = Vec::new();
+
+        for (ent_id, ent) in self.state.entities.iter().enumerate() {
+            let baseline = &ent.baseline;
+            if baseline.model_id == 0 {
+                continue;
+            }
+
+            // only fall back to the FitzQuake command when the baseline needs it
+            if baseline.alpha < 1.0 || baseline.model_id > u8::MAX as usize {
+                cmds.push(ServerCmd::SpawnBaseline2 {
+                    ent_id: ent_id as u16,
+                    model_id: baseline.model_id as u16,
+                    frame_id: baseline.frame_id as u16,
+                    colormap: baseline.colormap,
+                    skin_id: baseline.skin_id as u8,
+                    origin: baseline.origin,
+                    angles: baseline.angles,
+                    alpha: net::encode_entity_alpha(baseline.alpha),
+                });
+                continue;
+            }
+
+            cmds.push(ServerCmd::SpawnBaseline {
+                ent_id: ent_id as u16,
+                model_id: baseline.model_id as u8,
+                frame_id: baseline.frame_id as u8,
+                colormap: baseline.colormap,
+                skin_id: baseline.skin_id as u8,
+                origin: baseline.origin,
+                angles: baseline.angles,
+            });
+        }
+
+        for ent in self.state.static_entities.iter() {
+            let baseline = &ent.baseline;
+            cmds.push(ServerCmd::SpawnStatic {
+                model_id: baseline.model_id as u8,
+                frame_id: baseline.frame_id as u8,
+                colormap: baseline.colormap,
+                skin_id: baseline.skin_id as u8,
+                origin: baseline.origin,
+                angles: baseline.angles,
+            });
+        }
+
+        cmds.push(ServerCmd::SignOnStage {
+            stage: SignOnStage::ClientInfo,
+        });
+
+        for (player_id, info) in self.state.player_info.iter().enumerate() {
+            if let Some(info) = info {
+                cmds.push(ServerCmd::UpdateName {
+                    player_id: player_id as u8,
+                    new_name: info.name.clone(),
+                });
+                cmds.push(ServerCmd::UpdateFrags {
+                    player_id: player_id as u8,
+                    new_frags: info.frags as i16,
+                });
+                cmds.push(ServerCmd::UpdateColors {
+                    player_id: player_id as u8,
+                    new_colors: info.colors,
+                });
+            }
+        }
+
+        let mut light_styles: Vec<_> = self.state.light_styles.iter().collect();
+        light_styles.sort_by_key(|(id, _)| **id);
+        for (id, value) in light_styles {
+            cmds.push(ServerCmd::LightStyle {
+                id: *id,
+                value: value.clone(),
+            });
+        }
+
+        for (stat_id, value) in self.state.stats.iter().enumerate() {
+            if let Some(stat) = ClientStat::from_usize(stat_id) {
+                cmds.push(ServerCmd::UpdateStat {
+                    stat,
+                    value: *value,
+                });
+            }
+        }
+
+        cmds.push(ServerCmd::SignOnStage {
+            stage: SignOnStage::Begin,
+        });
+
+        recorder.write_cmds(angles, &cmds)?;
+
+        Ok(())
+    }
+
     fn frame(
         &mut self,
         frame_time: Duration,
         vfs: &Vfs,
-        gfx_state: &GraphicsState,
+        gfx_state: Option<&GraphicsState>,
         cmds: &mut CmdRegistry,
         console: &mut Console,
         music_player: &mut MusicPlayer,
@@ -746,24 +871,65 @@ impl Connection {
         kick_vars: KickVars,
         roll_vars: RollVars,
         bob_vars: BobVars,
+        gun_offset: Vector3<f32>,
         cl_nolerp: f32,
         sv_gravity: f32,
+        cl_shownet: f32,
+        net_log: &mut Option<NetLog>,
+        server_msgs: &mut Option<Vec<Vec<u8>>>,
+        demo_recorder: &mut Option<DemoRecorder>,
     ) -> Result<ConnectionStatus, ClientError> {
         debug!("frame time: {}ms", frame_time.num_milliseconds());
 
         // do this _before_ parsing server messages so that we know when to
         // request the next message from the demo server.
         self.state.advance_time(frame_time);
-        match self.parse_server_msg(vfs, gfx_state, cmds, console, music_player, kick_vars)? {
-            ConnectionStatus::Maintain => (),
-            // if Disconnect or NextDemo, delegate up the chain
-            s => return Ok(s),
-        };
+
+        // timedemos play one message every frame, however long it took
+        if let ConnectionKind::Demo(ref demo_srv) = self.kind {
+            if demo_srv.timedemo() {
+                self.state.time = self.state.msg_times[0];
+            }
+        }
+
+        loop {
+            match self.parse_server_msg(
+                vfs,
+                gfx_state,
+                cmds,
+                console,
+                music_player,
+                kick_vars,
+                cl_shownet,
+                net_log,
+                server_msgs,
+                demo_recorder,
+            )? {
+                ConnectionStatus::Maintain => (),
+                // if Disconnect or NextDemo, delegate up the chain
+                s => return Ok(s),
+            };
+
+            // demos may need several messages per frame to keep up with fast
+            // playback, and seeking plays back messages without waiting
+            match self.kind {
+                ConnectionKind::Demo(ref demo_srv) if demo_srv.seeking() => {
+                    self.state.time = self.state.msg_times[0];
+                }
+                ConnectionKind::Demo(ref demo_srv) if demo_srv.timedemo() => break,
+                ConnectionKind::Demo(_) if self.state.time > self.state.msg_times[0] => (),
+                _ => break,
+            }
+        }
 
         self.state.update_interp_ratio(cl_nolerp);
 
         // interpolate entity data and spawn particle effects, lights
-        self.state.update_entities()?;
+        let hidden_entity_id = match self.kind {
+            ConnectionKind::Demo(_) => self.demo_camera.hidden_entity_id(&self.state),
+            ConnectionKind::Server { .. } => Some(self.state.view_entity_id()),
+        };
+        self.state.update_entities(hidden_entity_id)?;
 
         // update temp entities (lightning, etc.)
         self.state.update_temp_entities()?;
@@ -783,16 +949,31 @@ impl Connection {
         {
             // respond to the server
             if qsock.can_send() && !compose.is_empty() {
+                if let Some(log) = net_log {
+                    if let Err(e) = log.log_client_msg(compose, true) {
+                        console.println(format!("Couldn't write to net log: {}", e));
+                        *net_log = None;
+                    }
+                }
+
                 qsock.begin_send_msg(&compose)?;
                 compose.clear();
             }
+
+            self.net_graph.update(frame_time, qsock.stats());
         }
 
         // these all require the player entity to have spawned
-        if let ConnectionState::Connected(_) = self.conn_state {
+        if let ConnectionState::Connected(ref mut world) = self.conn_state {
+            // load the skybox if the map or the sky command changed it
+            if let (Some(world), Some(gfx)) = (world, gfx_state) {
+                world.update_skybox(gfx, self.state.skybox());
+                world.update_player_skins(gfx, self.state.iter_visible_entities());
+            }
+
             // update view
             self.state
-                .calc_final_view(idle_vars, kick_vars, roll_vars, bob_vars);
+                .calc_final_view(idle_vars, kick_vars, roll_vars, bob_vars, gun_offset);
 
             // update ear positions
             self.state.update_listener();
@@ -814,12 +995,19 @@ pub struct Client {
     cmds: Rc<RefCell<CmdRegistry>>,
     console: Rc<RefCell<Console>>,
     input: Rc<RefCell<Input>>,
-    _output_stream: OutputStream,
-    output_stream_handle: OutputStreamHandle,
+    _output_stream: Option<OutputStream>,
+    sound_output: SoundOutput,
     music_player: Rc<RefCell<MusicPlayer>>,
     conn: Rc<RefCell<Option<Connection>>>,
-    renderer: ClientRenderer,
+    renderer: Option<ClientRenderer>,
     demo_queue: Rc<RefCell<VecDeque<String>>>,
+    net_log: Rc<RefCell<Option<NetLog>>>,
+    server_msgs: Option<Vec<Vec<u8>>>,
+    server_query: Rc<RefCell<Option<ServerQuery>>>,
+    rcon: Rc<RefCell<Option<RconClient>>>,
+    demo_recorder: Rc<RefCell<Option<DemoRecorder>>>,
+    timedemo: Rc<RefCell<Option<TimeDemo>>>,
+    capture: Rc<RefCell<Option<DemoCapture>>>,
 }
 
 impl Client {
@@ -832,14 +1020,64 @@ impl Client {
         gfx_state: &GraphicsState,
         menu: &Menu,
     ) -> Client {
-        let conn = Rc::new(RefCell::new(None));
-
         let (stream, handle) = match OutputStream::try_default() {
-            Ok(o) => o,
-            // TODO: proceed without sound and allow configuration in menu
-            Err(_) => Err(ClientError::OutputStream).unwrap(),
+            Ok((stream, handle)) => (Some(stream), SoundOutput::Stream(handle)),
+            // TODO: allow configuration in menu
+            Err(e) => {
+                warn!("Failed to open audio output stream, sound disabled: {}", e);
+                (None, SoundOutput::Null)
+            }
         };
 
+        let renderer = ClientRenderer::new(gfx_state, menu);
+        Client::with_output(
+            vfs,
+            cvars,
+            cmds,
+            console,
+            input,
+            stream,
+            handle,
+            Some(renderer),
+        )
+    }
+
+    /// Create a client without a window or an audio device.
+    ///
+    /// Headless clients run the full connection and input pipeline but discard
+    /// all sound and never build a renderer, so [`Client::frame`] must be
+    /// called without a [`GraphicsState`] and [`Client::render`] does nothing.
+    pub fn headless(
+        vfs: Rc<Vfs>,
+        cvars: Rc<RefCell<CvarRegistry>>,
+        cmds: Rc<RefCell<CmdRegistry>>,
+        console: Rc<RefCell<Console>>,
+        input: Rc<RefCell<Input>>,
+    ) -> Client {
+        Client::with_output(
+            vfs,
+            cvars,
+            cmds,
+            console,
+            input,
+            None,
+            SoundOutput::Null,
+            None,
+        )
+    }
+
+    fn with_output(
+        vfs: Rc<Vfs>,
+        cvars: Rc<RefCell<CvarRegistry>>,
+        cmds: Rc<RefCell<CmdRegistry>>,
+        console: Rc<RefCell<Console>>,
+        input: Rc<RefCell<Input>>,
+        stream: Option<OutputStream>,
+        handle: SoundOutput,
+        renderer: Option<ClientRenderer>,
+    ) -> Client {
+        let conn = Rc::new(RefCell::new(None));
+
         // set up overlay/ui toggles
         cmds.borrow_mut()
             .insert_or_replace(
@@ -864,6 +1102,30 @@ impl Client {
         cmds.borrow_mut()
             .insert_or_replace("disconnect", cmd_disconnect(conn.clone(), input.clone()))
             .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("net_stats", cmd_net_stats(conn.clone()))
+            .unwrap();
+
+        let net_log = Rc::new(RefCell::new(None));
+        cmds.borrow_mut()
+            .insert_or_replace("net_log", cmd_net_log(net_log.clone()))
+            .unwrap();
+
+        let server_query = Rc::new(RefCell::new(None));
+        cmds.borrow_mut()
+            .insert_or_replace("slist", cmd_slist(cvars.clone(), server_query.clone()))
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace(
+                "slist_master",
+                cmd_slist_master(cvars.clone(), server_query.clone()),
+            )
+            .unwrap();
+
+        let rcon = Rc::new(RefCell::new(None));
+        cmds.borrow_mut()
+            .insert_or_replace("rcon", cmd_rcon(conn.clone(), cvars.clone(), rcon.clone()))
+            .unwrap();
 
         // set up demo playback
         cmds.borrow_mut()
@@ -873,7 +1135,63 @@ impl Client {
             )
             .unwrap();
 
+        let timedemo = Rc::new(RefCell::new(None));
+        cmds.borrow_mut()
+            .insert_or_replace(
+                "timedemo",
+                cmd_timedemo(
+                    conn.clone(),
+                    vfs.clone(),
+                    input.clone(),
+                    handle.clone(),
+                    timedemo.clone(),
+                ),
+            )
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("demo_seek", cmd_demo_seek(conn.clone()))
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("demo_pause", cmd_demo_pause(conn.clone()))
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("demo_camera", cmd_demo_camera(conn.clone()))
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("demo_nextplayer", cmd_demo_cycleplayer(conn.clone(), true))
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("demo_prevplayer", cmd_demo_cycleplayer(conn.clone(), false))
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("demo_track", cmd_demo_track(conn.clone()))
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("sky", cmd_sky(conn.clone()))
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("fog", cmd_fog(conn.clone()))
+            .unwrap();
+
         let demo_queue = Rc::new(RefCell::new(VecDeque::new()));
+
+        // set up demo recording
+        let demo_recorder = Rc::new(RefCell::new(None));
+        cmds.borrow_mut()
+            .insert_or_replace(
+                "record",
+                cmd_record(
+                    conn.clone(),
+                    vfs.clone(),
+                    console.clone(),
+                    demo_recorder.clone(),
+                ),
+            )
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("stop", cmd_stop(conn.clone(), demo_recorder.clone()))
+            .unwrap();
+
         cmds.borrow_mut()
             .insert_or_replace(
                 "startdemos",
@@ -901,6 +1219,21 @@ impl Client {
             .insert_or_replace("music_resume", cmd_music_resume(music_player.clone()))
             .unwrap();
 
+        // set up demo capture
+        let capture = Rc::new(RefCell::new(None));
+        cmds.borrow_mut()
+            .insert_or_replace(
+                "capturedemo",
+                cmd_capturedemo(
+                    conn.clone(),
+                    vfs.clone(),
+                    input.clone(),
+                    music_player.clone(),
+                    capture.clone(),
+                ),
+            )
+            .unwrap();
+
         Client {
             vfs,
             cvars,
@@ -908,11 +1241,18 @@ impl Client {
             console,
             input,
             _output_stream: stream,
-            output_stream_handle: handle,
+            sound_output: handle,
             music_player,
             conn,
-            renderer: ClientRenderer::new(gfx_state, menu),
+            renderer,
             demo_queue,
+            net_log,
+            server_msgs: None,
+            server_query,
+            rcon,
+            demo_recorder,
+            timedemo,
+            capture,
         }
     }
 
@@ -921,40 +1261,258 @@ impl Client {
         self.input.borrow_mut().set_focus(InputFocus::Console);
     }
 
-    pub fn frame(
-        &mut self,
-        frame_time: Duration,
-        gfx_state: &GraphicsState,
-    ) -> Result<(), ClientError> {
-        let cl_nolerp = self.cvar_value("cl_nolerp")?;
-        let sv_gravity = self.cvar_value("sv_gravity")?;
-        let idle_vars = self.idle_vars()?;
-        let kick_vars = self.kick_vars()?;
-        let roll_vars = self.roll_vars()?;
-        let bob_vars = self.bob_vars()?;
+    /// Print the results of a finished `slist` query.
+    fn poll_server_query(&mut self) {
+        let result = match *self.server_query.borrow() {
+            Some(ref receiver) => match receiver.try_recv() {
+                Ok(result) => result,
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => {
+                    Err(NetError::with_msg("Server query thread exited"))
+                }
+            },
+            None => return,
+        };
+        self.server_query.replace(None);
 
-        let status = match *self.conn.borrow_mut() {
-            Some(ref mut conn) => conn.frame(
-                frame_time,
-                &self.vfs,
-                gfx_state,
-                &mut self.cmds.borrow_mut(),
-                &mut self.console.borrow_mut(),
-                &mut self.music_player.borrow_mut(),
-                idle_vars,
+        let console = self.console.borrow();
+        match result {
+            Ok(servers) if servers.is_empty() => console.println("No Quake servers found."),
+            Ok(servers) => {
+                console.println(format!("{:<15} {:<15} Users", "Server", "Map"));
+                console.println(format!("{:-<15} {:-<15} -----", "", ""));
+                for server in servers {
+                    console.println(format!(
+                        "{:<15.15} {:<15.15} {:>2}/{:>2}",
+                        server.hostname, server.map, server.client_count, server.client_max
+                    ));
+                }
+            }
+            Err(e) => console.println(format!("Server query failed: {}", e)),
+        }
+    }
+
+    /// Returns true if a timedemo is running.
+    ///
+    /// Timedemos should run as fast as possible, ignoring `host_maxfps`.
+    pub fn timedemo_running(&self) -> bool {
+        self.timedemo.borrow().is_some()
+    }
+
+    /// Record the time taken by the last frame of a running timedemo.
+    fn update_timedemo(&mut self, frame_time: Duration) {
+        let mut timedemo = self.timedemo.borrow_mut();
+        let td = match *timedemo {
+            Some(ref mut td) => td,
+            None => return,
+        };
+
+        match *self.conn.borrow() {
+            Some(Connection {
+                kind: ConnectionKind::Demo(ref demo_srv),
+                ref conn_state,
+                ..
+            }) if demo_srv.timedemo() => {
+                // don't count the time spent loading the level
+                if let ConnectionState::Connected(_) = conn_state {
+                    td.record_frame(frame_time);
+                }
+            }
+
+            // something else replaced the demo
+            _ => *timedemo = None,
+        }
+    }
+
+    /// Report the results of a finished timedemo.
+    fn finish_timedemo(&mut self) {
+        let td = match self.timedemo.replace(None) {
+            Some(td) => td,
+            None => return,
+        };
+
+        let report = td.report();
+        let console = self.console.borrow();
+        console.print(report.to_string());
+
+        if let Some(path) = td.output() {
+            match report.write_json(path) {
+                Ok(()) => console.println(format!("wrote timedemo report to {}", path.display())),
+                Err(e) => console.println(format!("Couldn't write {}: {}", path.display(), e)),
+            }
+        }
+    }
+
+    /// Returns true if a demo is being captured.
+    ///
+    /// Captured demos are simulated at a fixed frame rate, so frames should be
+    /// run as fast as possible, ignoring `host_maxfps`.
+    pub fn capture_running(&self) -> bool {
+        self.capture.borrow().is_some()
+    }
+
+    /// Returns the simulated duration of the next frame of a demo capture.
+    ///
+    /// While this returns `Some`, it should be used as the frame time instead
+    /// of the real time elapsed.
+    pub fn capture_frame_time(&self) -> Option<Duration> {
+        self.capture.borrow().as_ref().map(|c| c.frame_time())
+    }
+
+    /// Returns the path the last rendered frame should be captured to, if any.
+    pub fn take_capture_frame(&self) -> Option<PathBuf> {
+        self.capture
+            .borrow_mut()
+            .as_mut()
+            .and_then(|c| c.take_pending_frame())
+    }
+
+    /// Capture the audio for the last frame of a running demo capture.
+    fn update_capture(&self) {
+        let mut capture = self.capture.borrow_mut();
+        let cap = match *capture {
+            Some(ref mut cap) => cap,
+            None => return,
+        };
+
+        match *self.conn.borrow() {
+            Some(Connection {
+                kind: ConnectionKind::Demo(_),
+                ref conn_state,
+                ..
+            }) => {
+                // start capturing once the level is loaded
+                if let ConnectionState::Connected(_) = conn_state {
+                    if let Err(e) = cap.capture_frame() {
+                        self.console
+                            .borrow()
+                            .println(format!("Couldn't capture frame: {}", e));
+                        drop(capture);
+                        self.finish_capture();
+                    }
+                }
+            }
+
+            // something else replaced the demo
+            _ => {
+                drop(capture);
+                self.finish_capture();
+            }
+        }
+    }
+
+    /// Finish a demo capture and return sound to the audio device.
+    fn finish_capture(&self) {
+        let cap = match self.capture.replace(None) {
+            Some(cap) => cap,
+            None => return,
+        };
+
+        let console = self.console.borrow();
+        let frames = cap.frames();
+        let dir = cap.dir().to_owned();
+        match cap.finish() {
+            Ok(()) => console.println(format!("captured {} frames to {}", frames, dir.display())),
+            Err(e) => console.println(format!("Couldn't finish capture: {}", e)),
+        }
+
+        if let Err(e) = self
+            .music_player
+            .borrow_mut()
+            .set_output(self.sound_output.clone())
+        {
+            console.println(format!("{}", e));
+        }
+    }
+
+    /// Print any output received in reply to `rcon` commands.
+    fn poll_rcon(&mut self) {
+        let rcon = self.rcon.borrow();
+        let rcon = match *rcon {
+            Some(ref r) => r,
+            None => return,
+        };
+
+        let console = self.console.borrow();
+        loop {
+            match rcon.poll() {
+                Ok(Some(text)) => console.print(text),
+                Ok(None) => break,
+                Err(e) => {
+                    console.println(format!("rcon: {}", e));
+                    break;
+                }
+            }
+        }
+    }
+
+    pub fn frame(
+        &mut self,
+        frame_time: Duration,
+        gfx_state: Option<&GraphicsState>,
+    ) -> Result<(), ClientError> {
+        self.poll_server_query();
+        self.poll_rcon();
+
+        let cl_nolerp = self.cvar_value("cl_nolerp")?;
+        let sv_gravity = self.cvar_value("sv_gravity")?;
+        let cl_shownet = self.cvar_value("cl_shownet")?;
+        let demo_speed = self.cvar_value("demo_speed")?;
+        let idle_vars = self.idle_vars()?;
+        let kick_vars = self.kick_vars()?;
+        let roll_vars = self.roll_vars()?;
+        let bob_vars = self.bob_vars()?;
+        let gun_offset = self.gun_offset()?;
+
+        let status = match *self.conn.borrow_mut() {
+            Some(ref mut conn) => conn.frame(
+                conn.playback_frame_time(frame_time, demo_speed),
+                &self.vfs,
+                gfx_state,
+                &mut self.cmds.borrow_mut(),
+                &mut self.console.borrow_mut(),
+                &mut self.music_player.borrow_mut(),
+                idle_vars,
                 kick_vars,
                 roll_vars,
                 bob_vars,
+                gun_offset,
                 cl_nolerp,
                 sv_gravity,
+                cl_shownet,
+                &mut self.net_log.borrow_mut(),
+                &mut self.server_msgs,
+                &mut self.demo_recorder.borrow_mut(),
             )?,
             None => ConnectionStatus::Disconnect,
         };
 
         use ConnectionStatus::*;
         match status {
-            Maintain => (),
+            Maintain => {
+                self.update_timedemo(frame_time);
+                self.update_capture();
+            }
             _ => {
+                self.finish_timedemo();
+                self.finish_capture();
+
+                // the connection is over, so finish any recording in progress.
+                // a recording that hasn't started yet is waiting for the next
+                // connection and is left alone.
+                let recording = match *self.demo_recorder.borrow() {
+                    Some(ref r) => r.message_count() > 0,
+                    None => false,
+                };
+                if recording {
+                    let recorder = self.demo_recorder.replace(None).unwrap();
+                    let console = self.console.borrow();
+                    match recorder.finish(Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0))) {
+                        Ok(()) => console.println("Completed demo"),
+                        Err(e) => console.println(format!("Couldn't finish demo: {}", e)),
+                    }
+                }
+
                 let conn = match status {
                     // if client is already disconnected, this is a no-op
                     Disconnect => None,
@@ -962,7 +1520,7 @@ impl Client {
                     // get the next demo from the queue
                     NextDemo => match self.demo_queue.borrow_mut().pop_front() {
                         Some(demo) => {
-                            let mut demo_file = match self.vfs.open(format!("{}.dem", demo)) {
+                            let mut demo_file = match self.vfs.open(demo_file_name(&demo)) {
                                 Ok(f) => Some(f),
                                 Err(e) => {
                                     // log the error, dump the demo queue and disconnect
@@ -975,8 +1533,11 @@ impl Client {
                             demo_file.as_mut().and_then(|df| match DemoServer::new(df) {
                                 Ok(d) => Some(Connection {
                                     kind: ConnectionKind::Demo(d),
-                                    state: ClientState::new(self.output_stream_handle.clone()),
+                                    state: ClientState::new(self.sound_output.clone()),
                                     conn_state: ConnectionState::SignOn(SignOnStage::Prespawn),
+                                    net_graph: NetGraph::new(),
+                                    server_info: None,
+                                    demo_camera: DemoCamera::default(),
                                 }),
                                 Err(e) => {
                                     self.console.borrow_mut().println(format!("{}", e));
@@ -1018,10 +1579,16 @@ impl Client {
         focus: InputFocus,
     ) -> Result<(), ClientError> {
         let fov = Deg(self.cvar_value("fov")?);
+
+        let renderer = match self.renderer {
+            Some(ref mut r) => r,
+            None => return Ok(()),
+        };
+
         let cvars = self.cvars.borrow();
         let console = self.console.borrow();
 
-        self.renderer.render(
+        renderer.render(
             gfx_state,
             encoder,
             self.conn.borrow().as_ref(),
@@ -1055,26 +1622,126 @@ impl Client {
         let move_vars = self.move_vars()?;
         let mouse_vars = self.mouse_vars()?;
 
-        match *self.conn.borrow_mut() {
+        let move_cmd = match *self.conn.borrow_mut() {
             Some(Connection {
                 ref mut state,
+                kind: ConnectionKind::Server { .. },
+                ..
+            }) => state.handle_input(game_input, frame_time, move_vars, mouse_vars),
+
+            Some(Connection {
+                kind: ConnectionKind::Demo(_),
+                ref mut demo_camera,
+                ..
+            }) => {
+                demo_camera.handle_input(game_input, frame_time, move_vars, mouse_vars);
+                game_input.refresh();
+                return Ok(());
+            }
+
+            _ => return Ok(()),
+        };
+
+        self.send_unreliable(&move_cmd)?;
+
+        // clear mouse and impulse
+        game_input.refresh();
+
+        Ok(())
+    }
+
+    /// Connect to a server, replacing any existing connection.
+    pub fn connect<A>(&mut self, server_addrs: A) -> Result<(), ClientError>
+    where
+        A: ToSocketAddrs,
+    {
+        let new_conn = connect(server_addrs, self.sound_output.clone())?;
+        self.conn.replace(Some(new_conn));
+        self.input.borrow_mut().set_focus(InputFocus::Game);
+        Ok(())
+    }
+
+    /// Returns `true` if the client has finished signing on.
+    pub fn signed_on(&self) -> bool {
+        matches!(
+            *self.conn.borrow(),
+            Some(Connection {
+                conn_state: ConnectionState::Connected(_),
+                ..
+            })
+        )
+    }
+
+    /// Borrow the state of the current connection, if there is one.
+    pub fn state(&self) -> Option<Ref<'_, ClientState>> {
+        let conn = self.conn.borrow();
+        if conn.is_none() {
+            return None;
+        }
+
+        Some(Ref::map(conn, |c| &c.as_ref().unwrap().state))
+    }
+
+    /// Send a command to the server immediately and unreliably.
+    pub fn send_unreliable(&self, cmd: &ClientCmd) -> Result<(), ClientError> {
+        match *self.conn.borrow_mut() {
+            Some(Connection {
                 kind: ConnectionKind::Server { ref mut qsock, .. },
                 ..
             }) => {
-                let move_cmd = state.handle_input(game_input, frame_time, move_vars, mouse_vars);
                 // TODO: arrayvec here
                 let mut msg = Vec::new();
-                move_cmd.serialize(&mut msg)?;
+                cmd.serialize(&mut msg)?;
+
+                let mut net_log = self.net_log.borrow_mut();
+                if let Some(ref mut log) = *net_log {
+                    if let Err(e) = log.log_client_msg(&msg, false) {
+                        self.console
+                            .borrow()
+                            .println(format!("Couldn't write to net log: {}", e));
+                        *net_log = None;
+                    }
+                }
+
                 qsock.send_msg_unreliable(&msg)?;
+                Ok(())
+            }
 
-                // clear mouse and impulse
-                game_input.refresh();
+            _ => Err(ClientError::NotConnected),
+        }
+    }
+
+    /// Queue a command to be sent to the server reliably at the end of the
+    /// next frame.
+    pub fn send_reliable(&self, cmd: &ClientCmd) -> Result<(), ClientError> {
+        let mut conn = self.conn.borrow_mut();
+        match conn.as_mut().map(|c| &mut c.kind) {
+            Some(ConnectionKind::Server { compose, .. }) => {
+                cmd.serialize(compose)?;
+                Ok(())
             }
 
-            _ => (),
+            _ => Err(ClientError::NotConnected),
         }
+    }
 
-        Ok(())
+    /// Start or stop keeping a copy of every message received from the server.
+    ///
+    /// Stopping discards any messages that haven't been taken yet.
+    pub fn set_capture_server_msgs(&mut self, capture: bool) {
+        self.server_msgs = if capture {
+            Some(self.server_msgs.take().unwrap_or_default())
+        } else {
+            None
+        };
+    }
+
+    /// Take the messages received since the last call, oldest first.
+    pub fn take_server_msgs(&mut self) -> Vec<Vec<u8>> {
+        match self.server_msgs {
+            Some(ref mut msgs) => std::mem::take(msgs),
+            None => Vec::new(),
+        }
     }
 
     fn move_vars(&self) -> Result<MoveVars, ClientError> {
@@ -1133,6 +1800,22 @@ impl Client {
         })
     }
 
+    /// Parses `cl_gun_offset` as forward, right and up offsets for the
+    /// viewmodel. Missing or malformed components are treated as zero.
+    fn gun_offset(&self) -> Result<Vector3<f32>, ClientError> {
+        let offset = self
+            .cvars
+            .borrow()
+            .get("cl_gun_offset")
+            .map_err(ClientError::Cvar)?;
+        let mut components = offset
+            .split_whitespace()
+            .map(|c| c.parse::<f32>().unwrap_or(0.0));
+        let mut next = || components.next().unwrap_or(0.0);
+
+        Ok(Vector3::new(next(), next(), next()))
+    }
+
     pub fn view_entity_id(&self) -> Option<usize> {
         match *self.conn.borrow() {
             Some(Connection { ref state, .. }) => Some(state.view_entity_id()),
@@ -1244,7 +1927,7 @@ fn cmd_togglemenu(
     })
 }
 
-fn connect<A>(server_addrs: A, stream: OutputStreamHandle) -> Result<Connection, ClientError>
+fn connect<A>(server_addrs: A, stream: SoundOutput) -> Result<Connection, ClientError>
 where
     A: ToSocketAddrs,
 {
@@ -1324,11 +2007,14 @@ where
             compose: Vec::new(),
         },
         conn_state: ConnectionState::SignOn(SignOnStage::Prespawn),
+        net_graph: NetGraph::new(),
+        server_info: None,
+        demo_camera: DemoCamera::default(),
     })
 }
 
-// TODO: when an audio device goes down, every command with an
-// OutputStreamHandle needs to be reconstructed so it doesn't pass out
+// TODO: when an audio device goes down, every command with a
+// SoundOutput needs to be reconstructed so it doesn't pass out
 // references to a dead output stream
 
 // TODO: this will hang while connecting. ideally, input should be handled in a
@@ -1336,7 +2022,7 @@ where
 fn cmd_connect(
     conn: Rc<RefCell<Option<Connection>>>,
     input: Rc<RefCell<Input>>,
-    stream: OutputStreamHandle,
+    stream: SoundOutput,
 ) -> Box<dyn Fn(&[&str]) -> String> {
     Box::new(move |args| {
         if args.len() < 1 {
@@ -1389,18 +2075,285 @@ fn cmd_disconnect(
     })
 }
 
+fn cmd_net_stats(conn: Rc<RefCell<Option<Connection>>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |_| {
+        let conn = conn.borrow();
+        let stats = match *conn {
+            Some(Connection {
+                kind: ConnectionKind::Server { ref qsock, .. },
+                ..
+            }) => qsock.stats(),
+            Some(_) => return "not connected to a server".to_string(),
+            None => return "not connected".to_string(),
+        };
+
+        format!(
+            "packets sent:     {}\n\
+             packets received: {}\n\
+             bytes sent:       {}\n\
+             bytes received:   {}\n\
+             dropped:          {}\n\
+             out of order:     {}\n\
+             duplicate:        {}\n\
+             short:            {}",
+            stats.packets_sent,
+            stats.packets_received,
+            stats.bytes_sent,
+            stats.bytes_received,
+            stats.dropped_packets,
+            stats.out_of_order_packets,
+            stats.duplicate_packets,
+            stats.short_packets,
+        )
+    })
+}
+
+fn cmd_net_log(net_log: Rc<RefCell<Option<NetLog>>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| match args.len() {
+        // stop logging
+        0 => match net_log.replace(None) {
+            Some(_) => "net log closed".to_owned(),
+            None => "usage: net_log [FILE]".to_owned(),
+        },
+
+        1 => match NetLog::create(args[0]) {
+            Ok(log) => {
+                net_log.replace(Some(log));
+                format!("logging network messages to {}", args[0])
+            }
+            Err(e) => format!("Couldn't open {}: {}", args[0], e),
+        },
+
+        _ => "usage: net_log [FILE]".to_owned(),
+    })
+}
+
+fn cmd_slist(
+    cvars: Rc<RefCell<CvarRegistry>>,
+    server_query: Rc<RefCell<Option<ServerQuery>>>,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |_| {
+        if server_query.borrow().is_some() {
+            return "Still looking...".to_owned();
+        }
+
+        let port_min = cvars.borrow().get_value("slist_port_min").unwrap() as u16;
+        let port_max = cvars.borrow().get_value("slist_port_max").unwrap() as u16;
+
+        let ports = port_min..=port_max.max(port_min);
+        server_query.replace(Some(slist::spawn_query(move || {
+            slist::query_lan(ports, Duration::milliseconds(slist::DEFAULT_TIMEOUT_MS))
+        })));
+
+        "Looking for Quake servers...".to_owned()
+    })
+}
+
+fn cmd_slist_master(
+    cvars: Rc<RefCell<CvarRegistry>>,
+    server_query: Rc<RefCell<Option<ServerQuery>>>,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        let master = match args.len() {
+            0 => cvars.borrow().get("net_master").unwrap(),
+            1 => args[0].to_owned(),
+            _ => return "usage: slist_master [ADDRESS]".to_owned(),
+        };
+
+        if server_query.borrow().is_some() {
+            return "Still looking...".to_owned();
+        }
+
+        // use the default master port if none was given
+        let master = if master.contains(':') {
+            master
+        } else {
+            format!("{}:{}", master, master::DEFAULT_MASTER_PORT)
+        };
+
+        let msg = format!("Querying master server {}...", master);
+        server_query.replace(Some(slist::spawn_query(move || {
+            slist::query_internet(
+                master.as_str(),
+                Duration::milliseconds(slist::DEFAULT_TIMEOUT_MS),
+            )
+        })));
+
+        msg
+    })
+}
+
+fn cmd_rcon(
+    conn: Rc<RefCell<Option<Connection>>>,
+    cvars: Rc<RefCell<CvarRegistry>>,
+    rcon: Rc<RefCell<Option<RconClient>>>,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        if args.is_empty() {
+            return "usage: rcon <command>".to_owned();
+        }
+
+        let password = cvars.borrow().get("rcon_password").unwrap();
+        if password.is_empty() {
+            return "You must set 'rcon_password' before issuing an rcon command.".to_owned();
+        }
+
+        // send to rcon_address if set, otherwise to the server we're connected to
+        let address = cvars.borrow().get("rcon_address").unwrap();
+        let remote = if !address.is_empty() {
+            let address = if address.contains(':') {
+                address
+            } else {
+                format!("{}:{}", address, slist::DEFAULT_PORT)
+            };
+
+            match address.to_socket_addrs().map(|mut a| a.next()) {
+                Ok(Some(r)) => r,
+                Ok(None) => return format!("Couldn't resolve {}", address),
+                Err(e) => return format!("Couldn't resolve {}: {}", address, e),
+            }
+        } else {
+            match *conn.borrow() {
+                Some(Connection {
+                    kind: ConnectionKind::Server { ref qsock, .. },
+                    ..
+                }) => qsock.remote(),
+                _ => return "Not connected; set 'rcon_address' to issue rcon commands.".to_owned(),
+            }
+        };
+
+        // reuse the socket unless the destination changed
+        let reuse = matches!(*rcon.borrow(), Some(ref r) if r.remote() == remote);
+        if !reuse {
+            match RconClient::new(remote) {
+                Ok(r) => {
+                    rcon.replace(Some(r));
+                }
+                Err(e) => return format!("rcon: {}", e),
+            }
+        }
+
+        let command = args.join(" ");
+        let rcon = rcon.borrow();
+        match rcon.as_ref().unwrap().send(&password, &command) {
+            Ok(()) => String::new(),
+            Err(e) => format!("rcon: {}", e),
+        }
+    })
+}
+
+fn cmd_record(
+    conn: Rc<RefCell<Option<Connection>>>,
+    vfs: Rc<Vfs>,
+    console: Rc<RefCell<Console>>,
+    demo_recorder: Rc<RefCell<Option<DemoRecorder>>>,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        if args.is_empty() || args.len() > 2 {
+            return "usage: record <demoname> [map]".to_owned();
+        }
+
+        if args[0].contains("..") {
+            return "Relative pathnames are not allowed.".to_owned();
+        }
+
+        if demo_recorder.borrow().is_some() {
+            return "Already recording. Use 'stop' to end the current demo.".to_owned();
+        }
+
+        let conn_borrow = conn.borrow();
+        let mid_game = match *conn_borrow {
+            // we'll disconnect and record the new map from its signon
+            Some(_) if args.len() == 2 => false,
+            Some(Connection {
+                kind: ConnectionKind::Demo(_),
+                ..
+            }) => return "Can't record during demo playback.".to_owned(),
+            Some(Connection {
+                conn_state: ConnectionState::SignOn(_),
+                ..
+            }) => return "Can't record while signing on.".to_owned(),
+            Some(_) => true,
+            None => false,
+        };
+
+        let game_dir = match vfs.game_dir() {
+            Some(d) => d,
+            None => return "No game directory to write demos to.".to_owned(),
+        };
+
+        let name = if args[0].ends_with(".dem") {
+            args[0].to_owned()
+        } else {
+            format!("{}.dem", args[0])
+        };
+        let path = game_dir.join(&name);
+
+        let mut recorder = match DemoRecorder::create(&path, None) {
+            Ok(r) => r,
+            Err(e) => return format!("Couldn't create {}: {}", path.display(), e),
+        };
+
+        // if we're joining the game partway through, the server won't send the
+        // signon again, so reconstruct it from the current state
+        if mid_game {
+            if let Err(e) = conn_borrow
+                .as_ref()
+                .unwrap()
+                .write_demo_signon(&mut recorder)
+            {
+                return format!("Couldn't write demo signon: {}", e);
+            }
+        }
+
+        drop(conn_borrow);
+
+        // start recording from the beginning of the map
+        if args.len() == 2 {
+            conn.replace(None);
+            console.borrow().stuff_text(format!("map {}", args[1]));
+        }
+
+        demo_recorder.replace(Some(recorder));
+
+        format!("recording to {}", name)
+    })
+}
+
+fn cmd_stop(
+    conn: Rc<RefCell<Option<Connection>>>,
+    demo_recorder: Rc<RefCell<Option<DemoRecorder>>>,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |_| {
+        let recorder = match demo_recorder.replace(None) {
+            Some(r) => r,
+            None => return "Not recording a demo.".to_owned(),
+        };
+
+        let angles = match *conn.borrow() {
+            Some(ref c) => c.demo_view_angles(),
+            None => Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+        };
+
+        match recorder.finish(angles) {
+            Ok(()) => "Completed demo".to_owned(),
+            Err(e) => format!("Couldn't finish demo: {}", e),
+        }
+    })
+}
+
 fn cmd_playdemo(
     conn: Rc<RefCell<Option<Connection>>>,
     vfs: Rc<Vfs>,
     input: Rc<RefCell<Input>>,
-    stream: OutputStreamHandle,
+    stream: SoundOutput,
 ) -> Box<dyn Fn(&[&str]) -> String> {
     Box::new(move |args| {
         if args.len() != 1 {
             return "usage: playdemo [DEMOFILE]".to_owned();
         }
 
-        let mut demo_file = match vfs.open(format!("{}.dem", args[0])) {
+        let mut demo_file = match vfs.open(demo_file_name(args[0])) {
             Ok(f) => f,
             Err(e) => return format!("{}", e),
         };
@@ -1414,7 +2367,355 @@ fn cmd_playdemo(
             state: ClientState::new(stream.clone()),
             kind: ConnectionKind::Demo(demo_server),
             conn_state: ConnectionState::SignOn(SignOnStage::Prespawn),
+            net_graph: NetGraph::new(),
+            server_info: None,
+            demo_camera: DemoCamera::default(),
+        }));
+
+        input.borrow_mut().set_focus(InputFocus::Game);
+        String::new()
+    })
+}
+
+fn cmd_timedemo(
+    conn: Rc<RefCell<Option<Connection>>>,
+    vfs: Rc<Vfs>,
+    input: Rc<RefCell<Input>>,
+    stream: SoundOutput,
+    timedemo: Rc<RefCell<Option<TimeDemo>>>,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        if args.is_empty() || args.len() > 2 {
+            return "usage: timedemo <demoname> [JSON file]".to_owned();
+        }
+
+        let mut demo_file = match vfs.open(demo_file_name(args[0])) {
+            Ok(f) => f,
+            Err(e) => return format!("{}", e),
+        };
+
+        let mut demo_server = match DemoServer::new(&mut demo_file) {
+            Ok(d) => d,
+            Err(e) => return format!("{}", e),
+        };
+        demo_server.set_timedemo(true);
+
+        conn.replace(Some(Connection {
+            state: ClientState::new(stream.clone()),
+            kind: ConnectionKind::Demo(demo_server),
+            conn_state: ConnectionState::SignOn(SignOnStage::Prespawn),
+            net_graph: NetGraph::new(),
+            server_info: None,
+            demo_camera: DemoCamera::default(),
+        }));
+
+        let output = args.get(1).map(PathBuf::from);
+        timedemo.replace(Some(TimeDemo::new(args[0], output)));
+
+        input.borrow_mut().set_focus(InputFocus::Game);
+        String::new()
+    })
+}
+
+fn cmd_demo_seek(conn: Rc<RefCell<Option<Connection>>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        if args.len() != 1 {
+            return "usage: demo_seek [+|-]<seconds>".to_owned();
+        }
+
+        let secs = match args[0].parse::<f32>() {
+            Ok(s) => s,
+            Err(_) => return format!("Invalid time: {}", args[0]),
+        };
+
+        let mut conn = conn.borrow_mut();
+        let conn = match *conn {
+            Some(ref mut c) => c,
+            None => return "not playing a demo".to_owned(),
+        };
+
+        let demo_srv = match conn.kind {
+            ConnectionKind::Demo(ref mut d) => d,
+            ConnectionKind::Server { .. } => return "not playing a demo".to_owned(),
+        };
+
+        // a leading sign seeks relative to the current position
+        let target = if args[0].starts_with('+') || args[0].starts_with('-') {
+            demo_srv.elapsed() + engine::duration_from_f32(secs)
+        } else {
+            engine::duration_from_f32(secs)
+        };
+
+        if demo_srv.seek(target) {
+            // the client state is rebuilt from the demo's signon
+            conn.conn_state = ConnectionState::SignOn(SignOnStage::Prespawn);
+        }
+
+        String::new()
+    })
+}
+
+fn cmd_demo_pause(conn: Rc<RefCell<Option<Connection>>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |_| match *conn.borrow_mut() {
+        Some(Connection {
+            kind: ConnectionKind::Demo(ref mut demo_srv),
+            ..
+        }) => {
+            let paused = !demo_srv.paused();
+            demo_srv.set_paused(paused);
+            if paused {
+                "demo paused".to_owned()
+            } else {
+                "demo resumed".to_owned()
+            }
+        }
+        _ => "not playing a demo".to_owned(),
+    })
+}
+
+fn cmd_demo_camera(conn: Rc<RefCell<Option<Connection>>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| match *conn.borrow_mut() {
+        Some(Connection {
+            ref state,
+            conn_state: ConnectionState::Connected(_),
+            kind: ConnectionKind::Demo(_),
+            ref mut demo_camera,
+            ..
+        }) => {
+            match args {
+                [] => return format!("demo camera: {}", demo_camera),
+                ["recorded"] => *demo_camera = DemoCamera::Recorded,
+                ["player"] => demo_camera.first_person(state),
+                ["chase"] => demo_camera.chase(state),
+                ["free"] => demo_camera.free_fly(state),
+                _ => return "usage: demo_camera [recorded | player | chase | free]".to_owned(),
+            }
+
+            String::new()
+        }
+        _ => "not playing a demo".to_owned(),
+    })
+}
+
+fn cmd_demo_cycleplayer(
+    conn: Rc<RefCell<Option<Connection>>>,
+    forward: bool,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |_| match *conn.borrow_mut() {
+        Some(Connection {
+            ref state,
+            conn_state: ConnectionState::Connected(_),
+            kind: ConnectionKind::Demo(_),
+            ref mut demo_camera,
+            ..
+        }) => match demo_camera.cycle_player(state, forward) {
+            Some(ent_id) => match state.player_info.get(ent_id - 1) {
+                Some(Some(info)) => format!("watching {}", info.name),
+                _ => String::new(),
+            },
+            None => "no players to watch".to_owned(),
+        },
+        _ => "not playing a demo".to_owned(),
+    })
+}
+
+fn cmd_demo_track(conn: Rc<RefCell<Option<Connection>>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        let mut conn = conn.borrow_mut();
+        let (state, conn_state, demo_camera, server) = match *conn {
+            Some(Connection {
+                ref state,
+                ref mut conn_state,
+                ref mut demo_camera,
+                kind: ConnectionKind::Demo(ref mut server),
+                ..
+            }) => (state, conn_state, demo_camera, server),
+            _ => return "not playing a demo".to_owned(),
+        };
+
+        let players = server.trackable_players().to_vec();
+        if players.is_empty() {
+            return "demo_track is only available for multi-view demos".to_owned();
+        }
+
+        let player_name = |player_id: u8| match state.player_info.get(player_id as usize) {
+            Some(Some(info)) => info.name.clone(),
+            _ => String::new(),
+        };
+
+        let tracked = server.tracked_player();
+        let current = players.iter().position(|&p| Some(p) == tracked);
+        let player_id = match args {
+            [] => {
+                let mut list = "usage: demo_track [next | prev | <slot or name>]\n".to_owned();
+                for &p in players.iter() {
+                    let marker = if Some(p) == tracked { '*' } else { ' ' };
+                    list.push_str(&format!("{} {:2} {}\n", marker, p, player_name(p)));
+                }
+                return list;
+            }
+
+            ["next"] => players[current.map(|i| (i + 1) % players.len()).unwrap_or(0)],
+            ["prev"] => {
+                players[current
+                    .map(|i| (i + players.len() - 1) % players.len())
+                    .unwrap_or(0)]
+            }
+
+            [arg] => match arg.parse::<u8>() {
+                Ok(p) => p,
+                Err(_) => match players.iter().find(|&&p| player_name(p) == *arg) {
+                    Some(&p) => p,
+                    None => return format!("no player named {}", arg),
+                },
+            },
+
+            _ => return "usage: demo_track [next | prev | <slot or name>]".to_owned(),
+        };
+
+        if let Err(e) = server.track_player(player_id) {
+            return format!("{}", e);
+        }
+
+        // the client state is rebuilt from the new player's point of view
+        *conn_state = ConnectionState::SignOn(SignOnStage::Prespawn);
+        *demo_camera = DemoCamera::Recorded;
+        format!("tracking {}", player_name(player_id))
+    })
+}
+
+fn cmd_sky(conn: Rc<RefCell<Option<Connection>>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| match *conn.borrow_mut() {
+        Some(Connection { ref mut state, .. }) => match args {
+            [] => match state.skybox() {
+                Some(name) => format!("skybox: {}", name),
+                None => "no skybox".to_owned(),
+            },
+            // an empty name reverts to the map's own sky
+            [name] => {
+                state.skybox = Some(name.to_string()).filter(|n| !n.is_empty());
+                String::new()
+            }
+            _ => "usage: sky [name]".to_owned(),
+        },
+        None => "not connected".to_owned(),
+    })
+}
+
+fn cmd_fog(conn: Rc<RefCell<Option<Connection>>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        let mut conn = conn.borrow_mut();
+        let state = match *conn {
+            Some(Connection { ref mut state, .. }) => state,
+            None => return "not connected".to_owned(),
+        };
+
+        let values = match args
+            .iter()
+            .map(|arg| arg.parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(v) => v,
+            Err(_) => return "usage: fog [density] [r g b] [fade time]".to_owned(),
+        };
+
+        let current = state.fog.target();
+        let (params, fade_time) = match values.as_slice() {
+            [] => {
+                let FogParams { density, color } = current;
+                return format!(
+                    "fog: density {} color {} {} {}",
+                    density, color.x, color.y, color.z
+                );
+            }
+            [density] => (
+                FogParams {
+                    density: *density,
+                    ..current
+                },
+                0.0,
+            ),
+            [r, g, b] => (
+                FogParams {
+                    color: Vector3::new(*r, *g, *b),
+                    ..current
+                },
+                0.0,
+            ),
+            [density, r, g, b] => (
+                FogParams {
+                    density: *density,
+                    color: Vector3::new(*r, *g, *b),
+                },
+                0.0,
+            ),
+            [density, r, g, b, fade_time] => (
+                FogParams {
+                    density: *density,
+                    color: Vector3::new(*r, *g, *b),
+                },
+                *fade_time,
+            ),
+            _ => return "usage: fog [density] [r g b] [fade time]".to_owned(),
+        };
+
+        let time = state.time;
+        state
+            .fog
+            .set(params, time, engine::duration_from_f32(fade_time.max(0.0)));
+        String::new()
+    })
+}
+
+fn cmd_capturedemo(
+    conn: Rc<RefCell<Option<Connection>>>,
+    vfs: Rc<Vfs>,
+    input: Rc<RefCell<Input>>,
+    music_player: Rc<RefCell<MusicPlayer>>,
+    capture: Rc<RefCell<Option<DemoCapture>>>,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        if args.len() < 2 || args.len() > 3 {
+            return "usage: capturedemo <demoname> <directory> [fps]".to_owned();
+        }
+
+        let fps = match args.get(2) {
+            Some(arg) => match arg.parse::<u32>() {
+                Ok(fps) if fps > 0 => fps,
+                _ => return format!("invalid frame rate: {}", arg),
+            },
+            None => DEFAULT_CAPTURE_FPS,
+        };
+
+        let mut demo_file = match vfs.open(demo_file_name(args[0])) {
+            Ok(f) => f,
+            Err(e) => return format!("{}", e),
+        };
+
+        let demo_server = match DemoServer::new(&mut demo_file) {
+            Ok(d) => d,
+            Err(e) => return format!("{}", e),
+        };
+
+        let (new_capture, output) = match DemoCapture::new(args[1], fps) {
+            Ok(c) => c,
+            Err(e) => return format!("Couldn't start capture in {}: {}", args[1], e),
+        };
+
+        // music is captured along with everything else
+        if let Err(e) = music_player.borrow_mut().set_output(output.clone()) {
+            return format!("{}", e);
+        }
+
+        conn.replace(Some(Connection {
+            state: ClientState::new(output),
+            kind: ConnectionKind::Demo(demo_server),
+            conn_state: ConnectionState::SignOn(SignOnStage::Prespawn),
+            net_graph: NetGraph::new(),
+            server_info: None,
+            demo_camera: DemoCamera::default(),
         }));
+        capture.replace(Some(new_capture));
 
         input.borrow_mut().set_focus(InputFocus::Game);
         String::new()
@@ -1425,7 +2726,7 @@ fn cmd_startdemos(
     conn: Rc<RefCell<Option<Connection>>>,
     vfs: Rc<Vfs>,
     input: Rc<RefCell<Input>>,
-    stream: OutputStreamHandle,
+    stream: SoundOutput,
     demo_queue: Rc<RefCell<VecDeque<String>>>,
 ) -> Box<dyn Fn(&[&str]) -> String> {
     Box::new(move |args| {
@@ -1437,9 +2738,8 @@ fn cmd_startdemos(
             demo_queue.borrow_mut().push_back(arg.to_string());
         }
 
-        let mut demo_file = match vfs.open(format!(
-            "{}.dem",
-            demo_queue.borrow_mut().pop_front().unwrap()
+        let mut demo_file = match vfs.open(demo_file_name(
+            &demo_queue.borrow_mut().pop_front().unwrap(),
         )) {
             Ok(f) => f,
             Err(e) => return format!("{}", e),
@@ -1454,6 +2754,9 @@ fn cmd_startdemos(
             state: ClientState::new(stream.clone()),
             kind: ConnectionKind::Demo(demo_server),
             conn_state: ConnectionState::SignOn(SignOnStage::Prespawn),
+            net_graph: NetGraph::new(),
+            server_info: None,
+            demo_camera: DemoCamera::default(),
         }));
 
         input.borrow_mut().set_focus(InputFocus::Game);
@@ -0,0 +1,140 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::collections::VecDeque;
+
+use crate::common::net::NetStats;
+
+use chrono::Duration;
+
+/// The number of frames of history kept by the netgraph.
+pub const NETGRAPH_FRAMES: usize = 128;
+
+/// Network traffic during a single client frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NetGraphSample {
+    pub frame_time: Duration,
+    pub bytes_received: usize,
+    pub bytes_sent: usize,
+    pub dropped_packets: usize,
+}
+
+/// Per-frame history of a connection's network traffic.
+#[derive(Debug, Default)]
+pub struct NetGraph {
+    samples: VecDeque<NetGraphSample>,
+    stats: NetStats,
+}
+
+impl NetGraph {
+    pub fn new() -> NetGraph {
+        NetGraph::default()
+    }
+
+    /// Records a new frame given the current connection counters.
+    ///
+    /// The sample stores the difference between `stats` and the counters from
+    /// the previous call.
+    pub fn update(&mut self, frame_time: Duration, stats: &NetStats) {
+        let prev = self.stats;
+
+        if self.samples.len() == NETGRAPH_FRAMES {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(NetGraphSample {
+            frame_time,
+            bytes_received: stats.bytes_received - prev.bytes_received,
+            bytes_sent: stats.bytes_sent - prev.bytes_sent,
+            dropped_packets: stats.dropped_packets - prev.dropped_packets,
+        });
+
+        self.stats = *stats;
+    }
+
+    /// Returns the recorded samples, oldest first.
+    pub fn samples(&self) -> impl Iterator<Item = &NetGraphSample> {
+        self.samples.iter()
+    }
+
+    /// Returns the counters as of the most recent update.
+    pub fn stats(&self) -> &NetStats {
+        &self.stats
+    }
+
+    /// Returns the average incoming and outgoing traffic over the recorded
+    /// history in bytes per second.
+    pub fn rates(&self) -> (f32, f32) {
+        let (time, bytes_in, bytes_out) = self.samples.iter().fold(
+            (Duration::zero(), 0, 0),
+            |(time, bytes_in, bytes_out), s| {
+                (
+                    time + s.frame_time,
+                    bytes_in + s.bytes_received,
+                    bytes_out + s.bytes_sent,
+                )
+            },
+        );
+
+        let secs = time.num_microseconds().unwrap_or(0) as f32 / 1_000_000.0;
+        if secs <= 0.0 {
+            return (0.0, 0.0);
+        }
+
+        (bytes_in as f32 / secs, bytes_out as f32 / secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_netgraph_update_records_deltas() {
+        let mut graph = NetGraph::new();
+        let mut stats = NetStats {
+            bytes_received: 100,
+            bytes_sent: 50,
+            ..Default::default()
+        };
+        graph.update(Duration::milliseconds(500), &stats);
+
+        stats.bytes_received += 300;
+        stats.dropped_packets += 2;
+        graph.update(Duration::milliseconds(500), &stats);
+
+        let samples: Vec<_> = graph.samples().collect();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[1].bytes_received, 300);
+        assert_eq!(samples[1].bytes_sent, 0);
+        assert_eq!(samples[1].dropped_packets, 2);
+        assert_eq!(graph.rates(), (400.0, 50.0));
+    }
+
+    #[test]
+    fn test_netgraph_history_is_bounded() {
+        let mut graph = NetGraph::new();
+        for _ in 0..NETGRAPH_FRAMES * 2 {
+            graph.update(Duration::milliseconds(10), &NetStats::default());
+        }
+
+        assert_eq!(graph.samples().count(), NETGRAPH_FRAMES);
+    }
+}
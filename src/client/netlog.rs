@@ -0,0 +1,198 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Capture of network traffic to a JSON-lines file.
+//!
+//! Each line of the log is a JSON object describing a single message, e.g.
+//!
+//! ```text
+//! {"time":"2020-05-01T12:00:00.000Z","elapsed_ms":16,"direction":"recv","reliable":null,
+//!  "len":7,"data":"0700002041...","cmds":["Time { time: Duration { ... } }"],"error":null}
+//! ```
+//!
+//! Messages are decoded with [`ServerCmd::deserialize`] and
+//! [`ClientCmd::deserialize`]. If decoding fails partway through a message, the
+//! commands decoded so far are kept and the error is recorded alongside the raw
+//! message data.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufWriter, Write},
+    path::Path,
+};
+
+use crate::common::net::{ClientCmd, NetError, ServerCmd};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetLogDirection {
+    Recv,
+    Send,
+}
+
+#[derive(Serialize)]
+struct NetLogEntry {
+    time: String,
+    elapsed_ms: i64,
+    direction: NetLogDirection,
+    reliable: Option<bool>,
+    len: usize,
+    data: String,
+    cmds: Vec<String>,
+    error: Option<String>,
+}
+
+/// A JSON-lines capture of the messages sent and received by the client.
+pub struct NetLog {
+    writer: BufWriter<File>,
+    start: DateTime<Utc>,
+}
+
+impl NetLog {
+    /// Create a new log at the given path, truncating any existing file.
+    pub fn create<P>(path: P) -> Result<NetLog, io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(NetLog {
+            writer: BufWriter::new(File::create(path)?),
+            start: Utc::now(),
+        })
+    }
+
+    /// Log a message received from the server.
+    ///
+    /// Received messages are already reassembled by the socket, so whether
+    /// they arrived reliably is not known.
+    pub fn log_server_msg(&mut self, msg: &[u8]) -> Result<(), io::Error> {
+        let (cmds, error) = decode_all(msg, |reader| {
+            Ok(ServerCmd::deserialize(reader)?.map(|cmd| format!("{:?}", cmd)))
+        });
+        self.write_entry(NetLogDirection::Recv, None, msg, cmds, error)
+    }
+
+    /// Log a message sent to the server.
+    pub fn log_client_msg(&mut self, msg: &[u8], reliable: bool) -> Result<(), io::Error> {
+        let (cmds, error) = decode_all(msg, |reader| {
+            if reader.fill_buf()?.is_empty() {
+                return Ok(None);
+            }
+
+            Ok(Some(format!("{:?}", ClientCmd::deserialize(reader)?)))
+        });
+        self.write_entry(NetLogDirection::Send, Some(reliable), msg, cmds, error)
+    }
+
+    fn write_entry(
+        &mut self,
+        direction: NetLogDirection,
+        reliable: Option<bool>,
+        msg: &[u8],
+        cmds: Vec<String>,
+        error: Option<String>,
+    ) -> Result<(), io::Error> {
+        let now = Utc::now();
+        let entry = NetLogEntry {
+            time: now.to_rfc3339_opts(SecondsFormat::Millis, true),
+            elapsed_ms: now.signed_duration_since(self.start).num_milliseconds(),
+            direction,
+            reliable,
+            len: msg.len(),
+            data: msg.iter().map(|b| format!("{:02x}", b)).collect(),
+            cmds,
+            error,
+        };
+
+        serde_json::to_writer(&mut self.writer, &entry)?;
+        self.writer.write_all(b"\n")?;
+
+        // flush every entry so the log is intact even if the client crashes
+        self.writer.flush()
+    }
+}
+
+// Decode commands from `msg` until `decode` returns `Ok(None)` or an error.
+fn decode_all<F>(msg: &[u8], mut decode: F) -> (Vec<String>, Option<String>)
+where
+    F: FnMut(&mut &[u8]) -> Result<Option<String>, NetError>,
+{
+    let mut reader = msg;
+    let mut cmds = Vec::new();
+
+    loop {
+        match decode(&mut reader) {
+            Ok(Some(cmd)) => cmds.push(cmd),
+            Ok(None) => return (cmds, None),
+            Err(e) => return (cmds, Some(e.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    #[test]
+    fn test_net_log_client_msg() {
+        let path =
+            std::env::temp_dir().join(format!("richter-netlog-{}.jsonl", std::process::id()));
+
+        let mut msg = Vec::new();
+        ClientCmd::StringCmd {
+            cmd: String::from("prespawn"),
+        }
+        .serialize(&mut msg)
+        .unwrap();
+        ClientCmd::NoOp.serialize(&mut msg).unwrap();
+
+        let mut log = NetLog::create(&path).unwrap();
+        log.log_client_msg(&msg, true).unwrap();
+        log.log_server_msg(&[0xff]).unwrap();
+        drop(log);
+
+        let mut contents = String::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+
+        assert_eq!(lines[0]["direction"], "send");
+        assert_eq!(lines[0]["reliable"], true);
+        assert_eq!(lines[0]["len"], msg.len());
+        assert_eq!(lines[0]["cmds"].as_array().unwrap().len(), 2);
+        assert!(lines[0]["error"].is_null());
+
+        assert_eq!(lines[1]["direction"], "recv");
+        assert_eq!(lines[1]["data"], "ff");
+        assert!(!lines[1]["error"].is_null());
+    }
+}
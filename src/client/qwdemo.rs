@@ -0,0 +1,1881 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! QuakeWorld demo playback.
+//!
+//! QuakeWorld client demos (`.qwd`) and multi-view server demos (`.mvd`) use
+//! different framing and a different protocol from NetQuake demos. Rather than
+//! teach the client a second protocol, these demos are translated into
+//! NetQuake server messages when they are loaded, so they can be played back,
+//! seeked and benchmarked like any other demo.
+//!
+//! A multi-view demo records every player in the game, but the translated
+//! messages only carry the stats and point of view of one of them, so
+//! switching players means translating the demo again.
+
+use std::{collections::BTreeSet, io::Read as _, ops::Range};
+
+use crate::{
+    client::demo::DemoServerError,
+    common::{
+        net::{
+            self, BeamEntityKind, ClientStat, EntityEffects, EntityUpdate, GameType, ItemFlags,
+            NetError, PlayerColor, PlayerData, PointEntityKind, ServerCmd, SignOnStage, TempEntity,
+        },
+        util::read_f32_3,
+    },
+};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use cgmath::{Deg, Vector3, Zero as _};
+use num::FromPrimitive;
+
+/// The QuakeWorld protocol version supported for playback.
+pub const QW_PROTOCOL_VERSION: i32 = 28;
+
+// protocol extension headers which may precede the protocol version
+const PROTOCOL_VERSION_FTE: u32 = u32::from_le_bytes(*b"FTEX");
+const PROTOCOL_VERSION_FTE2: u32 = u32::from_le_bytes(*b"FTE2");
+const PROTOCOL_VERSION_MVD1: u32 = u32::from_le_bytes(*b"MVD1");
+
+const QW_MAX_CLIENTS: usize = 32;
+const QW_MAX_EDICTS: usize = 512;
+const QW_MAX_STATS: usize = 32;
+const QW_STAT_ITEMS: usize = 15;
+
+// number of entity frames kept for delta compression
+const UPDATE_BACKUP: usize = 64;
+
+// size of a usercmd_t as written to client demos
+const USERCMD_SIZE: usize = 24;
+
+// nails aren't entities in QuakeWorld, so they're given ids past the last one
+const NAIL_ENTITY_BASE: u16 = QW_MAX_EDICTS as u16;
+
+// demo block types
+const DEM_CMD: u8 = 0;
+const DEM_READ: u8 = 1;
+const DEM_SET: u8 = 2;
+const DEM_MULTIPLE: u8 = 3;
+const DEM_SINGLE: u8 = 4;
+const DEM_STATS: u8 = 5;
+const DEM_ALL: u8 = 6;
+
+// entity delta flags
+const U_ANGLE1: u32 = 1 << 0;
+const U_ANGLE3: u32 = 1 << 1;
+const U_MODEL: u32 = 1 << 2;
+const U_COLORMAP: u32 = 1 << 3;
+const U_SKIN: u32 = 1 << 4;
+const U_EFFECTS: u32 = 1 << 5;
+const U_ORIGIN1: u32 = 1 << 9;
+const U_ORIGIN2: u32 = 1 << 10;
+const U_ORIGIN3: u32 = 1 << 11;
+const U_ANGLE2: u32 = 1 << 12;
+const U_FRAME: u32 = 1 << 13;
+const U_REMOVE: u32 = 1 << 14;
+const U_MOREBITS: u32 = 1 << 15;
+
+// player info flags in client demos
+const PF_MSEC: u16 = 1 << 0;
+const PF_COMMAND: u16 = 1 << 1;
+const PF_VELOCITY1: u16 = 1 << 2;
+const PF_MODEL: u16 = 1 << 5;
+const PF_SKINNUM: u16 = 1 << 6;
+const PF_EFFECTS: u16 = 1 << 7;
+const PF_WEAPONFRAME: u16 = 1 << 8;
+
+// player info flags in multi-view demos
+const DF_ORIGIN: u16 = 1 << 0;
+const DF_ANGLES: u16 = 1 << 3;
+const DF_EFFECTS: u16 = 1 << 6;
+const DF_SKINNUM: u16 = 1 << 7;
+const DF_WEAPONFRAME: u16 = 1 << 10;
+const DF_MODEL: u16 = 1 << 11;
+
+// usercmd delta flags
+const CM_ANGLE1: u8 = 1 << 0;
+const CM_ANGLE3: u8 = 1 << 1;
+const CM_FORWARD: u8 = 1 << 2;
+const CM_SIDE: u8 = 1 << 3;
+const CM_UP: u8 = 1 << 4;
+const CM_BUTTONS: u8 = 1 << 5;
+const CM_IMPULSE: u8 = 1 << 6;
+const CM_ANGLE2: u8 = 1 << 7;
+
+// sound flags, stored in the channel field
+const SND_VOLUME: u16 = 1 << 15;
+const SND_ATTENUATION: u16 = 1 << 14;
+
+// effects with no NetQuake equivalent, shown as a dim light
+const QW_EF_BLUE: u8 = 1 << 6;
+const QW_EF_RED: u8 = 1 << 7;
+
+#[derive(Clone, Copy, Debug, Eq, FromPrimitive, PartialEq)]
+enum QwCmdCode {
+    Bad = 0,
+    NoOp = 1,
+    Disconnect = 2,
+    UpdateStat = 3,
+    Sound = 6,
+    Print = 8,
+    StuffText = 9,
+    SetAngle = 10,
+    ServerData = 11,
+    LightStyle = 12,
+    UpdateFrags = 14,
+    StopSound = 16,
+    Damage = 19,
+    SpawnStatic = 20,
+    SpawnBaseline = 22,
+    TempEntity = 23,
+    SetPause = 24,
+    CenterPrint = 26,
+    KilledMonster = 27,
+    FoundSecret = 28,
+    SpawnStaticSound = 29,
+    Intermission = 30,
+    Finale = 31,
+    CdTrack = 32,
+    SellScreen = 33,
+    SmallKick = 34,
+    BigKick = 35,
+    UpdatePing = 36,
+    UpdateEnterTime = 37,
+    UpdateStatLong = 38,
+    MuzzleFlash = 39,
+    UpdateUserInfo = 40,
+    Download = 41,
+    PlayerInfo = 42,
+    Nails = 43,
+    ChokeCount = 44,
+    ModelList = 45,
+    SoundList = 46,
+    PacketEntities = 47,
+    DeltaPacketEntities = 48,
+    MaxSpeed = 49,
+    EntGravity = 50,
+    SetInfo = 51,
+    ServerInfo = 52,
+    UpdatePl = 53,
+    Nails2 = 54,
+}
+
+/// The container format of a demo file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DemoFormat {
+    /// A NetQuake demo (`.dem`).
+    NetQuake,
+
+    /// A QuakeWorld client demo (`.qwd`).
+    QuakeWorld,
+
+    /// A QuakeWorld multi-view demo (`.mvd`).
+    MultiView,
+}
+
+impl DemoFormat {
+    /// Determine the format of a demo from the start of its data.
+    pub fn detect(data: &[u8]) -> DemoFormat {
+        // NetQuake demos begin with a CD track number on its own line
+        if let Some(newline) = data.iter().take(4).position(|&b| b == b'\n') {
+            let track = match data[..newline].split_first() {
+                Some((b'-', digits)) => digits,
+                _ => &data[..newline],
+            };
+
+            if !track.is_empty() && track.iter().all(|b| b.is_ascii_digit()) {
+                return DemoFormat::NetQuake;
+            }
+        }
+
+        // client demos begin with a timestamp, multi-view demos with a
+        // single-byte time delta. the first message of a multi-view demo is
+        // never long enough for its header to look like a plausible timestamp.
+        if data.len() > 4 {
+            let time = f32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            if (time == 0.0 || (1e-3..1e7).contains(&time)) && data[4] <= DEM_SET {
+                return DemoFormat::QuakeWorld;
+            }
+        }
+
+        DemoFormat::MultiView
+    }
+}
+
+// The recipients of a demo block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Target {
+    All,
+    Multiple(u32),
+    Single(u8),
+    Stats(u8),
+}
+
+impl Target {
+    fn includes(&self, player: Option<u8>) -> bool {
+        match (*self, player) {
+            (Target::All, _) => true,
+            (Target::Multiple(mask), Some(p)) => mask & 1 << p != 0,
+            (Target::Single(s), Some(p)) | (Target::Stats(s), Some(p)) => s == p,
+            _ => false,
+        }
+    }
+}
+
+enum Block {
+    // the recording client's view angles
+    Cmd {
+        view_angles: Vector3<Deg<f32>>,
+    },
+
+    // a server message
+    Read {
+        time: f32,
+        sequence: u32,
+        target: Target,
+        data: Range<usize>,
+    },
+}
+
+/// NetQuake server messages translated from a QuakeWorld demo.
+pub struct Translation {
+    /// The translated messages, each with the view angles to play it back with.
+    pub messages: Vec<(Vector3<Deg<f32>>, Vec<u8>)>,
+
+    /// The player whose point of view was translated, if any.
+    pub tracked: Option<u8>,
+
+    /// The players who appear in the demo.
+    pub players: Vec<u8>,
+}
+
+/// A QuakeWorld demo split into its blocks.
+pub struct QwDemo {
+    format: DemoFormat,
+    data: Vec<u8>,
+    blocks: Vec<Block>,
+}
+
+impl QwDemo {
+    /// Split a `.qwd` or `.mvd` demo into blocks.
+    pub fn parse(format: DemoFormat, data: Vec<u8>) -> Result<QwDemo, DemoServerError> {
+        let blocks = match format {
+            DemoFormat::QuakeWorld => parse_qwd(&data)?,
+            DemoFormat::MultiView => parse_mvd(&data)?,
+            DemoFormat::NetQuake => return Err(DemoServerError::NotQuakeWorld),
+        };
+
+        Ok(QwDemo {
+            format,
+            data,
+            blocks,
+        })
+    }
+
+    pub fn format(&self) -> DemoFormat {
+        self.format
+    }
+
+    /// Translate the demo into NetQuake server messages.
+    ///
+    /// Multi-view demos are translated from the point of view of `track`, or
+    /// of the first player to appear if `track` is `None`. Client demos are
+    /// always translated from the point of view of the recording player.
+    pub fn translate(&self, track: Option<u8>) -> Result<Translation, DemoServerError> {
+        let multiview = self.format == DemoFormat::MultiView;
+        let translation = self.translate_for(multiview, track)?;
+
+        // per-player messages before the first frame were skipped, so go again
+        // now that we know who to follow
+        match (multiview, track, translation.tracked) {
+            (true, None, Some(tracked)) => self.translate_for(multiview, Some(tracked)),
+            _ => Ok(translation),
+        }
+    }
+
+    fn translate_for(
+        &self,
+        multiview: bool,
+        track: Option<u8>,
+    ) -> Result<Translation, DemoServerError> {
+        let mut tr = Translator::new(multiview, track);
+
+        for block in self.blocks.iter() {
+            match *block {
+                Block::Cmd { view_angles } => tr.cmd_angles = view_angles,
+
+                Block::Read {
+                    time,
+                    sequence,
+                    target,
+                    ref data,
+                } => {
+                    if !target.includes(tr.track) {
+                        continue;
+                    }
+
+                    tr.time = time;
+                    tr.sequence = sequence;
+                    match tr.parse_message(&self.data[data.clone()]) {
+                        Ok(()) => (),
+                        // a damaged message only loses what's left of it
+                        Err(DemoServerError::Io(e)) => {
+                            warn!("Truncated QuakeWorld demo message: {}", e)
+                        }
+                        Err(DemoServerError::Net(NetError::InvalidData(e))) => {
+                            warn!("Invalid QuakeWorld demo message: {}", e)
+                        }
+                        Err(e) => return Err(e),
+                    }
+                    tr.flush()?;
+
+                    if tr.done {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(Translation {
+            messages: tr.messages,
+            tracked: tr.track,
+            players: tr.appeared.into_iter().collect(),
+        })
+    }
+}
+
+fn parse_qwd(data: &[u8]) -> Result<Vec<Block>, DemoServerError> {
+    let mut reader = data;
+    let mut blocks = Vec::new();
+
+    while !reader.is_empty() {
+        let time = reader.read_f32::<LittleEndian>()?;
+        match reader.read_u8()? {
+            DEM_CMD => {
+                // the movement command is followed by the client's view angles
+                let mut usercmd = [0; USERCMD_SIZE];
+                reader.read_exact(&mut usercmd)?;
+                let angles = read_f32_3(&mut reader)?;
+                blocks.push(Block::Cmd {
+                    view_angles: Vector3::new(Deg(angles[0]), Deg(angles[1]), Deg(angles[2])),
+                });
+            }
+
+            DEM_READ => {
+                let (start, packet) = read_block_data(data, &mut reader)?;
+
+                // skip connectionless packets and packets too short for the
+                // netchan header
+                if packet.len() < 8 || packet[..4] == [0xFF; 4] {
+                    continue;
+                }
+
+                let sequence = u32::from_le_bytes([packet[0], packet[1], packet[2], packet[3]]);
+                blocks.push(Block::Read {
+                    time,
+                    sequence: sequence & 0x7FFF_FFFF,
+                    target: Target::All,
+                    data: start + 8..start + packet.len(),
+                });
+            }
+
+            // netchan sequence numbers
+            DEM_SET => {
+                reader.read_u32::<LittleEndian>()?;
+                reader.read_u32::<LittleEndian>()?;
+            }
+
+            b => Err(DemoServerError::InvalidBlock(b))?,
+        }
+    }
+
+    Ok(blocks)
+}
+
+fn parse_mvd(data: &[u8]) -> Result<Vec<Block>, DemoServerError> {
+    let mut reader = data;
+    let mut blocks = Vec::new();
+    let mut time_ms: u64 = 0;
+    let mut sequence = 0;
+
+    while !reader.is_empty() {
+        time_ms += reader.read_u8()? as u64;
+        let block_type = reader.read_u8()?;
+
+        let target = match block_type & 0b111 {
+            DEM_READ | DEM_ALL => Target::All,
+            DEM_MULTIPLE => Target::Multiple(reader.read_u32::<LittleEndian>()?),
+            DEM_SINGLE => Target::Single(block_type >> 3),
+            DEM_STATS => Target::Stats(block_type >> 3),
+
+            DEM_SET => {
+                reader.read_u32::<LittleEndian>()?;
+                reader.read_u32::<LittleEndian>()?;
+                continue;
+            }
+
+            b => Err(DemoServerError::InvalidBlock(b))?,
+        };
+
+        let (start, msg) = read_block_data(data, &mut reader)?;
+        sequence += 1;
+        blocks.push(Block::Read {
+            time: time_ms as f32 / 1000.0,
+            sequence,
+            target,
+            data: start..start + msg.len(),
+        });
+    }
+
+    Ok(blocks)
+}
+
+// Read a length-prefixed block, returning its offset in `data` and its contents.
+fn read_block_data<'a>(
+    data: &[u8],
+    reader: &mut &'a [u8],
+) -> Result<(usize, &'a [u8]), DemoServerError> {
+    let len = reader.read_u32::<LittleEndian>()? as usize;
+    if len > net::MAX_MESSAGE {
+        Err(DemoServerError::MessageTooLong(len as u32))?;
+    }
+
+    if len > reader.len() {
+        Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))?;
+    }
+
+    let start = data.len() - reader.len();
+    let (block, rest) = reader.split_at(len);
+    *reader = rest;
+    Ok((start, block))
+}
+
+#[derive(Clone, Copy, Debug)]
+struct QwEntity {
+    number: u16,
+    model_id: u8,
+    frame_id: u8,
+    colormap: u8,
+    skin_id: u8,
+    effects: u8,
+    origin: Vector3<f32>,
+    angles: Vector3<Deg<f32>>,
+}
+
+impl QwEntity {
+    fn new(number: u16) -> QwEntity {
+        QwEntity {
+            number,
+            model_id: 0,
+            frame_id: 0,
+            colormap: 0,
+            skin_id: 0,
+            effects: 0,
+            origin: Vector3::zero(),
+            angles: Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct QwPlayer {
+    name: String,
+    top_color: u8,
+    bottom_color: u8,
+    spectator: bool,
+    frags: i16,
+
+    origin: Vector3<f32>,
+    view_angles: Vector3<Deg<f32>>,
+    velocity: Vector3<f32>,
+    model_id: u8,
+    frame_id: u8,
+    skin_id: u8,
+    effects: u8,
+
+    // whether the player was sent in the current frame
+    active: bool,
+}
+
+impl QwPlayer {
+    fn new() -> QwPlayer {
+        QwPlayer {
+            name: String::new(),
+            top_color: 0,
+            bottom_color: 0,
+            spectator: false,
+            frags: 0,
+            origin: Vector3::zero(),
+            view_angles: Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+            velocity: Vector3::zero(),
+            model_id: 0,
+            frame_id: 0,
+            skin_id: 0,
+            effects: 0,
+            active: false,
+        }
+    }
+
+    // apply a key from an info string
+    fn set_info(&mut self, key: &str, value: &str) {
+        match key {
+            "name" => self.name = value.to_owned(),
+            "topcolor" => self.top_color = value.parse::<u8>().unwrap_or(0) & 0xF,
+            "bottomcolor" => self.bottom_color = value.parse::<u8>().unwrap_or(0) & 0xF,
+            "*spectator" => self.spectator = !value.is_empty() && value != "0",
+            _ => (),
+        }
+    }
+}
+
+struct Nail {
+    // nails in multi-view demos are numbered so they can be interpolated
+    id: Option<u8>,
+    origin: Vector3<f32>,
+    angles: Vector3<Deg<f32>>,
+}
+
+// Translation state for a single pass over a demo.
+struct Translator {
+    multiview: bool,
+
+    // the player whose point of view is being translated
+    track: Option<u8>,
+
+    // the player who recorded a client demo
+    own_player: Option<u8>,
+
+    time: f32,
+    sequence: u32,
+    cmd_angles: Vector3<Deg<f32>>,
+
+    level_name: String,
+    model_precache: Vec<String>,
+    sound_precache: Vec<String>,
+    models_done: bool,
+    sounds_done: bool,
+    player_model: u8,
+    spike_model: Option<u8>,
+
+    // ServerInfo has been sent for this level
+    signed_on: bool,
+
+    // the signon has been completed for this level
+    begun: bool,
+
+    baselines: Vec<QwEntity>,
+    frames: Vec<Vec<QwEntity>>,
+    entities: Vec<QwEntity>,
+    frame_ready: bool,
+
+    players: Vec<QwPlayer>,
+    appeared: BTreeSet<u8>,
+    stats: [i32; QW_MAX_STATS],
+    nails: Vec<Nail>,
+    muzzle_flashes: Vec<u16>,
+
+    pending: Vec<u8>,
+    messages: Vec<(Vector3<Deg<f32>>, Vec<u8>)>,
+    done: bool,
+}
+
+impl Translator {
+    fn new(multiview: bool, track: Option<u8>) -> Translator {
+        Translator {
+            multiview,
+            track: if multiview { track } else { None },
+            own_player: None,
+            time: 0.0,
+            sequence: 0,
+            cmd_angles: Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+            level_name: String::new(),
+            model_precache: Vec::new(),
+            sound_precache: Vec::new(),
+            models_done: false,
+            sounds_done: false,
+            player_model: 0,
+            spike_model: None,
+            signed_on: false,
+            begun: false,
+            baselines: (0..QW_MAX_EDICTS as u16).map(QwEntity::new).collect(),
+            frames: vec![Vec::new(); UPDATE_BACKUP],
+            entities: Vec::new(),
+            frame_ready: false,
+            players: vec![QwPlayer::new(); QW_MAX_CLIENTS],
+            appeared: BTreeSet::new(),
+            stats: [0; QW_MAX_STATS],
+            nails: Vec::new(),
+            muzzle_flashes: Vec::new(),
+            pending: Vec::new(),
+            messages: Vec::new(),
+            done: false,
+        }
+    }
+
+    fn emit(&mut self, cmd: ServerCmd) -> Result<(), DemoServerError> {
+        cmd.serialize(&mut self.pending)?;
+        Ok(())
+    }
+
+    fn parse_message(&mut self, mut reader: &[u8]) -> Result<(), DemoServerError> {
+        while !reader.is_empty() {
+            let code_byte = reader.read_u8()?;
+            let code = match QwCmdCode::from_u8(code_byte) {
+                Some(c) => c,
+                None => Err(NetError::InvalidData(format!(
+                    "QuakeWorld command code {}",
+                    code_byte
+                )))?,
+            };
+
+            match code {
+                QwCmdCode::Bad => Err(NetError::InvalidData("svc_bad".to_owned()))?,
+                QwCmdCode::NoOp
+                | QwCmdCode::SmallKick
+                | QwCmdCode::BigKick
+                | QwCmdCode::SellScreen => (),
+
+                QwCmdCode::Disconnect => {
+                    self.done = true;
+                    return Ok(());
+                }
+
+                QwCmdCode::UpdateStat => {
+                    let stat = reader.read_u8()?;
+                    let value = reader.read_u8()? as i32;
+                    self.update_stat(stat, value)?;
+                }
+
+                QwCmdCode::UpdateStatLong => {
+                    let stat = reader.read_u8()?;
+                    let value = reader.read_i32::<LittleEndian>()?;
+                    self.update_stat(stat, value)?;
+                }
+
+                QwCmdCode::Sound => {
+                    let channel = reader.read_u16::<LittleEndian>()?;
+                    let volume = match channel & SND_VOLUME {
+                        0 => None,
+                        _ => Some(reader.read_u8()?),
+                    };
+                    let attenuation = match channel & SND_ATTENUATION {
+                        0 => None,
+                        _ => Some(reader.read_u8()? as f32 / 64.0),
+                    };
+                    let sound_id = reader.read_u8()?;
+                    let position = read_coord_vector3(&mut reader)?;
+                    self.emit(ServerCmd::Sound {
+                        volume,
+                        attenuation,
+                        entity_id: (channel >> 3) & 1023,
+                        channel: (channel & 0b111) as i8,
+                        sound_id,
+                        position,
+                    })?;
+                }
+
+                QwCmdCode::StopSound => {
+                    let channel = reader.read_u16::<LittleEndian>()?;
+                    self.emit(ServerCmd::StopSound {
+                        entity_id: channel >> 3,
+                        channel: (channel & 0b111) as u8,
+                    })?;
+                }
+
+                QwCmdCode::Print => {
+                    let _level = reader.read_u8()?;
+                    let text = read_string(&mut reader)?;
+                    self.emit(ServerCmd::Print { text })?;
+                }
+
+                QwCmdCode::CenterPrint => {
+                    let text = read_string(&mut reader)?;
+                    self.emit(ServerCmd::CenterPrint { text })?;
+                }
+
+                // these are commands for a live client (e.g. "cmd spawn"), not
+                // something to replay
+                QwCmdCode::StuffText => {
+                    read_string(&mut reader)?;
+                }
+
+                QwCmdCode::SetAngle => {
+                    if self.multiview {
+                        let _player = reader.read_u8()?;
+                    }
+                    for _ in 0..3 {
+                        reader.read_u8()?;
+                    }
+                }
+
+                QwCmdCode::ServerData => self.parse_server_data(&mut reader)?,
+
+                QwCmdCode::LightStyle => {
+                    let id = reader.read_u8()?;
+                    let value = read_string(&mut reader)?;
+                    self.emit(ServerCmd::LightStyle { id, value })?;
+                }
+
+                QwCmdCode::UpdateFrags => {
+                    let player_id = reader.read_u8()?;
+                    let new_frags = reader.read_i16::<LittleEndian>()?;
+                    self.player_mut(player_id)?.frags = new_frags;
+                    if self.signed_on && (player_id as usize) < net::MAX_CLIENTS {
+                        self.emit(ServerCmd::UpdateFrags {
+                            player_id,
+                            new_frags,
+                        })?;
+                    }
+                }
+
+                QwCmdCode::Damage => {
+                    let armor = reader.read_u8()?;
+                    let blood = reader.read_u8()?;
+                    let source = read_coord_vector3(&mut reader)?;
+                    self.emit(ServerCmd::Damage {
+                        armor,
+                        blood,
+                        source,
+                    })?;
+                }
+
+                QwCmdCode::SpawnStatic => {
+                    let ent = read_entity_state(&mut reader, 0)?;
+                    self.emit(ServerCmd::SpawnStatic {
+                        model_id: ent.model_id,
+                        frame_id: ent.frame_id,
+                        colormap: ent.colormap,
+                        skin_id: ent.skin_id,
+                        origin: ent.origin,
+                        angles: ent.angles,
+                    })?;
+                }
+
+                QwCmdCode::SpawnBaseline => {
+                    let ent_id = reader.read_u16::<LittleEndian>()?;
+                    let ent = read_entity_state(&mut reader, ent_id)?;
+                    if ent_id as usize >= QW_MAX_EDICTS {
+                        Err(NetError::InvalidData(format!("baseline entity {}", ent_id)))?;
+                    }
+                    self.baselines[ent_id as usize] = ent;
+                    self.emit(ServerCmd::SpawnBaseline {
+                        ent_id,
+                        model_id: ent.model_id,
+                        frame_id: ent.frame_id,
+                        colormap: ent.colormap,
+                        skin_id: ent.skin_id,
+                        origin: ent.origin,
+                        angles: ent.angles,
+                    })?;
+                }
+
+                QwCmdCode::TempEntity => self.parse_temp_entity(&mut reader)?,
+
+                QwCmdCode::SetPause => {
+                    let paused = reader.read_u8()? != 0;
+                    self.emit(ServerCmd::SetPause { paused })?;
+                }
+
+                QwCmdCode::KilledMonster => self.emit(ServerCmd::KilledMonster)?,
+                QwCmdCode::FoundSecret => self.emit(ServerCmd::FoundSecret)?,
+
+                QwCmdCode::SpawnStaticSound => {
+                    let origin = read_coord_vector3(&mut reader)?;
+                    let sound_id = reader.read_u8()?;
+                    let volume = reader.read_u8()?;
+                    let attenuation = reader.read_u8()?;
+                    self.emit(ServerCmd::SpawnStaticSound {
+                        origin,
+                        sound_id,
+                        volume,
+                        attenuation,
+                    })?;
+                }
+
+                QwCmdCode::Intermission => {
+                    // camera position and angles
+                    read_coord_vector3(&mut reader)?;
+                    for _ in 0..3 {
+                        reader.read_u8()?;
+                    }
+                    self.emit(ServerCmd::Intermission)?;
+                }
+
+                QwCmdCode::Finale => {
+                    let text = read_string(&mut reader)?;
+                    self.emit(ServerCmd::Finale { text })?;
+                }
+
+                QwCmdCode::CdTrack => {
+                    let track = reader.read_u8()?;
+                    self.emit(ServerCmd::CdTrack {
+                        track,
+                        loop_: track,
+                    })?;
+                }
+
+                QwCmdCode::UpdatePing => {
+                    reader.read_u8()?;
+                    reader.read_i16::<LittleEndian>()?;
+                }
+
+                QwCmdCode::UpdateEnterTime => {
+                    reader.read_u8()?;
+                    reader.read_f32::<LittleEndian>()?;
+                }
+
+                QwCmdCode::UpdatePl | QwCmdCode::ChokeCount => {
+                    if code == QwCmdCode::UpdatePl {
+                        reader.read_u8()?;
+                    }
+                    reader.read_u8()?;
+                }
+
+                QwCmdCode::MaxSpeed | QwCmdCode::EntGravity => {
+                    reader.read_f32::<LittleEndian>()?;
+                }
+
+                QwCmdCode::MuzzleFlash => {
+                    let ent_id = reader.read_u16::<LittleEndian>()?;
+                    self.muzzle_flashes.push(ent_id);
+                }
+
+                QwCmdCode::UpdateUserInfo => {
+                    let player_id = reader.read_u8()?;
+                    let _user_id = reader.read_i32::<LittleEndian>()?;
+                    let info = read_string(&mut reader)?;
+
+                    let player = self.player_mut(player_id)?;
+                    *player = QwPlayer {
+                        frags: player.frags,
+                        model_id: player.model_id,
+                        ..QwPlayer::new()
+                    };
+
+                    // info strings are of the form \key\value\key\value
+                    let mut fields = info.split('\\').skip(1);
+                    while let (Some(key), Some(value)) = (fields.next(), fields.next()) {
+                        player.set_info(key, value);
+                    }
+
+                    self.emit_player_info(player_id)?;
+                }
+
+                QwCmdCode::SetInfo => {
+                    let player_id = reader.read_u8()?;
+                    let key = read_string(&mut reader)?;
+                    let value = read_string(&mut reader)?;
+                    self.player_mut(player_id)?.set_info(&key, &value);
+                    self.emit_player_info(player_id)?;
+                }
+
+                QwCmdCode::ServerInfo => {
+                    read_string(&mut reader)?;
+                    read_string(&mut reader)?;
+                }
+
+                QwCmdCode::Download => {
+                    let size = reader.read_i16::<LittleEndian>()?;
+                    let _percent = reader.read_u8()?;
+                    for _ in 0..size.max(0) {
+                        reader.read_u8()?;
+                    }
+                }
+
+                QwCmdCode::PlayerInfo => {
+                    if self.multiview {
+                        self.parse_mvd_player_info(&mut reader)?;
+                    } else {
+                        self.parse_player_info(&mut reader)?;
+                    }
+                }
+
+                QwCmdCode::Nails | QwCmdCode::Nails2 => {
+                    let count = reader.read_u8()?;
+                    for _ in 0..count {
+                        let id = match code {
+                            QwCmdCode::Nails2 => Some(reader.read_u8()?),
+                            _ => None,
+                        };
+                        let mut bits = [0u8; 6];
+                        reader.read_exact(&mut bits)?;
+                        let (origin, angles) = unpack_nail(bits);
+                        self.nails.push(Nail { id, origin, angles });
+                    }
+                }
+
+                QwCmdCode::ModelList | QwCmdCode::SoundList => {
+                    let _start = reader.read_u8()?;
+                    loop {
+                        let name = read_string(&mut reader)?;
+                        if name.is_empty() {
+                            break;
+                        }
+
+                        if code == QwCmdCode::ModelList {
+                            self.model_precache.push(name);
+                        } else {
+                            self.sound_precache.push(name);
+                        }
+                    }
+
+                    // a nonzero index means there are more names to come
+                    if reader.read_u8()? == 0 {
+                        if code == QwCmdCode::ModelList {
+                            self.models_done = true;
+                        } else {
+                            self.sounds_done = true;
+                        }
+                    }
+
+                    self.begin_level()?;
+                }
+
+                QwCmdCode::PacketEntities => self.parse_packet_entities(&mut reader, false)?,
+                QwCmdCode::DeltaPacketEntities => self.parse_packet_entities(&mut reader, true)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn player_mut(&mut self, player_id: u8) -> Result<&mut QwPlayer, DemoServerError> {
+        match self.players.get_mut(player_id as usize) {
+            Some(p) => Ok(p),
+            None => Err(NetError::InvalidData(format!("player {}", player_id)))?,
+        }
+    }
+
+    fn update_stat(&mut self, stat: u8, value: i32) -> Result<(), DemoServerError> {
+        if let Some(s) = self.stats.get_mut(stat as usize) {
+            *s = value;
+        }
+
+        // most stats are sent as part of the player data for each frame, but
+        // this covers the rest
+        if let Some(stat) = ClientStat::from_u8(stat) {
+            self.emit(ServerCmd::UpdateStat { stat, value })?;
+        }
+
+        Ok(())
+    }
+
+    fn parse_server_data(&mut self, reader: &mut &[u8]) -> Result<(), DemoServerError> {
+        let mut protocol = reader.read_i32::<LittleEndian>()?;
+        while let PROTOCOL_VERSION_FTE | PROTOCOL_VERSION_FTE2 | PROTOCOL_VERSION_MVD1 =
+            protocol as u32
+        {
+            let extensions = reader.read_u32::<LittleEndian>()?;
+            if extensions != 0 {
+                Err(DemoServerError::QwExtension(protocol as u32, extensions))?;
+            }
+            protocol = reader.read_i32::<LittleEndian>()?;
+        }
+
+        if protocol != QW_PROTOCOL_VERSION {
+            Err(DemoServerError::QwProtocol(protocol))?;
+        }
+
+        let _server_count = reader.read_i32::<LittleEndian>()?;
+        let _game_dir = read_string(reader)?;
+
+        if self.multiview {
+            let _time = reader.read_f32::<LittleEndian>()?;
+        } else {
+            // the high bit marks a spectator, who has no entity to follow
+            let player = reader.read_u8()?;
+            self.own_player = match player & 0x80 {
+                0 if player as usize >= QW_MAX_CLIENTS => {
+                    Err(DemoServerError::NoSuchPlayer(player))?
+                }
+                0 => Some(player),
+                _ => None,
+            };
+            self.track = self.own_player;
+        }
+
+        self.level_name = read_string(reader)?;
+
+        // movement variables
+        for _ in 0..10 {
+            reader.read_f32::<LittleEndian>()?;
+        }
+
+        // start a new level
+        self.model_precache.clear();
+        self.sound_precache.clear();
+        self.models_done = false;
+        self.sounds_done = false;
+        self.signed_on = false;
+        self.begun = false;
+        self.baselines = (0..QW_MAX_EDICTS as u16).map(QwEntity::new).collect();
+        self.frames = vec![Vec::new(); UPDATE_BACKUP];
+        self.entities.clear();
+
+        Ok(())
+    }
+
+    // Send the server info once both precache lists are complete.
+    fn begin_level(&mut self) -> Result<(), DemoServerError> {
+        if self.signed_on || !self.models_done || !self.sounds_done {
+            return Ok(());
+        }
+
+        let model_id = |name: &str, models: &[String]| {
+            models.iter().position(|m| m == name).map(|i| i as u8 + 1)
+        };
+        self.player_model = model_id("progs/player.mdl", &self.model_precache).unwrap_or(0);
+        self.spike_model = model_id("progs/spike.mdl", &self.model_precache);
+        for player in self.players.iter_mut() {
+            player.model_id = self.player_model;
+        }
+
+        self.emit(ServerCmd::ServerInfo {
+            protocol_version: net::PROTOCOL_VERSION as i32,
+            max_clients: net::MAX_CLIENTS as u8,
+            game_type: GameType::Deathmatch,
+            message: self.level_name.clone(),
+            model_precache: self.model_precache.clone(),
+            sound_precache: self.sound_precache.clone(),
+        })?;
+        self.emit(ServerCmd::SignOnStage {
+            stage: SignOnStage::Prespawn,
+        })?;
+        self.signed_on = true;
+
+        // the scoreboard is reset with the level
+        for player_id in 0..net::MAX_CLIENTS as u8 {
+            self.emit_player_info(player_id)?;
+        }
+
+        Ok(())
+    }
+
+    fn emit_player_info(&mut self, player_id: u8) -> Result<(), DemoServerError> {
+        // the NetQuake scoreboard has no room for the higher QuakeWorld slots
+        if !self.signed_on || player_id as usize >= net::MAX_CLIENTS {
+            return Ok(());
+        }
+
+        let player = self.players[player_id as usize].clone();
+        if player.name.is_empty() && player.frags == 0 {
+            return Ok(());
+        }
+
+        // spectators don't belong on the scoreboard
+        let new_name = match player.spectator {
+            true => String::new(),
+            false => player.name,
+        };
+        self.emit(ServerCmd::UpdateName {
+            player_id,
+            new_name,
+        })?;
+        self.emit(ServerCmd::UpdateFrags {
+            player_id,
+            new_frags: player.frags,
+        })?;
+        self.emit(ServerCmd::UpdateColors {
+            player_id,
+            new_colors: PlayerColor::new(player.top_color, player.bottom_color),
+        })?;
+
+        Ok(())
+    }
+
+    fn parse_temp_entity(&mut self, reader: &mut &[u8]) -> Result<(), DemoServerError> {
+        let code = reader.read_u8()?;
+
+        let point_kind = match code {
+            0 => Some(PointEntityKind::Spike),
+            1 => Some(PointEntityKind::SuperSpike),
+            2 => {
+                let _count = reader.read_u8()?;
+                Some(PointEntityKind::Gunshot)
+            }
+            3 => Some(PointEntityKind::Explosion),
+            4 => Some(PointEntityKind::TarExplosion),
+            7 => Some(PointEntityKind::WizSpike),
+            8 => Some(PointEntityKind::KnightSpike),
+            10 => Some(PointEntityKind::LavaSplash),
+            11 => Some(PointEntityKind::Teleport),
+            _ => None,
+        };
+
+        let cmd = match (point_kind, code) {
+            (Some(kind), _) => ServerCmd::TempEntity {
+                temp_entity: TempEntity::Point {
+                    kind,
+                    origin: read_coord_vector3(reader)?,
+                },
+            },
+
+            (None, 5 | 6 | 9) => {
+                let entity_id = reader.read_i16::<LittleEndian>()?;
+                let start = read_coord_vector3(reader)?;
+                let end = read_coord_vector3(reader)?;
+                ServerCmd::TempEntity {
+                    temp_entity: TempEntity::Beam {
+                        kind: BeamEntityKind::Lightning {
+                            model_id: match code {
+                                5 => 1,
+                                6 => 2,
+                                _ => 3,
+                            },
+                        },
+                        entity_id,
+                        start,
+                        end,
+                    },
+                }
+            }
+
+            // blood is a particle effect in NetQuake. a count of 255 means an
+            // explosion, so stay below it
+            (None, 12) => {
+                let count = reader.read_u8()?;
+                ServerCmd::Particle {
+                    origin: read_coord_vector3(reader)?,
+                    direction: Vector3::zero(),
+                    count: (count as u32 * 20).min(254) as u8,
+                    color: 73,
+                }
+            }
+
+            (None, 13) => ServerCmd::Particle {
+                origin: read_coord_vector3(reader)?,
+                direction: Vector3::zero(),
+                count: 50,
+                color: 225,
+            },
+
+            (None, c) => Err(NetError::InvalidData(format!(
+                "QuakeWorld temp entity code {}",
+                c
+            )))?,
+        };
+
+        self.emit(cmd)
+    }
+
+    fn parse_player_info(&mut self, reader: &mut &[u8]) -> Result<(), DemoServerError> {
+        let player_id = reader.read_u8()?;
+        let flags = reader.read_u16::<LittleEndian>()?;
+        let origin = read_coord_vector3(reader)?;
+        let frame_id = reader.read_u8()?;
+
+        if flags & PF_MSEC != 0 {
+            reader.read_u8()?;
+        }
+
+        let view_angles = match flags & PF_COMMAND {
+            0 => None,
+            _ => Some(read_delta_usercmd(reader)?),
+        };
+
+        let mut velocity = Vector3::zero();
+        for i in 0..3 {
+            if flags & PF_VELOCITY1 << i != 0 {
+                velocity[i] = reader.read_i16::<LittleEndian>()? as f32;
+            }
+        }
+
+        let mut read_opt = |flag| match flags & flag {
+            0 => Ok(None),
+            _ => reader.read_u8().map(Some),
+        };
+        let model_id = read_opt(PF_MODEL)?;
+        let skin_id = read_opt(PF_SKINNUM)?;
+        let effects = read_opt(PF_EFFECTS)?;
+        let _weapon_frame = read_opt(PF_WEAPONFRAME)?;
+
+        let player_model = self.player_model;
+        let player = self.player_mut(player_id)?;
+        player.origin = origin;
+        player.frame_id = frame_id;
+        if let Some(angles) = view_angles {
+            player.view_angles = angles;
+        }
+        player.velocity = velocity;
+        player.model_id = model_id.unwrap_or(player_model);
+        player.skin_id = skin_id.unwrap_or(0);
+        player.effects = effects.unwrap_or(0);
+        player.active = true;
+
+        Ok(())
+    }
+
+    // player info in multi-view demos is delta compressed against the
+    // player's last state
+    fn parse_mvd_player_info(&mut self, reader: &mut &[u8]) -> Result<(), DemoServerError> {
+        let player_id = reader.read_u8()?;
+        let flags = reader.read_u16::<LittleEndian>()?;
+        let frame_id = reader.read_u8()?;
+
+        let player = match self.players.get_mut(player_id as usize) {
+            Some(p) => p,
+            None => Err(NetError::InvalidData(format!("player {}", player_id)))?,
+        };
+
+        player.frame_id = frame_id;
+
+        for i in 0..3 {
+            if flags & DF_ORIGIN << i != 0 {
+                player.origin[i] = read_coord(reader)?;
+            }
+        }
+
+        for i in 0..3 {
+            if flags & DF_ANGLES << i != 0 {
+                player.view_angles[i] = read_angle16(reader)?;
+            }
+        }
+
+        if flags & DF_MODEL != 0 {
+            player.model_id = reader.read_u8()?;
+        }
+
+        if flags & DF_SKINNUM != 0 {
+            player.skin_id = reader.read_u8()?;
+        }
+
+        if flags & DF_EFFECTS != 0 {
+            player.effects = reader.read_u8()?;
+        }
+
+        if flags & DF_WEAPONFRAME != 0 {
+            reader.read_u8()?;
+        }
+
+        player.active = true;
+
+        Ok(())
+    }
+
+    fn parse_packet_entities(
+        &mut self,
+        reader: &mut &[u8],
+        delta: bool,
+    ) -> Result<(), DemoServerError> {
+        let old = if delta {
+            let from = reader.read_u8()? as usize;
+
+            // multi-view demos always delta from the previous frame
+            if self.multiview {
+                self.entities.clone()
+            } else {
+                self.frames[from % UPDATE_BACKUP].clone()
+            }
+        } else {
+            Vec::new()
+        };
+
+        let mut new = Vec::with_capacity(old.len());
+        let mut old = old.into_iter().peekable();
+
+        loop {
+            let word = reader.read_u16::<LittleEndian>()? as u32;
+            let number = match word {
+                0 => u16::MAX,
+                w => (w & 511) as u16,
+            };
+
+            // entities that weren't mentioned are unchanged
+            while let Some(ent) = old.next_if(|ent| ent.number < number) {
+                new.push(ent);
+            }
+
+            if word == 0 {
+                break;
+            }
+
+            let from_old = old.next_if(|ent| ent.number == number);
+            if word & U_REMOVE != 0 {
+                continue;
+            }
+
+            let base = from_old.unwrap_or(self.baselines[number as usize]);
+            new.push(read_entity_delta(reader, word, base)?);
+        }
+
+        if !self.multiview {
+            self.frames[self.sequence as usize % UPDATE_BACKUP] = new.clone();
+        }
+        self.entities = new;
+        self.frame_ready = true;
+
+        Ok(())
+    }
+
+    fn view_angles(&self) -> Vector3<Deg<f32>> {
+        match self.track {
+            Some(t) if Some(t) == self.own_player => self.cmd_angles,
+            Some(t) => self
+                .players
+                .get(t as usize)
+                .map_or(self.cmd_angles, |p| p.view_angles),
+            None => self.cmd_angles,
+        }
+    }
+
+    // Finish the current message, turning it into a frame if it contained
+    // entity updates.
+    fn flush(&mut self) -> Result<(), DemoServerError> {
+        if self.frame_ready && self.signed_on {
+            self.frame_ready = false;
+            self.emit_frame()?;
+        } else if !self.pending.is_empty() {
+            let msg = std::mem::take(&mut self.pending);
+            self.messages.push((self.view_angles(), msg));
+        }
+
+        Ok(())
+    }
+
+    fn emit_frame(&mut self) -> Result<(), DemoServerError> {
+        // the first frame decides who to follow if nobody has been chosen
+        if self.track.is_none() {
+            self.track = self.players.iter().position(|p| p.active).map(|p| p as u8);
+        }
+
+        for (player_id, player) in self.players.iter().enumerate() {
+            if player.active {
+                self.appeared.insert(player_id as u8);
+            }
+        }
+
+        if !self.begun {
+            self.begun = true;
+            if let Some(track) = self.track {
+                self.emit(ServerCmd::SetView {
+                    ent_id: track as i16 + 1,
+                })?;
+            }
+            self.emit(ServerCmd::SignOnStage {
+                stage: SignOnStage::ClientInfo,
+            })?;
+            self.emit(ServerCmd::SignOnStage {
+                stage: SignOnStage::Begin,
+            })?;
+
+            let msg = std::mem::take(&mut self.pending);
+            self.messages.push((self.view_angles(), msg));
+        }
+
+        // every frame restates the position of everything visible
+        let mut msg = Vec::new();
+        ServerCmd::Time { time: self.time }.serialize(&mut msg)?;
+        msg.append(&mut self.pending);
+
+        if let Some(player) = self.track.and_then(|t| self.players.get(t as usize)) {
+            let stat = |s: ClientStat| self.stats[s as usize];
+            let byte_stat = |s: ClientStat| stat(s).clamp(0, 255) as u8;
+            ServerCmd::PlayerData(PlayerData {
+                view_height: Some(net::DEFAULT_VIEWHEIGHT),
+                ideal_pitch: None,
+                punch_pitch: None,
+                velocity_x: Some(player.velocity.x),
+                punch_yaw: None,
+                velocity_y: Some(player.velocity.y),
+                punch_roll: None,
+                velocity_z: Some(player.velocity.z),
+                items: ItemFlags::from_bits_truncate(self.stats[QW_STAT_ITEMS] as u32),
+                on_ground: false,
+                in_water: false,
+                weapon_frame: Some(byte_stat(ClientStat::WeaponFrame)),
+                armor: Some(byte_stat(ClientStat::Armor)),
+                weapon: Some(byte_stat(ClientStat::Weapon)),
+                health: stat(ClientStat::Health) as i16,
+                ammo: byte_stat(ClientStat::Ammo),
+                ammo_shells: byte_stat(ClientStat::Shells),
+                ammo_nails: byte_stat(ClientStat::Nails),
+                ammo_rockets: byte_stat(ClientStat::Rockets),
+                ammo_cells: byte_stat(ClientStat::Cells),
+                active_weapon: byte_stat(ClientStat::ActiveWeapon),
+            })
+            .serialize(&mut msg)?;
+        }
+
+        for (player_id, player) in self.players.iter().enumerate() {
+            if !player.active || player.model_id == 0 {
+                continue;
+            }
+
+            let ent_id = player_id as u16 + 1;
+            let mut effects = player.effects;
+            if self.muzzle_flashes.contains(&ent_id) {
+                effects |= EntityEffects::MUZZLE_FLASH.bits();
+            }
+
+            ServerCmd::FastUpdate(entity_update(
+                &QwEntity {
+                    number: ent_id,
+                    model_id: player.model_id,
+                    frame_id: player.frame_id,
+                    colormap: ent_id as u8,
+                    skin_id: player.skin_id,
+                    effects,
+                    origin: player.origin,
+                    // the model only leans a third of the way
+                    angles: Vector3::new(
+                        player.view_angles.x / -3.0,
+                        player.view_angles.y,
+                        Deg(0.0),
+                    ),
+                },
+                false,
+            ))
+            .serialize(&mut msg)?;
+        }
+
+        for ent in self.entities.iter() {
+            ServerCmd::FastUpdate(entity_update(ent, false)).serialize(&mut msg)?;
+        }
+
+        if let Some(spike_model) = self.spike_model {
+            for (i, nail) in self.nails.iter().enumerate() {
+                let mut ent = QwEntity::new(NAIL_ENTITY_BASE + nail.id.unwrap_or(i as u8) as u16);
+                ent.model_id = spike_model;
+                ent.origin = nail.origin;
+                ent.angles = nail.angles;
+
+                // unnumbered nails can't be matched up between frames
+                ServerCmd::FastUpdate(entity_update(&ent, nail.id.is_none()))
+                    .serialize(&mut msg)?;
+            }
+        }
+
+        self.messages.push((self.view_angles(), msg));
+
+        for player in self.players.iter_mut() {
+            player.active = false;
+        }
+        self.nails.clear();
+        self.muzzle_flashes.clear();
+
+        Ok(())
+    }
+}
+
+fn entity_update(ent: &QwEntity, no_lerp: bool) -> EntityUpdate {
+    let mut effects = EntityEffects::from_bits_truncate(ent.effects);
+    if ent.effects & (QW_EF_BLUE | QW_EF_RED) != 0 {
+        effects |= EntityEffects::DIM_LIGHT;
+    }
+
+    EntityUpdate {
+        ent_id: ent.number,
+        model_id: Some(ent.model_id),
+        frame_id: Some(ent.frame_id),
+        colormap: Some(ent.colormap),
+        skin_id: Some(ent.skin_id),
+        effects: Some(effects),
+        origin_x: Some(ent.origin.x),
+        pitch: Some(ent.angles.x),
+        origin_y: Some(ent.origin.y),
+        yaw: Some(ent.angles.y),
+        origin_z: Some(ent.origin.z),
+        roll: Some(ent.angles.z),
+        no_lerp,
+    }
+}
+
+// Unpack a nail's position and direction from 6 bytes.
+fn unpack_nail(bits: [u8; 6]) -> (Vector3<f32>, Vector3<Deg<f32>>) {
+    let [b0, b1, b2, b3, b4, b5] = bits.map(|b| b as i32);
+    let origin = Vector3::new(
+        ((b0 + ((b1 & 15) << 8)) << 1) - 4096,
+        (((b1 >> 4) + (b2 << 4)) << 1) - 4096,
+        ((b3 + ((b4 & 15) << 8)) << 1) - 4096,
+    );
+    let angles = Vector3::new(
+        Deg(360.0 * (b4 >> 4) as f32 / 16.0),
+        Deg(360.0 * b5 as f32 / 256.0),
+        Deg(0.0),
+    );
+
+    (origin.cast().unwrap(), angles)
+}
+
+// Read the delta from `base` for an entity in a packet entities message.
+fn read_entity_delta(
+    reader: &mut &[u8],
+    word: u32,
+    base: QwEntity,
+) -> Result<QwEntity, DemoServerError> {
+    let mut bits = word & !511;
+    if bits & U_MOREBITS != 0 {
+        bits |= reader.read_u8()? as u32;
+    }
+
+    let mut ent = base;
+    ent.number = (word & 511) as u16;
+
+    if bits & U_MODEL != 0 {
+        ent.model_id = reader.read_u8()?;
+    }
+    if bits & U_FRAME != 0 {
+        ent.frame_id = reader.read_u8()?;
+    }
+    if bits & U_COLORMAP != 0 {
+        ent.colormap = reader.read_u8()?;
+    }
+    if bits & U_SKIN != 0 {
+        ent.skin_id = reader.read_u8()?;
+    }
+    if bits & U_EFFECTS != 0 {
+        ent.effects = reader.read_u8()?;
+    }
+
+    for (i, (origin_flag, angle_flag)) in [
+        (U_ORIGIN1, U_ANGLE1),
+        (U_ORIGIN2, U_ANGLE2),
+        (U_ORIGIN3, U_ANGLE3),
+    ]
+    .iter()
+    .enumerate()
+    {
+        if bits & origin_flag != 0 {
+            ent.origin[i] = read_coord(reader)?;
+        }
+        if bits & angle_flag != 0 {
+            ent.angles[i] = read_angle(reader)?;
+        }
+    }
+
+    Ok(ent)
+}
+
+// Read an entity's state as sent in baselines and static entities.
+fn read_entity_state(reader: &mut &[u8], number: u16) -> Result<QwEntity, DemoServerError> {
+    let mut ent = QwEntity::new(number);
+    ent.model_id = reader.read_u8()?;
+    ent.frame_id = reader.read_u8()?;
+    ent.colormap = reader.read_u8()?;
+    ent.skin_id = reader.read_u8()?;
+    for i in 0..3 {
+        ent.origin[i] = read_coord(reader)?;
+        ent.angles[i] = read_angle(reader)?;
+    }
+
+    Ok(ent)
+}
+
+// Read a movement command, delta compressed against an empty one, returning
+// its view angles.
+fn read_delta_usercmd(reader: &mut &[u8]) -> Result<Vector3<Deg<f32>>, DemoServerError> {
+    let bits = reader.read_u8()?;
+    let mut angles = Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0));
+
+    if bits & CM_ANGLE1 != 0 {
+        angles.x = read_angle16(reader)?;
+    }
+    if bits & CM_ANGLE2 != 0 {
+        angles.y = read_angle16(reader)?;
+    }
+    if bits & CM_ANGLE3 != 0 {
+        angles.z = read_angle16(reader)?;
+    }
+
+    // movement
+    for &flag in [CM_FORWARD, CM_SIDE, CM_UP].iter() {
+        if bits & flag != 0 {
+            reader.read_i16::<LittleEndian>()?;
+        }
+    }
+
+    // buttons and impulse
+    for &flag in [CM_BUTTONS, CM_IMPULSE].iter() {
+        if bits & flag != 0 {
+            reader.read_u8()?;
+        }
+    }
+
+    // duration
+    reader.read_u8()?;
+
+    Ok(angles)
+}
+
+fn read_coord(reader: &mut &[u8]) -> Result<f32, DemoServerError> {
+    Ok(reader.read_i16::<LittleEndian>()? as f32 / 8.0)
+}
+
+fn read_coord_vector3(reader: &mut &[u8]) -> Result<Vector3<f32>, DemoServerError> {
+    Ok(Vector3::new(
+        read_coord(reader)?,
+        read_coord(reader)?,
+        read_coord(reader)?,
+    ))
+}
+
+fn read_angle(reader: &mut &[u8]) -> Result<Deg<f32>, DemoServerError> {
+    Ok(Deg(reader.read_i8()? as f32 * (360.0 / 256.0)))
+}
+
+fn read_angle16(reader: &mut &[u8]) -> Result<Deg<f32>, DemoServerError> {
+    Ok(Deg(
+        reader.read_i16::<LittleEndian>()? as f32 * (360.0 / 65536.0)
+    ))
+}
+
+// Read a null-terminated string in the Quake character set.
+//
+// Colored text is made plain and the special characters for brackets and
+// digits are replaced with their ASCII equivalents.
+fn read_string(reader: &mut &[u8]) -> Result<String, DemoServerError> {
+    let mut text = String::new();
+    loop {
+        let c = match reader.read_u8()? {
+            0 => break,
+            b => b & 0x7F,
+        };
+
+        text.push(match c {
+            b'\n' | 0x20..=0x7E => c as char,
+            0x10 => '[',
+            0x11 => ']',
+            0x12..=0x1B => (b'0' + c - 0x12) as char,
+            _ => '.',
+        });
+    }
+
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write as _;
+
+    use byteorder::WriteBytesExt;
+
+    fn write_string(msg: &mut Vec<u8>, s: &str) {
+        msg.write_all(s.as_bytes()).unwrap();
+        msg.write_u8(0).unwrap();
+    }
+
+    fn mvd_block(demo: &mut Vec<u8>, delta_ms: u8, block_type: u8, msg: &[u8]) {
+        demo.write_u8(delta_ms).unwrap();
+        demo.write_u8(block_type).unwrap();
+        demo.write_u32::<LittleEndian>(msg.len() as u32).unwrap();
+        demo.write_all(msg).unwrap();
+    }
+
+    // A multi-view demo with two players and one other entity, followed by a
+    // second frame in which the entity moves.
+    fn test_mvd() -> Vec<u8> {
+        let mut demo = Vec::new();
+
+        let mut msg = Vec::new();
+        msg.write_u8(QwCmdCode::ServerData as u8).unwrap();
+        msg.write_i32::<LittleEndian>(QW_PROTOCOL_VERSION).unwrap();
+        msg.write_i32::<LittleEndian>(1).unwrap();
+        write_string(&mut msg, "qw");
+        msg.write_f32::<LittleEndian>(0.0).unwrap();
+        write_string(&mut msg, "The Test Level");
+        for _ in 0..10 {
+            msg.write_f32::<LittleEndian>(0.0).unwrap();
+        }
+        msg.write_u8(QwCmdCode::SoundList as u8).unwrap();
+        msg.write_u8(0).unwrap();
+        write_string(&mut msg, "");
+        msg.write_u8(0).unwrap();
+        msg.write_u8(QwCmdCode::ModelList as u8).unwrap();
+        msg.write_u8(0).unwrap();
+        write_string(&mut msg, "maps/test.bsp");
+        write_string(&mut msg, "progs/player.mdl");
+        write_string(&mut msg, "");
+        msg.write_u8(0).unwrap();
+        for (player_id, name) in [(0, "alice"), (3, "bob")].iter() {
+            msg.write_u8(QwCmdCode::UpdateUserInfo as u8).unwrap();
+            msg.write_u8(*player_id).unwrap();
+            msg.write_i32::<LittleEndian>(*player_id as i32).unwrap();
+            write_string(&mut msg, &format!("\\name\\{}\\topcolor\\4", name));
+        }
+        mvd_block(&mut demo, 0, DEM_ALL, &msg);
+
+        // bob's health goes to the stats of the player tracking him
+        let mut msg = Vec::new();
+        msg.write_u8(QwCmdCode::UpdateStat as u8).unwrap();
+        msg.write_u8(ClientStat::Health as u8).unwrap();
+        msg.write_u8(75).unwrap();
+        mvd_block(&mut demo, 0, DEM_STATS | 3 << 3, &msg);
+
+        for (frame, x) in [(0, 64i16), (1, 128)].iter() {
+            let mut msg = Vec::new();
+            for player_id in [0u8, 3].iter() {
+                msg.write_u8(QwCmdCode::PlayerInfo as u8).unwrap();
+                msg.write_u8(*player_id).unwrap();
+                msg.write_u16::<LittleEndian>(DF_ORIGIN).unwrap();
+                msg.write_u8(0).unwrap();
+                msg.write_i16::<LittleEndian>(*player_id as i16 * 8)
+                    .unwrap();
+            }
+            if *frame == 0 {
+                msg.write_u8(QwCmdCode::PacketEntities as u8).unwrap();
+            } else {
+                msg.write_u8(QwCmdCode::DeltaPacketEntities as u8).unwrap();
+                msg.write_u8(0).unwrap();
+            }
+            msg.write_u16::<LittleEndian>(40 | U_ORIGIN1 as u16 | U_MOREBITS as u16)
+                .unwrap();
+            msg.write_u8(U_MODEL as u8).unwrap();
+            msg.write_u8(1).unwrap();
+            msg.write_i16::<LittleEndian>(x * 8).unwrap();
+            msg.write_u16::<LittleEndian>(0).unwrap();
+            mvd_block(&mut demo, 100, DEM_ALL, &msg);
+        }
+
+        demo
+    }
+
+    fn translated_cmds(translation: &Translation) -> Vec<Vec<ServerCmd>> {
+        translation
+            .messages
+            .iter()
+            .map(|(_, msg)| {
+                let mut reader = msg.as_slice();
+                let mut cmds = Vec::new();
+                while let Some(cmd) = ServerCmd::deserialize(&mut reader).unwrap() {
+                    cmds.push(cmd);
+                }
+                cmds
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(DemoFormat::detect(b"-1\n\x00\x00"), DemoFormat::NetQuake);
+        assert_eq!(DemoFormat::detect(b"2\n\x00\x00"), DemoFormat::NetQuake);
+        for data in &[
+            &b"\n\x00\x00\x00\x00\x00"[..],
+            b"-\n\x00\x00\x00\x00",
+            b"1-2\n\x00\x00",
+        ] {
+            assert_ne!(DemoFormat::detect(data), DemoFormat::NetQuake);
+        }
+
+        let mut qwd = Vec::new();
+        qwd.write_f32::<LittleEndian>(12.5).unwrap();
+        qwd.write_u8(DEM_READ).unwrap();
+        assert_eq!(DemoFormat::detect(&qwd), DemoFormat::QuakeWorld);
+
+        assert_eq!(DemoFormat::detect(&test_mvd()), DemoFormat::MultiView);
+        assert!(matches!(
+            QwDemo::parse(DemoFormat::NetQuake, b"-1\n".to_vec()),
+            Err(DemoServerError::NotQuakeWorld)
+        ));
+    }
+
+    #[test]
+    fn test_translate_mvd() {
+        let demo = QwDemo::parse(DemoFormat::MultiView, test_mvd()).unwrap();
+        let translation = demo.translate(None).unwrap();
+        assert_eq!(translation.tracked, Some(0));
+        assert_eq!(translation.players, vec![0, 3]);
+
+        let cmds = translated_cmds(&translation);
+        match cmds[0][0] {
+            ServerCmd::ServerInfo {
+                ref message,
+                ref model_precache,
+                ..
+            } => {
+                assert_eq!(message, "The Test Level");
+                assert_eq!(model_precache.len(), 2);
+            }
+            ref c => panic!("expected ServerInfo, got {:?}", c),
+        }
+        assert!(cmds[0].contains(&ServerCmd::UpdateName {
+            player_id: 3,
+            new_name: String::from("bob"),
+        }));
+
+        // signon, then two frames
+        assert_eq!(cmds.len(), 4);
+        assert_eq!(cmds[1][0], ServerCmd::SetView { ent_id: 1 });
+        for (frame, x) in [(2, 64.0), (3, 128.0)].iter() {
+            let updates: Vec<&EntityUpdate> = cmds[*frame]
+                .iter()
+                .filter_map(|c| match c {
+                    ServerCmd::FastUpdate(u) => Some(u),
+                    _ => None,
+                })
+                .collect();
+            let ids: Vec<u16> = updates.iter().map(|u| u.ent_id).collect();
+            assert_eq!(ids, vec![1, 4, 40]);
+            assert_eq!(updates[1].origin_x, Some(3.0));
+            assert_eq!(updates[2].origin_x, Some(*x));
+        }
+        assert_eq!(cmds[3][0], ServerCmd::Time { time: 0.2 });
+    }
+
+    #[test]
+    fn test_translate_mvd_tracked_player() {
+        let demo = QwDemo::parse(DemoFormat::MultiView, test_mvd()).unwrap();
+        let translation = demo.translate(Some(3)).unwrap();
+        assert_eq!(translation.tracked, Some(3));
+
+        let cmds = translated_cmds(&translation);
+        let signon = cmds
+            .iter()
+            .position(|msg| msg.contains(&ServerCmd::SetView { ent_id: 4 }))
+            .unwrap();
+        let health = cmds[signon + 1].iter().find_map(|c| match c {
+            ServerCmd::PlayerData(data) => Some(data.health),
+            _ => None,
+        });
+        assert_eq!(health, Some(75));
+    }
+
+    #[test]
+    fn test_translate_qwd_invalid_player() {
+        let mut packet = Vec::new();
+        packet.write_u32::<LittleEndian>(1).unwrap();
+        packet.write_u32::<LittleEndian>(0).unwrap();
+        packet.write_u8(QwCmdCode::ServerData as u8).unwrap();
+        packet
+            .write_i32::<LittleEndian>(QW_PROTOCOL_VERSION)
+            .unwrap();
+        packet.write_i32::<LittleEndian>(1).unwrap();
+        write_string(&mut packet, "qw");
+        packet.write_u8(40).unwrap();
+        write_string(&mut packet, "The Test Level");
+        for _ in 0..10 {
+            packet.write_f32::<LittleEndian>(0.0).unwrap();
+        }
+
+        let mut qwd = Vec::new();
+        qwd.write_f32::<LittleEndian>(0.0).unwrap();
+        qwd.write_u8(DEM_READ).unwrap();
+        qwd.write_u32::<LittleEndian>(packet.len() as u32).unwrap();
+        qwd.write_all(&packet).unwrap();
+
+        let demo = QwDemo::parse(DemoFormat::QuakeWorld, qwd).unwrap();
+        assert!(matches!(
+            demo.translate(None),
+            Err(DemoServerError::NoSuchPlayer(40))
+        ));
+    }
+}
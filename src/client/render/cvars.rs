@@ -21,6 +21,21 @@
 use crate::common::console::CvarRegistry;
 
 pub fn register_cvars(cvars: &CvarRegistry) {
+    cvars
+        .register_archive("gl_texture_anisotropy", "1")
+        .unwrap();
+    cvars
+        .register_archive("gl_texturemode", "GL_NEAREST_MIPMAP_LINEAR")
+        .unwrap();
+    cvars.register("r_drawviewmodel", "1").unwrap();
+    cvars.register_archive("r_lavaalpha", "1").unwrap();
+    cvars.register("r_lerpmodels", "1").unwrap();
     cvars.register("r_lightmap", "0").unwrap();
     cvars.register("r_msaa_samples", "4").unwrap();
+    cvars.register("r_netgraph", "0").unwrap();
+    cvars.register_archive("r_slimealpha", "1").unwrap();
+    cvars.register("r_speeds", "0").unwrap();
+    cvars.register_archive("r_telealpha", "1").unwrap();
+    cvars.register_archive("r_viewmodel_fov", "90").unwrap();
+    cvars.register_archive("r_wateralpha", "1").unwrap();
 }
@@ -28,6 +28,7 @@
 ///     - `AliasPipeline`
 ///     - `BrushPipeline`
 ///     - `SpritePipeline`
+///     - `SkyboxPipeline`
 ///   - Output: `InitialPassTarget`
 /// - Deferred lighting pass
 ///   - Inputs:
@@ -91,14 +92,20 @@ use crate::{
                 deferred::DeferredPipeline,
                 particle::ParticlePipeline,
                 postprocess::{self, PostProcessPipeline},
+                skybox::SkyboxPipeline,
                 sprite::SpritePipeline,
+                translucent::{
+                    TranslucentAliasPipeline, TranslucentBrushPipeline, TranslucentSpritePipeline,
+                },
                 EntityUniforms,
             },
         },
+        spectator::DemoCamera,
         Connection, ConnectionKind,
     },
     common::{
         console::{Console, CvarRegistry},
+        math,
         model::Model,
         net::SignOnStage,
         vfs::Vfs,
@@ -152,32 +159,64 @@ pub fn create_texture<'a>(
     height: u32,
     data: &TextureData,
 ) -> wgpu::Texture {
+    create_mipmapped_texture(
+        device,
+        queue,
+        label,
+        width,
+        height,
+        std::slice::from_ref(data),
+    )
+}
+
+/// Create a texture with one mip level per element of `mipmaps`.
+///
+/// Each mip level is half the size of the one before it, starting at `width` by
+/// `height`. All levels must have the same format.
+pub fn create_mipmapped_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    label: Option<&str>,
+    width: u32,
+    height: u32,
+    mipmaps: &[TextureData],
+) -> wgpu::Texture {
+    let format = mipmaps[0].format();
     trace!(
-        "Creating texture ({:?}: {}x{})",
-        data.format(),
+        "Creating texture ({:?}: {}x{}, {} mip levels)",
+        format,
         width,
-        height
-    );
-    let texture = device.create_texture(&texture_descriptor(label, width, height, data.format()));
-    queue.write_texture(
-        wgpu::ImageCopyTexture {
-            texture: &texture,
-            mip_level: 0,
-            origin: wgpu::Origin3d::ZERO,
-            aspect: Default::default(),
-        },
-        data.data(),
-        wgpu::ImageDataLayout {
-            offset: 0,
-            bytes_per_row: Some(width * data.stride()),
-            rows_per_image: None,
-        },
-        wgpu::Extent3d {
-            width,
-            height,
-            depth_or_array_layers: 1,
-        },
+        height,
+        mipmaps.len()
     );
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        mip_level_count: mipmaps.len() as u32,
+        ..texture_descriptor(label, width, height, format)
+    });
+
+    for (level, data) in mipmaps.iter().enumerate() {
+        let mip_width = (width >> level).max(1);
+        let mip_height = (height >> level).max(1);
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: level as u32,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: Default::default(),
+            },
+            data.data(),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(mip_width * data.stride()),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width: mip_width,
+                height: mip_height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
 
     texture
 }
@@ -253,6 +292,110 @@ impl std::convert::From<winit::dpi::PhysicalSize<u32>> for Extent2d {
     }
 }
 
+/// Filtering applied to world textures, set with `gl_texturemode` and
+/// `gl_texture_anisotropy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextureFiltering {
+    filter: wgpu::FilterMode,
+    mipmap_filter: Option<wgpu::FilterMode>,
+    anisotropy: u16,
+}
+
+impl TextureFiltering {
+    /// Parses an OpenGL texture filtering mode, e.g. `GL_LINEAR_MIPMAP_LINEAR`.
+    ///
+    /// The anisotropy is clamped to the range supported by wgpu.
+    pub fn from_texture_mode(mode: &str, anisotropy: u16) -> Option<TextureFiltering> {
+        use wgpu::FilterMode::*;
+
+        let (filter, mipmap_filter) = match mode.to_uppercase().as_str() {
+            "GL_NEAREST" => (Nearest, None),
+            "GL_LINEAR" => (Linear, None),
+            "GL_NEAREST_MIPMAP_NEAREST" => (Nearest, Some(Nearest)),
+            "GL_LINEAR_MIPMAP_NEAREST" => (Linear, Some(Nearest)),
+            "GL_NEAREST_MIPMAP_LINEAR" => (Nearest, Some(Linear)),
+            "GL_LINEAR_MIPMAP_LINEAR" => (Linear, Some(Linear)),
+            _ => return None,
+        };
+
+        Some(TextureFiltering {
+            filter,
+            mipmap_filter,
+            anisotropy: anisotropy.clamp(1, 16),
+        })
+    }
+
+    fn sampler_descriptor(&self) -> wgpu::SamplerDescriptor<'static> {
+        use wgpu::FilterMode::*;
+
+        // wgpu only allows anisotropic filtering if every filter is linear
+        let anisotropy_clamp = match (self.filter, self.mipmap_filter) {
+            (Linear, Some(Linear)) => self.anisotropy,
+            _ => 1,
+        };
+
+        wgpu::SamplerDescriptor {
+            label: Some("world diffuse sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: self.filter,
+            min_filter: self.filter,
+            mipmap_filter: self.mipmap_filter.unwrap_or(Nearest),
+            lod_min_clamp: 0.0,
+            // without mipmapping, always sample the full-size level
+            lod_max_clamp: match self.mipmap_filter {
+                Some(_) => 1000.0,
+                None => 0.0,
+            },
+            compare: None,
+            anisotropy_clamp,
+            ..Default::default()
+        }
+    }
+}
+
+impl std::default::Default for TextureFiltering {
+    fn default() -> Self {
+        TextureFiltering {
+            filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: Some(wgpu::FilterMode::Linear),
+            anisotropy: 1,
+        }
+    }
+}
+
+fn create_per_entity_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    entity_uniform_buffer: &wgpu::Buffer,
+    diffuse_sampler: &wgpu::Sampler,
+    lightmap_sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("brush per-entity bind group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: entity_uniform_buffer,
+                    offset: 0,
+                    size: Some(NonZeroU64::new(size_of::<EntityUniforms>() as u64).unwrap()),
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(diffuse_sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(lightmap_sampler),
+            },
+        ],
+    })
+}
+
 pub struct GraphicsState {
     device: wgpu::Device,
     queue: wgpu::Queue,
@@ -270,11 +413,19 @@ pub struct GraphicsState {
     diffuse_sampler: wgpu::Sampler,
     lightmap_sampler: wgpu::Sampler,
 
+    // world textures are sampled separately so the UI isn't affected by gl_texturemode
+    texture_filtering: TextureFiltering,
+    world_sampler: wgpu::Sampler,
+
     sample_count: Cell<u32>,
 
     alias_pipeline: AliasPipeline,
     brush_pipeline: BrushPipeline,
     sprite_pipeline: SpritePipeline,
+    skybox_pipeline: SkyboxPipeline,
+    translucent_brush_pipeline: TranslucentBrushPipeline,
+    translucent_alias_pipeline: TranslucentAliasPipeline,
+    translucent_sprite_pipeline: TranslucentSpritePipeline,
     deferred_pipeline: DeferredPipeline,
     particle_pipeline: ParticlePipeline,
     postprocess_pipeline: PostProcessPipeline,
@@ -347,6 +498,9 @@ impl GraphicsState {
             ..Default::default()
         });
 
+        let texture_filtering = TextureFiltering::default();
+        let world_sampler = device.create_sampler(&texture_filtering.sampler_descriptor());
+
         let world_bind_group_layouts: Vec<wgpu::BindGroupLayout> =
             world::BIND_GROUP_LAYOUT_DESCRIPTORS
                 .iter()
@@ -365,30 +519,13 @@ impl GraphicsState {
                     }),
                 }],
             }),
-            device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: Some("brush per-entity bind group"),
-                layout: &world_bind_group_layouts[world::BindGroupLayoutId::PerEntity as usize],
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                            buffer: &entity_uniform_buffer.borrow().buffer(),
-                            offset: 0,
-                            size: Some(
-                                NonZeroU64::new(size_of::<EntityUniforms>() as u64).unwrap(),
-                            ),
-                        }),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&diffuse_sampler),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: wgpu::BindingResource::Sampler(&lightmap_sampler),
-                    },
-                ],
-            }),
+            create_per_entity_bind_group(
+                &device,
+                &world_bind_group_layouts[world::BindGroupLayoutId::PerEntity as usize],
+                entity_uniform_buffer.borrow().buffer(),
+                &world_sampler,
+                &lightmap_sampler,
+            ),
         ];
 
         let alias_pipeline = AliasPipeline::new(
@@ -410,6 +547,33 @@ impl GraphicsState {
             &world_bind_group_layouts,
             sample_count,
         );
+        let skybox_pipeline = SkyboxPipeline::new(
+            &device,
+            &mut compiler,
+            &world_bind_group_layouts,
+            sample_count,
+        );
+        let translucent_brush_pipeline = TranslucentBrushPipeline::new(
+            &device,
+            &mut compiler,
+            &world_bind_group_layouts,
+            brush_pipeline.bind_group_layouts(),
+            sample_count,
+        );
+        let translucent_alias_pipeline = TranslucentAliasPipeline::new(
+            &device,
+            &mut compiler,
+            &world_bind_group_layouts,
+            alias_pipeline.bind_group_layouts(),
+            sample_count,
+        );
+        let translucent_sprite_pipeline = TranslucentSpritePipeline::new(
+            &device,
+            &mut compiler,
+            &world_bind_group_layouts,
+            sprite_pipeline.bind_group_layouts(),
+            sample_count,
+        );
         let deferred_pipeline = DeferredPipeline::new(&device, &mut compiler, sample_count);
         let particle_pipeline =
             ParticlePipeline::new(&device, &queue, &mut compiler, sample_count, &palette);
@@ -448,6 +612,10 @@ impl GraphicsState {
             alias_pipeline,
             brush_pipeline,
             sprite_pipeline,
+            skybox_pipeline,
+            translucent_brush_pipeline,
+            translucent_alias_pipeline,
+            translucent_sprite_pipeline,
             deferred_pipeline,
             particle_pipeline,
             postprocess_pipeline,
@@ -457,6 +625,8 @@ impl GraphicsState {
 
             diffuse_sampler,
             lightmap_sampler,
+            texture_filtering,
+            world_sampler,
             default_lightmap,
             default_lightmap_view,
             vfs,
@@ -476,13 +646,38 @@ impl GraphicsState {
         create_texture(&self.device, &self.queue, label, width, height, data)
     }
 
-    /// Update graphics state with the new framebuffer size and sample count.
+    pub fn create_mipmapped_texture(
+        &self,
+        label: Option<&str>,
+        width: u32,
+        height: u32,
+        mipmaps: &[TextureData],
+    ) -> wgpu::Texture {
+        create_mipmapped_texture(&self.device, &self.queue, label, width, height, mipmaps)
+    }
+
+    /// Update graphics state with the new framebuffer size, sample count and texture filtering.
     ///
     /// If the framebuffer size has changed, this recreates all render targets with the new size.
     ///
     /// If the framebuffer sample count has changed, this recreates all render targets with the
     /// new sample count and rebuilds the render pipelines to output that number of samples.
-    pub fn update(&mut self, size: Extent2d, sample_count: u32) {
+    ///
+    /// If the texture filtering has changed, this recreates the world texture sampler.
+    pub fn update(&mut self, size: Extent2d, sample_count: u32, filtering: TextureFiltering) {
+        if self.texture_filtering != filtering {
+            self.texture_filtering = filtering;
+            self.world_sampler = self.device.create_sampler(&filtering.sampler_descriptor());
+            self.world_bind_groups[world::BindGroupLayoutId::PerEntity as usize] =
+                create_per_entity_bind_group(
+                    &self.device,
+                    &self.world_bind_group_layouts[world::BindGroupLayoutId::PerEntity as usize],
+                    self.entity_uniform_buffer.borrow().buffer(),
+                    &self.world_sampler,
+                    &self.lightmap_sampler,
+                );
+        }
+
         if self.sample_count.get() != sample_count {
             self.sample_count.set(sample_count);
             self.recreate_pipelines(sample_count);
@@ -535,6 +730,33 @@ impl GraphicsState {
             &self.world_bind_group_layouts,
             sample_count,
         );
+        self.skybox_pipeline.rebuild(
+            &self.device,
+            &mut self.compiler.borrow_mut(),
+            &self.world_bind_group_layouts,
+            sample_count,
+        );
+        self.translucent_brush_pipeline.rebuild(
+            &self.device,
+            &mut self.compiler.borrow_mut(),
+            &self.world_bind_group_layouts,
+            self.brush_pipeline.bind_group_layouts(),
+            sample_count,
+        );
+        self.translucent_alias_pipeline.rebuild(
+            &self.device,
+            &mut self.compiler.borrow_mut(),
+            &self.world_bind_group_layouts,
+            self.alias_pipeline.bind_group_layouts(),
+            sample_count,
+        );
+        self.translucent_sprite_pipeline.rebuild(
+            &self.device,
+            &mut self.compiler.borrow_mut(),
+            &self.world_bind_group_layouts,
+            self.sprite_pipeline.bind_group_layouts(),
+            sample_count,
+        );
         self.deferred_pipeline
             .rebuild(&self.device, &mut self.compiler.borrow_mut(), sample_count);
         self.postprocess_pipeline.rebuild(
@@ -623,6 +845,22 @@ impl GraphicsState {
         &self.sprite_pipeline
     }
 
+    pub fn skybox_pipeline(&self) -> &SkyboxPipeline {
+        &self.skybox_pipeline
+    }
+
+    pub fn translucent_brush_pipeline(&self) -> &TranslucentBrushPipeline {
+        &self.translucent_brush_pipeline
+    }
+
+    pub fn translucent_alias_pipeline(&self) -> &TranslucentAliasPipeline {
+        &self.translucent_alias_pipeline
+    }
+
+    pub fn translucent_sprite_pipeline(&self) -> &TranslucentSpritePipeline {
+        &self.translucent_sprite_pipeline
+    }
+
     pub fn deferred_pipeline(&self) -> &DeferredPipeline {
         &self.deferred_pipeline
     }
@@ -707,20 +945,43 @@ impl ClientRenderer {
             state: ref cl_state,
             ref conn_state,
             ref kind,
+            ref demo_camera,
+            ..
         }) = conn
         {
             match conn_state {
-                ConnectionState::Connected(ref world) => {
+                ConnectionState::Connected(Some(ref world)) => {
                     // if client is fully connected, draw world
                     let camera = match kind {
                         ConnectionKind::Demo(_) => {
-                            cl_state.demo_camera(width as f32 / height as f32, fov)
+                            cl_state.demo_camera(demo_camera, width as f32 / height as f32, fov)
                         }
                         ConnectionKind::Server { .. } => {
                             cl_state.camera(width as f32 / height as f32, fov)
                         }
                     };
 
+                    // scale density like FitzQuake's GL_EXP2 fog
+                    let fog: [f32; 4] = {
+                        let fog = cl_state.fog();
+                        fog.color.extend(fog.density / 64.0).into()
+                    };
+
+                    let lightstyle_values = cl_state.lightstyle_values().unwrap();
+
+                    // the viewmodel has its own field of view, and the
+                    // deferred pass needs its projection to light it
+                    let viewmodel_camera = {
+                        let aspect = width as f32 / height as f32;
+                        let viewmodel_fov = Deg(cvars.get_value("r_viewmodel_fov").unwrap());
+                        let fov_y = math::fov_x_to_fov_y(viewmodel_fov, aspect).unwrap();
+                        Camera::new(
+                            camera.origin(),
+                            camera.angles(),
+                            cgmath::perspective(fov_y, aspect, 4.0, 4096.0),
+                        )
+                    };
+
                     // initial render pass
                     {
                         let init_pass_builder =
@@ -737,10 +998,34 @@ impl ClientRenderer {
                             cl_state.time(),
                             cl_state.iter_visible_entities(),
                             cl_state.iter_particles(),
-                            cl_state.lightstyle_values().unwrap().as_slice(),
-                            cl_state.viewmodel_id(),
+                            lightstyle_values.as_slice(),
                             cvars,
                         );
+
+                        // the viewmodel is only drawn from the player's own
+                        // point of view
+                        let first_person = match kind {
+                            ConnectionKind::Demo(_) => {
+                                matches!(demo_camera, DemoCamera::Recorded)
+                            }
+                            ConnectionKind::Server { .. } => true,
+                        };
+                        let viewmodel = cl_state
+                            .viewmodel()
+                            .filter(|_| first_person)
+                            .filter(|_| cvars.get_value("r_drawviewmodel").unwrap() != 0.0);
+                        if let Some(viewmodel) = viewmodel {
+                            world.render_viewmodel(
+                                gfx_state,
+                                &mut init_pass,
+                                &self.bump,
+                                &viewmodel_camera,
+                                cl_state.time(),
+                                viewmodel,
+                                lightstyle_values.as_slice(),
+                                cvars,
+                            );
+                        }
                     }
 
                     // deferred lighting pass
@@ -768,8 +1053,10 @@ impl ClientRenderer {
 
                         let uniforms = DeferredUniforms {
                             inv_projection: camera.inverse_projection().into(),
+                            viewmodel_inv_projection: viewmodel_camera.inverse_projection().into(),
                             light_count,
                             _pad: [0; 3],
+                            fog,
                             lights,
                         };
 
@@ -784,18 +1071,44 @@ impl ClientRenderer {
                         self.deferred_renderer
                             .record_draw(gfx_state, &mut deferred_pass, uniforms);
                     }
+
+                    // translucent pass, blended over the lit scene
+                    {
+                        let translucent_pass_builder = gfx_state
+                            .deferred_pass_target()
+                            .translucent_pass_builder(gfx_state.initial_pass_target().depth_view());
+                        let mut translucent_pass =
+                            encoder.begin_render_pass(&translucent_pass_builder.descriptor());
+
+                        world.render_translucent_pass(
+                            gfx_state,
+                            &mut translucent_pass,
+                            &self.bump,
+                            &camera,
+                            cl_state.time(),
+                            cl_state.iter_visible_entities(),
+                            lightstyle_values.as_slice(),
+                            fog,
+                            cvars,
+                        );
+                    }
                 }
 
                 // if client is still signing on, draw the loading screen
                 ConnectionState::SignOn(_) => {
                     // TODO: loading screen
                 }
+
+                // connections made without graphics have nothing to draw
+                ConnectionState::Connected(None) => (),
             }
         }
 
         let ui_state = match conn {
             Some(Connection {
                 state: ref cl_state,
+                ref conn_state,
+                ref net_graph,
                 ..
             }) => UiState::InGame {
                 hud: match cl_state.intermission() {
@@ -813,6 +1126,19 @@ impl ClientRenderer {
                         stats: cl_state.stats(),
                         face_anim_time: cl_state.face_anim_time(),
                         console,
+                        net_graph: if cvars.get_value("r_netgraph").unwrap_or(0.0) != 0.0 {
+                            Some(net_graph)
+                        } else {
+                            None
+                        },
+                        render_stats: match conn_state {
+                            ConnectionState::Connected(Some(ref world))
+                                if cvars.get_value("r_speeds").unwrap_or(0.0) != 0.0 =>
+                            {
+                                Some(world.stats())
+                            }
+                            _ => None,
+                        },
                     },
                 },
 
@@ -875,3 +1201,33 @@ impl ClientRenderer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_texture_filtering_from_texture_mode() {
+        use wgpu::FilterMode::*;
+
+        let filtering = TextureFiltering::from_texture_mode("gl_linear_mipmap_nearest", 1).unwrap();
+        assert_eq!(filtering.filter, Linear);
+        assert_eq!(filtering.mipmap_filter, Some(Nearest));
+
+        let filtering = TextureFiltering::from_texture_mode("GL_NEAREST", 1).unwrap();
+        assert_eq!(filtering.mipmap_filter, None);
+        assert_eq!(filtering.sampler_descriptor().lod_max_clamp, 0.0);
+
+        assert!(TextureFiltering::from_texture_mode("GL_BILINEAR", 1).is_none());
+    }
+
+    #[test]
+    fn test_texture_filtering_anisotropy() {
+        let filtering = TextureFiltering::from_texture_mode("GL_LINEAR_MIPMAP_LINEAR", 64).unwrap();
+        assert_eq!(filtering.sampler_descriptor().anisotropy_clamp, 16);
+
+        // anisotropy requires linear filtering throughout
+        let filtering = TextureFiltering::from_texture_mode("GL_NEAREST_MIPMAP_LINEAR", 8).unwrap();
+        assert_eq!(filtering.sampler_descriptor().anisotropy_clamp, 1);
+    }
+}
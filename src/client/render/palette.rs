@@ -2,11 +2,41 @@ use std::{borrow::Cow, io::BufReader};
 
 use crate::{
     client::render::{DiffuseData, FullbrightData},
-    common::vfs::Vfs,
+    common::{net::PlayerColor, vfs::Vfs},
 };
 
 use byteorder::ReadBytesExt;
 
+/// First palette index of the shirt colors in player skins.
+const TOP_RANGE: usize = 16;
+
+/// First palette index of the pants colors in player skins.
+const BOTTOM_RANGE: usize = 96;
+
+/// Builds a palette index translation that recolors a player skin.
+///
+/// The 16-color shirt and pants ranges of the skin are replaced with the
+/// palette rows selected by `colors`. The upper half of the palette has its
+/// ramps stored from bright to dark, so those rows are reversed.
+pub fn player_translation(colors: PlayerColor) -> [u8; 256] {
+    let mut translation = [0; 256];
+    for (i, index) in translation.iter_mut().enumerate() {
+        *index = i as u8;
+    }
+
+    for (range, row) in [(TOP_RANGE, colors.top()), (BOTTOM_RANGE, colors.bottom())] {
+        let row_start = row as usize * 16;
+        for i in 0..16 {
+            translation[range + i] = match row < 8 {
+                true => row_start + i,
+                false => row_start + 15 - i,
+            } as u8;
+        }
+    }
+
+    translation
+}
+
 pub struct Palette {
     rgb: [[u8; 3]; 256],
 }
@@ -80,3 +110,27 @@ impl Palette {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_player_translation() {
+        let translation = player_translation(PlayerColor::new(4, 13));
+
+        // colors outside the shirt and pants ranges are untouched
+        assert_eq!(translation[0], 0);
+        assert_eq!(translation[15], 15);
+        assert_eq!(translation[32], 32);
+        assert_eq!(translation[255], 255);
+
+        // shirt row 4 runs forward
+        assert_eq!(translation[16], 64);
+        assert_eq!(translation[31], 79);
+
+        // pants row 13 is reversed
+        assert_eq!(translation[96], 223);
+        assert_eq!(translation[111], 208);
+    }
+}
@@ -307,6 +307,36 @@ impl DeferredPassTarget {
     pub fn color_view(&self) -> &wgpu::TextureView {
         &self.color_view
     }
+
+    /// Returns a builder for the translucent pass.
+    ///
+    /// Translucent surfaces can't be stored in the G-buffer, so they're blended
+    /// directly over the lit output of the deferred pass. They're depth tested
+    /// against `depth_view`, the depth attachment of the initial pass, but do
+    /// not write to it.
+    pub fn translucent_pass_builder<'a>(
+        &'a self,
+        depth_view: &'a wgpu::TextureView,
+    ) -> RenderPassBuilder<'a> {
+        RenderPassBuilder {
+            color_attachments: vec![Some(wgpu::RenderPassColorAttachment {
+                view: self.color_view(),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: false,
+                }),
+                stencil_ops: None,
+            }),
+        }
+    }
 }
 
 impl RenderTarget for DeferredPassTarget {
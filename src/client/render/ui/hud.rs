@@ -2,12 +2,15 @@ use std::{collections::HashMap, iter::FromIterator};
 
 use crate::{
     client::{
+        netgraph::NetGraph,
         render::{
             ui::{
                 glyph::GlyphRendererCommand,
                 layout::{Anchor, Layout, ScreenPosition, Size},
+                netgraph::NetGraphRenderer,
                 quad::{QuadRendererCommand, QuadTexture},
             },
+            world::RenderStats,
             GraphicsState,
         },
         IntermissionKind,
@@ -41,6 +44,8 @@ pub enum HudState<'a> {
         stats: &'a [i32],
         face_anim_time: Duration,
         console: &'a Console,
+        net_graph: Option<&'a NetGraph>,
+        render_stats: Option<RenderStats>,
     },
     Intermission {
         kind: &'a IntermissionKind,
@@ -191,6 +196,7 @@ impl std::fmt::Display for FaceId {
 
 pub struct HudRenderer {
     textures: HashMap<HudTextureId, QuadTexture>,
+    net_graph_renderer: NetGraphRenderer,
 }
 
 impl HudRenderer {
@@ -263,7 +269,10 @@ impl HudRenderer {
             textures.insert(id, QuadTexture::from_qpic(state, &qpic));
         }
 
-        HudRenderer { textures }
+        HudRenderer {
+            textures,
+            net_graph_renderer: NetGraphRenderer::new(state),
+        }
     }
 
     fn cmd_number<'a>(
@@ -656,6 +665,8 @@ impl HudRenderer {
                 stats,
                 face_anim_time,
                 console,
+                net_graph,
+                render_stats,
             } => {
                 self.cmd_sbar(
                     time,
@@ -668,6 +679,31 @@ impl HudRenderer {
                     glyph_cmds,
                 );
 
+                if let Some(net_graph) = net_graph {
+                    self.net_graph_renderer
+                        .generate_commands(net_graph, scale, quad_cmds, glyph_cmds);
+                }
+
+                if let Some(stats) = render_stats {
+                    glyph_cmds.push(GlyphRendererCommand::Text {
+                        text: format!(
+                            "{} wpoly {} epoly {} ents | culled {} nodes {} ents",
+                            stats.world_faces,
+                            stats.entity_faces,
+                            stats.entities,
+                            stats.nodes_culled,
+                            stats.entities_culled,
+                        ),
+                        position: ScreenPosition::Relative {
+                            anchor: Anchor::TOP_RIGHT,
+                            x_ofs: 0,
+                            y_ofs: 0,
+                        },
+                        anchor: Anchor::TOP_RIGHT,
+                        scale,
+                    });
+                }
+
                 let output = console.output();
                 for (id, line) in output.recent_lines(console_timeout, 100, 10).enumerate() {
                     for (chr_id, chr) in line.into_iter().enumerate() {
@@ -3,6 +3,7 @@ pub mod glyph;
 pub mod hud;
 pub mod layout;
 pub mod menu;
+pub mod netgraph;
 pub mod quad;
 
 use std::cell::RefCell;
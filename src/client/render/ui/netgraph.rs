@@ -0,0 +1,93 @@
+use crate::client::{
+    netgraph::{NetGraph, NetGraphSample},
+    render::{
+        ui::{
+            glyph::GlyphRendererCommand,
+            layout::{Anchor, Layout, ScreenPosition, Size},
+            quad::{QuadRendererCommand, QuadTexture},
+        },
+        GraphicsState,
+    },
+};
+
+// place the graph just above the status and inventory bars
+const GRAPH_X_OFS: i32 = 0;
+const GRAPH_Y_OFS: i32 = 48;
+
+// maximum height of a bar in virtual pixels
+const GRAPH_HEIGHT: u32 = 32;
+
+// incoming bytes represented by one virtual pixel of bar height
+const BYTES_PER_PIXEL: usize = 16;
+
+const COLOR_TRAFFIC: [u8; 4] = [0, 255, 0, 255];
+const COLOR_DROPPED: [u8; 4] = [255, 0, 0, 255];
+
+pub struct NetGraphRenderer {
+    traffic: QuadTexture,
+    dropped: QuadTexture,
+}
+
+impl NetGraphRenderer {
+    pub fn new(state: &GraphicsState) -> NetGraphRenderer {
+        NetGraphRenderer {
+            traffic: QuadTexture::from_color(state, COLOR_TRAFFIC),
+            dropped: QuadTexture::from_color(state, COLOR_DROPPED),
+        }
+    }
+
+    // Select the bar color and height (in virtual pixels) for a frame.
+    fn bar(&self, sample: &NetGraphSample) -> (&QuadTexture, u32) {
+        if sample.dropped_packets > 0 {
+            (&self.dropped, GRAPH_HEIGHT)
+        } else {
+            let height = (sample.bytes_received / BYTES_PER_PIXEL) as u32;
+            (&self.traffic, height.min(GRAPH_HEIGHT))
+        }
+    }
+
+    /// Generate render commands to draw the netgraph.
+    pub fn generate_commands<'a>(
+        &'a self,
+        net_graph: &NetGraph,
+        scale: f32,
+        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+        glyph_cmds: &mut Vec<GlyphRendererCommand>,
+    ) {
+        for (x, sample) in net_graph.samples().enumerate() {
+            let (texture, height) = self.bar(sample);
+            if height == 0 {
+                continue;
+            }
+
+            quad_cmds.push(QuadRendererCommand {
+                texture,
+                layout: Layout {
+                    position: ScreenPosition::Relative {
+                        anchor: Anchor::BOTTOM_LEFT,
+                        x_ofs: GRAPH_X_OFS + x as i32,
+                        y_ofs: GRAPH_Y_OFS,
+                    },
+                    anchor: Anchor::BOTTOM_LEFT,
+                    size: Size::Absolute {
+                        width: (scale as u32).max(1),
+                        height: (height as f32 * scale) as u32,
+                    },
+                },
+            });
+        }
+
+        let (rate_in, rate_out) = net_graph.rates();
+
+        glyph_cmds.push(GlyphRendererCommand::Text {
+            text: format!("in {}B/s out {}B/s", rate_in as u32, rate_out as u32),
+            position: ScreenPosition::Relative {
+                anchor: Anchor::BOTTOM_LEFT,
+                x_ofs: GRAPH_X_OFS,
+                y_ofs: GRAPH_Y_OFS + GRAPH_HEIGHT as i32,
+            },
+            anchor: Anchor::BOTTOM_LEFT,
+            scale,
+        });
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    borrow::Cow,
     cell::{Ref, RefCell, RefMut},
     mem::size_of,
     num::NonZeroU64,
@@ -11,7 +12,7 @@ use crate::{
             screen_space_vertex_transform,
         },
         uniform::{self, DynamicUniformBuffer, DynamicUniformBufferBlock},
-        Extent2d, GraphicsState, Pipeline, TextureData, DIFFUSE_ATTACHMENT_FORMAT,
+        DiffuseData, Extent2d, GraphicsState, Pipeline, TextureData, DIFFUSE_ATTACHMENT_FORMAT,
     },
     common::{util::any_slice_as_bytes, wad::QPic},
 };
@@ -279,12 +280,32 @@ pub struct QuadTexture {
 impl QuadTexture {
     pub fn from_qpic(state: &GraphicsState, qpic: &QPic) -> QuadTexture {
         let (diffuse_data, _) = state.palette().translate(qpic.indices());
-        let texture = state.create_texture(
-            None,
-            qpic.width(),
-            qpic.height(),
-            &TextureData::Diffuse(diffuse_data),
-        );
+        QuadTexture::from_diffuse(state, qpic.width(), qpic.height(), diffuse_data)
+    }
+
+    /// Create a 1x1 texture filled with the given RGBA color.
+    ///
+    /// Since quads stretch their textures, this can be used to draw solid
+    /// rectangles of any size.
+    pub fn from_color(state: &GraphicsState, rgba: [u8; 4]) -> QuadTexture {
+        QuadTexture::from_diffuse(
+            state,
+            1,
+            1,
+            DiffuseData {
+                rgba: Cow::Owned(rgba.to_vec()),
+            },
+        )
+    }
+
+    fn from_diffuse(
+        state: &GraphicsState,
+        width: u32,
+        height: u32,
+        diffuse_data: DiffuseData,
+    ) -> QuadTexture {
+        let texture =
+            state.create_texture(None, width, height, &TextureData::Diffuse(diffuse_data));
         let texture_view = texture.create_view(&Default::default());
         let bind_group = state
             .device()
@@ -301,8 +322,8 @@ impl QuadTexture {
             texture,
             texture_view,
             bind_group,
-            width: qpic.width(),
-            height: qpic.height(),
+            width,
+            height,
         }
     }
 
@@ -1,17 +1,25 @@
-use std::{mem::size_of, ops::Range};
+use std::{collections::HashMap, mem::size_of, ops::Range};
 
 use crate::{
     client::render::{
-        world::{BindGroupLayoutId, WorldPipelineBase},
+        palette,
+        pipeline::PushConstantUpdate,
+        world::{
+            translucent::{AliasFragmentPushConstants, TranslucentAliasPipeline},
+            BindGroupLayoutId, WorldPipelineBase,
+        },
         GraphicsState, Pipeline, TextureData,
     },
     common::{
         mdl::{self, AliasModel},
+        model::ModelFlags,
+        net::PlayerColor,
         util::any_slice_as_bytes,
     },
 };
 
-use cgmath::{InnerSpace as _, Matrix4, Vector3, Zero as _};
+use bumpalo::Bump;
+use cgmath::{Angle as _, Deg, InnerSpace as _, Matrix4, Vector3, Zero as _};
 use chrono::Duration;
 use failure::Error;
 
@@ -64,6 +72,66 @@ impl AliasPipeline {
 pub struct VertexPushConstants {
     pub transform: Matrix4<f32>,
     pub model_view: Matrix4<f32>,
+    /// Direction towards the shading light in model space.
+    pub shade_vector: Vector3<f32>,
+    /// Blend factor between the two poses bound to the pipeline.
+    pub lerp: f32,
+    pub ambient_light: f32,
+    pub shade_light: f32,
+}
+
+/// Lighting beyond which ambient light is clamped.
+const MAX_AMBIENT_LIGHT: f32 = 128.0;
+
+/// Combined ambient and shade light beyond which shade light is clamped.
+const MAX_TOTAL_LIGHT: f32 = 192.0;
+
+/// Lighting for fullbright models, matching fullbright texels on brush models.
+const FULLBRIGHT_LIGHT: f32 = 256.0;
+
+/// Static lighting for an alias model, in the same units as `BspData::light_point`.
+///
+/// Every face receives the ambient light, and faces turned towards the shade
+/// vector receive up to the shade light on top of it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AliasLighting {
+    pub ambient: f32,
+    pub shade: f32,
+    pub shade_vector: Vector3<f32>,
+}
+
+impl AliasLighting {
+    /// Lights a model standing in `light` and facing `yaw`.
+    ///
+    /// As in the original engine, the light is clamped so that models don't
+    /// overbright as much as the world, and then raised to at least `min_light`.
+    pub fn new(light: f32, yaw: Deg<f32>, min_light: f32) -> AliasLighting {
+        let mut ambient = light.min(MAX_AMBIENT_LIGHT);
+        let mut shade = light.min(MAX_TOTAL_LIGHT - ambient);
+        if ambient < min_light {
+            ambient = min_light;
+            shade = min_light;
+        }
+
+        // the light shines down from above the world's +x axis, so turn it
+        // against the model's yaw to bring it into model space
+        let shade_vector = Vector3::new((-yaw).cos(), (-yaw).sin(), 1.0).normalize();
+
+        AliasLighting {
+            ambient,
+            shade,
+            shade_vector,
+        }
+    }
+
+    /// Lights a model evenly at full brightness.
+    pub fn fullbright() -> AliasLighting {
+        AliasLighting {
+            ambient: FULLBRIGHT_LIGHT,
+            shade: 0.0,
+            shade_vector: Vector3::unit_z(),
+        }
+    }
 }
 
 lazy_static! {
@@ -71,13 +139,21 @@ lazy_static! {
         wgpu::vertex_attr_array![
             // frame 0 position
             0 => Float32x3,
-            // frame 1 position
-            // 1 => Float32x3,
-            // normal
+            // frame 0 normal
             2 => Float32x3,
             // texcoord
             3 => Float32x2,
         ];
+
+    // texcoords are shared between frames, so only position and normal are
+    // read from the second pose
+    static ref LERP_VERTEX_ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![
+            // frame 1 position
+            1 => Float32x3,
+            // frame 1 normal
+            4 => Float32x3,
+        ];
 }
 
 impl Pipeline for AliasPipeline {
@@ -133,11 +209,19 @@ impl Pipeline for AliasPipeline {
 
     // NOTE: if the vertex format is changed, this descriptor must also be changed accordingly.
     fn vertex_buffer_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
-        vec![wgpu::VertexBufferLayout {
-            array_stride: size_of::<AliasVertex>() as u64,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &VERTEX_ATTRIBUTES[..],
-        }]
+        // the same vertex buffer is bound twice, once for each pose being blended
+        vec![
+            wgpu::VertexBufferLayout {
+                array_stride: size_of::<AliasVertex>() as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &VERTEX_ATTRIBUTES[..],
+            },
+            wgpu::VertexBufferLayout {
+                array_stride: size_of::<AliasVertex>() as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &LERP_VERTEX_ATTRIBUTES[..],
+            },
+        ]
     }
 }
 
@@ -187,6 +271,55 @@ impl Keyframe {
             }
         }
     }
+
+    /// Returns the vertex ranges of the two frames surrounding `time` and the
+    /// blend factor between them.
+    ///
+    /// Static keyframes have only one pose, which is returned twice.
+    fn interpolate(&self, time: Duration) -> (Range<u32>, Range<u32>, f32) {
+        match self {
+            Keyframe::Static { vertex_range } => (vertex_range.clone(), vertex_range.clone(), 0.0),
+            Keyframe::Animated {
+                vertex_ranges,
+                total_duration,
+                durations,
+            } => {
+                let mut time_ms = time.num_milliseconds() % total_duration.num_milliseconds();
+
+                for (frame_id, frame_duration) in durations.iter().enumerate() {
+                    let duration_ms = frame_duration.num_milliseconds();
+                    if time_ms < duration_ms {
+                        let next_id = (frame_id + 1) % vertex_ranges.len();
+                        return (
+                            vertex_ranges[frame_id].clone(),
+                            vertex_ranges[next_id].clone(),
+                            time_ms as f32 / duration_ms as f32,
+                        );
+                    }
+                    time_ms -= duration_ms;
+                }
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+/// The keyframe pose in which an alias model is drawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AliasPose {
+    /// Draw a single keyframe without interpolation.
+    Snap(usize),
+
+    /// Blend from one keyframe to another.
+    ///
+    /// Frames within animated keyframes are also blended with their successors.
+    Lerp {
+        prev_keyframe_id: usize,
+        keyframe_id: usize,
+        /// How far the blend has progressed, in the range `[0, 1]`.
+        factor: f32,
+    },
 }
 
 enum Texture {
@@ -205,6 +338,78 @@ enum Texture {
 }
 
 impl Texture {
+    /// Uploads a skin, optionally remapping its palette indices first.
+    fn new(
+        state: &GraphicsState,
+        width: u32,
+        height: u32,
+        texture: &mdl::Texture,
+        translation: Option<&[u8; 256]>,
+    ) -> Texture {
+        let create_frame = |indices: &[u8]| {
+            let (diffuse_data, _fullbright_data) = match translation {
+                Some(t) => {
+                    let translated: Vec<u8> = indices.iter().map(|i| t[*i as usize]).collect();
+                    state.palette.translate(&translated)
+                }
+                None => state.palette.translate(indices),
+            };
+            let diffuse_texture =
+                state.create_texture(None, width, height, &TextureData::Diffuse(diffuse_data));
+            let diffuse_view = diffuse_texture.create_view(&Default::default());
+            let bind_group = state
+                .device()
+                .create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: None,
+                    // TODO: per-pipeline bind group layout ids
+                    layout: &state.alias_pipeline().bind_group_layouts()
+                        [BindGroupLayoutId::PerTexture as usize - 2],
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&diffuse_view),
+                    }],
+                });
+
+            (diffuse_texture, diffuse_view, bind_group)
+        };
+
+        match *texture {
+            mdl::Texture::Static(ref tex) => {
+                let (diffuse_texture, diffuse_view, bind_group) = create_frame(tex.indices());
+                Texture::Static {
+                    diffuse_texture,
+                    diffuse_view,
+                    bind_group,
+                }
+            }
+            mdl::Texture::Animated(ref tex) => {
+                let mut total_duration = Duration::zero();
+                let mut durations = Vec::new();
+                let mut diffuse_textures = Vec::new();
+                let mut diffuse_views = Vec::new();
+                let mut bind_groups = Vec::new();
+
+                for frame in tex.frames() {
+                    total_duration = total_duration + frame.duration();
+                    durations.push(frame.duration());
+
+                    let (diffuse_texture, diffuse_view, bind_group) = create_frame(frame.indices());
+                    diffuse_textures.push(diffuse_texture);
+                    diffuse_views.push(diffuse_view);
+                    bind_groups.push(bind_group);
+                }
+
+                Texture::Animated {
+                    diffuse_textures,
+                    diffuse_views,
+                    bind_groups,
+                    total_duration,
+                    durations,
+                }
+            }
+        }
+    }
+
     fn animate(&self, time: Duration) -> &wgpu::BindGroup {
         match self {
             Texture::Static { ref bind_group, .. } => bind_group,
@@ -231,13 +436,25 @@ impl Texture {
 }
 
 pub struct AliasRenderer {
+    flags: ModelFlags,
+    radius: f32,
     keyframes: Vec<Keyframe>,
     textures: Vec<Texture>,
     vertex_buffer: wgpu::Buffer,
+
+    // indexed skins are kept so they can be recolored for players
+    skin_width: u32,
+    skin_height: u32,
+    skins: Vec<mdl::Texture>,
+    translated_textures: HashMap<(usize, PlayerColor), Texture>,
 }
 
 impl AliasRenderer {
-    pub fn new(state: &GraphicsState, alias_model: &AliasModel) -> Result<AliasRenderer, Error> {
+    pub fn new(
+        state: &GraphicsState,
+        alias_model: &AliasModel,
+        flags: ModelFlags,
+    ) -> Result<AliasRenderer, Error> {
         let mut vertices = Vec::new();
         let mut keyframes = Vec::new();
 
@@ -337,100 +554,229 @@ impl AliasRenderer {
                 usage: wgpu::BufferUsages::VERTEX,
             });
 
-        let mut textures = Vec::new();
-        for texture in alias_model.textures() {
-            match *texture {
-                mdl::Texture::Static(ref tex) => {
-                    let (diffuse_data, _fullbright_data) = state.palette.translate(tex.indices());
-                    let diffuse_texture =
-                        state.create_texture(None, w, h, &TextureData::Diffuse(diffuse_data));
-                    let diffuse_view = diffuse_texture.create_view(&Default::default());
-                    let bind_group = state
-                        .device()
-                        .create_bind_group(&wgpu::BindGroupDescriptor {
-                            label: None,
-                            // TODO: per-pipeline bind group layout ids
-                            layout: &state.alias_pipeline().bind_group_layouts()
-                                [BindGroupLayoutId::PerTexture as usize - 2],
-                            entries: &[wgpu::BindGroupEntry {
-                                binding: 0,
-                                resource: wgpu::BindingResource::TextureView(&diffuse_view),
-                            }],
-                        });
-                    textures.push(Texture::Static {
-                        diffuse_texture,
-                        diffuse_view,
-                        bind_group,
-                    });
-                }
-                mdl::Texture::Animated(ref tex) => {
-                    let mut total_duration = Duration::zero();
-                    let mut durations = Vec::new();
-                    let mut diffuse_textures = Vec::new();
-                    let mut diffuse_views = Vec::new();
-                    let mut bind_groups = Vec::new();
-
-                    for frame in tex.frames() {
-                        total_duration = total_duration + frame.duration();
-                        durations.push(frame.duration());
-
-                        let (diffuse_data, _fullbright_data) =
-                            state.palette.translate(frame.indices());
-                        let diffuse_texture =
-                            state.create_texture(None, w, h, &TextureData::Diffuse(diffuse_data));
-                        let diffuse_view = diffuse_texture.create_view(&Default::default());
-                        let bind_group =
-                            state
-                                .device()
-                                .create_bind_group(&wgpu::BindGroupDescriptor {
-                                    label: None,
-                                    layout: &state.alias_pipeline().bind_group_layouts()
-                                        [BindGroupLayoutId::PerTexture as usize - 2],
-                                    entries: &[wgpu::BindGroupEntry {
-                                        binding: 0,
-                                        resource: wgpu::BindingResource::TextureView(&diffuse_view),
-                                    }],
-                                });
-
-                        diffuse_textures.push(diffuse_texture);
-                        diffuse_views.push(diffuse_view);
-                        bind_groups.push(bind_group);
-                    }
-
-                    textures.push(Texture::Animated {
-                        diffuse_textures,
-                        diffuse_views,
-                        bind_groups,
-                        total_duration,
-                        durations,
-                    });
-                }
-            }
-        }
+        let textures = alias_model
+            .textures()
+            .iter()
+            .map(|texture| Texture::new(state, w, h, texture, None))
+            .collect();
 
         Ok(AliasRenderer {
+            flags,
+            radius: alias_model.radius(),
             keyframes,
             textures,
             vertex_buffer,
+            skin_width: w,
+            skin_height: h,
+            skins: alias_model.textures().to_vec(),
+            translated_textures: HashMap::new(),
         })
     }
 
+    /// Uploads a copy of skin `skin_id` with its shirt and pants recolored to
+    /// `colors`, unless one already exists.
+    ///
+    /// The copy is used by `record_draw` when it's given the same colors.
+    pub fn translate_skin(&mut self, state: &GraphicsState, skin_id: usize, colors: PlayerColor) {
+        if skin_id >= self.skins.len() || self.translated_textures.contains_key(&(skin_id, colors))
+        {
+            return;
+        }
+
+        let texture = Texture::new(
+            state,
+            self.skin_width,
+            self.skin_height,
+            &self.skins[skin_id],
+            Some(&palette::player_translation(colors)),
+        );
+        self.translated_textures.insert((skin_id, colors), texture);
+    }
+
+    /// Returns the flags of the model this renderer was created from.
+    pub fn flags(&self) -> ModelFlags {
+        self.flags
+    }
+
+    /// Returns the bounding radius of the model around its origin.
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    /// Returns the vertex ranges to blend between and the blend factor for
+    /// `pose` at `time`.
+    fn pose_vertices(&self, time: Duration, pose: AliasPose) -> (Range<u32>, Range<u32>, f32) {
+        match pose {
+            AliasPose::Snap(keyframe_id) => {
+                let range = self.keyframes[keyframe_id].animate(time);
+                (range.clone(), range, 0.0)
+            }
+
+            // the entity is holding a keyframe, so blend within its frame group
+            AliasPose::Lerp {
+                prev_keyframe_id,
+                keyframe_id,
+                factor,
+            } if prev_keyframe_id == keyframe_id || factor >= 1.0 => {
+                self.keyframes[keyframe_id].interpolate(time)
+            }
+
+            AliasPose::Lerp {
+                prev_keyframe_id,
+                keyframe_id,
+                factor,
+            } => (
+                self.keyframes[prev_keyframe_id].animate(time),
+                self.keyframes[keyframe_id].animate(time),
+                factor,
+            ),
+        }
+    }
+
     pub fn record_draw<'a>(
         &'a self,
         state: &'a GraphicsState,
         pass: &mut wgpu::RenderPass<'a>,
+        bump: &'a Bump,
         time: Duration,
-        keyframe_id: usize,
+        transform: Matrix4<f32>,
+        model_view: Matrix4<f32>,
+        pose: AliasPose,
         texture_id: usize,
+        colors: Option<PlayerColor>,
+        lighting: AliasLighting,
     ) {
+        use PushConstantUpdate::*;
+
         pass.set_pipeline(state.alias_pipeline().pipeline());
-        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        let (vertex_count, lerp) = self.bind_pose(pass, time, pose, texture_id, colors);
+        AliasPipeline::set_push_constants(
+            pass,
+            Update(bump.alloc(VertexPushConstants {
+                transform,
+                model_view,
+                shade_vector: lighting.shade_vector,
+                lerp,
+                ambient_light: lighting.ambient,
+                shade_light: lighting.shade,
+            })),
+            Clear,
+            Clear,
+        );
+        pass.draw(0..vertex_count, 0..1)
+    }
+
+    /// Draws the model blended over the lit scene with the given `alpha`.
+    ///
+    /// `fog` holds the fog color and density as passed to the deferred pass.
+    pub fn record_translucent_draw<'a>(
+        &'a self,
+        state: &'a GraphicsState,
+        pass: &mut wgpu::RenderPass<'a>,
+        bump: &'a Bump,
+        time: Duration,
+        transform: Matrix4<f32>,
+        model_view: Matrix4<f32>,
+        pose: AliasPose,
+        texture_id: usize,
+        colors: Option<PlayerColor>,
+        lighting: AliasLighting,
+        alpha: f32,
+        fog: [f32; 4],
+    ) {
+        use PushConstantUpdate::*;
+
+        pass.set_pipeline(state.translucent_alias_pipeline().pipeline());
+        let (vertex_count, lerp) = self.bind_pose(pass, time, pose, texture_id, colors);
+        TranslucentAliasPipeline::set_push_constants(
+            pass,
+            Update(bump.alloc(VertexPushConstants {
+                transform,
+                model_view,
+                shade_vector: lighting.shade_vector,
+                lerp,
+                ambient_light: lighting.ambient,
+                shade_light: lighting.shade,
+            })),
+            Clear,
+            Update(bump.alloc(AliasFragmentPushConstants {
+                alpha,
+                _pad: [0.0],
+                fog,
+            })),
+        );
+        pass.draw(0..vertex_count, 0..1)
+    }
 
+    /// Binds the vertices and skin for `pose`, returning the number of
+    /// vertices to draw and the blend factor between the two poses.
+    fn bind_pose<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        time: Duration,
+        pose: AliasPose,
+        texture_id: usize,
+        colors: Option<PlayerColor>,
+    ) -> (u32, f32) {
+        let (from, to, lerp) = self.pose_vertices(time, pose);
+        let stride = size_of::<AliasVertex>() as u64;
+
+        pass.set_vertex_buffer(
+            0,
+            self.vertex_buffer
+                .slice(from.start as u64 * stride..from.end as u64 * stride),
+        );
+        pass.set_vertex_buffer(
+            1,
+            self.vertex_buffer
+                .slice(to.start as u64 * stride..to.end as u64 * stride),
+        );
+
+        // fall back to the original skin until a recolored one is uploaded
+        let texture = colors
+            .and_then(|c| self.translated_textures.get(&(texture_id, c)))
+            .unwrap_or(&self.textures[texture_id]);
         pass.set_bind_group(
             BindGroupLayoutId::PerTexture as u32,
-            self.textures[texture_id].animate(time),
+            texture.animate(time),
             &[],
         );
-        pass.draw(self.keyframes[keyframe_id].animate(time), 0..1)
+
+        (from.end - from.start, lerp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alias_lighting_clamp() {
+        // dim light is used as-is for both ambient and shade light
+        let lighting = AliasLighting::new(50.0, Deg(0.0), 0.0);
+        assert_eq!(lighting.ambient, 50.0);
+        assert_eq!(lighting.shade, 50.0);
+
+        // bright light is clamped so models don't overbright
+        let lighting = AliasLighting::new(255.0, Deg(0.0), 0.0);
+        assert_eq!(lighting.ambient, MAX_AMBIENT_LIGHT);
+        assert_eq!(lighting.ambient + lighting.shade, MAX_TOTAL_LIGHT);
+
+        // models in the dark are raised to the minimum
+        let lighting = AliasLighting::new(2.0, Deg(0.0), 8.0);
+        assert_eq!(lighting.ambient, 8.0);
+        assert_eq!(lighting.shade, 8.0);
+    }
+
+    #[test]
+    fn test_alias_lighting_shade_vector() {
+        let lighting = AliasLighting::new(100.0, Deg(0.0), 0.0);
+        let expected = Vector3::new(1.0, 0.0, 1.0).normalize();
+        assert!((lighting.shade_vector - expected).magnitude() < 1e-6);
+
+        // turning the model left swings the light around to its right
+        let lighting = AliasLighting::new(100.0, Deg(90.0), 0.0);
+        let expected = Vector3::new(0.0, -1.0, 1.0).normalize();
+        assert!((lighting.shade_vector - expected).magnitude() < 1e-6);
     }
 }
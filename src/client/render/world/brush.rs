@@ -32,14 +32,19 @@ use crate::{
     client::render::{
         pipeline::PushConstantUpdate,
         warp,
-        world::{BindGroupLayoutId, WorldPipelineBase},
-        Camera, GraphicsState, LightmapData, Pipeline, TextureData,
+        world::{
+            skybox::{self, Skybox, SkyboxPipeline},
+            translucent::{self, TranslucentBrushPipeline},
+            BindGroupLayoutId, RenderStats, WorldPipelineBase,
+        },
+        Camera, DiffuseData, GraphicsState, LightmapData, Pipeline, TextureData,
     },
     common::{
         bsp::{
-            self, BspData, BspFace, BspLeaf, BspModel, BspTexInfo, BspTexture, BspTextureKind,
-            BspTextureMipmap,
+            self, BspData, BspFace, BspLeaf, BspModel, BspRenderNodeChild, BspTexInfo, BspTexture,
+            BspTextureFrame, BspTextureKind, BspTextureMipmap,
         },
+        console::CvarRegistry,
         math,
         util::any_slice_as_bytes,
     },
@@ -260,14 +265,114 @@ struct BrushVertex {
 }
 
 #[repr(u32)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum TextureKind {
     Normal = 0,
     Warp = 1,
     Sky = 2,
 }
 
+/// The kinds of liquid, each of which has its own opacity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LiquidKind {
+    Water,
+    Lava,
+    Slime,
+    Tele,
+}
+
+impl LiquidKind {
+    /// Classifies a texture by name, returning `None` if it isn't a liquid.
+    fn from_texture_name(name: &str) -> Option<LiquidKind> {
+        if !name.starts_with('*') {
+            return None;
+        }
+
+        Some(if name.starts_with("*lava") {
+            LiquidKind::Lava
+        } else if name.starts_with("*slime") {
+            LiquidKind::Slime
+        } else if name.starts_with("*tele") {
+            LiquidKind::Tele
+        } else {
+            LiquidKind::Water
+        })
+    }
+}
+
+/// The opacity of each kind of liquid.
+///
+/// Liquids with an opacity below 1 are drawn in the translucent pass.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LiquidAlpha {
+    pub water: f32,
+    pub lava: f32,
+    pub slime: f32,
+    pub tele: f32,
+}
+
+impl LiquidAlpha {
+    /// Reads liquid opacities from `r_wateralpha`, `r_lavaalpha`,
+    /// `r_slimealpha` and `r_telealpha`.
+    pub fn from_cvars(cvars: &CvarRegistry) -> LiquidAlpha {
+        let get = |name| cvars.get_value(name).unwrap().clamp(0.0, 1.0);
+
+        LiquidAlpha {
+            water: get("r_wateralpha"),
+            lava: get("r_lavaalpha"),
+            slime: get("r_slimealpha"),
+            tele: get("r_telealpha"),
+        }
+    }
+
+    pub fn get(&self, kind: LiquidKind) -> f32 {
+        match kind {
+            LiquidKind::Water => self.water,
+            LiquidKind::Lava => self.lava,
+            LiquidKind::Slime => self.slime,
+            LiquidKind::Tele => self.tele,
+        }
+    }
+}
+
+/// A face to be drawn in the translucent pass.
+#[derive(Clone, Copy, Debug)]
+pub struct TranslucentFace {
+    face_id: usize,
+    alpha: f32,
+
+    /// Distance from the camera to the center of the face.
+    pub distance: f32,
+}
+
+/// Converts the integer bounds of a BSP node or leaf to a vector.
+fn bounds_to_vec(bounds: [i16; 3]) -> Vector3<f32> {
+    Vector3::new(bounds[0] as f32, bounds[1] as f32, bounds[2] as f32)
+}
+
+/// Splits a sky texture into its back and front layers.
+///
+/// Sky textures are twice as wide as they are tall. The right half holds the
+/// solid back layer and the left half holds the front layer, which is
+/// transparent wherever it uses palette index 0. Returns the palette indices of
+/// the back and front layers, in that order.
+fn split_sky_texture(indices: &[u8], width: u32, height: u32) -> (Vec<u8>, Vec<u8>) {
+    let half_width = (width / 2) as usize;
+    let mut back = Vec::with_capacity(half_width * height as usize);
+    let mut front = Vec::with_capacity(half_width * height as usize);
+
+    for row in indices.chunks_exact(width as usize) {
+        front.extend_from_slice(&row[..half_width]);
+        back.extend_from_slice(&row[half_width..]);
+    }
+
+    (back, front)
+}
+
 /// A single frame of a brush texture.
+///
+/// Sky textures store their back layer in `diffuse` and their alpha-masked
+/// front layer in `fullbright`, since sky has no fullbright pixels of its own.
 pub struct BrushTextureFrame {
     bind_group_id: usize,
     diffuse: wgpu::Texture,
@@ -315,8 +420,8 @@ struct BrushFace {
     /// Indicates whether the face should be drawn this frame.
     ///
     /// This is set to false by default, and will be set to true if the model is
-    /// a worldmodel and the containing leaf is in the PVS. If the model is not
-    /// a worldmodel, this flag is ignored.
+    /// a worldmodel, the containing leaf is in the PVS and the face intersects
+    /// the view frustum. If the model is not a worldmodel, this flag is ignored.
     draw_flag: Cell<bool>,
 }
 
@@ -339,6 +444,8 @@ where
 pub struct BrushRendererBuilder {
     bsp_data: Rc<BspData>,
     face_range: Range<usize>,
+    min: Vector3<f32>,
+    max: Vector3<f32>,
 
     leaves: Option<Vec<BrushLeaf>>,
 
@@ -358,6 +465,8 @@ impl BrushRendererBuilder {
         BrushRendererBuilder {
             bsp_data: bsp_model.bsp_data().clone(),
             face_range: bsp_model.face_id..bsp_model.face_id + bsp_model.face_count,
+            min: bsp_model.min(),
+            max: bsp_model.max(),
             leaves: if worldmodel {
                 Some(
                     bsp_model
@@ -542,7 +651,7 @@ impl BrushRendererBuilder {
     fn create_brush_texture_frame<S>(
         &self,
         state: &GraphicsState,
-        mipmap: &[u8],
+        bsp_frame: &BspTextureFrame,
         width: u32,
         height: u32,
         name: S,
@@ -552,19 +661,6 @@ impl BrushRendererBuilder {
     {
         let name = name.as_ref();
 
-        let (diffuse_data, fullbright_data) = state.palette().translate(mipmap);
-        let diffuse =
-            state.create_texture(None, width, height, &TextureData::Diffuse(diffuse_data));
-        let fullbright = state.create_texture(
-            None,
-            width,
-            height,
-            &TextureData::Fullbright(fullbright_data),
-        );
-
-        let diffuse_view = diffuse.create_view(&Default::default());
-        let fullbright_view = fullbright.create_view(&Default::default());
-
         let kind = if name.starts_with("sky") {
             TextureKind::Sky
         } else if name.starts_with("*") {
@@ -573,6 +669,60 @@ impl BrushRendererBuilder {
             TextureKind::Normal
         };
 
+        let (diffuse, fullbright) = match kind {
+            TextureKind::Sky => {
+                // the sky is projected rather than mapped, so it isn't mipmapped
+                let mipmap = bsp_frame.mipmap(BspTextureMipmap::Full);
+                let (back, front) = split_sky_texture(mipmap, width, height);
+                let (back_data, _) = state.palette().translate(&back);
+                let (front_data, _) = state.palette().translate(&front);
+
+                // index 0 is see-through in the front layer
+                let mut front_rgba = front_data.rgba.into_owned();
+                for (pixel, index) in front_rgba.chunks_exact_mut(4).zip(front.iter()) {
+                    if *index == 0 {
+                        pixel[3] = 0;
+                    }
+                }
+
+                let layer_width = width / 2;
+                (
+                    state.create_texture(
+                        None,
+                        layer_width,
+                        height,
+                        &TextureData::Diffuse(back_data),
+                    ),
+                    state.create_texture(
+                        None,
+                        layer_width,
+                        height,
+                        &TextureData::Diffuse(DiffuseData {
+                            rgba: Cow::Owned(front_rgba),
+                        }),
+                    ),
+                )
+            }
+
+            _ => {
+                let mut diffuse_mipmaps = Vec::with_capacity(bsp::MIPLEVELS);
+                let mut fullbright_mipmaps = Vec::with_capacity(bsp::MIPLEVELS);
+                for mipmap in bsp_frame.iter_mipmaps() {
+                    let (diffuse_data, fullbright_data) = state.palette().translate(mipmap);
+                    diffuse_mipmaps.push(TextureData::Diffuse(diffuse_data));
+                    fullbright_mipmaps.push(TextureData::Fullbright(fullbright_data));
+                }
+
+                (
+                    state.create_mipmapped_texture(None, width, height, &diffuse_mipmaps),
+                    state.create_mipmapped_texture(None, width, height, &fullbright_mipmaps),
+                )
+            }
+        };
+
+        let diffuse_view = diffuse.create_view(&Default::default());
+        let fullbright_view = fullbright.create_view(&Default::default());
+
         let mut frame = BrushTextureFrame {
             bind_group_id: 0,
             diffuse,
@@ -594,7 +744,6 @@ impl BrushRendererBuilder {
     }
 
     pub fn create_brush_texture(&self, state: &GraphicsState, tex: &BspTexture) -> BrushTexture {
-        // TODO: upload mipmaps
         let (width, height) = tex.dimensions();
 
         match tex.kind() {
@@ -602,27 +751,13 @@ impl BrushRendererBuilder {
             BspTextureKind::Animated { primary, alternate } => {
                 let primary_frames: Vec<_> = primary
                     .iter()
-                    .map(|f| {
-                        self.create_brush_texture_frame(
-                            state,
-                            f.mipmap(BspTextureMipmap::Full),
-                            width,
-                            height,
-                            tex.name(),
-                        )
-                    })
+                    .map(|f| self.create_brush_texture_frame(state, f, width, height, tex.name()))
                     .collect();
 
                 let alternate_frames: Option<Vec<_>> = alternate.as_ref().map(|a| {
                     a.iter()
                         .map(|f| {
-                            self.create_brush_texture_frame(
-                                state,
-                                f.mipmap(BspTextureMipmap::Full),
-                                width,
-                                height,
-                                tex.name(),
-                            )
+                            self.create_brush_texture_frame(state, f, width, height, tex.name())
                         })
                         .collect()
                 });
@@ -636,7 +771,7 @@ impl BrushRendererBuilder {
             BspTextureKind::Static(bsp_tex) => {
                 BrushTexture::Static(self.create_brush_texture_frame(
                     state,
-                    bsp_tex.mipmap(BspTextureMipmap::Full),
+                    bsp_tex,
                     tex.width(),
                     tex.height(),
                     tex.name(),
@@ -680,8 +815,18 @@ impl BrushRendererBuilder {
                 usage: wgpu::BufferUsages::VERTEX,
             });
 
+        let liquids = self
+            .bsp_data
+            .textures()
+            .iter()
+            .map(|tex| LiquidKind::from_texture_name(tex.name()))
+            .collect();
+
         Ok(BrushRenderer {
+            liquids,
             bsp_data: self.bsp_data,
+            min: self.min,
+            max: self.max,
             vertex_buffer,
             leaves: self.leaves,
             per_texture_bind_groups: self.per_texture_bind_groups.into_inner(),
@@ -697,6 +842,8 @@ impl BrushRendererBuilder {
 
 pub struct BrushRenderer {
     bsp_data: Rc<BspData>,
+    min: Vector3<f32>,
+    max: Vector3<f32>,
 
     leaves: Option<Vec<BrushLeaf>>,
 
@@ -709,12 +856,106 @@ pub struct BrushRenderer {
     texture_chains: HashMap<usize, Vec<usize>>,
     faces: Vec<BrushFace>,
     textures: Vec<BrushTexture>,
+    // the liquid kind of each texture, if any, indexed like textures
+    liquids: Vec<Option<LiquidKind>>,
     lightmaps: Vec<wgpu::Texture>,
     //lightmap_views: Vec<wgpu::TextureView>,
 }
 
 impl BrushRenderer {
+    /// Returns the minimum extent of this brush model.
+    pub fn min(&self) -> Vector3<f32> {
+        self.min
+    }
+
+    /// Returns the maximum extent of this brush model.
+    pub fn max(&self) -> Vector3<f32> {
+        self.max
+    }
+
+    /// Returns the BSP data this brush model was built from.
+    pub fn bsp_data(&self) -> &BspData {
+        &self.bsp_data
+    }
+
+    /// Returns the opacity of the given texture, which is less than 1 only for
+    /// translucent liquids.
+    fn texture_alpha(&self, tex_id: usize, liquid_alpha: &LiquidAlpha) -> f32 {
+        self.liquids[tex_id].map_or(1.0, |kind| liquid_alpha.get(kind))
+    }
+
+    /// Returns the bind group of the texture frame to draw for `tex_id`.
+    fn texture_bind_group_id(&self, tex_id: usize, frame_id: usize, time: Duration) -> usize {
+        match &self.textures[tex_id] {
+            BrushTexture::Static(ref frame) => frame.bind_group_id,
+            BrushTexture::Animated { primary, alternate } => {
+                // if frame is not zero and this texture has an alternate
+                // animation, use it
+                let anim = if frame_id == 0 {
+                    primary
+                } else if let Some(a) = alternate {
+                    a
+                } else {
+                    primary
+                };
+
+                let time_ms = time.num_milliseconds();
+                let total_ms = (bsp::frame_duration() * anim.len() as i32).num_milliseconds();
+                let anim_ms = if total_ms == 0 { 0 } else { time_ms % total_ms };
+                anim[(anim_ms / bsp::frame_duration().num_milliseconds()) as usize].bind_group_id
+            }
+        }
+    }
+
+    /// Marks the faces of potentially visible leaves under `node_id` that
+    /// intersect the view frustum, skipping subtrees that fall outside it.
+    fn mark_visible_faces(
+        &self,
+        node_id: usize,
+        leaves: &[BrushLeaf],
+        pvs: &[bool],
+        camera: &Camera,
+        stats: &mut RenderStats,
+    ) {
+        let node = &self.bsp_data.render_nodes()[node_id];
+        if camera.cull_box(bounds_to_vec(node.min), bounds_to_vec(node.max)) {
+            stats.nodes_culled += 1;
+            return;
+        }
+
+        for child in node.children.iter() {
+            match *child {
+                BspRenderNodeChild::Node(child_id) => {
+                    self.mark_visible_faces(child_id, leaves, pvs, camera, stats)
+                }
+
+                BspRenderNodeChild::Leaf(leaf_id) => {
+                    if !pvs.get(leaf_id).copied().unwrap_or(false) {
+                        continue;
+                    }
+
+                    let leaf = &self.bsp_data.leaves()[leaf_id];
+                    if camera.cull_box(bounds_to_vec(leaf.min), bounds_to_vec(leaf.max)) {
+                        stats.nodes_culled += 1;
+                        continue;
+                    }
+
+                    for facelist_id in leaves[leaf_id].facelist_ids.clone() {
+                        let face = &self.faces[self.bsp_data.facelist()[facelist_id]];
+                        if !camera.cull_box(face.min, face.max) {
+                            face.draw_flag.set(true);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
     /// Record the draw commands for this brush model to the given `wgpu::RenderPass`.
+    ///
+    /// If `skybox` is given, sky surfaces are drawn with it instead of the
+    /// model's scrolling sky layers. Liquids made translucent by
+    /// `liquid_alpha` are skipped and left to `translucent_faces`.
     pub fn record_draw<'a>(
         &'a self,
         state: &'a GraphicsState,
@@ -723,28 +964,46 @@ impl BrushRenderer {
         time: Duration,
         camera: &Camera,
         frame_id: usize,
+        skybox: Option<&'a Skybox>,
+        liquid_alpha: &LiquidAlpha,
+        stats: &mut RenderStats,
     ) {
         pass.set_pipeline(state.brush_pipeline().pipeline());
         pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
 
         // if this is a worldmodel, mark faces to be drawn
         if let Some(ref leaves) = self.leaves {
-            let pvs = self
+            let mut pvs = vec![false; leaves.len()];
+            for leaf_id in self
                 .bsp_data
-                .get_pvs(self.bsp_data.find_leaf(camera.origin), leaves.len());
-
-            // only draw faces in pvs
-            for leaf_id in pvs {
-                for facelist_id in leaves[leaf_id].facelist_ids.clone() {
-                    let face = &self.faces[self.bsp_data.facelist()[facelist_id]];
-
-                    // TODO: frustum culling
-                    face.draw_flag.set(true);
+                .get_pvs(self.bsp_data.find_leaf(camera.origin), leaves.len())
+            {
+                if let Some(visible) = pvs.get_mut(leaf_id) {
+                    *visible = true;
                 }
             }
+
+            // the world model is always rooted at the first render node
+            self.mark_visible_faces(0, leaves, &pvs, camera, stats);
         }
 
+        let face_count = match self.leaves {
+            Some(_) => &mut stats.world_faces,
+            None => &mut stats.entity_faces,
+        };
+
         for (tex_id, face_ids) in self.texture_chains.iter() {
+            // sky faces are drawn separately below
+            if skybox.is_some() && self.textures[*tex_id].kind() == TextureKind::Sky {
+                continue;
+            }
+
+            // translucent faces are drawn in the translucent pass, which also
+            // clears their draw flags
+            if self.texture_alpha(*tex_id, liquid_alpha) < 1.0 {
+                continue;
+            }
+
             use PushConstantUpdate::*;
             BrushPipeline::set_push_constants(
                 pass,
@@ -755,27 +1014,7 @@ impl BrushRenderer {
                 Retain,
             );
 
-            let bind_group_id = match &self.textures[*tex_id] {
-                BrushTexture::Static(ref frame) => frame.bind_group_id,
-                BrushTexture::Animated { primary, alternate } => {
-                    // if frame is not zero and this texture has an alternate
-                    // animation, use it
-                    let anim = if frame_id == 0 {
-                        primary
-                    } else if let Some(a) = alternate {
-                        a
-                    } else {
-                        primary
-                    };
-
-                    let time_ms = time.num_milliseconds();
-                    let total_ms = (bsp::frame_duration() * anim.len() as i32).num_milliseconds();
-                    let anim_ms = if total_ms == 0 { 0 } else { time_ms % total_ms };
-                    anim[(anim_ms / bsp::frame_duration().num_milliseconds()) as usize]
-                        .bind_group_id
-                }
-            };
-
+            let bind_group_id = self.texture_bind_group_id(*tex_id, frame_id, time);
             pass.set_bind_group(
                 BindGroupLayoutId::PerTexture as u32,
                 &self.per_texture_bind_groups[bind_group_id],
@@ -797,7 +1036,172 @@ impl BrushRenderer {
                 );
 
                 pass.draw(face.vertices.clone(), 0..1);
+                *face_count += 1;
+            }
+        }
+
+        if let Some(skybox) = skybox {
+            use PushConstantUpdate::*;
+            pass.set_pipeline(state.skybox_pipeline().pipeline());
+            SkyboxPipeline::set_push_constants(
+                pass,
+                Update(bump.alloc(skybox::VertexPushConstants {
+                    transform: camera.view_projection(),
+                    model_view: camera.view(),
+                })),
+                Clear,
+                Clear,
+            );
+            pass.set_bind_group(
+                BindGroupLayoutId::PerTexture as u32,
+                skybox.bind_group(),
+                &[],
+            );
+
+            for (tex_id, face_ids) in self.texture_chains.iter() {
+                if self.textures[*tex_id].kind() != TextureKind::Sky {
+                    continue;
+                }
+
+                for face_id in face_ids.iter() {
+                    let face = &self.faces[*face_id];
+                    if self.leaves.is_some() && !face.draw_flag.replace(false) {
+                        continue;
+                    }
+
+                    pass.draw(face.vertices.clone(), 0..1);
+                    *face_count += 1;
+                }
+            }
+        }
+    }
+
+    /// Returns the faces of this model that belong in the translucent pass.
+    ///
+    /// If `entity_alpha` is less than 1, every face but sky is translucent;
+    /// otherwise only translucent liquids are. `origin` is the position of the
+    /// model in the world, used to find each face's distance from the camera.
+    /// For a worldmodel, this must follow `record_draw` in the same frame, and
+    /// only faces it marked visible are returned.
+    pub fn translucent_faces(
+        &self,
+        camera: &Camera,
+        origin: Vector3<f32>,
+        liquid_alpha: &LiquidAlpha,
+        entity_alpha: f32,
+    ) -> Vec<TranslucentFace> {
+        let mut translucent = Vec::new();
+
+        for (tex_id, face_ids) in self.texture_chains.iter() {
+            if self.textures[*tex_id].kind() == TextureKind::Sky {
+                continue;
+            }
+
+            let alpha = entity_alpha * self.texture_alpha(*tex_id, liquid_alpha);
+            if alpha >= 1.0 {
+                continue;
             }
+
+            for face_id in face_ids.iter() {
+                let face = &self.faces[*face_id];
+                if self.leaves.is_some() && !face.draw_flag.replace(false) {
+                    continue;
+                }
+
+                // invisible faces still need their draw flags cleared above
+                if alpha <= 0.0 {
+                    continue;
+                }
+
+                let center = origin + (face.min + face.max) / 2.0;
+                translucent.push(TranslucentFace {
+                    face_id: *face_id,
+                    alpha,
+                    distance: (center - camera.origin()).magnitude(),
+                });
+            }
+        }
+
+        translucent
+    }
+
+    /// Record the draw commands for a face returned by `translucent_faces`.
+    ///
+    /// The translucent brush pipeline must already be bound, along with the
+    /// per-frame and per-entity bind groups and vertex push constants for this
+    /// model. `fog` holds the fog color and density, as in the deferred pass.
+    pub fn record_translucent_face<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        bump: &'a Bump,
+        time: Duration,
+        frame_id: usize,
+        face: &TranslucentFace,
+        fog: [f32; 4],
+    ) {
+        use PushConstantUpdate::*;
+
+        let brush_face = &self.faces[face.face_id];
+        let tex_id = brush_face.texture_id;
+
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        TranslucentBrushPipeline::set_push_constants(
+            pass,
+            Retain,
+            Update(bump.alloc(SharedPushConstants {
+                texture_kind: self.textures[tex_id].kind() as u32,
+            })),
+            Update(bump.alloc(translucent::FragmentPushConstants {
+                alpha: face.alpha,
+                _pad: [0.0; 2],
+                fog,
+            })),
+        );
+        pass.set_bind_group(
+            BindGroupLayoutId::PerTexture as u32,
+            &self.per_texture_bind_groups[self.texture_bind_group_id(tex_id, frame_id, time)],
+            &[],
+        );
+        pass.set_bind_group(
+            BindGroupLayoutId::PerFace as u32,
+            &self.per_face_bind_groups[face.face_id],
+            &[],
+        );
+        pass.draw(brush_face.vertices.clone(), 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_sky_texture() {
+        #[rustfmt::skip]
+        let indices = [
+            1, 2, 3, 4,
+            5, 6, 7, 8,
+        ];
+
+        let (back, front) = split_sky_texture(&indices, 4, 2);
+        assert_eq!(back, vec![3, 4, 7, 8]);
+        assert_eq!(front, vec![1, 2, 5, 6]);
+    }
+
+    #[test]
+    fn test_liquid_kind_from_texture_name() {
+        let cases = [
+            ("*water0", Some(LiquidKind::Water)),
+            ("*04water1", Some(LiquidKind::Water)),
+            ("*lava1", Some(LiquidKind::Lava)),
+            ("*slime0", Some(LiquidKind::Slime)),
+            ("*teleport", Some(LiquidKind::Tele)),
+            ("sky4", None),
+            ("+0button", None),
+        ];
+
+        for (name, kind) in cases.iter() {
+            assert_eq!(LiquidKind::from_texture_name(name), *kind, "{}", name);
         }
     }
 }
@@ -21,8 +21,12 @@ pub struct PointLight {
 #[derive(Clone, Copy, Debug)]
 pub struct DeferredUniforms {
     pub inv_projection: [[f32; 4]; 4],
+    /// Inverse of the projection the viewmodel was drawn with.
+    pub viewmodel_inv_projection: [[f32; 4]; 4],
     pub light_count: u32,
     pub _pad: [u32; 3],
+    /// Fog color in `xyz` and density in `w`.
+    pub fog: [f32; 4],
     pub lights: [PointLight; MAX_LIGHTS],
 }
 
@@ -47,8 +51,10 @@ impl DeferredPipeline {
             contents: unsafe {
                 any_as_bytes(&DeferredUniforms {
                     inv_projection: Matrix4::identity().into(),
+                    viewmodel_inv_projection: Matrix4::identity().into(),
                     light_count: 0,
                     _pad: [0; 3],
+                    fog: [0.0; 4],
                     lights: [PointLight {
                         origin: Vector3::zero(),
                         radius: 0.0,
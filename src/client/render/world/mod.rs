@@ -3,9 +3,15 @@ pub mod brush;
 pub mod deferred;
 pub mod particle;
 pub mod postprocess;
+pub mod skybox;
 pub mod sprite;
+pub mod translucent;
 
-use std::{cell::RefCell, mem::size_of};
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    mem::size_of,
+};
 
 use crate::{
     client::{
@@ -14,11 +20,16 @@ use crate::{
             pipeline::{Pipeline, PushConstantUpdate},
             uniform::{DynamicUniformBufferBlock, UniformArrayFloat, UniformBool},
             world::{
-                alias::{AliasPipeline, AliasRenderer},
-                brush::{BrushPipeline, BrushRenderer, BrushRendererBuilder},
+                alias::{AliasLighting, AliasPose, AliasRenderer},
+                brush::{
+                    BrushPipeline, BrushRenderer, BrushRendererBuilder, LiquidAlpha,
+                    TranslucentFace,
+                },
+                skybox::Skybox,
                 sprite::{SpritePipeline, SpriteRenderer},
+                translucent::TranslucentBrushPipeline,
             },
-            GraphicsState, DEPTH_ATTACHMENT_FORMAT, DIFFUSE_ATTACHMENT_FORMAT,
+            Extent2d, GraphicsState, DEPTH_ATTACHMENT_FORMAT, DIFFUSE_ATTACHMENT_FORMAT,
             LIGHT_ATTACHMENT_FORMAT, NORMAL_ATTACHMENT_FORMAT,
         },
         ClientEntity,
@@ -26,17 +37,30 @@ use crate::{
     common::{
         console::CvarRegistry,
         engine,
-        math::Angles,
-        model::{Model, ModelKind},
+        math::{Angles, Hyperplane},
+        model::{Model, ModelFlags, ModelKind},
         sprite::SpriteKind,
         util::any_as_bytes,
     },
 };
 
 use bumpalo::Bump;
-use cgmath::{Euler, InnerSpace, Matrix4, SquareMatrix as _, Vector3, Vector4};
+use cgmath::{Euler, InnerSpace, Matrix as _, Matrix4, SquareMatrix as _, Vector3, Vector4};
 use chrono::Duration;
 
+/// Minimum lighting for players, so that they never go completely dark.
+const PLAYER_MIN_LIGHT: f32 = 8.0;
+
+/// Minimum lighting for the viewmodel.
+const VIEWMODEL_MIN_LIGHT: f32 = 24.0;
+
+/// The viewmodel is drawn into the front portion of the depth range, ending
+/// here, and everything else into the rest, so that the viewmodel stays in
+/// front of nearby world geometry and the deferred pass can tell them apart.
+///
+/// If this is changed, it must also be changed in `shaders/deferred.frag`.
+const VIEWMODEL_DEPTH_MAX: f32 = 0.3;
+
 lazy_static! {
     static ref BIND_GROUP_LAYOUT_DESCRIPTOR_BINDINGS: [Vec<wgpu::BindGroupLayoutEntry>; 2] = [
         vec![
@@ -195,7 +219,7 @@ pub struct Camera {
     view_projection: Matrix4<f32>,
     projection: Matrix4<f32>,
     inverse_projection: Matrix4<f32>,
-    clipping_planes: [Vector4<f32>; 6],
+    frustum: [Hyperplane; 6],
 }
 
 impl Camera {
@@ -209,20 +233,27 @@ impl Camera {
         let view = rotation * translation;
         let view_projection = projection * view;
 
+        // extract the frustum planes in Quake coordinates so they can be tested
+        // directly against BSP bounds and entity origins
         // see https://www.gamedevs.org/uploads/fast-extraction-viewing-frustum-planes-from-world-view-projection-matrix.pdf
-        let clipping_planes = [
+        let m = view_projection * quake_to_wgpu();
+        let plane = |v: Vector4<f32>| {
+            let len = v.truncate().magnitude();
+            Hyperplane::new(v.truncate() / len, -v.w / len)
+        };
+        let frustum = [
             // left
-            view_projection.w + view_projection.x,
+            plane(m.row(3) + m.row(0)),
             // right
-            view_projection.w - view_projection.x,
+            plane(m.row(3) - m.row(0)),
             // bottom
-            view_projection.w + view_projection.y,
+            plane(m.row(3) + m.row(1)),
             // top
-            view_projection.w - view_projection.y,
-            // near
-            view_projection.w + view_projection.z,
+            plane(m.row(3) - m.row(1)),
+            // near (wgpu clip space depth is [0, 1])
+            plane(m.row(2)),
             // far
-            view_projection.w - view_projection.z,
+            plane(m.row(3) - m.row(2)),
         ];
 
         Camera {
@@ -232,7 +263,7 @@ impl Camera {
             view_projection,
             projection,
             inverse_projection: projection.invert().unwrap(),
-            clipping_planes,
+            frustum,
         }
     }
 
@@ -260,17 +291,43 @@ impl Camera {
         self.inverse_projection
     }
 
-    // TODO: this seems to be too lenient
     /// Determines whether a point falls outside the viewing frustum.
     pub fn cull_point(&self, p: Vector3<f32>) -> bool {
-        for plane in self.clipping_planes.iter() {
-            if (self.view_projection() * p.extend(1.0)).dot(*plane) < 0.0 {
-                return true;
-            }
-        }
+        self.cull_sphere(p, 0.0)
+    }
 
-        false
+    /// Determines whether a sphere falls entirely outside the viewing frustum.
+    pub fn cull_sphere(&self, center: Vector3<f32>, radius: f32) -> bool {
+        self.frustum
+            .iter()
+            .any(|plane| plane.point_dist(center) < -radius)
     }
+
+    /// Determines whether an axis-aligned box falls entirely outside the
+    /// viewing frustum.
+    pub fn cull_box(&self, min: Vector3<f32>, max: Vector3<f32>) -> bool {
+        self.frustum.iter().any(|plane| {
+            // test the corner furthest along the plane normal
+            let n = plane.normal();
+            let corner = Vector3::new(
+                if n.x >= 0.0 { max.x } else { min.x },
+                if n.y >= 0.0 { max.y } else { min.y },
+                if n.z >= 0.0 { max.z } else { min.z },
+            );
+
+            plane.point_dist(corner) < 0.0
+        })
+    }
+}
+
+/// Returns the matrix converting Quake coordinates to wgpu coordinates.
+fn quake_to_wgpu() -> Matrix4<f32> {
+    Matrix4::from_cols(
+        -Vector4::unit_z(),
+        -Vector4::unit_x(),
+        Vector4::unit_y(),
+        Vector4::unit_w(),
+    )
 }
 
 #[repr(C, align(256))]
@@ -296,6 +353,78 @@ pub struct EntityUniforms {
     model: Matrix4<f32>,
 }
 
+/// Per-frame rendering counters, displayed with `r_speeds`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderStats {
+    /// World faces drawn.
+    pub world_faces: usize,
+    /// Brush entity faces drawn.
+    pub entity_faces: usize,
+    /// World BSP nodes and leaves rejected by the view frustum.
+    pub nodes_culled: usize,
+    /// Entities drawn.
+    pub entities: usize,
+    /// Entities rejected by the view frustum.
+    pub entities_culled: usize,
+}
+
+/// Something to be drawn in the translucent pass.
+enum TranslucentDraw<'a> {
+    /// A brush face, with the position of its entity in the entity list and
+    /// the entity itself, or `None` for the worldmodel.
+    Face {
+        ent: Option<(usize, &'a ClientEntity)>,
+        model: &'a BrushRenderer,
+        face: TranslucentFace,
+    },
+
+    /// A whole alias model or sprite.
+    Entity {
+        ent_pos: usize,
+        ent: &'a ClientEntity,
+        distance: f32,
+    },
+}
+
+impl<'a> TranslucentDraw<'a> {
+    /// Distance from the camera, used to draw from back to front.
+    fn distance(&self) -> f32 {
+        match *self {
+            TranslucentDraw::Face { ref face, .. } => face.distance,
+            TranslucentDraw::Entity { distance, .. } => distance,
+        }
+    }
+}
+
+/// Returns the pose to draw an alias model entity in.
+fn alias_pose(ent: &ClientEntity, time: Duration, lerp_models: bool) -> AliasPose {
+    if lerp_models {
+        AliasPose::Lerp {
+            prev_keyframe_id: ent.prev_frame_id(),
+            keyframe_id: ent.frame_id(),
+            factor: ent.frame_lerp(time),
+        }
+    } else {
+        AliasPose::Snap(ent.frame_id())
+    }
+}
+
+/// Returns the minimum lighting for an alias model entity.
+fn entity_min_light(ent: &ClientEntity) -> f32 {
+    // anything wearing a player colormap is a player or a player's corpse
+    if ent.colormap().is_some() {
+        PLAYER_MIN_LIGHT
+    } else {
+        0.0
+    }
+}
+
+/// Maps normalized device depth to `min..max` in the depth attachment.
+fn set_depth_range(state: &GraphicsState, pass: &mut wgpu::RenderPass, min: f32, max: f32) {
+    let Extent2d { width, height } = state.initial_pass_target().size();
+    pass.set_viewport(0.0, 0.0, width as f32, height as f32, min, max);
+}
+
 enum EntityRenderer {
     Alias(AliasRenderer),
     Brush(BrushRenderer),
@@ -310,6 +439,12 @@ pub struct WorldRenderer {
 
     world_uniform_block: DynamicUniformBufferBlock<EntityUniforms>,
     entity_uniform_blocks: RefCell<Vec<DynamicUniformBufferBlock<EntityUniforms>>>,
+
+    // the requested skybox is tracked separately so a failed load isn't retried
+    skybox_name: Option<String>,
+    skybox: Option<Skybox>,
+
+    stats: Cell<RenderStats>,
 }
 
 impl WorldRenderer {
@@ -337,7 +472,7 @@ impl WorldRenderer {
             } else {
                 match *model.kind() {
                     ModelKind::Alias(ref amodel) => entity_renderers.push(EntityRenderer::Alias(
-                        AliasRenderer::new(state, amodel).unwrap(),
+                        AliasRenderer::new(state, amodel, model.flags()).unwrap(),
                     )),
 
                     ModelKind::Brush(ref bmodel) => {
@@ -366,6 +501,51 @@ impl WorldRenderer {
             entity_renderers,
             world_uniform_block,
             entity_uniform_blocks: RefCell::new(Vec::new()),
+            skybox_name: None,
+            skybox: None,
+            stats: Cell::new(RenderStats::default()),
+        }
+    }
+
+    /// Returns the rendering counters from the last call to `render_pass`.
+    pub fn stats(&self) -> RenderStats {
+        self.stats.get()
+    }
+
+    /// Loads the named skybox if it differs from the current one.
+    ///
+    /// Passing `None` reverts to the map's own sky textures.
+    pub fn update_skybox(&mut self, state: &GraphicsState, name: Option<&str>) {
+        if self.skybox_name.as_deref() == name {
+            return;
+        }
+
+        self.skybox_name = name.map(str::to_owned);
+        self.skybox = name.and_then(|name| match Skybox::load(state, name) {
+            Ok(skybox) => Some(skybox),
+            Err(e) => {
+                warn!("Couldn't load skybox {}: {}", name, e);
+                None
+            }
+        });
+    }
+
+    /// Uploads recolored skins for any of `entities` wearing player colors.
+    pub fn update_player_skins<'a, I>(&mut self, state: &GraphicsState, entities: I)
+    where
+        I: Iterator<Item = &'a ClientEntity>,
+    {
+        for ent in entities {
+            let colors = match ent.colors() {
+                Some(c) if ent.model_id() != 0 => c,
+                _ => continue,
+            };
+
+            // subtract 1 from index because world entity isn't counted
+            if let EntityRenderer::Alias(ref mut alias) = self.entity_renderers[ent.model_id() - 1]
+            {
+                alias.translate_skin(state, ent.skin_id(), colors);
+            }
         }
     }
 
@@ -437,7 +617,6 @@ impl WorldRenderer {
         entities: E,
         particles: P,
         lightstyle_values: &[f32],
-        viewmodel_id: usize,
         cvars: &CvarRegistry,
     ) where
         E: Iterator<Item = &'a ClientEntity> + Clone,
@@ -454,12 +633,16 @@ impl WorldRenderer {
             cvars,
         );
 
+        set_depth_range(state, pass, VIEWMODEL_DEPTH_MAX, 1.0);
         pass.set_bind_group(
             BindGroupLayoutId::PerFrame as u32,
             &state.world_bind_groups()[BindGroupLayoutId::PerFrame as usize],
             &[],
         );
 
+        let mut stats = RenderStats::default();
+        let liquid_alpha = LiquidAlpha::from_cvars(cvars);
+
         // draw world
         info!("Drawing world");
         pass.set_pipeline(state.brush_pipeline().pipeline());
@@ -477,12 +660,38 @@ impl WorldRenderer {
             &state.world_bind_groups()[BindGroupLayoutId::PerEntity as usize],
             &[self.world_uniform_block.offset()],
         );
-        self.worldmodel_renderer
-            .record_draw(state, pass, &bump, time, camera, 0);
+        self.worldmodel_renderer.record_draw(
+            state,
+            pass,
+            &bump,
+            time,
+            camera,
+            0,
+            self.skybox.as_ref(),
+            &liquid_alpha,
+            &mut stats,
+        );
 
         // draw entities
         info!("Drawing entities");
+        let lerp_models = cvars.get_value("r_lerpmodels").unwrap() != 0.0;
         for (ent_pos, ent) in entities.enumerate() {
+            // fully transparent entities aren't drawn at all
+            if ent.alpha() <= 0.0 {
+                continue;
+            }
+
+            if self.cull_entity(camera, ent) {
+                stats.entities_culled += 1;
+                continue;
+            }
+            stats.entities += 1;
+
+            // translucent entities are drawn in the translucent pass
+            if ent.alpha() < 1.0 {
+                continue;
+            }
+
             pass.set_bind_group(
                 BindGroupLayoutId::PerEntity as u32,
                 &state.world_bind_groups()[BindGroupLayoutId::PerEntity as usize],
@@ -501,20 +710,31 @@ impl WorldRenderer {
                         Clear,
                         Clear,
                     );
-                    bmodel.record_draw(state, pass, &bump, time, camera, ent.frame_id);
+                    bmodel.record_draw(
+                        state,
+                        pass,
+                        &bump,
+                        time,
+                        camera,
+                        ent.frame_id,
+                        None,
+                        &liquid_alpha,
+                        &mut stats,
+                    );
                 }
                 EntityRenderer::Alias(ref alias) => {
-                    pass.set_pipeline(state.alias_pipeline().pipeline());
-                    AliasPipeline::set_push_constants(
+                    alias.record_draw(
+                        state,
                         pass,
-                        Update(bump.alloc(alias::VertexPushConstants {
-                            transform: self.calculate_mvp_transform(camera, ent),
-                            model_view: self.calculate_mv_transform(camera, ent),
-                        })),
-                        Clear,
-                        Clear,
+                        bump,
+                        time,
+                        self.calculate_mvp_transform(camera, ent),
+                        self.calculate_mv_transform(camera, ent),
+                        alias_pose(ent, time, lerp_models),
+                        ent.skin_id(),
+                        ent.colors(),
+                        self.alias_lighting(alias, ent, lightstyle_values, entity_min_light(ent)),
                     );
-                    alias.record_draw(state, pass, time, ent.frame_id(), ent.skin_id());
                 }
                 EntityRenderer::Sprite(ref sprite) => {
                     pass.set_pipeline(state.sprite_pipeline().pipeline());
@@ -526,37 +746,286 @@ impl WorldRenderer {
             }
         }
 
-        let viewmodel_orig = camera.origin();
-        let cam_angles = camera.angles();
-        let viewmodel_mat = Matrix4::from_translation(Vector3::new(
-            -viewmodel_orig.y,
-            viewmodel_orig.z,
-            -viewmodel_orig.x,
-        )) * Matrix4::from_angle_y(cam_angles.yaw)
-            * Matrix4::from_angle_x(-cam_angles.pitch)
-            * Matrix4::from_angle_z(cam_angles.roll);
-        match self.entity_renderers[viewmodel_id] {
-            EntityRenderer::Alias(ref alias) => {
-                pass.set_pipeline(state.alias_pipeline().pipeline());
-                AliasPipeline::set_push_constants(
+        log::debug!("Drawing particles");
+        state
+            .particle_pipeline()
+            .record_draw(pass, &bump, camera, particles);
+
+        self.stats.set(stats);
+    }
+
+    /// Draws translucent surfaces and entities over the lit scene from back to
+    /// front.
+    ///
+    /// This must follow `render_pass` in the same frame and be given the same
+    /// entities, since it reuses the uniforms and visible faces computed there.
+    /// `fog` holds the fog color and density as passed to the deferred pass.
+    pub fn render_translucent_pass<'a, E>(
+        &'a self,
+        state: &'a GraphicsState,
+        pass: &mut wgpu::RenderPass<'a>,
+        bump: &'a Bump,
+        camera: &Camera,
+        time: Duration,
+        entities: E,
+        lightstyle_values: &[f32],
+        fog: [f32; 4],
+        cvars: &CvarRegistry,
+    ) where
+        E: Iterator<Item = &'a ClientEntity>,
+    {
+        use PushConstantUpdate::*;
+
+        // depth must be written and tested the same way as in `render_pass`
+        set_depth_range(state, pass, VIEWMODEL_DEPTH_MAX, 1.0);
+
+        let liquid_alpha = LiquidAlpha::from_cvars(cvars);
+        let lerp_models = cvars.get_value("r_lerpmodels").unwrap() != 0.0;
+        let mut stats = self.stats.get();
+
+        let mut draws = Vec::new();
+        for face in self.worldmodel_renderer.translucent_faces(
+            camera,
+            Vector3::new(0.0, 0.0, 0.0),
+            &liquid_alpha,
+            1.0,
+        ) {
+            draws.push(TranslucentDraw::Face {
+                ent: None,
+                model: &self.worldmodel_renderer,
+                face,
+            });
+        }
+
+        for (ent_pos, ent) in entities.enumerate() {
+            if ent.alpha() <= 0.0 || self.cull_entity(camera, ent) {
+                continue;
+            }
+
+            match self.renderer_for_entity(ent) {
+                EntityRenderer::Brush(ref bmodel) => {
+                    for face in bmodel.translucent_faces(
+                        camera,
+                        ent.get_origin(),
+                        &liquid_alpha,
+                        ent.alpha(),
+                    ) {
+                        draws.push(TranslucentDraw::Face {
+                            ent: Some((ent_pos, ent)),
+                            model: bmodel,
+                            face,
+                        });
+                    }
+                }
+
+                EntityRenderer::Alias(_) | EntityRenderer::Sprite(_) if ent.alpha() < 1.0 => {
+                    draws.push(TranslucentDraw::Entity {
+                        ent_pos,
+                        ent,
+                        distance: (ent.get_origin() - camera.origin()).magnitude(),
+                    });
+                }
+
+                _ => (),
+            }
+        }
+
+        draws.sort_by(|a, b| {
+            b.distance()
+                .partial_cmp(&a.distance())
+                .unwrap_or(Ordering::Equal)
+        });
+
+        pass.set_bind_group(
+            BindGroupLayoutId::PerFrame as u32,
+            &state.world_bind_groups()[BindGroupLayoutId::PerFrame as usize],
+            &[],
+        );
+
+        // only rebind brush state when the next face belongs to a different
+        // entity, or follows an alias model or sprite
+        let mut bound_ent_pos = None;
+        for draw in draws.iter() {
+            let (ent, model, face) = match *draw {
+                TranslucentDraw::Face {
+                    ent,
+                    model,
+                    ref face,
+                } => (ent, model, face),
+
+                TranslucentDraw::Entity { ent_pos, ent, .. } => {
+                    pass.set_bind_group(
+                        BindGroupLayoutId::PerEntity as u32,
+                        &state.world_bind_groups()[BindGroupLayoutId::PerEntity as usize],
+                        &[self.entity_uniform_blocks.borrow()[ent_pos].offset()],
+                    );
+                    bound_ent_pos = None;
+
+                    match self.renderer_for_entity(ent) {
+                        EntityRenderer::Alias(ref alias) => alias.record_translucent_draw(
+                            state,
+                            pass,
+                            bump,
+                            time,
+                            self.calculate_mvp_transform(camera, ent),
+                            self.calculate_mv_transform(camera, ent),
+                            alias_pose(ent, time, lerp_models),
+                            ent.skin_id(),
+                            ent.colors(),
+                            self.alias_lighting(
+                                alias,
+                                ent,
+                                lightstyle_values,
+                                entity_min_light(ent),
+                            ),
+                            ent.alpha(),
+                            fog,
+                        ),
+                        EntityRenderer::Sprite(ref sprite) => sprite.record_translucent_draw(
+                            state,
+                            pass,
+                            bump,
+                            self.calculate_mvp_transform(camera, ent),
+                            self.calculate_mv_transform(camera, ent),
+                            ent.frame_id(),
+                            time,
+                            ent.alpha(),
+                            fog,
+                        ),
+                        _ => unreachable!(),
+                    }
+                    continue;
+                }
+            };
+
+            let ent_pos = ent.map(|(ent_pos, _)| ent_pos);
+            if bound_ent_pos != Some(ent_pos) {
+                let (offset, transform, model_view) = match ent {
+                    Some((ent_pos, ent)) => (
+                        self.entity_uniform_blocks.borrow()[ent_pos].offset(),
+                        self.calculate_mvp_transform(camera, ent),
+                        self.calculate_mv_transform(camera, ent),
+                    ),
+                    None => (
+                        self.world_uniform_block.offset(),
+                        camera.view_projection(),
+                        camera.view(),
+                    ),
+                };
+
+                pass.set_pipeline(state.translucent_brush_pipeline().pipeline());
+                pass.set_bind_group(
+                    BindGroupLayoutId::PerEntity as u32,
+                    &state.world_bind_groups()[BindGroupLayoutId::PerEntity as usize],
+                    &[offset],
+                );
+                TranslucentBrushPipeline::set_push_constants(
                     pass,
-                    Update(bump.alloc(alias::VertexPushConstants {
-                        transform: camera.view_projection() * viewmodel_mat,
-                        model_view: camera.view() * viewmodel_mat,
+                    Update(bump.alloc(brush::VertexPushConstants {
+                        transform,
+                        model_view,
                     })),
-                    Clear,
-                    Clear,
+                    Retain,
+                    Retain,
                 );
-                alias.record_draw(state, pass, time, 0, 0);
+                bound_ent_pos = Some(ent_pos);
             }
 
-            _ => unreachable!("non-alias viewmodel"),
+            let frame_id = ent.map_or(0, |(_, ent)| ent.frame_id);
+            model.record_translucent_face(pass, bump, time, frame_id, face, fog);
+
+            match ent {
+                Some(_) => stats.entity_faces += 1,
+                None => stats.world_faces += 1,
+            }
         }
 
-        log::debug!("Drawing particles");
-        state
-            .particle_pipeline()
-            .record_draw(pass, &bump, camera, particles);
+        self.stats.set(stats);
+    }
+
+    /// Draws the first-person weapon model.
+    ///
+    /// The viewmodel is drawn with its own projection and squeezed into the
+    /// front of the depth range so that it never clips into walls. This should
+    /// be called after everything else in the pass has been drawn, and the
+    /// deferred pass must be given the inverse of the same projection.
+    pub fn render_viewmodel<'a>(
+        &'a self,
+        state: &'a GraphicsState,
+        pass: &mut wgpu::RenderPass<'a>,
+        bump: &'a Bump,
+        camera: &Camera,
+        time: Duration,
+        viewmodel: &ClientEntity,
+        lightstyle_values: &[f32],
+        cvars: &CvarRegistry,
+    ) {
+        let alias = match self.renderer_for_entity(viewmodel) {
+            EntityRenderer::Alias(ref alias) => alias,
+            _ => {
+                warn!("non-alias viewmodel");
+                return;
+            }
+        };
+
+        let origin = viewmodel.get_origin();
+        let angles = viewmodel.get_angles();
+        let model_transform =
+            Matrix4::from_translation(Vector3::new(-origin.y, origin.z, -origin.x))
+                * Matrix4::from_angle_y(angles.y)
+                * Matrix4::from_angle_x(-angles.x)
+                * Matrix4::from_angle_z(angles.z);
+
+        let pose = if cvars.get_value("r_lerpmodels").unwrap() != 0.0 {
+            AliasPose::Lerp {
+                prev_keyframe_id: viewmodel.prev_frame_id(),
+                keyframe_id: viewmodel.frame_id(),
+                factor: viewmodel.frame_lerp(time),
+            }
+        } else {
+            AliasPose::Snap(viewmodel.frame_id())
+        };
+
+        set_depth_range(state, pass, 0.0, VIEWMODEL_DEPTH_MAX);
+        pass.set_bind_group(
+            BindGroupLayoutId::PerEntity as u32,
+            &state.world_bind_groups()[BindGroupLayoutId::PerEntity as usize],
+            &[self.world_uniform_block.offset()],
+        );
+        alias.record_draw(
+            state,
+            pass,
+            bump,
+            time,
+            camera.view_projection() * model_transform,
+            camera.view() * model_transform,
+            pose,
+            viewmodel.skin_id(),
+            None,
+            self.alias_lighting(alias, viewmodel, lightstyle_values, VIEWMODEL_MIN_LIGHT),
+        );
+        set_depth_range(state, pass, VIEWMODEL_DEPTH_MAX, 1.0);
+    }
+
+    /// Calculates the static lighting of an alias model from the lightmap beneath it.
+    fn alias_lighting(
+        &self,
+        alias: &AliasRenderer,
+        ent: &ClientEntity,
+        lightstyle_values: &[f32],
+        min_light: f32,
+    ) -> AliasLighting {
+        if alias.flags().contains(ModelFlags::FULLBRIGHT) {
+            return AliasLighting::fullbright();
+        }
+
+        let light = self
+            .worldmodel_renderer
+            .bsp_data()
+            .light_point(ent.get_origin(), lightstyle_values)
+            .unwrap_or(0.0);
+
+        AliasLighting::new(light, ent.get_angles().y, min_light)
     }
 
     fn renderer_for_entity(&self, ent: &ClientEntity) -> &EntityRenderer {
@@ -564,6 +1033,26 @@ impl WorldRenderer {
         &self.entity_renderers[ent.model_id() - 1]
     }
 
+    /// Determines whether an entity falls entirely outside the view frustum.
+    fn cull_entity(&self, camera: &Camera, ent: &ClientEntity) -> bool {
+        let origin = ent.get_origin();
+        match self.renderer_for_entity(ent) {
+            EntityRenderer::Brush(ref bmodel) => {
+                let angles = ent.get_angles();
+                if angles.x.0 != 0.0 || angles.y.0 != 0.0 || angles.z.0 != 0.0 {
+                    // rotated bounds could extend in any direction
+                    let radius = bmodel.min().magnitude().max(bmodel.max().magnitude());
+                    camera.cull_sphere(origin, radius)
+                } else {
+                    camera.cull_box(origin + bmodel.min(), origin + bmodel.max())
+                }
+            }
+            EntityRenderer::Alias(ref alias) => camera.cull_sphere(origin, alias.radius()),
+            EntityRenderer::Sprite(ref sprite) => camera.cull_sphere(origin, sprite.radius()),
+            EntityRenderer::None => false,
+        }
+    }
+
     fn calculate_mvp_transform(&self, camera: &Camera, entity: &ClientEntity) -> Matrix4<f32> {
         let model_transform = self.calculate_model_transform(camera, entity);
 
@@ -603,3 +1092,67 @@ impl WorldRenderer {
         Matrix4::from_translation(Vector3::new(-origin.y, origin.z, -origin.x)) * rotation
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::{Deg, Zero as _};
+
+    fn test_camera() -> Camera {
+        Camera::new(
+            Vector3::zero(),
+            Angles::zero(),
+            cgmath::perspective(Deg(90.0), 1.0, 4.0, 4096.0),
+        )
+    }
+
+    #[test]
+    fn test_camera_cull_point() {
+        let camera = test_camera();
+
+        // Quake's +x is straight ahead with zero angles
+        assert!(!camera.cull_point(Vector3::new(100.0, 0.0, 0.0)));
+        assert!(!camera.cull_point(Vector3::new(100.0, 50.0, -50.0)));
+
+        assert!(camera.cull_point(Vector3::new(-100.0, 0.0, 0.0)));
+        assert!(camera.cull_point(Vector3::new(100.0, 200.0, 0.0)));
+        assert!(camera.cull_point(Vector3::new(100.0, 0.0, -200.0)));
+        assert!(camera.cull_point(Vector3::new(1.0, 0.0, 0.0)));
+        assert!(camera.cull_point(Vector3::new(5000.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn test_camera_cull_sphere() {
+        let camera = test_camera();
+
+        assert!(!camera.cull_sphere(Vector3::new(100.0, 150.0, 0.0), 100.0));
+        assert!(camera.cull_sphere(Vector3::new(100.0, 150.0, 0.0), 10.0));
+        assert!(camera.cull_sphere(Vector3::new(-100.0, 0.0, 0.0), 50.0));
+    }
+
+    #[test]
+    fn test_camera_cull_box() {
+        let camera = test_camera();
+
+        // box surrounding the camera
+        assert!(!camera.cull_box(
+            Vector3::new(-10.0, -10.0, -10.0),
+            Vector3::new(10.0, 10.0, 10.0)
+        ));
+        // box behind the camera
+        assert!(camera.cull_box(
+            Vector3::new(-200.0, -10.0, -10.0),
+            Vector3::new(-100.0, 10.0, 10.0)
+        ));
+        // box off to the side but crossing into view
+        assert!(!camera.cull_box(
+            Vector3::new(50.0, 40.0, -10.0),
+            Vector3::new(100.0, 300.0, 10.0)
+        ));
+        assert!(camera.cull_box(
+            Vector3::new(50.0, 200.0, -10.0),
+            Vector3::new(100.0, 300.0, 10.0)
+        ));
+    }
+}
@@ -0,0 +1,291 @@
+use crate::{
+    client::render::{
+        world::{brush::BrushPipeline, BindGroupLayoutId, WorldPipelineBase},
+        GraphicsState, Pipeline, DIFFUSE_TEXTURE_FORMAT,
+    },
+    common::image::{Image, ImageError},
+};
+
+use cgmath::Matrix4;
+
+/// Suffixes of the six skybox face images, in cubemap layer order.
+///
+/// The cubemap is sampled with the Quake Y and Z axes swapped (see
+/// `skybox.frag`), so the layers hold Quake's +X, -X, +Z, -Z, +Y and -Y faces.
+/// The top and bottom faces are stored rotated relative to the cubemap
+/// convention and must be turned before upload.
+const FACES: [(&str, FaceRotation); 6] = [
+    ("rt", FaceRotation::None),
+    ("lf", FaceRotation::None),
+    ("up", FaceRotation::CounterClockwise),
+    ("dn", FaceRotation::Clockwise),
+    ("bk", FaceRotation::None),
+    ("ft", FaceRotation::None),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FaceRotation {
+    None,
+    Clockwise,
+    CounterClockwise,
+}
+
+/// Rotates a square RGBA image by a quarter turn.
+fn rotate_face(rgba: &[u8], size: u32, rotation: FaceRotation) -> Vec<u8> {
+    let size = size as usize;
+    let mut rotated = Vec::with_capacity(rgba.len());
+    for y in 0..size {
+        for x in 0..size {
+            let (src_row, src_col) = match rotation {
+                FaceRotation::None => (y, x),
+                FaceRotation::Clockwise => (size - 1 - x, y),
+                FaceRotation::CounterClockwise => (x, size - 1 - y),
+            };
+            let src = (src_row * size + src_col) * 4;
+            rotated.extend_from_slice(&rgba[src..src + 4]);
+        }
+    }
+
+    rotated
+}
+
+pub struct SkyboxPipeline {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+}
+
+impl SkyboxPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        compiler: &mut shaderc::Compiler,
+        world_bind_group_layouts: &[wgpu::BindGroupLayout],
+        sample_count: u32,
+    ) -> SkyboxPipeline {
+        let (pipeline, bind_group_layouts) =
+            SkyboxPipeline::create(device, compiler, world_bind_group_layouts, sample_count);
+
+        SkyboxPipeline {
+            pipeline,
+            bind_group_layouts,
+        }
+    }
+
+    pub fn rebuild(
+        &mut self,
+        device: &wgpu::Device,
+        compiler: &mut shaderc::Compiler,
+        world_bind_group_layouts: &[wgpu::BindGroupLayout],
+        sample_count: u32,
+    ) {
+        let layout_refs: Vec<_> = world_bind_group_layouts
+            .iter()
+            .chain(self.bind_group_layouts.iter())
+            .collect();
+        self.pipeline = SkyboxPipeline::recreate(device, compiler, &layout_refs, sample_count);
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    pub fn bind_group_layouts(&self) -> &[wgpu::BindGroupLayout] {
+        &self.bind_group_layouts
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct VertexPushConstants {
+    pub transform: Matrix4<f32>,
+    pub model_view: Matrix4<f32>,
+}
+
+impl Pipeline for SkyboxPipeline {
+    type VertexPushConstants = VertexPushConstants;
+    type SharedPushConstants = ();
+    type FragmentPushConstants = ();
+
+    fn name() -> &'static str {
+        "skybox"
+    }
+
+    fn vertex_shader() -> &'static str {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/skybox.vert"))
+    }
+
+    fn fragment_shader() -> &'static str {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/skybox.frag"))
+    }
+
+    fn bind_group_layout_descriptors() -> Vec<wgpu::BindGroupLayoutDescriptor<'static>> {
+        vec![
+            // group 2: updated when the skybox changes
+            wgpu::BindGroupLayoutDescriptor {
+                label: Some("skybox bind group"),
+                entries: &[
+                    // skybox cubemap
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            view_dimension: wgpu::TextureViewDimension::Cube,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                ],
+            },
+        ]
+    }
+
+    fn primitive_state() -> wgpu::PrimitiveState {
+        WorldPipelineBase::primitive_state()
+    }
+
+    fn color_target_states() -> Vec<Option<wgpu::ColorTargetState>> {
+        WorldPipelineBase::color_target_states()
+    }
+
+    fn depth_stencil_state() -> Option<wgpu::DepthStencilState> {
+        WorldPipelineBase::depth_stencil_state()
+    }
+
+    // sky surfaces are drawn straight from the brush model's vertex buffer
+    fn vertex_buffer_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
+        BrushPipeline::vertex_buffer_layouts()
+    }
+}
+
+/// A cubemap skybox loaded from `gfx/env/<name>{rt,bk,lf,ft,up,dn}`.
+pub struct Skybox {
+    #[allow(dead_code)]
+    texture: wgpu::Texture,
+    #[allow(dead_code)]
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Skybox {
+    pub fn load<S>(state: &GraphicsState, name: S) -> Result<Skybox, ImageError>
+    where
+        S: AsRef<str>,
+    {
+        let name = name.as_ref();
+
+        let mut faces = Vec::with_capacity(FACES.len());
+        for (suffix, _) in FACES.iter() {
+            faces.push(Image::load(
+                state.vfs(),
+                format!("gfx/env/{}{}", name, suffix),
+            )?);
+        }
+
+        let size = faces[0].width();
+        if faces
+            .iter()
+            .any(|f| f.width() != size || f.height() != size)
+        {
+            return Err(ImageError::Unsupported(format!(
+                "skybox {} faces must be square and equally sized",
+                name
+            )));
+        }
+
+        let texture = state.device().create_texture(&wgpu::TextureDescriptor {
+            label: Some("skybox"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: FACES.len() as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: DIFFUSE_TEXTURE_FORMAT,
+            usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        for (layer, (face, (_, rotation))) in faces.iter().zip(FACES.iter()).enumerate() {
+            state.queue().write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                    aspect: Default::default(),
+                },
+                &rotate_face(face.rgba(), size, *rotation),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(size * 4),
+                    rows_per_image: None,
+                },
+                wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let bind_group = state
+            .device()
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("skybox bind group"),
+                layout: &state.skybox_pipeline().bind_group_layouts()
+                    [BindGroupLayoutId::PerTexture as usize - 2],
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                }],
+            });
+
+        Ok(Skybox {
+            texture,
+            view,
+            bind_group,
+        })
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate_face() {
+        // 2x2 image with one distinct byte per pixel
+        #[rustfmt::skip]
+        let rgba = [
+            1, 0, 0, 0,  2, 0, 0, 0,
+            3, 0, 0, 0,  4, 0, 0, 0,
+        ];
+        let firsts = |v: Vec<u8>| v.chunks(4).map(|p| p[0]).collect::<Vec<_>>();
+
+        assert_eq!(
+            firsts(rotate_face(&rgba, 2, FaceRotation::None)),
+            [1, 2, 3, 4]
+        );
+        assert_eq!(
+            firsts(rotate_face(&rgba, 2, FaceRotation::Clockwise)),
+            [3, 1, 4, 2]
+        );
+        assert_eq!(
+            firsts(rotate_face(&rgba, 2, FaceRotation::CounterClockwise)),
+            [2, 4, 1, 3]
+        );
+    }
+}
@@ -2,7 +2,12 @@ use std::mem::size_of;
 
 use crate::{
     client::render::{
-        world::{BindGroupLayoutId, WorldPipelineBase},
+        pipeline::PushConstantUpdate,
+        world::{
+            brush::VertexPushConstants,
+            translucent::{SpriteFragmentPushConstants, TranslucentSpritePipeline},
+            BindGroupLayoutId, WorldPipelineBase,
+        },
         GraphicsState, Pipeline, TextureData,
     },
     common::{
@@ -11,6 +16,8 @@ use crate::{
     },
 };
 
+use bumpalo::Bump;
+use cgmath::Matrix4;
 use chrono::Duration;
 
 pub struct SpritePipeline {
@@ -301,6 +308,7 @@ impl Frame {
 
 pub struct SpriteRenderer {
     kind: SpriteKind,
+    radius: f32,
     frames: Vec<Frame>,
 }
 
@@ -314,6 +322,7 @@ impl SpriteRenderer {
 
         SpriteRenderer {
             kind: sprite.kind(),
+            radius: sprite.radius(),
             frames,
         }
     }
@@ -335,7 +344,52 @@ impl SpriteRenderer {
         pass.draw(0..VERTICES.len() as u32, 0..1);
     }
 
+    /// Draws the sprite blended over the lit scene with the given `alpha`.
+    ///
+    /// `fog` holds the fog color and density as passed to the deferred pass.
+    pub fn record_translucent_draw<'a>(
+        &'a self,
+        state: &'a GraphicsState,
+        pass: &mut wgpu::RenderPass<'a>,
+        bump: &'a Bump,
+        transform: Matrix4<f32>,
+        model_view: Matrix4<f32>,
+        frame_id: usize,
+        time: Duration,
+        alpha: f32,
+        fog: [f32; 4],
+    ) {
+        use PushConstantUpdate::*;
+
+        pass.set_pipeline(state.translucent_sprite_pipeline().pipeline());
+        TranslucentSpritePipeline::set_push_constants(
+            pass,
+            Update(bump.alloc(VertexPushConstants {
+                transform,
+                model_view,
+            })),
+            Clear,
+            Update(bump.alloc(SpriteFragmentPushConstants {
+                alpha,
+                _pad: [0.0; 3],
+                fog,
+            })),
+        );
+        pass.set_vertex_buffer(0, state.sprite_pipeline().vertex_buffer().slice(..));
+        pass.set_bind_group(
+            BindGroupLayoutId::PerTexture as u32,
+            self.frames[frame_id].animate(time),
+            &[],
+        );
+        pass.draw(0..VERTICES.len() as u32, 0..1);
+    }
+
     pub fn kind(&self) -> SpriteKind {
         self.kind
     }
+
+    /// Returns the bounding radius of the sprite around its origin.
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
 }
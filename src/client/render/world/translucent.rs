@@ -0,0 +1,342 @@
+use crate::client::render::{
+    world::{
+        alias::{self, AliasPipeline},
+        brush::{BrushPipeline, SharedPushConstants, VertexPushConstants},
+        sprite::SpritePipeline,
+        WorldPipelineBase,
+    },
+    Pipeline, DEPTH_ATTACHMENT_FORMAT, DIFFUSE_ATTACHMENT_FORMAT,
+};
+
+/// Draws translucent brush surfaces over the output of the deferred pass.
+///
+/// This pipeline reuses the brush pipeline's vertex format and bind group
+/// layouts so that brush models can be drawn with either one.
+pub struct TranslucentBrushPipeline {
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl TranslucentBrushPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        compiler: &mut shaderc::Compiler,
+        world_bind_group_layouts: &[wgpu::BindGroupLayout],
+        brush_bind_group_layouts: &[wgpu::BindGroupLayout],
+        sample_count: u32,
+    ) -> TranslucentBrushPipeline {
+        let layout_refs: Vec<_> = world_bind_group_layouts
+            .iter()
+            .chain(brush_bind_group_layouts.iter())
+            .collect();
+
+        TranslucentBrushPipeline {
+            pipeline: TranslucentBrushPipeline::recreate(
+                device,
+                compiler,
+                &layout_refs,
+                sample_count,
+            ),
+        }
+    }
+
+    pub fn rebuild(
+        &mut self,
+        device: &wgpu::Device,
+        compiler: &mut shaderc::Compiler,
+        world_bind_group_layouts: &[wgpu::BindGroupLayout],
+        brush_bind_group_layouts: &[wgpu::BindGroupLayout],
+        sample_count: u32,
+    ) {
+        let layout_refs: Vec<_> = world_bind_group_layouts
+            .iter()
+            .chain(brush_bind_group_layouts.iter())
+            .collect();
+        self.pipeline =
+            TranslucentBrushPipeline::recreate(device, compiler, &layout_refs, sample_count);
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct FragmentPushConstants {
+    pub alpha: f32,
+    // aligns the fog vector to 16 bytes following the shared push constants
+    pub _pad: [f32; 2],
+    /// Fog color in `xyz` and density in `w`.
+    pub fog: [f32; 4],
+}
+
+impl Pipeline for TranslucentBrushPipeline {
+    type VertexPushConstants = VertexPushConstants;
+    type SharedPushConstants = SharedPushConstants;
+    type FragmentPushConstants = FragmentPushConstants;
+
+    fn name() -> &'static str {
+        "brush_translucent"
+    }
+
+    fn vertex_shader() -> &'static str {
+        BrushPipeline::vertex_shader()
+    }
+
+    fn fragment_shader() -> &'static str {
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/brush_translucent.frag"
+        ))
+    }
+
+    // the layouts themselves are borrowed from the brush pipeline in `new`
+    fn bind_group_layout_descriptors() -> Vec<wgpu::BindGroupLayoutDescriptor<'static>> {
+        BrushPipeline::bind_group_layout_descriptors()
+    }
+
+    fn primitive_state() -> wgpu::PrimitiveState {
+        WorldPipelineBase::primitive_state()
+    }
+
+    fn color_target_states() -> Vec<Option<wgpu::ColorTargetState>> {
+        translucent_color_target_states()
+    }
+
+    fn depth_stencil_state() -> Option<wgpu::DepthStencilState> {
+        translucent_depth_stencil_state()
+    }
+
+    fn vertex_buffer_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
+        BrushPipeline::vertex_buffer_layouts()
+    }
+}
+
+fn translucent_color_target_states() -> Vec<Option<wgpu::ColorTargetState>> {
+    vec![Some(wgpu::ColorTargetState {
+        format: DIFFUSE_ATTACHMENT_FORMAT,
+        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+        write_mask: wgpu::ColorWrites::ALL,
+    })]
+}
+
+// depth tested against the opaque geometry, but never written, since
+// translucent surfaces are sorted back to front instead
+fn translucent_depth_stencil_state() -> Option<wgpu::DepthStencilState> {
+    Some(wgpu::DepthStencilState {
+        format: DEPTH_ATTACHMENT_FORMAT,
+        depth_write_enabled: false,
+        ..WorldPipelineBase::depth_stencil_state().unwrap()
+    })
+}
+
+/// Draws translucent alias models over the output of the deferred pass.
+///
+/// Like [`TranslucentBrushPipeline`], this shares its vertex format and bind
+/// group layouts with the opaque pipeline.
+pub struct TranslucentAliasPipeline {
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl TranslucentAliasPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        compiler: &mut shaderc::Compiler,
+        world_bind_group_layouts: &[wgpu::BindGroupLayout],
+        alias_bind_group_layouts: &[wgpu::BindGroupLayout],
+        sample_count: u32,
+    ) -> TranslucentAliasPipeline {
+        let layout_refs: Vec<_> = world_bind_group_layouts
+            .iter()
+            .chain(alias_bind_group_layouts.iter())
+            .collect();
+
+        TranslucentAliasPipeline {
+            pipeline: TranslucentAliasPipeline::recreate(
+                device,
+                compiler,
+                &layout_refs,
+                sample_count,
+            ),
+        }
+    }
+
+    pub fn rebuild(
+        &mut self,
+        device: &wgpu::Device,
+        compiler: &mut shaderc::Compiler,
+        world_bind_group_layouts: &[wgpu::BindGroupLayout],
+        alias_bind_group_layouts: &[wgpu::BindGroupLayout],
+        sample_count: u32,
+    ) {
+        let layout_refs: Vec<_> = world_bind_group_layouts
+            .iter()
+            .chain(alias_bind_group_layouts.iter())
+            .collect();
+        self.pipeline =
+            TranslucentAliasPipeline::recreate(device, compiler, &layout_refs, sample_count);
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct AliasFragmentPushConstants {
+    pub alpha: f32,
+    // aligns the fog vector to 16 bytes following the vertex push constants
+    pub _pad: [f32; 1],
+    /// Fog color in `xyz` and density in `w`.
+    pub fog: [f32; 4],
+}
+
+impl Pipeline for TranslucentAliasPipeline {
+    type VertexPushConstants = alias::VertexPushConstants;
+    type SharedPushConstants = ();
+    type FragmentPushConstants = AliasFragmentPushConstants;
+
+    fn name() -> &'static str {
+        "alias_translucent"
+    }
+
+    fn vertex_shader() -> &'static str {
+        AliasPipeline::vertex_shader()
+    }
+
+    fn fragment_shader() -> &'static str {
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/alias_translucent.frag"
+        ))
+    }
+
+    // the layouts themselves are borrowed from the alias pipeline in `new`
+    fn bind_group_layout_descriptors() -> Vec<wgpu::BindGroupLayoutDescriptor<'static>> {
+        AliasPipeline::bind_group_layout_descriptors()
+    }
+
+    fn primitive_state() -> wgpu::PrimitiveState {
+        WorldPipelineBase::primitive_state()
+    }
+
+    fn color_target_states() -> Vec<Option<wgpu::ColorTargetState>> {
+        translucent_color_target_states()
+    }
+
+    fn depth_stencil_state() -> Option<wgpu::DepthStencilState> {
+        translucent_depth_stencil_state()
+    }
+
+    fn vertex_buffer_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
+        AliasPipeline::vertex_buffer_layouts()
+    }
+}
+
+/// Draws translucent sprites over the output of the deferred pass.
+///
+/// Unlike the opaque sprite pipeline, transforms are passed as push constants
+/// so that the view-space position is available for fog.
+pub struct TranslucentSpritePipeline {
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl TranslucentSpritePipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        compiler: &mut shaderc::Compiler,
+        world_bind_group_layouts: &[wgpu::BindGroupLayout],
+        sprite_bind_group_layouts: &[wgpu::BindGroupLayout],
+        sample_count: u32,
+    ) -> TranslucentSpritePipeline {
+        let layout_refs: Vec<_> = world_bind_group_layouts
+            .iter()
+            .chain(sprite_bind_group_layouts.iter())
+            .collect();
+
+        TranslucentSpritePipeline {
+            pipeline: TranslucentSpritePipeline::recreate(
+                device,
+                compiler,
+                &layout_refs,
+                sample_count,
+            ),
+        }
+    }
+
+    pub fn rebuild(
+        &mut self,
+        device: &wgpu::Device,
+        compiler: &mut shaderc::Compiler,
+        world_bind_group_layouts: &[wgpu::BindGroupLayout],
+        sprite_bind_group_layouts: &[wgpu::BindGroupLayout],
+        sample_count: u32,
+    ) {
+        let layout_refs: Vec<_> = world_bind_group_layouts
+            .iter()
+            .chain(sprite_bind_group_layouts.iter())
+            .collect();
+        self.pipeline =
+            TranslucentSpritePipeline::recreate(device, compiler, &layout_refs, sample_count);
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SpriteFragmentPushConstants {
+    pub alpha: f32,
+    // aligns the fog vector to 16 bytes following the vertex push constants
+    pub _pad: [f32; 3],
+    /// Fog color in `xyz` and density in `w`.
+    pub fog: [f32; 4],
+}
+
+impl Pipeline for TranslucentSpritePipeline {
+    type VertexPushConstants = VertexPushConstants;
+    type SharedPushConstants = ();
+    type FragmentPushConstants = SpriteFragmentPushConstants;
+
+    fn name() -> &'static str {
+        "sprite_translucent"
+    }
+
+    fn vertex_shader() -> &'static str {
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/sprite_translucent.vert"
+        ))
+    }
+
+    fn fragment_shader() -> &'static str {
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/sprite_translucent.frag"
+        ))
+    }
+
+    // the layouts themselves are borrowed from the sprite pipeline in `new`
+    fn bind_group_layout_descriptors() -> Vec<wgpu::BindGroupLayoutDescriptor<'static>> {
+        SpritePipeline::bind_group_layout_descriptors()
+    }
+
+    fn primitive_state() -> wgpu::PrimitiveState {
+        WorldPipelineBase::primitive_state()
+    }
+
+    fn color_target_states() -> Vec<Option<wgpu::ColorTargetState>> {
+        translucent_color_target_states()
+    }
+
+    fn depth_stencil_state() -> Option<wgpu::DepthStencilState> {
+        translucent_depth_stencil_state()
+    }
+
+    fn vertex_buffer_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
+        SpritePipeline::vertex_buffer_layouts()
+    }
+}
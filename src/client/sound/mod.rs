@@ -19,7 +19,9 @@
 // SOFTWARE.
 
 mod music;
+mod offline;
 pub use music::MusicPlayer;
+pub use offline::{OfflineMixer, OfflineSinks, WavWriter};
 
 use std::{
     cell::{Cell, RefCell},
@@ -51,6 +53,35 @@ pub enum SoundError {
     Decoder(#[from] rodio::decoder::DecoderError),
 }
 
+/// Where sounds are played.
+#[derive(Clone)]
+pub enum SoundOutput {
+    /// Play sounds on an audio device.
+    Stream(OutputStreamHandle),
+
+    /// Mix sounds into an [`OfflineMixer`] to be rendered on demand.
+    Offline(OfflineSinks),
+
+    /// Discard all sounds, for clients without an audio device.
+    Null,
+}
+
+impl SoundOutput {
+    /// Create a new sink which plays on this output.
+    ///
+    /// Returns `None` for [`SoundOutput::Null`]. A sink's queue is only emptied
+    /// by playing it, so sounds are dropped instead of being appended to a sink
+    /// that would never finish.
+    pub fn sink(&self) -> Option<Sink> {
+        match *self {
+            // TODO: handle PlayError once PR accepted
+            SoundOutput::Stream(ref stream) => Some(Sink::try_new(stream).unwrap()),
+            SoundOutput::Offline(ref sinks) => Some(sinks.sink()),
+            SoundOutput::Null => None,
+        }
+    }
+}
+
 /// Data needed for sound spatialization.
 ///
 /// This struct is updated every frame.
@@ -132,44 +163,45 @@ impl AudioSource {
 
 pub struct StaticSound {
     origin: Vector3<f32>,
-    sink: RefCell<Sink>,
+    sink: Option<Sink>,
     volume: f32,
     attenuation: f32,
 }
 
 impl StaticSound {
     pub fn new(
-        stream: &OutputStreamHandle,
+        stream: &SoundOutput,
         origin: Vector3<f32>,
         src: AudioSource,
         volume: f32,
         attenuation: f32,
         listener: &Listener,
     ) -> StaticSound {
-        // TODO: handle PlayError once PR accepted
-        let sink = Sink::try_new(&stream).unwrap();
-        let infinite = src.0.clone().repeat_infinite();
-        sink.append(infinite);
-        sink.set_volume(listener.attenuate(origin, volume, attenuation));
+        let sink = stream.sink();
+        if let Some(ref sink) = sink {
+            let infinite = src.0.clone().repeat_infinite();
+            sink.append(infinite);
+            sink.set_volume(listener.attenuate(origin, volume, attenuation));
+        }
 
         StaticSound {
             origin,
-            sink: RefCell::new(sink),
+            sink,
             volume,
             attenuation,
         }
     }
 
     pub fn update(&self, listener: &Listener) {
-        let sink = self.sink.borrow_mut();
-
-        sink.set_volume(listener.attenuate(self.origin, self.volume, self.attenuation));
+        if let Some(ref sink) = self.sink {
+            sink.set_volume(listener.attenuate(self.origin, self.volume, self.attenuation));
+        }
     }
 }
 
 /// Represents a single audio channel, capable of playing one sound at a time.
 pub struct Channel {
-    stream: OutputStreamHandle,
+    stream: SoundOutput,
     sink: RefCell<Option<Sink>>,
     master_vol: Cell<f32>,
     attenuation: Cell<f32>,
@@ -177,7 +209,7 @@ pub struct Channel {
 
 impl Channel {
     /// Create a new `Channel` backed by the given `Device`.
-    pub fn new(stream: OutputStreamHandle) -> Channel {
+    pub fn new(stream: SoundOutput) -> Channel {
         Channel {
             stream,
             sink: RefCell::new(None),
@@ -202,15 +234,17 @@ impl Channel {
         self.sink.replace(None);
 
         // start the new sound
-        let new_sink = Sink::try_new(&self.stream).unwrap();
-        new_sink.append(src.0);
-        new_sink.set_volume(listener.attenuate(
-            ent_pos,
-            self.master_vol.get(),
-            self.attenuation.get(),
-        ));
-
-        self.sink.replace(Some(new_sink));
+        let new_sink = self.stream.sink();
+        if let Some(ref sink) = new_sink {
+            sink.append(src.0);
+            sink.set_volume(listener.attenuate(
+                ent_pos,
+                self.master_vol.get(),
+                self.attenuation.get(),
+            ));
+        }
+
+        self.sink.replace(new_sink);
     }
 
     pub fn update(&self, ent_pos: Vector3<f32>, listener: &Listener) {
@@ -266,13 +300,13 @@ impl EntityChannel {
 }
 
 pub struct EntityMixer {
-    stream: OutputStreamHandle,
+    stream: SoundOutput,
     // TODO: replace with an array once const type parameters are implemented
     channels: Box<[Option<EntityChannel>]>,
 }
 
 impl EntityMixer {
-    pub fn new(stream: OutputStreamHandle) -> EntityMixer {
+    pub fn new(stream: SoundOutput) -> EntityMixer {
         let mut channel_vec = Vec::new();
 
         for _ in 0..MAX_ENTITY_CHANNELS {
@@ -358,7 +392,35 @@ impl EntityMixer {
         self.channels.iter().filter_map(|e| e.as_ref())
     }
 
-    pub fn stream(&self) -> OutputStreamHandle {
+    pub fn stream(&self) -> SoundOutput {
         self.stream.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::Zero as _;
+
+    #[test]
+    fn test_null_output_frees_channel() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 1, 11025).unwrap();
+        wav.write_samples(&[0.5; 1024]).unwrap();
+        let data = wav.finish().unwrap().into_inner();
+        let src = Decoder::new(Cursor::new(data))
+            .unwrap()
+            .convert_samples()
+            .buffered();
+
+        let channel = Channel::new(SoundOutput::Null);
+        channel.play(
+            AudioSource(src),
+            Vector3::zero(),
+            &Listener::new(),
+            1.0,
+            1.0,
+        );
+        assert!(!channel.in_use());
+    }
+}
@@ -3,20 +3,23 @@ use std::{
     rc::Rc,
 };
 
-use crate::{client::sound::SoundError, common::vfs::Vfs};
+use crate::{
+    client::sound::{SoundError, SoundOutput},
+    common::vfs::Vfs,
+};
 
-use rodio::{Decoder, OutputStreamHandle, Sink, Source};
+use rodio::{Decoder, Sink, Source};
 
 /// Plays music tracks.
 pub struct MusicPlayer {
     vfs: Rc<Vfs>,
-    stream: OutputStreamHandle,
+    stream: SoundOutput,
     playing: Option<String>,
     sink: Option<Sink>,
 }
 
 impl MusicPlayer {
-    pub fn new(vfs: Rc<Vfs>, stream: OutputStreamHandle) -> MusicPlayer {
+    pub fn new(vfs: Rc<Vfs>, stream: SoundOutput) -> MusicPlayer {
         MusicPlayer {
             vfs,
             stream,
@@ -68,14 +71,26 @@ impl MusicPlayer {
 
         // stop the old track before starting the new one so there's no overlap
         self.sink = None;
-        // TODO handle PlayError
-        let new_sink = Sink::try_new(&self.stream).unwrap();
-        new_sink.append(source);
-        self.sink = Some(new_sink);
+        self.sink = self.stream.sink();
+        if let Some(ref sink) = self.sink {
+            sink.append(source);
+        }
+        self.playing = Some(name.to_owned());
 
         Ok(())
     }
 
+    /// Play music on a different output.
+    ///
+    /// The current track is restarted on the new output.
+    pub fn set_output(&mut self, stream: SoundOutput) -> Result<(), SoundError> {
+        self.stream = stream;
+        match self.playing.take() {
+            Some(name) => self.play_named(name),
+            None => Ok(()),
+        }
+    }
+
     /// Start playing the track with the given number.
     ///
     /// Note that the first actual music track is track 2; track 1 on the
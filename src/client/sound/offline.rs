@@ -0,0 +1,257 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Sound rendering without an audio device.
+
+use std::{
+    io::{self, Seek, SeekFrom, Write},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::client::sound::SoundOutput;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use rodio::{
+    dynamic_mixer::{self, DynamicMixer, DynamicMixerController},
+    queue::SourcesQueueOutput,
+    Sink, Source,
+};
+
+// size of the RIFF and format chunks plus the data chunk header
+const WAV_HEADER_LEN: u32 = 44;
+
+/// Sinks waiting to be added to an [`OfflineMixer`].
+#[derive(Clone, Default)]
+pub struct OfflineSinks(Arc<Mutex<Vec<SourcesQueueOutput<f32>>>>);
+
+impl OfflineSinks {
+    pub(super) fn sink(&self) -> Sink {
+        let (sink, output) = Sink::new_idle();
+        self.0.lock().unwrap().push(output);
+        sink
+    }
+}
+
+// A sink's output with its first sample already read.
+//
+// Until a sink's queue has been read from, it reports the format of an empty
+// placeholder rather than that of the sound appended to it, and the mixer
+// would resample the start of the sound incorrectly.
+struct PrimedOutput {
+    first: Option<f32>,
+    output: SourcesQueueOutput<f32>,
+}
+
+impl PrimedOutput {
+    fn new(mut output: SourcesQueueOutput<f32>) -> PrimedOutput {
+        PrimedOutput {
+            first: output.next(),
+            output,
+        }
+    }
+}
+
+impl Iterator for PrimedOutput {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        self.first.take().or_else(|| self.output.next())
+    }
+}
+
+impl Source for PrimedOutput {
+    fn current_frame_len(&self) -> Option<usize> {
+        let first = self.first.is_some() as usize;
+        self.output.current_frame_len().map(|len| len + first)
+    }
+
+    fn channels(&self) -> u16 {
+        self.output.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.output.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Mixes sounds on demand rather than in real time.
+pub struct OfflineMixer {
+    controller: Arc<DynamicMixerController<f32>>,
+    mixer: DynamicMixer<f32>,
+    sinks: OfflineSinks,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl OfflineMixer {
+    /// Create a new mixer along with the output that feeds it.
+    pub fn new(channels: u16, sample_rate: u32) -> (OfflineMixer, SoundOutput) {
+        let (controller, mixer) = dynamic_mixer::mixer(channels, sample_rate);
+        let sinks = OfflineSinks::default();
+        (
+            OfflineMixer {
+                controller,
+                mixer,
+                sinks: sinks.clone(),
+                channels,
+                sample_rate,
+            },
+            SoundOutput::Offline(sinks),
+        )
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Mix the next `frames` sample frames into `out`.
+    ///
+    /// Each frame holds one sample per channel. Silence is mixed when nothing
+    /// is playing.
+    pub fn mix(&mut self, frames: usize, out: &mut Vec<f32>) {
+        // sounds have been appended to new sinks by now
+        for output in self.sinks.0.lock().unwrap().drain(..) {
+            self.controller.add(PrimedOutput::new(output));
+        }
+
+        let samples = frames * self.channels as usize;
+        out.extend((0..samples).map(|_| self.mixer.next().unwrap_or(0.0)));
+    }
+}
+
+/// Writes 16-bit PCM WAV audio.
+///
+/// The lengths in the header aren't known until all samples are written, so
+/// the writer must be finished with [`finish`](WavWriter::finish).
+pub struct WavWriter<W>
+where
+    W: Write + Seek,
+{
+    writer: W,
+    data_len: u32,
+}
+
+impl<W> WavWriter<W>
+where
+    W: Write + Seek,
+{
+    pub fn new(mut writer: W, channels: u16, sample_rate: u32) -> Result<WavWriter<W>, io::Error> {
+        let block_align = channels * 2;
+
+        writer.write_all(b"RIFF")?;
+        writer.write_u32::<LittleEndian>(WAV_HEADER_LEN - 8)?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_u32::<LittleEndian>(16)?;
+        // integer PCM
+        writer.write_u16::<LittleEndian>(1)?;
+        writer.write_u16::<LittleEndian>(channels)?;
+        writer.write_u32::<LittleEndian>(sample_rate)?;
+        writer.write_u32::<LittleEndian>(sample_rate * block_align as u32)?;
+        writer.write_u16::<LittleEndian>(block_align)?;
+        writer.write_u16::<LittleEndian>(16)?;
+
+        writer.write_all(b"data")?;
+        writer.write_u32::<LittleEndian>(0)?;
+
+        Ok(WavWriter {
+            writer,
+            data_len: 0,
+        })
+    }
+
+    /// Write interleaved samples in the range [-1, 1].
+    pub fn write_samples(&mut self, samples: &[f32]) -> Result<(), io::Error> {
+        for &sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.writer.write_i16::<LittleEndian>(sample)?;
+        }
+        self.data_len += samples.len() as u32 * 2;
+
+        Ok(())
+    }
+
+    /// Fill in the header and return the underlying writer.
+    pub fn finish(mut self) -> Result<W, io::Error> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_u32::<LittleEndian>(WAV_HEADER_LEN - 8 + self.data_len)?;
+        self.writer
+            .seek(SeekFrom::Start(WAV_HEADER_LEN as u64 - 4))?;
+        self.writer.write_u32::<LittleEndian>(self.data_len)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use rodio::buffer::SamplesBuffer;
+
+    #[test]
+    fn test_offline_mixer() {
+        let (mut mixer, output) = OfflineMixer::new(2, 8000);
+        let sink = output.sink().unwrap();
+        sink.append(SamplesBuffer::new(2, 8000, vec![0.5f32; 1600]));
+
+        // a tenth of a second of sound, then silence
+        let mut out = Vec::new();
+        mixer.mix(1000, &mut out);
+        assert_eq!(out.len(), 2000);
+        assert!(out[..1600].iter().all(|&s| s == 0.5));
+        assert!(out[1600..].iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn test_wav_writer() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 2, 44100).unwrap();
+        wav.write_samples(&[0.0, 1.0, -1.0, 2.0]).unwrap();
+        let data = wav.finish().unwrap().into_inner();
+
+        assert_eq!(data.len(), WAV_HEADER_LEN as usize + 8);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(&data[4..8], &(WAV_HEADER_LEN - 8 + 8).to_le_bytes());
+        assert_eq!(&data[22..24], &2u16.to_le_bytes());
+        assert_eq!(&data[24..28], &44100u32.to_le_bytes());
+        assert_eq!(&data[40..44], &8u32.to_le_bytes());
+
+        let samples: Vec<i16> = data[44..]
+            .chunks(2)
+            .map(|c| i16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        assert_eq!(samples, vec![0, i16::MAX, -i16::MAX, i16::MAX]);
+    }
+}
@@ -0,0 +1,352 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Spectator cameras for demo playback.
+//!
+//! By default a demo is viewed through the eyes of the player who recorded it,
+//! but the camera can also be moved into another player's head, set to orbit a
+//! player from a distance, or flown freely around the level.
+
+use std::fmt;
+
+use crate::{
+    client::{
+        input::game::{Action, GameInput},
+        state::ClientState,
+        view::{angle_vectors, MouseVars},
+        MoveVars,
+    },
+    common::{
+        engine::duration_to_f32,
+        math::{self, Angles},
+        net,
+    },
+};
+
+use cgmath::{Angle as _, Deg, Vector3};
+use chrono::Duration;
+
+/// The distance at which the chase camera starts out from its target.
+pub const CHASE_DISTANCE: f32 = 100.0;
+
+const CHASE_DISTANCE_MIN: f32 = 16.0;
+const CHASE_DISTANCE_MAX: f32 = 1024.0;
+
+/// The point of view used to render a demo.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum DemoCamera {
+    /// Follow the view entity recorded in the demo.
+    #[default]
+    Recorded,
+
+    /// Look through the eyes of a player entity.
+    Player { ent_id: usize },
+
+    /// Orbit a player entity at a distance.
+    Chase {
+        ent_id: usize,
+        angles: Angles,
+        distance: f32,
+    },
+
+    /// Fly around the level independently of any entity.
+    FreeFly {
+        origin: Vector3<f32>,
+        angles: Angles,
+    },
+}
+
+impl fmt::Display for DemoCamera {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DemoCamera::Recorded => write!(f, "recorded"),
+            DemoCamera::Player { ent_id } => write!(f, "player (entity {})", ent_id),
+            DemoCamera::Chase { ent_id, .. } => write!(f, "chase (entity {})", ent_id),
+            DemoCamera::FreeFly { .. } => write!(f, "free"),
+        }
+    }
+}
+
+impl DemoCamera {
+    /// Returns the player entity this camera is attached to, if any.
+    pub fn target(&self) -> Option<usize> {
+        match *self {
+            DemoCamera::Player { ent_id } | DemoCamera::Chase { ent_id, .. } => Some(ent_id),
+            _ => None,
+        }
+    }
+
+    /// Returns the entity the camera is inside of, which should not be drawn.
+    pub fn hidden_entity_id(&self, state: &ClientState) -> Option<usize> {
+        match *self {
+            DemoCamera::Recorded => Some(state.view_entity_id()),
+            DemoCamera::Player { ent_id } => Some(ent_id),
+            _ => None,
+        }
+    }
+
+    /// Calculate the origin and angles of the camera.
+    ///
+    /// The positions of other entities are interpolated before this is called,
+    /// so cameras attached to them move smoothly.
+    pub fn eye(&self, state: &ClientState) -> (Vector3<f32>, Angles) {
+        let recorded = || {
+            let angles = state.entities[state.view_entity_id()].angles;
+            (
+                state.view.final_origin(),
+                Angles {
+                    pitch: angles.x,
+                    roll: angles.z,
+                    yaw: angles.y,
+                },
+            )
+        };
+
+        match *self {
+            DemoCamera::Recorded => recorded(),
+
+            DemoCamera::Player { ent_id } => {
+                if ent_id == state.view_entity_id() || ent_id >= state.entities.len() {
+                    return recorded();
+                }
+
+                let ent = &state.entities[ent_id];
+                (
+                    ent.origin + Vector3::new(0.0, 0.0, net::DEFAULT_VIEWHEIGHT),
+                    Angles {
+                        // the server scales player model pitch by -1/3 so the
+                        // model doesn't lean too far
+                        pitch: ent.angles.x * -3.0,
+                        roll: ent.angles.z,
+                        yaw: ent.angles.y,
+                    },
+                )
+            }
+
+            DemoCamera::Chase {
+                ent_id,
+                angles,
+                distance,
+            } => {
+                if ent_id >= state.entities.len() {
+                    return recorded();
+                }
+
+                let target =
+                    state.entities[ent_id].origin + Vector3::new(0.0, 0.0, net::DEFAULT_VIEWHEIGHT);
+                let (forward, _) = angle_vectors(angles);
+                (target - forward * distance, angles)
+            }
+
+            DemoCamera::FreeFly { origin, angles } => (origin, angles),
+        }
+    }
+
+    /// Steer the camera.
+    ///
+    /// The free-fly camera moves with the movement actions and turns with the
+    /// mouse and the turning actions. The chase camera orbits its target the
+    /// same way, using forward and back to change its distance. Other cameras
+    /// ignore input.
+    pub fn handle_input(
+        &mut self,
+        game_input: &GameInput,
+        frame_time: Duration,
+        move_vars: MoveVars,
+        mouse_vars: MouseVars,
+    ) {
+        let frame_time_f32 = duration_to_f32(frame_time);
+
+        match *self {
+            DemoCamera::Chase {
+                ref mut angles,
+                ref mut distance,
+                ..
+            } => {
+                turn(angles, game_input, frame_time_f32, &move_vars, mouse_vars);
+
+                let (forward, _, _) = move_speeds(game_input, &move_vars);
+                *distance = (*distance - forward * frame_time_f32)
+                    .clamp(CHASE_DISTANCE_MIN, CHASE_DISTANCE_MAX);
+            }
+
+            DemoCamera::FreeFly {
+                ref mut origin,
+                ref mut angles,
+            } => {
+                turn(angles, game_input, frame_time_f32, &move_vars, mouse_vars);
+
+                let (forward_speed, side_speed, up_speed) = move_speeds(game_input, &move_vars);
+                let (forward, right) = angle_vectors(*angles);
+                *origin += (forward * forward_speed
+                    + right * side_speed
+                    + Vector3::new(0.0, 0.0, up_speed))
+                    * frame_time_f32;
+            }
+
+            _ => (),
+        }
+    }
+
+    /// Switch to the free-fly camera, starting from the current viewpoint.
+    pub fn free_fly(&mut self, state: &ClientState) {
+        let (origin, angles) = self.eye(state);
+        *self = DemoCamera::FreeFly {
+            origin,
+            angles: Angles {
+                roll: Deg(0.0),
+                ..angles
+            },
+        };
+    }
+
+    /// Switch to the chase camera, following the current target or the
+    /// recorded view entity.
+    pub fn chase(&mut self, state: &ClientState) {
+        let ent_id = self.target().unwrap_or_else(|| state.view_entity_id());
+        let (_, eye_angles) = self.eye(state);
+        *self = DemoCamera::Chase {
+            ent_id,
+            angles: Angles {
+                pitch: Deg(15.0),
+                roll: Deg(0.0),
+                yaw: eye_angles.yaw,
+            },
+            distance: CHASE_DISTANCE,
+        };
+    }
+
+    /// Switch to the point of view of the current target or the recorded view
+    /// entity.
+    pub fn first_person(&mut self, state: &ClientState) {
+        let ent_id = self.target().unwrap_or_else(|| state.view_entity_id());
+        *self = DemoCamera::Player { ent_id };
+    }
+
+    /// Move the camera to the next (or previous) player in the game.
+    ///
+    /// The chase camera keeps chasing, and any other camera switches to the
+    /// player's point of view. Returns the new target, or `None` if there are
+    /// no players to watch.
+    pub fn cycle_player(&mut self, state: &ClientState, forward: bool) -> Option<usize> {
+        let players: Vec<usize> = (1..=state.max_players)
+            .filter(|&id| {
+                state.player_info[id - 1].is_some()
+                    && id < state.entities.len()
+                    && state.entities[id].model_id != 0
+            })
+            .collect();
+        let current = self.target().unwrap_or_else(|| state.view_entity_id());
+        let next = next_player(&players, current, forward)?;
+
+        match *self {
+            DemoCamera::Chase { ref mut ent_id, .. } => *ent_id = next,
+            _ => *self = DemoCamera::Player { ent_id: next },
+        }
+
+        Some(next)
+    }
+}
+
+/// Choose the player after (or before) `current` in `players`, wrapping around.
+fn next_player(players: &[usize], current: usize, forward: bool) -> Option<usize> {
+    if forward {
+        players
+            .iter()
+            .find(|&&id| id > current)
+            .or_else(|| players.first())
+            .copied()
+    } else {
+        players
+            .iter()
+            .rev()
+            .find(|&&id| id < current)
+            .or_else(|| players.last())
+            .copied()
+    }
+}
+
+/// Calculate forward, sideways and upward speeds from the movement actions.
+fn move_speeds(game_input: &GameInput, move_vars: &MoveVars) -> (f32, f32, f32) {
+    let factor = |positive: Action, negative: Action| {
+        (game_input.action_state(positive) as i32 - game_input.action_state(negative) as i32) as f32
+    };
+
+    let mut forward = move_vars.cl_forwardspeed
+        * game_input.action_state(Action::Forward) as i32 as f32
+        - move_vars.cl_backspeed * game_input.action_state(Action::Back) as i32 as f32;
+    let mut side = move_vars.cl_sidespeed * factor(Action::MoveRight, Action::MoveLeft);
+    let mut up = move_vars.cl_upspeed * factor(Action::MoveUp, Action::MoveDown);
+
+    if game_input.action_state(Action::Speed) {
+        forward *= move_vars.cl_movespeedkey;
+        side *= move_vars.cl_movespeedkey;
+        up *= move_vars.cl_movespeedkey;
+    }
+
+    (forward, side, up)
+}
+
+/// Turn the camera with the turning actions and the mouse.
+fn turn(
+    angles: &mut Angles,
+    game_input: &GameInput,
+    frame_time: f32,
+    move_vars: &MoveVars,
+    mouse_vars: MouseVars,
+) {
+    let speed = if game_input.action_state(Action::Speed) {
+        frame_time * move_vars.cl_anglespeedkey
+    } else {
+        frame_time
+    };
+
+    let left = game_input.action_state(Action::Left) as i32 as f32;
+    let right = game_input.action_state(Action::Right) as i32 as f32;
+    angles.yaw += Deg(speed * move_vars.cl_yawspeed * (left - right));
+
+    let up = game_input.action_state(Action::LookUp) as i32 as f32;
+    let down = game_input.action_state(Action::LookDown) as i32 as f32;
+    angles.pitch += Deg(speed * move_vars.cl_pitchspeed * (down - up));
+
+    // spectator cameras always use mouse look
+    let (mouse_x, mouse_y) = game_input.mouse_delta();
+    angles.pitch += Deg(mouse_y as f32 * mouse_vars.m_pitch * mouse_vars.sensitivity);
+    angles.yaw -= Deg(mouse_x as f32 * mouse_vars.m_yaw * mouse_vars.sensitivity);
+
+    angles.yaw = angles.yaw.normalize();
+    angles.pitch = math::clamp_deg(angles.pitch, Deg(-89.0), Deg(89.0));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_player() {
+        let players = [1, 3, 4];
+        assert_eq!(next_player(&players, 1, true), Some(3));
+        assert_eq!(next_player(&players, 4, true), Some(1));
+        assert_eq!(next_player(&players, 2, true), Some(3));
+        assert_eq!(next_player(&players, 3, false), Some(1));
+        assert_eq!(next_player(&players, 1, false), Some(4));
+        assert_eq!(next_player(&[], 1, true), None);
+    }
+}
@@ -7,10 +7,12 @@ use crate::{
             particle::{Particle, Particles, TrailKind, MAX_PARTICLES},
             Beam, ClientEntity, Light, LightDesc, Lights, MAX_BEAMS, MAX_LIGHTS, MAX_TEMP_ENTITIES,
         },
+        fog::{Fog, FogParams},
         input::game::{Action, GameInput},
         render::Camera,
-        sound::{AudioSource, EntityMixer, Listener, StaticSound},
-        view::{IdleVars, KickVars, MouseVars, RollVars, View},
+        sound::{AudioSource, EntityMixer, Listener, SoundOutput, StaticSound},
+        spectator::DemoCamera,
+        view::{angle_vectors, IdleVars, KickVars, MouseVars, RollVars, View},
         ClientError, ColorShiftCode, IntermissionKind, MoveVars, MAX_STATS,
     },
     common::{
@@ -21,19 +23,19 @@ use crate::{
             self, BeamEntityKind, ButtonFlags, ColorShift, EntityEffects, ItemFlags, PlayerData,
             PointEntityKind, TempEntity,
         },
+        parse,
         vfs::Vfs,
     },
 };
 use arrayvec::ArrayVec;
 use cgmath::{Angle as _, Deg, InnerSpace as _, Matrix4, Vector3, Zero as _};
 use chrono::Duration;
-use net::{ClientCmd, ClientStat, EntityState, EntityUpdate, PlayerColor};
+use net::{ClientCmd, ClientStat, EntityState, EntityUpdate, PlayerColor, ServerCmd};
 use rand::{
     distributions::{Distribution as _, Uniform},
     rngs::SmallRng,
     SeedableRng,
 };
-use rodio::OutputStreamHandle;
 
 const CACHED_SOUND_NAMES: &[&'static str] = &[
     "hknight/hit.wav",
@@ -45,6 +47,40 @@ const CACHED_SOUND_NAMES: &[&'static str] = &[
     "wizard/hit.wav",
 ];
 
+/// Returns the skybox and fog settings from the worldspawn entity's `sky` and
+/// `fog` keys.
+fn read_worldspawn(ent_string: &str) -> (Option<String>, Option<FogParams>) {
+    let entities = match parse::entities(ent_string) {
+        Ok(e) => e,
+        Err(e) => {
+            warn!("Couldn't parse entity string: {}", e);
+            return (None, None);
+        }
+    };
+
+    let worldspawn = match entities
+        .iter()
+        .find(|ent| ent.get("classname") == Some(&"worldspawn"))
+    {
+        Some(w) => w,
+        None => return (None, None),
+    };
+
+    let skybox = worldspawn
+        .get("sky")
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_string());
+    let fog = worldspawn.get("fog").and_then(|value| {
+        let fog = FogParams::parse(value);
+        if fog.is_none() {
+            warn!("Invalid worldspawn fog: {}", value);
+        }
+        fog
+    });
+
+    (skybox, fog)
+}
+
 pub struct PlayerInfo {
     pub name: String,
     pub frags: i32,
@@ -75,6 +111,8 @@ pub struct ClientState {
     pub entities: Vec<ClientEntity>,
     pub static_entities: Vec<ClientEntity>,
     pub temp_entities: Vec<ClientEntity>,
+    // first-person weapon model, repositioned per-frame
+    pub viewmodel: ClientEntity,
     // dynamic point lights
     pub lights: Lights,
     // lightning bolts and grappling hook cable
@@ -111,6 +149,9 @@ pub struct ClientState {
     pub on_ground: bool,
     pub in_water: bool,
     pub intermission: Option<IntermissionKind>,
+    // cubemap skybox name, from worldspawn, svc_skybox or the sky command
+    pub skybox: Option<String>,
+    pub fog: Fog,
     pub start_time: Duration,
     pub completion_time: Option<Duration>,
 
@@ -120,7 +161,7 @@ pub struct ClientState {
 
 impl ClientState {
     // TODO: add parameter for number of player slots and reserve them in entity list
-    pub fn new(stream: OutputStreamHandle) -> ClientState {
+    pub fn new(stream: SoundOutput) -> ClientState {
         ClientState {
             rng: SmallRng::from_entropy(),
             models: vec![Model::none()],
@@ -131,6 +172,7 @@ impl ClientState {
             entities: Vec::new(),
             static_entities: Vec::new(),
             temp_entities: Vec::new(),
+            viewmodel: ClientEntity::uninitialized(),
             lights: Lights::with_capacity(MAX_LIGHTS),
             beams: [None; MAX_BEAMS],
             particles: Particles::with_capacity(MAX_PARTICLES),
@@ -169,6 +211,8 @@ impl ClientState {
             on_ground: false,
             in_water: false,
             intermission: None,
+            skybox: None,
+            fog: Fog::default(),
             start_time: Duration::zero(),
             completion_time: None,
             mixer: EntityMixer::new(stream),
@@ -178,7 +222,7 @@ impl ClientState {
 
     pub fn from_server_info(
         vfs: &Vfs,
-        stream: OutputStreamHandle,
+        stream: SoundOutput,
         max_clients: u8,
         model_precache: Vec<String>,
         sound_precache: Vec<String>,
@@ -187,11 +231,21 @@ impl ClientState {
         let mut models = Vec::with_capacity(model_precache.len());
         models.push(Model::none());
         let mut model_names = HashMap::new();
+        let mut skybox = None;
+        let mut fog = None;
         for mod_name in model_precache {
             // BSPs can have more than one model
             if mod_name.ends_with(".bsp") {
                 let bsp_data = vfs.open(&mod_name)?;
-                let (mut brush_models, _) = bsp::load(bsp_data).unwrap();
+                let (mut brush_models, ent_string) = bsp::load(bsp_data).unwrap();
+
+                // the first BSP is the world, whose worldspawn may set the sky and fog
+                if models.len() == 1 {
+                    let (world_skybox, world_fog) = read_worldspawn(&ent_string);
+                    skybox = world_skybox;
+                    fog = world_fog;
+                }
+
                 for bmodel in brush_models.drain(..) {
                     let id = models.len();
                     let name = bmodel.name().to_owned();
@@ -227,10 +281,29 @@ impl ClientState {
             sounds,
             cached_sounds,
             max_players: max_clients as usize,
+            skybox,
+            fog: Fog::new(fog.unwrap_or_else(FogParams::none)),
             ..ClientState::new(stream)
         })
     }
 
+    /// Create the state for a level without loading any of its models or sounds.
+    ///
+    /// Each precached model is replaced by an empty one, so entities can be
+    /// followed but not drawn. This is enough for tools that only track the
+    /// progress of a game, like demo analysis.
+    pub fn without_game_data(max_clients: u8, model_precache: &[String]) -> ClientState {
+        // model IDs start at 1, and each submodel has its own precache entry
+        let mut models = vec![Model::none()];
+        models.extend(model_precache.iter().map(|_| Model::none()));
+
+        ClientState {
+            models,
+            max_players: max_clients as usize,
+            ..ClientState::new(SoundOutput::Null)
+        }
+    }
+
     /// Advance the simulation time by the specified amount.
     ///
     /// This method does not change the state of the world to match the new time value.
@@ -304,7 +377,7 @@ impl ClientState {
     ///   message
     /// - Spawning particles on entities with particle effects
     /// - Spawning dynamic lights on entities with lighting effects
-    pub fn update_entities(&mut self) -> Result<(), ClientError> {
+    pub fn update_entities(&mut self, hidden_entity_id: Option<usize>) -> Result<(), ClientError> {
         lazy_static! {
             static ref MFLASH_DIMLIGHT_DISTRIBUTION: Uniform<f32> = Uniform::new(200.0, 232.0);
             static ref BRIGHTLIGHT_DISTRIBUTION: Uniform<f32> = Uniform::new(400.0, 432.0);
@@ -465,8 +538,8 @@ impl ClientState {
                     .create_trail(self.time, prev_origin, ent.origin, kind, false);
             }
 
-            // don't render the player model
-            if self.view.entity_id() != ent_id {
+            // don't render the model the camera is inside of
+            if hidden_entity_id != Some(ent_id) {
                 // mark entity for rendering
                 self.visible_entity_ids.push(ent_id);
             }
@@ -727,6 +800,7 @@ impl ClientState {
         kick_vars: KickVars,
         roll_vars: RollVars,
         bob_vars: BobVars,
+        gun_offset: Vector3<f32>,
     ) {
         self.view.calc_final_angles(
             self.time,
@@ -742,6 +816,34 @@ impl ClientState {
             self.velocity,
             bob_vars,
         );
+        self.update_viewmodel(gun_offset);
+    }
+
+    /// Moves the viewmodel in front of the camera and sets its model and frame
+    /// from the player's current weapon.
+    ///
+    /// `gun_offset` is applied along the viewmodel's forward, right and up
+    /// axes, in that order.
+    fn update_viewmodel(&mut self, gun_offset: Vector3<f32>) {
+        let model_id = self.stats[ClientStat::Weapon as usize] as usize;
+        let frame_id = self.stats[ClientStat::WeaponFrame as usize] as usize;
+        if self.viewmodel.model_id != model_id {
+            self.viewmodel.set_model(model_id, frame_id);
+        } else {
+            self.viewmodel.set_frame(self.time, frame_id);
+        }
+
+        let angles = self.view.viewmodel_angle();
+        let (forward, right) = angle_vectors(angles);
+        let up = right.cross(forward);
+        self.viewmodel.origin = self.view.viewmodel_origin()
+            + forward * gun_offset.x
+            + right * gun_offset.y
+            + up * gun_offset.z;
+
+        // unlike other entities, the viewmodel's pitch follows the view
+        // convention, where positive pitch looks down
+        self.viewmodel.angles = Vector3::new(angles.pitch, angles.yaw, angles.roll);
     }
 
     /// Spawn an entity with the given ID, also spawning any uninitialized
@@ -788,6 +890,7 @@ impl ClientState {
                 colormap: update.colormap.unwrap_or(0),
                 skin_id: update.skin_id.unwrap_or(0) as usize,
                 effects: EntityEffects::empty(),
+                alpha: 1.0,
             };
 
             self.spawn_entities(id, baseline)?;
@@ -807,20 +910,196 @@ impl ClientState {
             }
         }
 
-        if let Some(_c) = entity.colormap() {
-            // only players may have custom colormaps
-            if id > self.max_players {
-                warn!(
-                    "Server attempted to set colormap on entity {}, which is not a player",
-                    id
-                );
+        let colors = match entity.colormap() {
+            Some(c) => {
+                // colormaps may only refer to players
+                let player_id = c as usize - 1;
+                if player_id >= self.max_players {
+                    warn!(
+                        "Server attempted to set colormap {} on entity {}, which is not a player",
+                        c, id
+                    );
+                }
+
+                self.player_info
+                    .get(player_id)
+                    .and_then(|info| info.as_ref())
+                    .map(|info| info.colors)
             }
-            // TODO: set player custom colormaps
-        }
+
+            None => None,
+        };
+        entity.set_colors(colors);
 
         Ok(())
     }
 
+    /// Applies a server command that only updates the client state.
+    ///
+    /// This covers the scoreboard, stats, entity baselines and timing. Other
+    /// commands are returned unhandled, since they need game data, sound or the
+    /// console.
+    pub fn handle_server_cmd(&mut self, cmd: ServerCmd) -> Result<Option<ServerCmd>, ClientError> {
+        match cmd {
+            ServerCmd::PlayerData(player_data) => self.update_player(player_data),
+
+            ServerCmd::FoundSecret => self.stats[ClientStat::FoundSecrets as usize] += 1,
+            ServerCmd::Intermission => {
+                self.intermission = Some(IntermissionKind::Intermission);
+                self.completion_time = Some(self.time);
+            }
+            ServerCmd::KilledMonster => self.stats[ClientStat::KilledMonsters as usize] += 1,
+
+            ServerCmd::SetView { ent_id } => {
+                if ent_id <= 0 {
+                    Err(ClientError::InvalidViewEntity(ent_id as usize))?;
+                }
+
+                self.set_view_entity(ent_id as usize)?;
+            }
+
+            ServerCmd::SpawnBaseline {
+                ent_id,
+                model_id,
+                frame_id,
+                colormap,
+                skin_id,
+                origin,
+                angles,
+            } => {
+                self.spawn_entities(
+                    ent_id as usize,
+                    EntityState {
+                        model_id: model_id as usize,
+                        frame_id: frame_id as usize,
+                        colormap,
+                        skin_id: skin_id as usize,
+                        origin,
+                        angles,
+                        effects: EntityEffects::empty(),
+                        alpha: 1.0,
+                    },
+                )?;
+            }
+
+            ServerCmd::SpawnBaseline2 {
+                ent_id,
+                model_id,
+                frame_id,
+                colormap,
+                skin_id,
+                origin,
+                angles,
+                alpha,
+            } => {
+                self.spawn_entities(
+                    ent_id as usize,
+                    EntityState {
+                        model_id: model_id as usize,
+                        frame_id: frame_id as usize,
+                        colormap,
+                        skin_id: skin_id as usize,
+                        origin,
+                        angles,
+                        effects: EntityEffects::empty(),
+                        alpha: net::decode_entity_alpha(alpha),
+                    },
+                )?;
+            }
+
+            ServerCmd::Time { time } => {
+                self.msg_times[1] = self.msg_times[0];
+                self.msg_times[0] = engine::duration_from_f32(time);
+            }
+
+            ServerCmd::UpdateColors {
+                player_id,
+                new_colors,
+            } => {
+                let player_id = player_id as usize;
+                self.check_player_id(player_id)?;
+
+                match self.player_info[player_id] {
+                    Some(ref mut info) => {
+                        trace!(
+                            "Player {} (ID {}) colors: {:?} -> {:?}",
+                            info.name,
+                            player_id,
+                            info.colors,
+                            new_colors,
+                        );
+                        info.colors = new_colors;
+                    }
+
+                    None => {
+                        error!(
+                            "Attempted to set colors on nonexistent player with ID {}",
+                            player_id
+                        );
+                    }
+                }
+            }
+
+            ServerCmd::UpdateFrags {
+                player_id,
+                new_frags,
+            } => {
+                let player_id = player_id as usize;
+                self.check_player_id(player_id)?;
+
+                match self.player_info[player_id] {
+                    Some(ref mut info) => {
+                        trace!(
+                            "Player {} (ID {}) frags: {} -> {}",
+                            &info.name,
+                            player_id,
+                            info.frags,
+                            new_frags
+                        );
+                        info.frags = new_frags as i32;
+                    }
+                    None => {
+                        error!(
+                            "Attempted to set frags on nonexistent player with ID {}",
+                            player_id
+                        );
+                    }
+                }
+            }
+
+            ServerCmd::UpdateName {
+                player_id,
+                new_name,
+            } => {
+                let player_id = player_id as usize;
+                self.check_player_id(player_id)?;
+
+                if let Some(ref mut info) = self.player_info[player_id] {
+                    // if this player is already connected, it's a name change
+                    debug!("Player {} has changed name to {}", &info.name, &new_name);
+                    info.name = new_name.to_owned();
+                } else {
+                    // if this player is not connected, it's a join
+                    debug!("Player {} with ID {} has joined", &new_name, player_id);
+                    self.player_info[player_id] = Some(PlayerInfo {
+                        name: new_name.to_owned(),
+                        colors: PlayerColor::new(0, 0),
+                        frags: 0,
+                    });
+                }
+            }
+
+            ServerCmd::UpdateStat { stat, value } => {
+                debug!("{:?}: {} -> {}", stat, self.stats[stat as usize], value);
+                self.stats[stat as usize] = value;
+            }
+
+            cmd => return Ok(Some(cmd)),
+        }
+
+        Ok(None)
+    }
+
     pub fn spawn_temp_entity(&mut self, temp_entity: &TempEntity) {
         lazy_static! {
             static ref ZERO_ONE_DISTRIBUTION: Uniform<f32> = Uniform::new(0.0, 1.0);
@@ -1200,11 +1479,29 @@ impl ClientState {
         &self.models
     }
 
-    pub fn viewmodel_id(&self) -> usize {
-        match self.stats[ClientStat::Weapon as usize] as usize {
-            0 => 0,
-            x => x - 1,
+    /// Returns the name of the cubemap skybox, or `None` to draw the map's
+    /// scrolling sky texture instead.
+    pub fn skybox(&self) -> Option<&str> {
+        self.skybox.as_deref()
+    }
+
+    /// Returns the fog settings for the current time.
+    pub fn fog(&self) -> FogParams {
+        self.fog.params(self.time)
+    }
+
+    /// Returns the first-person weapon model, or `None` if it shouldn't be
+    /// drawn.
+    pub fn viewmodel(&self) -> Option<&ClientEntity> {
+        if self.viewmodel.model_id == 0
+            || self.intermission.is_some()
+            || self.items.contains(ItemFlags::INVISIBILITY)
+            || self.stats[ClientStat::Health as usize] <= 0
+        {
+            return None;
         }
+
+        Some(&self.viewmodel)
     }
 
     pub fn iter_visible_entities(&self) -> impl Iterator<Item = &ClientEntity> + Clone {
@@ -1240,16 +1537,12 @@ impl ClientState {
         )
     }
 
-    pub fn demo_camera(&self, aspect: f32, fov: Deg<f32>) -> Camera {
+    pub fn demo_camera(&self, demo_camera: &DemoCamera, aspect: f32, fov: Deg<f32>) -> Camera {
         let fov_y = math::fov_x_to_fov_y(fov, aspect).unwrap();
-        let angles = self.entities[self.view.entity_id()].angles;
+        let (origin, angles) = demo_camera.eye(self);
         Camera::new(
-            self.view.final_origin(),
-            Angles {
-                pitch: angles.x,
-                roll: angles.z,
-                yaw: angles.y,
-            },
+            origin,
+            angles,
             cgmath::perspective(fov_y, aspect, 4.0, 4096.0),
         )
     }
@@ -0,0 +1,235 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Demo benchmarking.
+//!
+//! In a timedemo, one demo message is played back per frame and the frame rate
+//! limit is lifted, so the time taken to play the demo depends only on how fast
+//! the client can process and render each frame.
+
+use std::{
+    fmt,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::common::engine;
+
+use chrono::Duration;
+use serde::Serialize;
+
+/// The upper bounds of the frame time histogram buckets, in milliseconds.
+///
+/// Frames longer than the last bound are counted in a final bucket.
+pub const HISTOGRAM_BOUNDS_MS: [i64; 8] = [1, 2, 4, 8, 16, 32, 64, 128];
+
+/// The number of frames taking a given range of time.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct HistogramBucket {
+    /// The shortest frame time counted in this bucket, in milliseconds.
+    pub min_ms: i64,
+
+    /// The frame time this bucket ends at, in milliseconds, or `None` if it
+    /// has no upper bound.
+    pub max_ms: Option<i64>,
+
+    pub frames: usize,
+}
+
+/// The results of a timedemo.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TimeDemoReport {
+    pub demo: String,
+    pub frames: usize,
+    pub seconds: f32,
+    pub avg_fps: f32,
+    pub min_fps: f32,
+    pub max_fps: f32,
+    pub histogram: Vec<HistogramBucket>,
+}
+
+impl TimeDemoReport {
+    /// Write the report to a file as JSON.
+    pub fn write_json<P>(&self, path: P) -> Result<(), io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for TimeDemoReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} frames {:.1} seconds {:.1} fps",
+            self.frames, self.seconds, self.avg_fps
+        )?;
+        writeln!(
+            f,
+            "min {:.1} fps, max {:.1} fps",
+            self.min_fps, self.max_fps
+        )?;
+
+        for bucket in self.histogram.iter() {
+            let range = match bucket.max_ms {
+                Some(max) => format!("{:>3}-{:<3}ms", bucket.min_ms, max),
+                None => format!("{:>3}+   ms", bucket.min_ms),
+            };
+            writeln!(f, "{} {}", range, bucket.frames)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Frame timing for a demo being benchmarked.
+#[derive(Debug)]
+pub struct TimeDemo {
+    demo: String,
+    output: Option<PathBuf>,
+    frame_times: Vec<Duration>,
+}
+
+impl TimeDemo {
+    /// Begin timing the named demo.
+    ///
+    /// If `output` is given, the report should also be written there as JSON.
+    pub fn new<S>(demo: S, output: Option<PathBuf>) -> TimeDemo
+    where
+        S: AsRef<str>,
+    {
+        TimeDemo {
+            demo: demo.as_ref().to_owned(),
+            output,
+            frame_times: Vec::new(),
+        }
+    }
+
+    /// Returns the path the JSON report should be written to, if any.
+    pub fn output(&self) -> Option<&Path> {
+        self.output.as_deref()
+    }
+
+    /// Record the duration of one frame.
+    pub fn record_frame(&mut self, frame_time: Duration) {
+        self.frame_times.push(frame_time);
+    }
+
+    /// Summarize the recorded frames.
+    pub fn report(&self) -> TimeDemoReport {
+        let frames = self.frame_times.len();
+        let total = self
+            .frame_times
+            .iter()
+            .fold(Duration::zero(), |acc, &t| acc + t);
+        let seconds = engine::duration_to_f32(total);
+
+        let fps = |t: Duration| match engine::duration_to_f32(t) {
+            s if s > 0.0 => 1.0 / s,
+            _ => 0.0,
+        };
+
+        let mut histogram = Vec::with_capacity(HISTOGRAM_BOUNDS_MS.len() + 1);
+        let mut min_ms = 0;
+        for &max_ms in HISTOGRAM_BOUNDS_MS.iter() {
+            histogram.push(HistogramBucket {
+                min_ms,
+                max_ms: Some(max_ms),
+                frames: 0,
+            });
+            min_ms = max_ms;
+        }
+        histogram.push(HistogramBucket {
+            min_ms,
+            max_ms: None,
+            frames: 0,
+        });
+
+        for t in self.frame_times.iter() {
+            let micros = t.num_microseconds().unwrap_or(i64::MAX);
+            let bucket = HISTOGRAM_BOUNDS_MS
+                .iter()
+                .position(|&max_ms| micros < max_ms * 1000)
+                .unwrap_or(HISTOGRAM_BOUNDS_MS.len());
+            histogram[bucket].frames += 1;
+        }
+
+        TimeDemoReport {
+            demo: self.demo.clone(),
+            frames,
+            seconds,
+            avg_fps: if seconds > 0.0 {
+                frames as f32 / seconds
+            } else {
+                0.0
+            },
+            // the slowest frame gives the lowest frame rate
+            min_fps: self
+                .frame_times
+                .iter()
+                .max()
+                .map(|&t| fps(t))
+                .unwrap_or(0.0),
+            max_fps: self
+                .frame_times
+                .iter()
+                .min()
+                .map(|&t| fps(t))
+                .unwrap_or(0.0),
+            histogram,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timedemo_report() {
+        let mut td = TimeDemo::new("demo1", None);
+        for ms in [10, 10, 20, 40, 200].iter() {
+            td.record_frame(Duration::milliseconds(*ms));
+        }
+
+        let report = td.report();
+        assert_eq!(report.frames, 5);
+        assert!((report.seconds - 0.28).abs() < 1e-5);
+        assert!((report.avg_fps - 5.0 / 0.28).abs() < 1e-3);
+        assert!((report.min_fps - 5.0).abs() < 1e-3);
+        assert!((report.max_fps - 100.0).abs() < 1e-3);
+
+        let counts: Vec<usize> = report.histogram.iter().map(|b| b.frames).collect();
+        assert_eq!(counts, vec![0, 0, 0, 0, 2, 1, 1, 0, 1]);
+        assert_eq!(report.histogram.last().unwrap().max_ms, None);
+    }
+
+    #[test]
+    fn test_timedemo_report_empty() {
+        let report = TimeDemo::new("demo1", None).report();
+        assert_eq!(report.frames, 0);
+        assert_eq!(report.avg_fps, 0.0);
+        assert_eq!(report.histogram.iter().map(|b| b.frames).sum::<usize>(), 0);
+    }
+}
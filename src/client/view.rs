@@ -42,6 +42,12 @@ pub struct View {
 
     // final origin accounting for view bob
     final_origin: Vector3<f32>,
+
+    // viewmodel angles, which sway against the idle motion of the view
+    viewmodel_angles: Angles,
+
+    // viewmodel origin accounting for view bob
+    viewmodel_origin: Vector3<f32>,
 }
 
 impl View {
@@ -57,6 +63,8 @@ impl View {
             punch_angles: Angles::zero(),
             final_angles: Angles::zero(),
             final_origin: Vector3::zero(),
+            viewmodel_angles: Angles::zero(),
+            viewmodel_origin: Vector3::zero(),
         }
     }
 
@@ -199,8 +207,10 @@ impl View {
         }
         let idle_angles = idle(time, idle_vars);
 
-        self.final_angles =
-            self.input_angles + move_angles + damage_angles + self.punch_angles + idle_angles;
+        // the viewmodel ignores idle sway and punch, so it drifts against the
+        // view while idling and stays put when the view is kicked
+        self.viewmodel_angles = self.input_angles + move_angles + damage_angles;
+        self.final_angles = self.viewmodel_angles + self.punch_angles + idle_angles;
     }
 
     pub fn final_angles(&self) -> Angles {
@@ -217,8 +227,13 @@ impl View {
         // offset the view by 1/32 unit to keep it from intersecting liquid planes
         let plane_offset = Vector3::new(1.0 / 32.0, 1.0 / 32.0, 1.0 / 32.0);
         let height_offset = Vector3::new(0.0, 0.0, self.view_height);
-        let bob_offset = Vector3::new(0.0, 0.0, bob(time, velocity, bob_vars));
+        let bob = bob(time, velocity, bob_vars);
+        let bob_offset = Vector3::new(0.0, 0.0, bob);
         self.final_origin = origin + plane_offset + height_offset + bob_offset;
+
+        // the viewmodel also bobs forward and back
+        let (forward, _) = angle_vectors(self.input_angles);
+        self.viewmodel_origin = self.final_origin + forward * bob * 0.4;
     }
 
     pub fn final_origin(&self) -> Vector3<f32> {
@@ -226,11 +241,26 @@ impl View {
     }
 
     pub fn viewmodel_angle(&self) -> Angles {
-        // TODO
-        self.final_angles()
+        self.viewmodel_angles
+    }
+
+    pub fn viewmodel_origin(&self) -> Vector3<f32> {
+        self.viewmodel_origin
     }
 }
 
+/// Calculate the forward and right vectors for a set of view angles, ignoring
+/// roll.
+pub fn angle_vectors(angles: Angles) -> (Vector3<f32>, Vector3<f32>) {
+    let (sin_pitch, cos_pitch) = angles.pitch.sin_cos();
+    let (sin_yaw, cos_yaw) = angles.yaw.sin_cos();
+
+    (
+        Vector3::new(cos_pitch * cos_yaw, cos_pitch * sin_yaw, -sin_pitch),
+        Vector3::new(sin_yaw, -cos_yaw, 0.0),
+    )
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct MouseVars {
     pub m_pitch: f32,
@@ -308,3 +338,69 @@ pub fn idle(time: Duration, vars: IdleVars) -> Angles {
 
     Angles { pitch, roll, yaw }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_angle_vectors() {
+        let (forward, right) = angle_vectors(Angles {
+            pitch: Deg(0.0),
+            roll: Deg(0.0),
+            yaw: Deg(90.0),
+        });
+        assert!((forward - Vector3::new(0.0, 1.0, 0.0)).magnitude() < 1e-5);
+        assert!((right - Vector3::new(1.0, 0.0, 0.0)).magnitude() < 1e-5);
+
+        // positive pitch looks down
+        let (forward, _) = angle_vectors(Angles {
+            pitch: Deg(90.0),
+            roll: Deg(0.0),
+            yaw: Deg(0.0),
+        });
+        assert!((forward - Vector3::new(0.0, 0.0, -1.0)).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn test_viewmodel_ignores_punch() {
+        let mut view = View::new();
+        view.update_input_angles(Angles {
+            pitch: Deg(10.0),
+            roll: Deg(0.0),
+            yaw: Deg(45.0),
+        });
+        view.set_punch_angles(Angles {
+            pitch: Deg(-2.0),
+            roll: Deg(0.0),
+            yaw: Deg(0.0),
+        });
+        view.calc_final_angles(
+            Duration::seconds(1),
+            None,
+            Vector3::zero(),
+            IdleVars {
+                v_idlescale: 0.0,
+                v_ipitch_cycle: 1.0,
+                v_ipitch_level: 0.3,
+                v_iroll_cycle: 0.5,
+                v_iroll_level: 0.1,
+                v_iyaw_cycle: 2.0,
+                v_iyaw_level: 0.3,
+            },
+            KickVars {
+                v_kickpitch: 0.6,
+                v_kickroll: 0.6,
+                v_kicktime: 0.5,
+            },
+            RollVars {
+                cl_rollangle: 2.0,
+                cl_rollspeed: 200.0,
+            },
+        );
+
+        assert_eq!(view.final_angles().pitch, Deg(8.0));
+        assert_eq!(view.viewmodel_angle().pitch, Deg(10.0));
+        assert_eq!(view.viewmodel_angle().yaw, Deg(45.0));
+    }
+}
@@ -125,7 +125,7 @@ use crate::common::math::{Hyperplane, HyperplaneSide, LinePlaneIntersect};
 // TODO: Either Trace should be moved into common or the functions requiring it should be moved into server
 use crate::server::world::{Trace, TraceEnd, TraceStart};
 
-use cgmath::Vector3;
+use cgmath::{InnerSpace as _, Vector3};
 use chrono::Duration;
 
 pub use self::load::{load, BspFileError};
@@ -199,6 +199,11 @@ impl BspTextureFrame {
     pub fn mipmap(&self, level: BspTextureMipmap) -> &[u8] {
         &self.mipmaps[level as usize]
     }
+
+    /// Returns an iterator over all mip levels, from full size down.
+    pub fn iter_mipmaps(&self) -> impl Iterator<Item = &[u8]> {
+        self.mipmaps.iter().map(|m| m.as_slice())
+    }
 }
 
 #[derive(Debug)]
@@ -828,6 +833,102 @@ impl BspData {
         }
     }
 
+    /// Samples the static lighting beneath the given point.
+    ///
+    /// A line is traced straight down through the render nodes and the lightmap
+    /// of the first lit surface it hits is sampled, with each of the surface's
+    /// light styles scaled by `lightstyle_values`. The result is in lightmap
+    /// units, where 255 is the brightest value a single style can store.
+    ///
+    /// Returns `None` if no surface lies below the point. Maps without any
+    /// lighting data are considered fully lit.
+    pub fn light_point(&self, point: Vector3<f32>, lightstyle_values: &[f32]) -> Option<f32> {
+        if self.lightmaps.is_empty() {
+            return Some(255.0);
+        }
+
+        let end = point - Vector3::new(0.0, 0.0, 2048.0);
+        self.light_point_recursive(&BspRenderNodeChild::Node(0), point, end, lightstyle_values)
+    }
+
+    fn light_point_recursive(
+        &self,
+        child: &BspRenderNodeChild,
+        start: Vector3<f32>,
+        end: Vector3<f32>,
+        lightstyle_values: &[f32],
+    ) -> Option<f32> {
+        let node = match *child {
+            BspRenderNodeChild::Node(node_id) => &self.render_nodes[node_id],
+            // the line ended in a leaf without hitting anything
+            BspRenderNodeChild::Leaf(_) => return None,
+        };
+
+        let plane = &self.planes[node.plane_id];
+        let front = plane.point_dist(start);
+        let back = plane.point_dist(end);
+        let side = (front < 0.0) as usize;
+
+        // the line doesn't cross this node's plane
+        if (back < 0.0) == (front < 0.0) {
+            return self.light_point_recursive(&node.children[side], start, end, lightstyle_values);
+        }
+
+        let mid = start + (end - start) * (front / (front - back));
+
+        // anything on the near side of the plane is hit first
+        if let Some(light) =
+            self.light_point_recursive(&node.children[side], start, mid, lightstyle_values)
+        {
+            return Some(light);
+        }
+
+        for face_id in node.face_id..node.face_id + node.face_count {
+            let face = &self.faces[face_id];
+            let texinfo = &self.texinfo[face.texinfo_id];
+
+            // sky and liquids have no lightmaps
+            if texinfo.special {
+                continue;
+            }
+
+            let s = mid.dot(texinfo.s_vector) + texinfo.s_offset;
+            let t = mid.dot(texinfo.t_vector) + texinfo.t_offset;
+            let ds = s - face.texture_mins[0] as f32;
+            let dt = t - face.texture_mins[1] as f32;
+            if ds < 0.0 || dt < 0.0 || ds > face.extents[0] as f32 || dt > face.extents[1] as f32 {
+                continue;
+            }
+
+            let lightmap_id = match face.lightmap_id {
+                Some(l) => l,
+                None => return Some(0.0),
+            };
+
+            // lightmap samples are spaced 16 texels apart
+            let lightmap_w = face.extents[0] as usize / 16 + 1;
+            let lightmap_h = face.extents[1] as usize / 16 + 1;
+            let sample_id = (dt as usize / 16) * lightmap_w + ds as usize / 16;
+
+            let light = face
+                .light_styles
+                .iter()
+                .take_while(|style| **style != 255)
+                .enumerate()
+                .map(|(i, style)| {
+                    let sample =
+                        self.lightmaps[lightmap_id + i * lightmap_w * lightmap_h + sample_id];
+                    sample as f32 * lightstyle_values[*style as usize]
+                })
+                .sum();
+
+            return Some(light);
+        }
+
+        // continue on the far side of the plane
+        self.light_point_recursive(&node.children[1 - side], mid, end, lightstyle_values)
+    }
+
     pub fn get_pvs(&self, leaf_id: usize, leaf_count: usize) -> Vec<usize> {
         // leaf 0 is outside the map, everything is visible
         if leaf_id == 0 {
@@ -1011,6 +1112,100 @@ impl BspModel {
 
 impl BspData {}
 
+#[cfg(test)]
+impl BspModel {
+    /// Creates a world model with a flat floor at height 0 that rises to
+    /// `step_height` where x >= `step_x`.
+    ///
+    /// The model only has collision hulls, so it can't be rendered.
+    pub(crate) fn with_step(
+        step_x: f32,
+        step_height: f32,
+        min: Vector3<f32>,
+        max: Vector3<f32>,
+    ) -> BspModel {
+        // like the map compiler, expand the solid by the bounds of each hull
+        let hull = |mins: Vector3<f32>, maxs: Vector3<f32>| {
+            let planes = vec![
+                Hyperplane::axis_x(step_x - maxs.x),
+                Hyperplane::axis_z(step_height - mins.z),
+                Hyperplane::axis_z(-mins.z),
+            ];
+            let nodes = vec![
+                BspCollisionNode {
+                    plane_id: 0,
+                    children: [
+                        BspCollisionNodeChild::Node(1),
+                        BspCollisionNodeChild::Node(2),
+                    ],
+                },
+                BspCollisionNode {
+                    plane_id: 1,
+                    children: [
+                        BspCollisionNodeChild::Contents(BspLeafContents::Empty),
+                        BspCollisionNodeChild::Contents(BspLeafContents::Solid),
+                    ],
+                },
+                BspCollisionNode {
+                    plane_id: 2,
+                    children: [
+                        BspCollisionNodeChild::Contents(BspLeafContents::Empty),
+                        BspCollisionNodeChild::Contents(BspLeafContents::Solid),
+                    ],
+                },
+            ];
+
+            BspCollisionHull {
+                planes: Rc::new(planes.into_boxed_slice()),
+                nodes: Rc::new(nodes.into_boxed_slice()),
+                node_id: 0,
+                node_count: 3,
+                mins,
+                maxs,
+            }
+        };
+
+        let hulls = [
+            hull(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0)),
+            hull(
+                Vector3::new(-16.0, -16.0, -24.0),
+                Vector3::new(16.0, 16.0, 32.0),
+            ),
+            hull(
+                Vector3::new(-32.0, -32.0, -24.0),
+                Vector3::new(32.0, 32.0, 64.0),
+            ),
+        ];
+
+        BspModel {
+            bsp_data: Rc::new(BspData {
+                planes: Rc::new(Vec::new().into_boxed_slice()),
+                textures: Vec::new().into_boxed_slice(),
+                vertices: Vec::new().into_boxed_slice(),
+                visibility: Vec::new().into_boxed_slice(),
+                render_nodes: Vec::new().into_boxed_slice(),
+                texinfo: Vec::new().into_boxed_slice(),
+                faces: Vec::new().into_boxed_slice(),
+                lightmaps: Vec::new().into_boxed_slice(),
+                leaves: Vec::new().into_boxed_slice(),
+                facelist: Vec::new().into_boxed_slice(),
+                edges: Vec::new().into_boxed_slice(),
+                edgelist: Vec::new().into_boxed_slice(),
+                hulls,
+            }),
+            min,
+            max,
+            origin: Vector3::new(0.0, 0.0, 0.0),
+            collision_node_ids: [0; MAX_HULLS],
+            collision_node_counts: [3; MAX_HULLS],
+            leaf_id: 0,
+            leaf_count: 0,
+            face_id: 0,
+            face_count: 0,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
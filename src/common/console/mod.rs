@@ -545,6 +545,9 @@ pub struct Console {
 
     out_buffer: RefCell<Vec<char>>,
     output: RefCell<ConsoleOutput>,
+
+    // if set, printed text is also captured here (see `begin_redirect`)
+    redirect: RefCell<Option<String>>,
 }
 
 impl Console {
@@ -634,6 +637,7 @@ impl Console {
             buffer: RefCell::new(String::new()),
             out_buffer: RefCell::new(Vec::new()),
             output,
+            redirect: RefCell::new(None),
         }
     }
 
@@ -642,6 +646,10 @@ impl Console {
     where
         S: AsRef<str>,
     {
+        if let Some(ref mut redirect) = *self.redirect.borrow_mut() {
+            redirect.push_str(s.as_ref());
+        }
+
         let mut buf = self.out_buffer.borrow_mut();
         let mut it = s.as_ref().chars();
 
@@ -819,4 +827,18 @@ impl Console {
     pub fn output(&self) -> Ref<ConsoleOutput> {
         self.output.borrow()
     }
+
+    /// Begin capturing printed text in addition to displaying it.
+    ///
+    /// This is used to send the output of remotely issued commands back to the
+    /// sender.
+    pub fn begin_redirect(&self) {
+        self.redirect.replace(Some(String::new()));
+    }
+
+    /// Stop capturing printed text and return everything printed since the
+    /// call to `begin_redirect`.
+    pub fn end_redirect(&self) -> String {
+        self.redirect.replace(None).unwrap_or_default()
+    }
 }
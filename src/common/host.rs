@@ -18,9 +18,18 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use std::cell::{Ref, RefMut};
+use std::{
+    cell::{Ref, RefCell, RefMut},
+    rc::Rc,
+};
 
-use crate::common::{console::CvarRegistry, engine};
+use crate::{
+    common::{
+        console::{CmdRegistry, CvarRegistry},
+        engine,
+    },
+    server::{self, Session},
+};
 
 use chrono::{DateTime, Duration, Utc};
 use winit::{
@@ -38,8 +47,15 @@ pub trait Program: Sized {
 
     fn frame(&mut self, frame_duration: Duration);
     fn shutdown(&mut self);
+
+    /// Returns whether frames should be limited to `host_maxfps`.
+    fn limit_frame_rate(&self) -> bool {
+        true
+    }
+
     fn cvars(&self) -> Ref<CvarRegistry>;
     fn cvars_mut(&self) -> RefMut<CvarRegistry>;
+    fn cmds_mut(&self) -> RefMut<CmdRegistry>;
 }
 
 pub struct Host<P>
@@ -48,6 +64,9 @@ where
 {
     program: P,
 
+    /// The local server, if one is running.
+    server: Rc<RefCell<Option<Session>>>,
+
     init_time: DateTime<Utc>,
     prev_frame_time: DateTime<Utc>,
     prev_frame_duration: Duration,
@@ -64,8 +83,13 @@ where
             .register_archive("host_maxfps", "72")
             .unwrap();
 
+        let server = Rc::new(RefCell::new(None));
+        server::cvars::register_cvars(&program.cvars()).unwrap();
+        server::register_commands(&mut program.cmds_mut(), server.clone());
+
         Host {
             program,
+            server,
             init_time,
             prev_frame_time: init_time,
             prev_frame_duration: Duration::zero(),
@@ -115,11 +139,22 @@ where
         // we're running this frame, so update the frame time
         self.prev_frame_time = new_frame_time;
 
+        // run the server first so the client sees this frame's results
+        if let Some(ref mut session) = *self.server.borrow_mut() {
+            if let Err(e) = session.frame(self.prev_frame_duration) {
+                error!("Server frame failed: {}", e);
+            }
+        }
+
         self.program.frame(self.prev_frame_duration);
     }
 
     // Returns whether enough time has elapsed to run the next frame.
     fn check_frame_duration(&mut self, frame_duration: Duration) -> bool {
+        if !self.program.limit_frame_rate() {
+            return true;
+        }
+
         let host_maxfps = self
             .program
             .cvars()
@@ -0,0 +1,402 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Decoders for the image formats used by replacement textures and skyboxes.
+//!
+//! All images are decoded to 8-bit RGBA with the first row at the top.
+
+use std::io::{self, Read};
+
+use crate::common::vfs::{Vfs, VfsError};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use thiserror::Error;
+
+/// Extensions tried by [`Image::load`], in order of preference.
+pub const EXTENSIONS: [&str; 3] = ["tga", "png", "pcx"];
+
+#[derive(Error, Debug)]
+pub enum ImageError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("PNG decoding error: {0}")]
+    Png(#[from] png::DecodingError),
+    #[error("Virtual filesystem error: {0}")]
+    Vfs(#[from] VfsError),
+    #[error("Unsupported image: {0}")]
+    Unsupported(String),
+}
+
+/// A decoded RGBA image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32, rgba: Vec<u8>) -> Image {
+        assert_eq!(rgba.len(), (width * height * 4) as usize);
+        Image {
+            width,
+            height,
+            rgba,
+        }
+    }
+
+    /// Loads the image at `path` (without extension) from the virtual
+    /// filesystem, trying each of the supported formats in turn.
+    pub fn load<S>(vfs: &Vfs, path: S) -> Result<Image, ImageError>
+    where
+        S: AsRef<str>,
+    {
+        let path = path.as_ref();
+        for ext in EXTENSIONS.iter() {
+            let name = format!("{}.{}", path, ext);
+            let mut file = match vfs.open(&name) {
+                Ok(f) => f,
+                Err(VfsError::NoSuchFile(_)) => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            return match *ext {
+                "tga" => decode_tga(&data),
+                "png" => decode_png(&data),
+                "pcx" => decode_pcx(&data),
+                _ => unreachable!(),
+            };
+        }
+
+        Err(VfsError::NoSuchFile(format!("{}.{{{}}}", path, EXTENSIONS.join(","))).into())
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn rgba(&self) -> &[u8] {
+        &self.rgba
+    }
+}
+
+/// Decodes a Truevision TGA image.
+///
+/// Uncompressed and run-length encoded images in 8-bit grayscale, 24-bit BGR
+/// and 32-bit BGRA are supported.
+pub fn decode_tga(data: &[u8]) -> Result<Image, ImageError> {
+    let mut reader = data;
+    let id_len = reader.read_u8()?;
+    let colormap_type = reader.read_u8()?;
+    let image_type = reader.read_u8()?;
+    let mut colormap_spec = [0; 5];
+    reader.read_exact(&mut colormap_spec)?;
+    let _x_origin = reader.read_u16::<LittleEndian>()?;
+    let _y_origin = reader.read_u16::<LittleEndian>()?;
+    let width = reader.read_u16::<LittleEndian>()? as u32;
+    let height = reader.read_u16::<LittleEndian>()? as u32;
+    let depth = reader.read_u8()?;
+    let descriptor = reader.read_u8()?;
+
+    if colormap_type != 0 {
+        return Err(ImageError::Unsupported("color-mapped TGA".to_owned()));
+    }
+
+    let (rle, gray) = match image_type {
+        2 => (false, false),
+        3 => (false, true),
+        10 => (true, false),
+        11 => (true, true),
+        t => return Err(ImageError::Unsupported(format!("TGA image type {}", t))),
+    };
+
+    let bytes_per_pixel = match (gray, depth) {
+        (true, 8) => 1,
+        (false, 24) => 3,
+        (false, 32) => 4,
+        (_, d) => return Err(ImageError::Unsupported(format!("{}-bit TGA", d))),
+    };
+
+    let mut skip = vec![0; id_len as usize];
+    reader.read_exact(&mut skip)?;
+
+    let pixel_count = (width * height) as usize;
+    let mut pixels = Vec::with_capacity(pixel_count * bytes_per_pixel);
+    if rle {
+        while pixels.len() < pixel_count * bytes_per_pixel {
+            let header = reader.read_u8()?;
+            let count = (header & 0x7F) as usize + 1;
+            if header & 0x80 != 0 {
+                let mut pixel = [0; 4];
+                reader.read_exact(&mut pixel[..bytes_per_pixel])?;
+                for _ in 0..count {
+                    pixels.extend_from_slice(&pixel[..bytes_per_pixel]);
+                }
+            } else {
+                let start = pixels.len();
+                pixels.resize(start + count * bytes_per_pixel, 0);
+                reader.read_exact(&mut pixels[start..])?;
+            }
+        }
+        pixels.truncate(pixel_count * bytes_per_pixel);
+    } else {
+        pixels.resize(pixel_count * bytes_per_pixel, 0);
+        reader.read_exact(&mut pixels)?;
+    }
+
+    let mut rgba = Vec::with_capacity(pixel_count * 4);
+    for pixel in pixels.chunks_exact(bytes_per_pixel) {
+        match *pixel {
+            [l] => rgba.extend_from_slice(&[l, l, l, 0xFF]),
+            [b, g, r] => rgba.extend_from_slice(&[r, g, b, 0xFF]),
+            [b, g, r, a] => rgba.extend_from_slice(&[r, g, b, a]),
+            _ => unreachable!(),
+        }
+    }
+
+    // rows are stored bottom-to-top unless bit 5 of the descriptor is set
+    if descriptor & 0x20 == 0 {
+        flip_rows(&mut rgba, width);
+    }
+
+    Ok(Image::new(width, height, rgba))
+}
+
+/// Decodes a PNG image.
+pub fn decode_png(data: &[u8]) -> Result<Image, ImageError> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    buf.truncate(info.buffer_size());
+
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => buf,
+        png::ColorType::Rgb => buf
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 0xFF])
+            .collect(),
+        png::ColorType::Grayscale => buf.iter().flat_map(|&l| [l, l, l, 0xFF]).collect(),
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        t => return Err(ImageError::Unsupported(format!("PNG color type {:?}", t))),
+    };
+
+    Ok(Image::new(info.width, info.height, rgba))
+}
+
+/// Decodes an 8-bit ZSoft PCX image with a trailing 256-color palette.
+pub fn decode_pcx(data: &[u8]) -> Result<Image, ImageError> {
+    const HEADER_SIZE: usize = 128;
+    const PALETTE_SIZE: usize = 768;
+
+    if data.len() < HEADER_SIZE + PALETTE_SIZE + 1 || data[0] != 0x0A {
+        return Err(ImageError::Unsupported("not a PCX file".to_owned()));
+    }
+
+    let mut header = &data[..HEADER_SIZE];
+    let _manufacturer = header.read_u8()?;
+    let _version = header.read_u8()?;
+    let encoding = header.read_u8()?;
+    let bits_per_pixel = header.read_u8()?;
+    let x_min = header.read_u16::<LittleEndian>()? as u32;
+    let y_min = header.read_u16::<LittleEndian>()? as u32;
+    let x_max = header.read_u16::<LittleEndian>()? as u32;
+    let y_max = header.read_u16::<LittleEndian>()? as u32;
+    let planes = data[65];
+    let bytes_per_line = (&data[66..68]).read_u16::<LittleEndian>()? as usize;
+
+    if encoding != 1 || bits_per_pixel != 8 || planes != 1 {
+        return Err(ImageError::Unsupported(format!(
+            "{}-bit, {}-plane PCX",
+            bits_per_pixel, planes
+        )));
+    }
+
+    let palette_start = data.len() - PALETTE_SIZE;
+    if data[palette_start - 1] != 0x0C {
+        return Err(ImageError::Unsupported("PCX without palette".to_owned()));
+    }
+    let palette = &data[palette_start..];
+
+    if x_max < x_min || y_max < y_min {
+        return Err(ImageError::Unsupported(format!(
+            "PCX with invalid bounds ({}, {}) to ({}, {})",
+            x_min, y_min, x_max, y_max
+        )));
+    }
+    let width = x_max - x_min + 1;
+    let height = y_max - y_min + 1;
+
+    if bytes_per_line < width as usize {
+        return Err(ImageError::Unsupported(format!(
+            "PCX with {} bytes per line for width {}",
+            bytes_per_line, width
+        )));
+    }
+
+    let truncated = |_| ImageError::Unsupported("truncated PCX data".to_owned());
+    let mut encoded = &data[HEADER_SIZE..palette_start - 1];
+    let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+    let mut line = Vec::with_capacity(bytes_per_line);
+    for _ in 0..height {
+        line.clear();
+        while line.len() < bytes_per_line {
+            let byte = encoded.read_u8().map_err(truncated)?;
+            if byte & 0xC0 == 0xC0 {
+                let value = encoded.read_u8().map_err(truncated)?;
+                let count = (byte & 0x3F) as usize;
+                line.resize(line.len() + count, value);
+            } else {
+                line.push(byte);
+            }
+        }
+
+        for &index in line.iter().take(width as usize) {
+            let color = &palette[index as usize * 3..index as usize * 3 + 3];
+            rgba.extend_from_slice(&[color[0], color[1], color[2], 0xFF]);
+        }
+    }
+
+    if rgba.len() != width as usize * height as usize * 4 {
+        return Err(ImageError::Unsupported(format!(
+            "PCX decoded to {} bytes, expected {}x{} pixels",
+            rgba.len(),
+            width,
+            height
+        )));
+    }
+
+    Ok(Image::new(width, height, rgba))
+}
+
+fn flip_rows(rgba: &mut [u8], width: u32) {
+    let stride = width as usize * 4;
+    let height = rgba.len() / stride;
+    for row in 0..height / 2 {
+        let (top, bottom) = rgba.split_at_mut((height - row - 1) * stride);
+        top[row * stride..(row + 1) * stride].swap_with_slice(&mut bottom[..stride]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_tga_bottom_up() {
+        let mut data = vec![0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 2, 0, 24, 0];
+        // bottom row first, BGR
+        data.extend_from_slice(&[0, 0, 255]);
+        data.extend_from_slice(&[255, 0, 0]);
+
+        let image = decode_tga(&data).unwrap();
+        assert_eq!(image.width(), 1);
+        assert_eq!(image.height(), 2);
+        assert_eq!(image.rgba(), &[0, 0, 255, 255, 255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_decode_tga_rle() {
+        let mut data = vec![0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 1, 0, 32, 0x20];
+        // run of 2 pixels followed by 1 raw pixel
+        data.extend_from_slice(&[0x81, 1, 2, 3, 4, 0x00, 5, 6, 7, 8]);
+
+        let image = decode_tga(&data).unwrap();
+        assert_eq!(image.rgba(), &[3, 2, 1, 4, 3, 2, 1, 4, 7, 6, 5, 8]);
+    }
+
+    fn pcx_header(x_max: u16, bytes_per_line: u16) -> Vec<u8> {
+        let mut data = vec![0; 128];
+        data[0] = 0x0A;
+        data[2] = 1;
+        data[3] = 8;
+        data[8..10].copy_from_slice(&x_max.to_le_bytes());
+        data[65] = 1;
+        data[66..68].copy_from_slice(&bytes_per_line.to_le_bytes());
+        data
+    }
+
+    fn with_palette(mut data: Vec<u8>) -> Vec<u8> {
+        data.push(0x0C);
+        data.extend_from_slice(&[0; 768]);
+        data
+    }
+
+    #[test]
+    fn test_decode_pcx_invalid() {
+        // x_min is greater than x_max
+        let mut data = pcx_header(0, 4);
+        data[4] = 1;
+        data.extend_from_slice(&[0xC4, 1]);
+        assert!(matches!(
+            decode_pcx(&with_palette(data)),
+            Err(ImageError::Unsupported(_))
+        ));
+
+        // lines are too short to hold the image
+        let mut data = pcx_header(3, 2);
+        data.extend_from_slice(&[0xC2, 1]);
+        assert!(matches!(
+            decode_pcx(&with_palette(data)),
+            Err(ImageError::Unsupported(_))
+        ));
+
+        // the encoded data ends partway through a line
+        let mut data = pcx_header(3, 4);
+        data.extend_from_slice(&[0xC2, 1]);
+        assert!(matches!(
+            decode_pcx(&with_palette(data)),
+            Err(ImageError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_pcx() {
+        let mut data = vec![0; 128];
+        data[0] = 0x0A;
+        data[2] = 1;
+        data[3] = 8;
+        // 3x1 image
+        data[8] = 2;
+        data[65] = 1;
+        data[66] = 4;
+        // run of 3 index 1, then a literal index 2 padding the line
+        data.extend_from_slice(&[0xC3, 1, 2]);
+        data.push(0x0C);
+        let mut palette = vec![0; 768];
+        palette[3..6].copy_from_slice(&[10, 20, 30]);
+        data.extend_from_slice(&palette);
+
+        let image = decode_pcx(&data).unwrap();
+        assert_eq!(image.width(), 3);
+        assert_eq!(image.height(), 1);
+        assert_eq!(image.rgba(), &[10, 20, 30, 255].repeat(3)[..]);
+    }
+}
@@ -366,7 +366,7 @@ where
         Err(MdlFileError::InvalidFlags(flags_bits))?;
     }
     let flags =
-        ModelFlags::from_bits(flags_bits as u8).ok_or(MdlFileError::InvalidFlags(flags_bits))?;
+        ModelFlags::from_bits(flags_bits as u16).ok_or(MdlFileError::InvalidFlags(flags_bits))?;
 
     // unused
     let _size = reader.read_i32::<LittleEndian>()?;
@@ -24,6 +24,7 @@ pub mod bsp;
 pub mod console;
 pub mod engine;
 pub mod host;
+pub mod image;
 pub mod math;
 pub mod mdl;
 pub mod model;
@@ -45,7 +45,7 @@ pub enum SyncType {
 
 bitflags! {
     #[derive(Debug, Clone, Copy)]
-    pub struct ModelFlags: u8 {
+    pub struct ModelFlags: u16 {
         const ROCKET  = 0b00000001;
         const GRENADE = 0b00000010;
         const GIB     = 0b00000100;
@@ -54,9 +54,15 @@ bitflags! {
         const ZOMGIB  = 0b00100000;
         const TRACER2 = 0b01000000;
         const TRACER3 = 0b10000000;
+
+        // not stored in model files; set on load for models drawn without shading
+        const FULLBRIGHT = 0b1_00000000;
     }
 }
 
+/// Alias models which are always drawn at full brightness.
+const FULLBRIGHT_MODELS: &[&str] = &["progs/flame.mdl", "progs/flame2.mdl"];
+
 #[derive(Debug)]
 pub struct Model {
     pub name: String,
@@ -126,7 +132,12 @@ impl Model {
     where
         S: AsRef<str>,
     {
-        let flags = alias_model.flags();
+        let mut flags = alias_model.flags();
+
+        // torches have no fullbright texels, so the original engine special-cases them by name
+        if FULLBRIGHT_MODELS.contains(&name.as_ref()) {
+            flags |= ModelFlags::FULLBRIGHT;
+        }
 
         Model {
             name: name.as_ref().to_owned(),
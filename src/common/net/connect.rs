@@ -25,7 +25,7 @@ use std::{
 };
 
 use crate::common::{
-    net::{NetError, QSocket, MAX_MESSAGE},
+    net::{master::MasterPacket, rcon::RconPacket, NetError, QSocket, MAX_MESSAGE},
     util,
 };
 
@@ -37,6 +37,12 @@ pub const CONNECT_PROTOCOL_VERSION: u8 = 3;
 const CONNECT_CONTROL: i32 = 1 << 31;
 const CONNECT_LENGTH_MASK: i32 = 0x0000FFFF;
 
+/// The header that marks a packet as out-of-band.
+///
+/// Out-of-band packets carry a text command instead of a `ConnectPacket` and
+/// are used by master servers and remote consoles.
+pub const OOB_HEADER: [u8; 4] = [0xFF; 4];
+
 pub trait ConnectPacket {
     /// Returns the numeric value of this packet's code.
     fn code(&self) -> u8;
@@ -525,6 +531,18 @@ impl ConnectPacket for Response {
     }
 }
 
+/// A packet received by a [`ConnectListener`].
+#[derive(Debug)]
+pub enum ListenerPacket {
+    Request(Request),
+
+    /// An out-of-band packet from a master server.
+    Master(MasterPacket),
+
+    /// An out-of-band remote console command.
+    Rcon(RconPacket),
+}
+
 /// A socket that listens for new connections or queries.
 pub struct ConnectListener {
     socket: UdpSocket,
@@ -543,19 +561,34 @@ impl ConnectListener {
 
     /// Receives a request and returns it along with its remote address.
     pub fn recv_request(&self) -> Result<(Request, SocketAddr), NetError> {
+        match self.recv_packet()? {
+            (ListenerPacket::Request(request), remote) => Ok((request, remote)),
+            (_, _) => Err(NetError::with_msg("Unexpected out-of-band packet")),
+        }
+    }
+
+    /// Receives a request or master server packet and returns it along with its remote address.
+    pub fn recv_packet(&self) -> Result<(ListenerPacket, SocketAddr), NetError> {
         // Original engine receives connection requests in `net_message`,
         // allocated at https://github.com/id-Software/Quake/blob/master/WinQuake/net_main.c#L851
         let mut recv_buf = [0u8; MAX_MESSAGE];
         let (len, remote) = self.socket.recv_from(&mut recv_buf)?;
-        let mut reader = BufReader::new(&recv_buf[..len]);
 
-        let control = reader.read_i32::<NetworkEndian>()?;
+        // a control value of -1 marks an out-of-band packet
+        if recv_buf[..len].starts_with(&OOB_HEADER) {
+            let packet = if RconPacket::is_rcon(&recv_buf[..len]) {
+                ListenerPacket::Rcon(RconPacket::from_bytes(&recv_buf[..len])?)
+            } else {
+                ListenerPacket::Master(MasterPacket::from_bytes(&recv_buf[..len])?)
+            };
 
-        // TODO: figure out what a control value of -1 means
-        if control == -1 {
-            return Err(NetError::with_msg("Control value is -1"));
+            return Ok((packet, remote));
         }
 
+        let mut reader = BufReader::new(&recv_buf[..len]);
+
+        let control = reader.read_i32::<NetworkEndian>()?;
+
         // high 4 bits must be 0x8000 (CONNECT_CONTROL)
         if control & !CONNECT_LENGTH_MASK != CONNECT_CONTROL {
             return Err(NetError::InvalidData(format!(
@@ -611,13 +644,47 @@ impl ConnectListener {
             }
         };
 
-        Ok((request, remote))
+        Ok((ListenerPacket::Request(request), remote))
+    }
+
+    /// Moves the listener into or out of nonblocking mode.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), NetError> {
+        self.socket.set_nonblocking(nonblocking)?;
+        Ok(())
+    }
+
+    /// Returns the local address this listener is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr, NetError> {
+        Ok(self.socket.local_addr()?)
     }
 
     pub fn send_response(&self, response: Response, remote: SocketAddr) -> Result<(), NetError> {
         self.socket.send_to(&response.to_bytes()?, remote)?;
         Ok(())
     }
+
+    /// Sends the output of a remote console command.
+    pub fn send_rcon_packet(
+        &self,
+        packet: &RconPacket,
+        remote: SocketAddr,
+    ) -> Result<(), NetError> {
+        self.socket.send_to(&packet.to_bytes()?, remote)?;
+        Ok(())
+    }
+
+    /// Sends an out-of-band packet to a master server.
+    ///
+    /// Heartbeats must be sent from the listener's socket, since the master
+    /// lists the address it receives them from.
+    pub fn send_master_packet(
+        &self,
+        packet: &MasterPacket,
+        remote: SocketAddr,
+    ) -> Result<(), NetError> {
+        self.socket.send_to(&packet.to_bytes()?, remote)?;
+        Ok(())
+    }
 }
 
 pub struct ConnectSocket {
@@ -634,6 +701,12 @@ impl ConnectSocket {
         Ok(ConnectSocket { socket })
     }
 
+    /// Allow requests to be sent to broadcast addresses.
+    pub fn set_broadcast(&mut self, broadcast: bool) -> Result<(), NetError> {
+        self.socket.set_broadcast(broadcast)?;
+        Ok(())
+    }
+
     pub fn into_qsocket(self, remote: SocketAddr) -> QSocket {
         QSocket::new(self.socket, remote)
     }
@@ -0,0 +1,624 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! The dpmaster master server protocol.
+//!
+//! Master server packets are sent out-of-band on the connection port: instead of
+//! the usual control header, they begin with four `0xFF` bytes followed by a
+//! text command. The exchange works as follows:
+//!
+//! - A server sends `heartbeat` to the master when it starts and periodically
+//!   afterwards.
+//! - The master replies with `getinfo <challenge>`, and the server answers with
+//!   an `infoResponse` containing its details and the same challenge.
+//! - A client sends `getservers <game> <protocol> empty full` and receives one or
+//!   more `getserversResponse` packets listing server addresses.
+//!
+//! See `doc/techinfo.txt` in the dpmaster distribution for details.
+
+use std::{
+    io::ErrorKind,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs, UdpSocket},
+};
+
+use crate::common::net::{connect::OOB_HEADER, NetError, MAX_MESSAGE};
+
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use chrono::{Duration, Utc};
+
+/// The port dpmaster listens on by default.
+pub const DEFAULT_MASTER_PORT: u16 = 27950;
+
+/// The game name NetQuake-compatible servers register under.
+pub const MASTER_GAME_NAME: &str = "DarkPlaces-Quake";
+
+/// The master protocol version for NetQuake-compatible servers.
+pub const MASTER_PROTOCOL_VERSION: u32 = 3;
+
+/// The protocol name sent in heartbeats.
+pub const HEARTBEAT_PROTOCOL: &str = "DarkPlaces";
+
+/// How often a server should send heartbeats, in seconds.
+pub const HEARTBEAT_INTERVAL_SECS: i64 = 300;
+
+// getserversResponse entries are a backslash, an IPv4 address and a port
+const SERVER_ENTRY_LEN: usize = 7;
+const EOT_MARKER: &[u8] = b"\\EOT\0\0\0";
+
+/// A packet exchanged with a master server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MasterPacket {
+    /// Sent by a server to announce itself.
+    Heartbeat { protocol: String },
+
+    /// Sent by the master to request a server's details.
+    GetInfo { challenge: String },
+
+    /// A server's reply to `GetInfo`, as a list of key-value pairs.
+    InfoResponse { info: Vec<(String, String)> },
+
+    /// Sent by a client to request the server list.
+    GetServers {
+        game_name: String,
+        protocol_version: u32,
+        empty: bool,
+        full: bool,
+    },
+
+    /// A (possibly partial) server list.
+    ///
+    /// `eot` is set on the last packet of the list.
+    GetServersResponse {
+        servers: Vec<SocketAddrV4>,
+        eot: bool,
+    },
+}
+
+impl MasterPacket {
+    /// Construct a heartbeat for a NetQuake-compatible server.
+    pub fn heartbeat() -> MasterPacket {
+        MasterPacket::Heartbeat {
+            protocol: HEARTBEAT_PROTOCOL.to_owned(),
+        }
+    }
+
+    /// Construct a server list request for NetQuake-compatible servers.
+    pub fn get_servers() -> MasterPacket {
+        MasterPacket::GetServers {
+            game_name: MASTER_GAME_NAME.to_owned(),
+            protocol_version: MASTER_PROTOCOL_VERSION,
+            empty: true,
+            full: true,
+        }
+    }
+
+    /// Construct the reply to a `GetInfo` from the master.
+    pub fn info_response<S>(challenge: S, details: &ServerDetails) -> MasterPacket
+    where
+        S: AsRef<str>,
+    {
+        MasterPacket::InfoResponse {
+            info: vec![
+                ("gamename".to_owned(), MASTER_GAME_NAME.to_owned()),
+                ("protocol".to_owned(), MASTER_PROTOCOL_VERSION.to_string()),
+                ("clients".to_owned(), details.client_count.to_string()),
+                ("sv_maxclients".to_owned(), details.client_max.to_string()),
+                ("mapname".to_owned(), details.map.clone()),
+                ("hostname".to_owned(), details.hostname.clone()),
+                ("challenge".to_owned(), challenge.as_ref().to_owned()),
+            ],
+        }
+    }
+
+    /// Returns the value of `key` if this is an `InfoResponse` containing it.
+    pub fn info_value(&self, key: &str) -> Option<&str> {
+        match *self {
+            MasterPacket::InfoResponse { ref info } => {
+                info.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+            }
+            _ => None,
+        }
+    }
+
+    /// Generates the byte representation of this packet for transmission.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, NetError> {
+        let mut packet = OOB_HEADER.to_vec();
+
+        match *self {
+            MasterPacket::Heartbeat { ref protocol } => {
+                packet.extend_from_slice(format!("heartbeat {}\n", protocol).as_bytes())
+            }
+
+            MasterPacket::GetInfo { ref challenge } => {
+                packet.extend_from_slice(format!("getinfo {}", challenge).as_bytes())
+            }
+
+            MasterPacket::InfoResponse { ref info } => {
+                packet.extend_from_slice(b"infoResponse\n");
+                for (key, val) in info.iter() {
+                    packet.extend_from_slice(format!("\\{}\\{}", key, val).as_bytes());
+                }
+            }
+
+            MasterPacket::GetServers {
+                ref game_name,
+                protocol_version,
+                empty,
+                full,
+            } => {
+                let mut cmd = format!("getservers {} {}", game_name, protocol_version);
+                if empty {
+                    cmd.push_str(" empty");
+                }
+                if full {
+                    cmd.push_str(" full");
+                }
+                packet.extend_from_slice(cmd.as_bytes());
+            }
+
+            MasterPacket::GetServersResponse { ref servers, eot } => {
+                packet.extend_from_slice(b"getserversResponse");
+                for server in servers.iter() {
+                    packet.push(b'\\');
+                    packet.extend_from_slice(&server.ip().octets());
+                    packet.write_u16::<NetworkEndian>(server.port())?;
+                }
+                if eot {
+                    packet.extend_from_slice(EOT_MARKER);
+                }
+            }
+        }
+
+        if packet.len() > MAX_MESSAGE {
+            return Err(NetError::with_msg(format!(
+                "Master packet too long ({} bytes)",
+                packet.len()
+            )));
+        }
+
+        Ok(packet)
+    }
+
+    /// Parse an out-of-band packet, including the `0xFF` header.
+    pub fn from_bytes(packet: &[u8]) -> Result<MasterPacket, NetError> {
+        let body = match packet.strip_prefix(&OOB_HEADER[..]) {
+            Some(b) => b,
+            None => {
+                return Err(NetError::InvalidData(
+                    "missing out-of-band header".to_owned(),
+                ))
+            }
+        };
+
+        // the server list is binary, so handle it before converting to text
+        if let Some(mut entries) = body.strip_prefix(&b"getserversResponse"[..]) {
+            let mut servers = Vec::new();
+            let mut eot = false;
+
+            while !entries.is_empty() {
+                if entries.starts_with(EOT_MARKER) {
+                    eot = true;
+                    break;
+                }
+
+                if entries.len() < SERVER_ENTRY_LEN || entries[0] != b'\\' {
+                    return Err(NetError::InvalidData(
+                        "malformed getserversResponse entry".to_owned(),
+                    ));
+                }
+
+                let ip = Ipv4Addr::new(entries[1], entries[2], entries[3], entries[4]);
+                let port = (&entries[5..7]).read_u16::<NetworkEndian>()?;
+                servers.push(SocketAddrV4::new(ip, port));
+                entries = &entries[SERVER_ENTRY_LEN..];
+            }
+
+            return Ok(MasterPacket::GetServersResponse { servers, eot });
+        }
+
+        let text = String::from_utf8_lossy(body);
+
+        if let Some(info) = text.strip_prefix("infoResponse\n") {
+            return Ok(MasterPacket::InfoResponse {
+                info: parse_info_string(info)?,
+            });
+        }
+
+        let mut args = text.split_whitespace();
+        match args.next() {
+            Some("heartbeat") => Ok(MasterPacket::Heartbeat {
+                protocol: args.next().unwrap_or("").to_owned(),
+            }),
+
+            Some("getinfo") => Ok(MasterPacket::GetInfo {
+                challenge: args.next().unwrap_or("").to_owned(),
+            }),
+
+            Some("getservers") => {
+                let game_name = args.next();
+                let protocol_version = args.next().and_then(|p| p.parse().ok());
+                let (game_name, protocol_version) = match (game_name, protocol_version) {
+                    (Some(g), Some(p)) => (g.to_owned(), p),
+                    _ => {
+                        return Err(NetError::InvalidData(format!(
+                            "malformed getservers request: {}",
+                            text
+                        )))
+                    }
+                };
+
+                let mut empty = false;
+                let mut full = false;
+                for arg in args {
+                    match arg {
+                        "empty" => empty = true,
+                        "full" => full = true,
+                        _ => (),
+                    }
+                }
+
+                Ok(MasterPacket::GetServers {
+                    game_name,
+                    protocol_version,
+                    empty,
+                    full,
+                })
+            }
+
+            _ => Err(NetError::InvalidData(format!(
+                "unknown out-of-band command: {}",
+                text
+            ))),
+        }
+    }
+}
+
+fn parse_info_string(info: &str) -> Result<Vec<(String, String)>, NetError> {
+    let info = info.trim_end_matches('\n');
+    let info = match info.strip_prefix('\\') {
+        Some(i) => i,
+        None if info.is_empty() => return Ok(Vec::new()),
+        None => {
+            return Err(NetError::InvalidData(format!(
+                "malformed info string: {}",
+                info
+            )))
+        }
+    };
+
+    let fields: Vec<&str> = info.split('\\').collect();
+    let pairs = fields.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return Err(NetError::InvalidData(format!(
+            "info string has a key with no value: {}",
+            info
+        )));
+    }
+
+    Ok(pairs
+        .map(|kv| (kv[0].to_owned(), kv[1].to_owned()))
+        .collect())
+}
+
+/// The details a server reports to the master.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerDetails {
+    pub hostname: String,
+    pub map: String,
+    pub client_count: u8,
+    pub client_max: u8,
+}
+
+/// Fetch the list of servers registered with a master server.
+///
+/// Responses are collected until the master marks the end of the list or
+/// `timeout` expires.
+pub fn query_master<A>(master: A, timeout: Duration) -> Result<Vec<SocketAddr>, NetError>
+where
+    A: ToSocketAddrs,
+{
+    let master = match master.to_socket_addrs()?.next() {
+        Some(m) => m,
+        None => return Err(NetError::with_msg("Master server address did not resolve")),
+    };
+
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.send_to(&MasterPacket::get_servers().to_bytes()?, master)?;
+
+    let deadline = Utc::now() + timeout;
+    let mut servers: Vec<SocketAddr> = Vec::new();
+    let mut recv_buf = [0u8; MAX_MESSAGE];
+
+    loop {
+        let remaining = deadline.signed_duration_since(Utc::now());
+        if remaining <= Duration::zero() {
+            break;
+        }
+
+        socket.set_read_timeout(Some(remaining.to_std().unwrap()))?;
+        let (len, remote) = match socket.recv_from(&mut recv_buf) {
+            Ok(r) => r,
+            Err(e) => match e.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => break,
+                _ => return Err(NetError::from(e)),
+            },
+        };
+
+        if remote != master {
+            debug!("Ignoring packet from {} during master query", remote);
+            continue;
+        }
+
+        match MasterPacket::from_bytes(&recv_buf[..len]) {
+            Ok(MasterPacket::GetServersResponse { servers: list, eot }) => {
+                for server in list {
+                    let server = SocketAddr::V4(server);
+                    if !servers.contains(&server) {
+                        servers.push(server);
+                    }
+                }
+
+                if eot {
+                    break;
+                }
+            }
+
+            Ok(p) => debug!("Ignoring unexpected master packet: {:?}", p),
+            Err(e) => warn!("Invalid packet from master server: {}", e),
+        }
+    }
+
+    Ok(servers)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{
+        cell::RefCell,
+        rc::Rc,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::{self, JoinHandle},
+    };
+
+    use crate::{
+        common::{
+            console::{CmdRegistry, Console, CvarRegistry},
+            net::connect::{ConnectListener, ListenerPacket},
+        },
+        server::{self, net::ServerListener},
+    };
+
+    /// An in-process master server for testing.
+    ///
+    /// It implements the same exchange as dpmaster: a heartbeat is answered with a
+    /// `getinfo` challenge, and the server is listed once it sends back an
+    /// `infoResponse` with the matching challenge. Servers are never expired.
+    pub struct MockMaster {
+        addr: SocketAddr,
+        shutdown: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl MockMaster {
+        /// Start a mock master on an ephemeral loopback port.
+        pub fn spawn() -> Result<MockMaster, NetError> {
+            let socket = UdpSocket::bind("127.0.0.1:0")?;
+            let addr = socket.local_addr()?;
+
+            // wake up periodically to check for shutdown
+            socket.set_read_timeout(Some(std::time::Duration::from_millis(20)))?;
+
+            let shutdown = Arc::new(AtomicBool::new(false));
+            let thread_shutdown = shutdown.clone();
+            let thread = thread::spawn(move || {
+                let mut pending: Vec<(SocketAddrV4, String)> = Vec::new();
+                let mut servers: Vec<SocketAddrV4> = Vec::new();
+                let mut next_challenge = 0u32;
+                let mut recv_buf = [0u8; MAX_MESSAGE];
+
+                while !thread_shutdown.load(Ordering::SeqCst) {
+                    let (len, remote) = match socket.recv_from(&mut recv_buf) {
+                        Ok(r) => r,
+                        Err(_) => continue,
+                    };
+
+                    let remote = match remote {
+                        SocketAddr::V4(r) => r,
+                        SocketAddr::V6(_) => continue,
+                    };
+
+                    let reply = match MasterPacket::from_bytes(&recv_buf[..len]) {
+                        Ok(MasterPacket::Heartbeat { .. }) => {
+                            let challenge = format!("mock{}", next_challenge);
+                            next_challenge += 1;
+                            pending.retain(|(addr, _)| *addr != remote);
+                            pending.push((remote, challenge.clone()));
+                            MasterPacket::GetInfo { challenge }
+                        }
+
+                        Ok(p @ MasterPacket::InfoResponse { .. }) => {
+                            let challenge = p.info_value("challenge");
+                            if pending
+                                .iter()
+                                .any(|(addr, c)| *addr == remote && Some(c.as_str()) == challenge)
+                            {
+                                pending.retain(|(addr, _)| *addr != remote);
+                                if !servers.contains(&remote) {
+                                    servers.push(remote);
+                                }
+                            }
+                            continue;
+                        }
+
+                        Ok(MasterPacket::GetServers { .. }) => MasterPacket::GetServersResponse {
+                            servers: servers.clone(),
+                            eot: true,
+                        },
+
+                        _ => continue,
+                    };
+
+                    if let Ok(bytes) = reply.to_bytes() {
+                        let _ = socket.send_to(&bytes, remote);
+                    }
+                }
+            });
+
+            Ok(MockMaster {
+                addr,
+                shutdown,
+                thread: Some(thread),
+            })
+        }
+
+        /// Returns the address the mock master is listening on.
+        pub fn addr(&self) -> SocketAddr {
+            self.addr
+        }
+    }
+
+    impl Drop for MockMaster {
+        fn drop(&mut self) {
+            self.shutdown.store(true, Ordering::SeqCst);
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    fn round_trip(packet: MasterPacket) {
+        let bytes = packet.to_bytes().unwrap();
+        assert_eq!(MasterPacket::from_bytes(&bytes).unwrap(), packet);
+    }
+
+    #[test]
+    fn test_master_packet_round_trip() {
+        round_trip(MasterPacket::heartbeat());
+        round_trip(MasterPacket::GetInfo {
+            challenge: String::from("abc123"),
+        });
+        round_trip(MasterPacket::get_servers());
+        round_trip(MasterPacket::info_response(
+            "abc123",
+            &ServerDetails {
+                hostname: String::from("richter"),
+                map: String::from("e1m1"),
+                client_count: 1,
+                client_max: 4,
+            },
+        ));
+        round_trip(MasterPacket::GetServersResponse {
+            // the port's high byte is a backslash, which must not confuse the parser
+            servers: vec![
+                SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 26000),
+                SocketAddrV4::new(Ipv4Addr::new(92, 92, 92, 92), 0x5C5C),
+            ],
+            eot: true,
+        });
+    }
+
+    #[test]
+    fn test_get_servers_wire_format() {
+        let bytes = MasterPacket::get_servers().to_bytes().unwrap();
+        assert_eq!(
+            &bytes[..],
+            &b"\xFF\xFF\xFF\xFFgetservers DarkPlaces-Quake 3 empty full"[..]
+        );
+    }
+
+    #[test]
+    fn test_mock_master_heartbeat_and_query() {
+        let master = MockMaster::spawn().unwrap();
+        let listener = ConnectListener::bind("127.0.0.1:0").unwrap();
+        let listener_addr = listener.local_addr().unwrap();
+        let details = ServerDetails {
+            hostname: String::from("richter"),
+            map: String::from("start"),
+            client_count: 0,
+            client_max: 8,
+        };
+
+        listener
+            .send_master_packet(&MasterPacket::heartbeat(), master.addr())
+            .unwrap();
+
+        // answer the master's challenge
+        match listener.recv_packet().unwrap() {
+            (ListenerPacket::Master(MasterPacket::GetInfo { challenge }), remote) => {
+                assert_eq!(remote, master.addr());
+                listener
+                    .send_master_packet(&MasterPacket::info_response(challenge, &details), remote)
+                    .unwrap();
+            }
+            p => panic!("expected getinfo, got {:?}", p),
+        }
+
+        // the master registers the server asynchronously, so allow a few attempts
+        let mut servers = Vec::new();
+        for _ in 0..10 {
+            servers = query_master(master.addr(), Duration::milliseconds(200)).unwrap();
+            if !servers.is_empty() {
+                break;
+            }
+        }
+
+        assert_eq!(servers, vec![listener_addr]);
+    }
+
+    #[test]
+    fn test_server_heartbeat() {
+        let master = MockMaster::spawn().unwrap();
+        let names = Rc::new(RefCell::new(Vec::new()));
+        let cmds = Rc::new(RefCell::new(CmdRegistry::new(names.clone())));
+        let cvars = Rc::new(RefCell::new(CvarRegistry::new(names)));
+        server::cvars::register_cvars(&cvars.borrow()).unwrap();
+        cvars.borrow().set("sv_public", "1").unwrap();
+        cvars
+            .borrow()
+            .set("net_master", &master.addr().to_string())
+            .unwrap();
+        let console = Console::new(cmds, cvars.clone());
+
+        let mut listener = ServerListener::bind("127.0.0.1:0").unwrap();
+        let details = ServerDetails {
+            hostname: String::from("richter"),
+            map: String::from("start"),
+            client_count: 0,
+            client_max: 8,
+        };
+
+        // the first frame sends the heartbeat, later frames answer the challenge
+        let mut servers = Vec::new();
+        for _ in 0..10 {
+            listener.frame(&cvars, &console, &details).unwrap();
+            servers = query_master(master.addr(), Duration::milliseconds(100)).unwrap();
+            if !servers.is_empty() {
+                break;
+            }
+        }
+
+        assert_eq!(servers, vec![listener.local_addr().unwrap()]);
+    }
+}
@@ -21,6 +21,9 @@
 // TODO: need to figure out an equivalence relation for read_/write_coord and read_/write_angle
 
 pub mod connect;
+pub mod master;
+pub mod rcon;
+pub mod slist;
 
 use std::{
     collections::VecDeque,
@@ -159,6 +162,15 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Flags for the FitzQuake `svc_spawnbaseline2` command.
+    pub struct BaselineFlags: u8 {
+        const LARGE_MODEL = 1 << 0;
+        const LARGE_FRAME = 1 << 1;
+        const ALPHA = 1 << 2;
+    }
+}
+
 bitflags! {
     pub struct SoundFlags: u8 {
         const VOLUME = 1 << 0;
@@ -208,7 +220,7 @@ bitflags! {
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct PlayerColor {
     top: u8,
     bottom: u8,
@@ -237,6 +249,16 @@ impl PlayerColor {
     pub fn bits(&self) -> u8 {
         self.top << 4 | (self.bottom & 0x0F)
     }
+
+    /// Returns the shirt color index.
+    pub fn top(&self) -> u8 {
+        self.top & 0x0F
+    }
+
+    /// Returns the pants color index.
+    pub fn bottom(&self) -> u8 {
+        self.bottom & 0x0F
+    }
 }
 
 impl ::std::convert::From<u8> for PlayerColor {
@@ -515,6 +537,9 @@ pub struct EntityState {
     pub colormap: u8,
     pub skin_id: usize,
     pub effects: EntityEffects,
+
+    /// Opacity in the range [0, 1].
+    pub alpha: f32,
 }
 
 impl EntityState {
@@ -527,10 +552,32 @@ impl EntityState {
             colormap: 0,
             skin_id: 0,
             effects: EntityEffects::empty(),
+            alpha: 1.0,
         }
     }
 }
 
+/// Decodes a FitzQuake entity alpha byte.
+///
+/// Zero is the default and leaves the entity opaque; 1 through 255 map
+/// linearly onto [0, 1].
+pub fn decode_entity_alpha(alpha: u8) -> f32 {
+    match alpha {
+        0 => 1.0,
+        a => (a - 1) as f32 / 254.0,
+    }
+}
+
+/// Encodes an entity alpha value for transmission, the inverse of
+/// `decode_entity_alpha`.
+pub fn encode_entity_alpha(alpha: f32) -> u8 {
+    if alpha >= 1.0 {
+        0
+    } else {
+        (alpha * 254.0 + 1.0).round().clamp(1.0, 255.0) as u8
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct EntityUpdate {
     pub ent_id: u16,
@@ -574,6 +621,70 @@ pub struct PlayerData {
 }
 
 impl EntityUpdate {
+    /// Write this update as a fast update command.
+    pub fn serialize<W>(&self, writer: &mut W) -> Result<(), NetError>
+    where
+        W: WriteBytesExt,
+    {
+        let mut flags = UpdateFlags::empty();
+        flags.set(UpdateFlags::LONG_ENTITY, self.ent_id > 0xFF);
+        flags.set(UpdateFlags::MODEL, self.model_id.is_some());
+        flags.set(UpdateFlags::FRAME, self.frame_id.is_some());
+        flags.set(UpdateFlags::COLORMAP, self.colormap.is_some());
+        flags.set(UpdateFlags::SKIN, self.skin_id.is_some());
+        flags.set(UpdateFlags::EFFECTS, self.effects.is_some());
+        flags.set(UpdateFlags::ORIGIN_X, self.origin_x.is_some());
+        flags.set(UpdateFlags::PITCH, self.pitch.is_some());
+        flags.set(UpdateFlags::ORIGIN_Y, self.origin_y.is_some());
+        flags.set(UpdateFlags::YAW, self.yaw.is_some());
+        flags.set(UpdateFlags::ORIGIN_Z, self.origin_z.is_some());
+        flags.set(UpdateFlags::ROLL, self.roll.is_some());
+        flags.set(UpdateFlags::NO_LERP, self.no_lerp);
+        flags.set(UpdateFlags::MORE_BITS, flags.bits() & 0xFF00 != 0);
+
+        writer.write_u8(flags.bits() as u8 | FAST_UPDATE_FLAG)?;
+        if flags.contains(UpdateFlags::MORE_BITS) {
+            writer.write_u8((flags.bits() >> 8) as u8)?;
+        }
+
+        if flags.contains(UpdateFlags::LONG_ENTITY) {
+            writer.write_u16::<LittleEndian>(self.ent_id)?;
+        } else {
+            writer.write_u8(self.ent_id as u8)?;
+        }
+
+        for byte in [
+            self.model_id,
+            self.frame_id,
+            self.colormap,
+            self.skin_id,
+            self.effects.map(|e| e.bits()),
+        ]
+        .iter()
+        .flatten()
+        {
+            writer.write_u8(*byte)?;
+        }
+
+        for (coord, angle) in [
+            (self.origin_x, self.pitch),
+            (self.origin_y, self.yaw),
+            (self.origin_z, self.roll),
+        ]
+        .iter()
+        {
+            if let Some(c) = coord {
+                write_coord(writer, *c)?;
+            }
+
+            if let Some(a) = angle {
+                write_angle(writer, *a)?;
+            }
+        }
+
+        Ok(())
+    }
+
     /// Create an `EntityState` from this update, filling in any `None` values
     /// from the specified baseline state.
     pub fn to_entity_state(&self, baseline: &EntityState) -> EntityState {
@@ -593,6 +704,7 @@ impl EntityUpdate {
             skin_id: self.skin_id.map_or(baseline.skin_id, |s| s as usize),
             effects: self.effects.unwrap_or(baseline.effects),
             colormap: self.colormap.unwrap_or(baseline.colormap),
+            alpha: baseline.alpha,
         }
     }
 }
@@ -651,6 +763,10 @@ pub enum ServerCmdCode {
     CdTrack = 32,
     SellScreen = 33,
     Cutscene = 34,
+    // FitzQuake extensions
+    SkyBox = 37,
+    Fog = 41,
+    SpawnBaseline2 = 42,
 }
 
 #[derive(Copy, Clone, Debug, Eq, FromPrimitive, PartialEq)]
@@ -784,6 +900,25 @@ pub enum ServerCmd {
     Cutscene {
         text: String,
     },
+    SkyBox {
+        name: String,
+    },
+    Fog {
+        density: f32,
+        color: Vector3<f32>,
+        fade_time: Duration,
+    },
+    SpawnBaseline2 {
+        ent_id: u16,
+        model_id: u16,
+        frame_id: u16,
+        colormap: u8,
+        skin_id: u8,
+        origin: Vector3<f32>,
+        angles: Vector3<Deg<f32>>,
+        /// Encoded as by `encode_entity_alpha`.
+        alpha: u8,
+    },
     FastUpdate(EntityUpdate),
 }
 
@@ -824,6 +959,9 @@ impl ServerCmd {
             ServerCmd::CdTrack { .. } => ServerCmdCode::CdTrack,
             ServerCmd::SellScreen => ServerCmdCode::SellScreen,
             ServerCmd::Cutscene { .. } => ServerCmdCode::Cutscene,
+            ServerCmd::SkyBox { .. } => ServerCmdCode::SkyBox,
+            ServerCmd::Fog { .. } => ServerCmdCode::Fog,
+            ServerCmd::SpawnBaseline2 { .. } => ServerCmdCode::SpawnBaseline2,
             // TODO: figure out a more elegant way of doing this
             ServerCmd::FastUpdate(_) => panic!("FastUpdate has no code"),
         };
@@ -1454,6 +1592,80 @@ impl ServerCmd {
 
                 ServerCmd::Cutscene { text }
             }
+
+            ServerCmdCode::SkyBox => {
+                let name = match util::read_cstring(reader) {
+                    Ok(n) => n,
+                    Err(e) => return Err(NetError::with_msg(format!("{}", e))),
+                };
+
+                ServerCmd::SkyBox { name }
+            }
+
+            ServerCmdCode::Fog => {
+                let density = reader.read_u8()? as f32 / 255.0;
+                let mut color = Vector3::zero();
+                for i in 0..3 {
+                    color[i] = reader.read_u8()? as f32 / 255.0;
+                }
+                // fade time is sent in hundredths of a second
+                let fade_time =
+                    Duration::milliseconds(reader.read_i16::<LittleEndian>()? as i64 * 10);
+
+                ServerCmd::Fog {
+                    density,
+                    color,
+                    fade_time,
+                }
+            }
+
+            ServerCmdCode::SpawnBaseline2 => {
+                let ent_id = reader.read_u16::<LittleEndian>()?;
+                let flags_bits = reader.read_u8()?;
+                let flags = match BaselineFlags::from_bits(flags_bits) {
+                    Some(f) => f,
+                    None => {
+                        return Err(NetError::InvalidData(format!(
+                            "BaselineFlags: {:b}",
+                            flags_bits
+                        )))
+                    }
+                };
+
+                let model_id = match flags.contains(BaselineFlags::LARGE_MODEL) {
+                    true => reader.read_u16::<LittleEndian>()?,
+                    false => reader.read_u8()? as u16,
+                };
+                let frame_id = match flags.contains(BaselineFlags::LARGE_FRAME) {
+                    true => reader.read_u16::<LittleEndian>()?,
+                    false => reader.read_u8()? as u16,
+                };
+                let colormap = reader.read_u8()?;
+                let skin_id = reader.read_u8()?;
+
+                let mut origin = Vector3::zero();
+                let mut angles = Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0));
+                for i in 0..3 {
+                    origin[i] = read_coord(reader)?;
+                    angles[i] = read_angle(reader)?;
+                }
+
+                let alpha = match flags.contains(BaselineFlags::ALPHA) {
+                    true => reader.read_u8()?,
+                    false => 0,
+                };
+
+                ServerCmd::SpawnBaseline2 {
+                    ent_id,
+                    model_id,
+                    frame_id,
+                    colormap,
+                    skin_id,
+                    origin,
+                    angles,
+                    alpha,
+                }
+            }
         };
 
         Ok(Some(cmd))
@@ -1463,6 +1675,11 @@ impl ServerCmd {
     where
         W: WriteBytesExt,
     {
+        // fast updates store their flags in place of the command code
+        if let ServerCmd::FastUpdate(ref update) = *self {
+            return update.serialize(writer);
+        }
+
         writer.write_u8(self.code())?;
 
         match *self {
@@ -1835,8 +2052,62 @@ impl ServerCmd {
                 writer.write_u8(0)?;
             }
 
-            // TODO
-            ServerCmd::FastUpdate(_) => unimplemented!(),
+            ServerCmd::SkyBox { ref name } => {
+                writer.write_all(name.as_bytes())?;
+                writer.write_u8(0)?;
+            }
+
+            ServerCmd::Fog {
+                density,
+                color,
+                fade_time,
+            } => {
+                writer.write_u8((density.clamp(0.0, 1.0) * 255.0).round() as u8)?;
+                for i in 0..3 {
+                    writer.write_u8((color[i].clamp(0.0, 1.0) * 255.0).round() as u8)?;
+                }
+                writer.write_i16::<LittleEndian>((fade_time.num_milliseconds() / 10) as i16)?;
+            }
+
+            ServerCmd::SpawnBaseline2 {
+                ent_id,
+                model_id,
+                frame_id,
+                colormap,
+                skin_id,
+                origin,
+                angles,
+                alpha,
+            } => {
+                let mut flags = BaselineFlags::empty();
+                flags.set(BaselineFlags::LARGE_MODEL, model_id > u8::MAX as u16);
+                flags.set(BaselineFlags::LARGE_FRAME, frame_id > u8::MAX as u16);
+                flags.set(BaselineFlags::ALPHA, alpha != 0);
+
+                writer.write_u16::<LittleEndian>(ent_id)?;
+                writer.write_u8(flags.bits())?;
+                match flags.contains(BaselineFlags::LARGE_MODEL) {
+                    true => writer.write_u16::<LittleEndian>(model_id)?,
+                    false => writer.write_u8(model_id as u8)?,
+                }
+                match flags.contains(BaselineFlags::LARGE_FRAME) {
+                    true => writer.write_u16::<LittleEndian>(frame_id)?,
+                    false => writer.write_u8(frame_id as u8)?,
+                }
+                writer.write_u8(colormap)?;
+                writer.write_u8(skin_id)?;
+
+                for i in 0..3 {
+                    write_coord(writer, origin[i])?;
+                    write_angle(writer, angles[i])?;
+                }
+
+                if flags.contains(BaselineFlags::ALPHA) {
+                    writer.write_u8(alpha)?;
+                }
+            }
+
+            ServerCmd::FastUpdate(_) => unreachable!(),
         }
 
         Ok(())
@@ -1985,6 +2256,34 @@ pub enum BlockingMode {
     Timeout(Duration),
 }
 
+/// Traffic counters for a single [`QSocket`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NetStats {
+    /// Packets sent, including acknowledgements and resends.
+    pub packets_sent: usize,
+
+    /// Packets received from the remote host, including acknowledgements.
+    pub packets_received: usize,
+
+    /// Bytes sent, including packet headers.
+    pub bytes_sent: usize,
+
+    /// Bytes received, including packet headers.
+    pub bytes_received: usize,
+
+    /// Unreliable packets that never arrived, as indicated by gaps in the sequence.
+    pub dropped_packets: usize,
+
+    /// Unreliable packets that arrived after a newer one and were discarded.
+    pub out_of_order_packets: usize,
+
+    /// Reliable packets that were received more than once.
+    pub duplicate_packets: usize,
+
+    /// Packets too short to contain a header.
+    pub short_packets: usize,
+}
+
 pub struct QSocket {
     socket: UdpSocket,
     remote: SocketAddr,
@@ -1998,11 +2297,11 @@ pub struct QSocket {
     send_queue: VecDeque<Box<[u8]>>,
     send_cache: Box<[u8]>,
     send_next: bool,
-    send_count: usize,
-    resend_count: usize,
 
     recv_sequence: u32,
     recv_buf: [u8; MAX_MESSAGE],
+
+    stats: NetStats,
 }
 
 impl QSocket {
@@ -2019,12 +2318,12 @@ impl QSocket {
             send_sequence: 0,
             send_queue: VecDeque::new(),
             send_cache: Box::new([]),
-            send_count: 0,
             send_next: false,
-            resend_count: 0,
 
             recv_sequence: 0,
             recv_buf: [0; MAX_MESSAGE],
+
+            stats: NetStats::default(),
         }
     }
 
@@ -2032,6 +2331,24 @@ impl QSocket {
         self.send_queue.is_empty() && self.send_cache.is_empty()
     }
 
+    /// Returns the address of the remote host this socket is connected to.
+    pub fn remote(&self) -> SocketAddr {
+        self.remote
+    }
+
+    /// Returns the traffic counters for this socket.
+    pub fn stats(&self) -> &NetStats {
+        &self.stats
+    }
+
+    // Send a raw packet to the remote host, updating the traffic counters.
+    fn send_packet(&mut self, packet: &[u8]) -> Result<(), NetError> {
+        self.socket.send_to(packet, self.remote)?;
+        self.stats.packets_sent += 1;
+        self.stats.bytes_sent += packet.len();
+        Ok(())
+    }
+
     /// Begin sending a reliable message over this socket.
     pub fn begin_send_msg(&mut self, msg: &[u8]) -> Result<(), NetError> {
         // make sure all reliable messages have been ACKed in their entirety
@@ -2072,8 +2389,10 @@ impl QSocket {
         if self.send_cache.is_empty() {
             Err(NetError::with_msg("Attempted resend with empty send cache"))
         } else {
-            self.socket.send_to(&self.send_cache, self.remote)?;
-            self.resend_count += 1;
+            let packet = std::mem::take(&mut self.send_cache);
+            let result = self.send_packet(&packet);
+            self.send_cache = packet;
+            result?;
 
             Ok(())
         }
@@ -2107,11 +2426,10 @@ impl QSocket {
         self.send_sequence += 1;
 
         // send the composed packet
-        self.socket.send_to(&self.send_cache, self.remote)?;
-
-        // TODO: update send time
-        // bump send count
-        self.send_count += 1;
+        let packet = std::mem::take(&mut self.send_cache);
+        let result = self.send_packet(&packet);
+        self.send_cache = packet;
+        result?;
 
         // don't send the next chunk until this one gets ACKed
         self.send_next = false;
@@ -2143,10 +2461,7 @@ impl QSocket {
         self.unreliable_send_sequence += 1;
 
         // send the message
-        self.socket.send_to(&packet, self.remote)?;
-
-        // bump send count
-        self.send_count += 1;
+        self.send_packet(&packet)?;
 
         Ok(())
     }
@@ -2195,6 +2510,15 @@ impl QSocket {
                 continue;
             }
 
+            self.stats.packets_received += 1;
+            self.stats.bytes_received += packet_len;
+
+            if packet_len < HEADER_SIZE {
+                debug!("short packet");
+                self.stats.short_packets += 1;
+                continue;
+            }
+
             let mut reader = BufReader::new(Cursor::new(&self.recv_buf[..packet_len]));
 
             let msg_kind_code = reader.read_u16::<NetworkEndian>()?;
@@ -2208,12 +2532,6 @@ impl QSocket {
                 }
             };
 
-            if packet_len < HEADER_SIZE {
-                // TODO: increment short packet count
-                debug!("short packet");
-                continue;
-            }
-
             let field_len = reader.read_u16::<NetworkEndian>()?;
             if field_len as usize != packet_len {
                 return Err(NetError::InvalidData(format!(
@@ -2237,6 +2555,7 @@ impl QSocket {
                     // we've received a newer datagram, ignore
                     if sequence < self.unreliable_recv_sequence {
                         println!("Stale datagram with sequence # {}", sequence);
+                        self.stats.out_of_order_packets += 1;
                         break;
                     }
 
@@ -2247,6 +2566,7 @@ impl QSocket {
                             "Dropped {} packet(s) ({} -> {})",
                             drop_count, sequence, self.unreliable_recv_sequence
                         );
+                        self.stats.dropped_packets += drop_count as usize;
                     }
 
                     self.unreliable_recv_sequence = sequence + 1;
@@ -2287,11 +2607,14 @@ impl QSocket {
                     ack_curs.write_u16::<NetworkEndian>(MsgKind::Ack as u16)?;
                     ack_curs.write_u16::<NetworkEndian>(HEADER_SIZE as u16)?;
                     ack_curs.write_u32::<NetworkEndian>(sequence)?;
-                    self.socket.send_to(ack_curs.into_inner(), self.remote)?;
+                    self.socket.send_to(&ack_buf, self.remote)?;
+                    self.stats.packets_sent += 1;
+                    self.stats.bytes_sent += ack_buf.len();
 
                     // if this was a duplicate, drop it
                     if sequence != self.recv_sequence {
                         println!("Duplicate message received");
+                        self.stats.duplicate_packets += 1;
                         continue;
                     }
 
@@ -2641,6 +2964,88 @@ mod test {
         assert_eq!(src, dst);
     }
 
+    #[test]
+    fn test_server_cmd_skybox_read_write_eq() {
+        let src = ServerCmd::SkyBox {
+            name: String::from("skybox_test"),
+        };
+        let mut packet = Vec::new();
+        src.serialize(&mut packet).unwrap();
+        let mut reader = BufReader::new(packet.as_slice());
+        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+
+        assert_eq!(src, dst);
+    }
+
+    #[test]
+    fn test_server_cmd_fog_read_write_eq() {
+        let src = ServerCmd::Fog {
+            density: 1.0,
+            color: Vector3::new(0.0, 1.0, 0.0),
+            fade_time: Duration::milliseconds(2500),
+        };
+        let mut packet = Vec::new();
+        src.serialize(&mut packet).unwrap();
+        let mut reader = BufReader::new(packet.as_slice());
+        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+
+        assert_eq!(src, dst);
+    }
+
+    #[test]
+    fn test_server_cmd_spawn_baseline_2_read_write_eq() {
+        let src = ServerCmd::SpawnBaseline2 {
+            ent_id: 17,
+            model_id: 300,
+            frame_id: 2,
+            colormap: 0,
+            skin_id: 1,
+            origin: Vector3::new(8.0, -16.0, 24.0),
+            angles: Vector3::new(Deg(0.0), Deg(90.0), Deg(0.0)),
+            alpha: encode_entity_alpha(0.5),
+        };
+        let mut packet = Vec::new();
+        src.serialize(&mut packet).unwrap();
+        let mut reader = BufReader::new(packet.as_slice());
+        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+
+        assert_eq!(src, dst);
+    }
+
+    #[test]
+    fn test_entity_alpha_encoding() {
+        assert_eq!(encode_entity_alpha(1.0), 0);
+        assert_eq!(decode_entity_alpha(0), 1.0);
+        assert_eq!(decode_entity_alpha(encode_entity_alpha(0.0)), 0.0);
+        assert_eq!(decode_entity_alpha(255), 1.0);
+        assert!((decode_entity_alpha(encode_entity_alpha(0.4)) - 0.4).abs() < 1.0 / 254.0);
+    }
+
+    #[test]
+    fn test_server_cmd_fast_update_read_write_eq() {
+        let src = ServerCmd::FastUpdate(EntityUpdate {
+            ent_id: 300,
+            model_id: Some(12),
+            frame_id: Some(3),
+            colormap: None,
+            skin_id: Some(1),
+            effects: Some(EntityEffects::DIM_LIGHT),
+            origin_x: Some(128.5),
+            pitch: None,
+            origin_y: Some(-64.0),
+            yaw: Some(Deg(90.0)),
+            origin_z: None,
+            roll: Some(Deg(-45.0)),
+            no_lerp: true,
+        });
+        let mut packet = Vec::new();
+        src.serialize(&mut packet).unwrap();
+        let mut reader = BufReader::new(packet.as_slice());
+        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+
+        assert_eq!(src, dst);
+    }
+
     #[test]
     fn test_client_cmd_string_cmd_read_write_eq() {
         let src = ClientCmd::StringCmd {
@@ -2700,6 +3105,34 @@ mod test {
         // TODO: assert can_send == true, send_next == false, etc
     }
 
+    #[test]
+    fn test_qsocket_stats_reliable_ack() {
+        let (mut src, mut dst) = gen_qsocket_pair();
+
+        let message = String::from("test message").into_bytes();
+        src.begin_send_msg(&message).unwrap();
+        dst.recv_msg(BlockingMode::Blocking).unwrap();
+
+        // receive the ACK
+        src.recv_msg(BlockingMode::Timeout(Duration::milliseconds(100)))
+            .unwrap();
+
+        let packet_len = HEADER_SIZE + message.len();
+
+        let src_stats = src.stats();
+        assert_eq!(src_stats.packets_sent, 1);
+        assert_eq!(src_stats.bytes_sent, packet_len);
+        assert_eq!(src_stats.packets_received, 1);
+        assert_eq!(src_stats.bytes_received, HEADER_SIZE);
+        assert!(src.can_send());
+
+        let dst_stats = dst.stats();
+        assert_eq!(dst_stats.packets_received, 1);
+        assert_eq!(dst_stats.bytes_received, packet_len);
+        assert_eq!(dst_stats.packets_sent, 1);
+        assert_eq!(dst_stats.duplicate_packets, 0);
+    }
+
     #[test]
     fn test_qsocket_send_msg_unreliable_recv_msg_eq() {
         let (mut src, mut dst) = gen_qsocket_pair();
@@ -0,0 +1,469 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Remote console.
+//!
+//! Commands are sent out-of-band to the server's connection port as
+//! `rcon <password> <command>`, in the same format as QuakeWorld and
+//! DarkPlaces. The server executes the command and replies with one or more
+//! `n<text>` packets containing the printed output.
+
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket},
+};
+
+use crate::common::{
+    console::Console,
+    net::{
+        connect::{ConnectListener, OOB_HEADER},
+        NetError, MAX_MESSAGE,
+    },
+};
+
+use chrono::{DateTime, Duration, Utc};
+
+const RCON_COMMAND: &[u8] = b"rcon ";
+const RCON_PRINT: u8 = b'n';
+
+// replies are split so each fits in a single unfragmented datagram
+const MAX_PRINT_LEN: usize = 1024;
+
+/// The number of attempts a single host may make in a burst.
+pub const RCON_BURST: u32 = 5;
+
+/// The time it takes for a host to be allowed one more attempt, in milliseconds.
+pub const RCON_REFILL_MS: i64 = 1000;
+
+/// A remote console packet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RconPacket {
+    /// A command to execute on the server.
+    Command { password: String, command: String },
+
+    /// Output printed by the server.
+    Print { text: String },
+}
+
+impl RconPacket {
+    /// Returns true if `packet` is an out-of-band remote console command.
+    pub fn is_rcon(packet: &[u8]) -> bool {
+        packet
+            .strip_prefix(&OOB_HEADER[..])
+            .map(|body| body.starts_with(RCON_COMMAND))
+            .unwrap_or(false)
+    }
+
+    /// Generates the byte representation of this packet for transmission.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, NetError> {
+        let mut packet = OOB_HEADER.to_vec();
+
+        match *self {
+            RconPacket::Command {
+                ref password,
+                ref command,
+            } => {
+                if password.is_empty() || password.contains(char::is_whitespace) {
+                    return Err(NetError::with_msg(
+                        "rcon password must be non-empty and contain no whitespace",
+                    ));
+                }
+
+                packet.extend_from_slice(RCON_COMMAND);
+                packet.extend_from_slice(password.as_bytes());
+                packet.push(b' ');
+                packet.extend_from_slice(command.as_bytes());
+            }
+
+            RconPacket::Print { ref text } => {
+                packet.push(RCON_PRINT);
+                packet.extend_from_slice(text.as_bytes());
+            }
+        }
+
+        if packet.len() > MAX_MESSAGE {
+            return Err(NetError::with_msg(format!(
+                "rcon packet too long ({} bytes)",
+                packet.len()
+            )));
+        }
+
+        Ok(packet)
+    }
+
+    /// Parse an out-of-band packet, including the `0xFF` header.
+    pub fn from_bytes(packet: &[u8]) -> Result<RconPacket, NetError> {
+        let body = match packet.strip_prefix(&OOB_HEADER[..]) {
+            Some(b) => b,
+            None => {
+                return Err(NetError::InvalidData(
+                    "missing out-of-band header".to_owned(),
+                ))
+            }
+        };
+
+        if let Some(args) = body.strip_prefix(RCON_COMMAND) {
+            let args = String::from_utf8_lossy(args);
+            let (password, command) = match args.split_once(' ') {
+                Some((p, c)) if !p.is_empty() => (p, c),
+                _ => return Err(NetError::InvalidData("malformed rcon command".to_owned())),
+            };
+
+            return Ok(RconPacket::Command {
+                password: password.to_owned(),
+                command: command.to_owned(),
+            });
+        }
+
+        match body.split_first() {
+            Some((&RCON_PRINT, text)) => Ok(RconPacket::Print {
+                text: String::from_utf8_lossy(text).into_owned(),
+            }),
+
+            _ => Err(NetError::InvalidData("unknown rcon packet".to_owned())),
+        }
+    }
+}
+
+/// Compare two byte strings in time independent of their contents.
+///
+/// Only the length of the longer input can be inferred from the timing.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let len = a.len().max(b.len());
+    let len_mismatch = a.len() != b.len();
+    let mut diff = 0u8;
+
+    for i in 0..len {
+        let x = a.get(i).copied().unwrap_or(0);
+        let y = b.get(i).copied().unwrap_or(0);
+        diff |= x ^ y;
+    }
+
+    !len_mismatch && diff == 0
+}
+
+/// The client side of the remote console.
+pub struct RconClient {
+    socket: UdpSocket,
+    remote: SocketAddr,
+}
+
+impl RconClient {
+    /// Create a client that sends commands to the server at `remote`.
+    pub fn new<A>(remote: A) -> Result<RconClient, NetError>
+    where
+        A: ToSocketAddrs,
+    {
+        let remote = match remote.to_socket_addrs()?.next() {
+            Some(r) => r,
+            None => return Err(NetError::with_msg("rcon address did not resolve")),
+        };
+
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_nonblocking(true)?;
+
+        Ok(RconClient { socket, remote })
+    }
+
+    /// Returns the address of the server commands are sent to.
+    pub fn remote(&self) -> SocketAddr {
+        self.remote
+    }
+
+    /// Send a command to the server.
+    pub fn send<S>(&self, password: S, command: S) -> Result<(), NetError>
+    where
+        S: AsRef<str>,
+    {
+        let packet = RconPacket::Command {
+            password: password.as_ref().to_owned(),
+            command: command.as_ref().to_owned(),
+        };
+        self.socket.send_to(&packet.to_bytes()?, self.remote)?;
+        Ok(())
+    }
+
+    /// Return the next piece of output from the server, if any has arrived.
+    ///
+    /// This never blocks.
+    pub fn poll(&self) -> Result<Option<String>, NetError> {
+        let mut recv_buf = [0u8; MAX_MESSAGE];
+
+        loop {
+            let (len, remote) = match self.socket.recv_from(&mut recv_buf) {
+                Ok(r) => r,
+                Err(e) => match e.kind() {
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut => return Ok(None),
+                    _ => return Err(NetError::from(e)),
+                },
+            };
+
+            if remote != self.remote {
+                debug!("Ignoring rcon packet from {}", remote);
+                continue;
+            }
+
+            match RconPacket::from_bytes(&recv_buf[..len]) {
+                Ok(RconPacket::Print { text }) => return Ok(Some(text)),
+                Ok(p) => debug!("Ignoring unexpected rcon packet: {:?}", p),
+                Err(e) => warn!("Invalid rcon reply: {}", e),
+            }
+        }
+    }
+}
+
+// A token bucket for one remote host.
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: u32,
+    last_refill: DateTime<Utc>,
+}
+
+/// Limits how often each remote host may attempt rcon commands.
+///
+/// Each host may make `RCON_BURST` attempts at once, after which it is allowed
+/// one more attempt every `RCON_REFILL_MS` milliseconds.
+#[derive(Debug, Default)]
+pub struct RconRateLimiter {
+    buckets: HashMap<IpAddr, Bucket>,
+}
+
+impl RconRateLimiter {
+    pub fn new() -> RconRateLimiter {
+        RconRateLimiter::default()
+    }
+
+    /// Record an attempt from `addr` at time `now`, returning whether it is allowed.
+    pub fn check(&mut self, addr: IpAddr, now: DateTime<Utc>) -> bool {
+        let refill = Duration::milliseconds(RCON_REFILL_MS);
+
+        // forget hosts that have been quiet long enough to have a full bucket
+        self.buckets
+            .retain(|_, b| now.signed_duration_since(b.last_refill) < refill * RCON_BURST as i32);
+
+        let bucket = self.buckets.entry(addr).or_insert(Bucket {
+            tokens: RCON_BURST,
+            last_refill: now,
+        });
+
+        let elapsed = now.signed_duration_since(bucket.last_refill);
+        let earned = (elapsed.num_milliseconds() / RCON_REFILL_MS).max(0) as u32;
+        if earned > 0 {
+            bucket.tokens = (bucket.tokens + earned).min(RCON_BURST);
+            bucket.last_refill += refill * earned as i32;
+        }
+
+        if bucket.tokens == 0 {
+            return false;
+        }
+
+        bucket.tokens -= 1;
+        true
+    }
+}
+
+/// The server side of the remote console.
+#[derive(Debug, Default)]
+pub struct RconServer {
+    limiter: RconRateLimiter,
+}
+
+impl RconServer {
+    pub fn new() -> RconServer {
+        RconServer::default()
+    }
+
+    /// Handle a remote console packet received by `listener`.
+    ///
+    /// If the password matches `rcon_password` (usually the value of the cvar
+    /// of the same name), the command is executed with [`Console::stuff_text`]
+    /// and everything it prints is sent back to `remote`. An empty
+    /// `rcon_password` disables remote commands. Attempts beyond the rate limit
+    /// are dropped without a reply.
+    pub fn handle(
+        &mut self,
+        listener: &ConnectListener,
+        console: &Console,
+        rcon_password: &str,
+        packet: RconPacket,
+        remote: SocketAddr,
+    ) -> Result<(), NetError> {
+        let (password, command) = match packet {
+            RconPacket::Command { password, command } => (password, command),
+            RconPacket::Print { .. } => return Ok(()),
+        };
+
+        if !self.limiter.check(remote.ip(), Utc::now()) {
+            warn!("Dropping rate-limited rcon attempt from {}", remote);
+            return Ok(());
+        }
+
+        let output = if rcon_password.is_empty() {
+            "rcon is disabled on this server.\n".to_owned()
+        } else if !constant_time_eq(password.as_bytes(), rcon_password.as_bytes()) {
+            warn!("Bad rcon_password from {}", remote);
+            "Bad rcon_password.\n".to_owned()
+        } else {
+            info!("rcon from {}: {}", remote, command);
+            console.begin_redirect();
+            console.stuff_text(&command);
+            console.execute();
+            console.end_redirect()
+        };
+
+        for text in split_output(&output, MAX_PRINT_LEN) {
+            listener.send_rcon_packet(&RconPacket::Print { text }, remote)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Split `text` into chunks of at most `max_len` bytes on character boundaries.
+fn split_output(text: &str, max_len: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+
+    for c in text.chars() {
+        if chunk.len() + c.len_utf8() > max_len {
+            chunks.push(std::mem::take(&mut chunk));
+        }
+        chunk.push(c);
+    }
+
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+
+    chunks
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::common::{
+        console::{CmdRegistry, CvarRegistry},
+        net::connect::ListenerPacket,
+    };
+
+    #[test]
+    fn test_rcon_packet_round_trip() {
+        for packet in [
+            RconPacket::Command {
+                password: String::from("hunter2"),
+                command: String::from("map e1m1"),
+            },
+            RconPacket::Print {
+                text: String::from("map changed\n"),
+            },
+        ] {
+            let bytes = packet.to_bytes().unwrap();
+            assert_eq!(RconPacket::from_bytes(&bytes).unwrap(), packet);
+        }
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"secret"));
+
+        // length differences must not wrap around to 0
+        assert!(!constant_time_eq(&[0; 256], b""));
+        assert!(!constant_time_eq(&[0; 65536], b""));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RconRateLimiter::new();
+        let addr: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        let start = Utc::now();
+
+        for _ in 0..RCON_BURST {
+            assert!(limiter.check(addr, start));
+        }
+        assert!(!limiter.check(addr, start));
+
+        // other hosts are unaffected
+        assert!(limiter.check(other, start));
+
+        // one attempt is earned back after the refill interval
+        let later = start + Duration::milliseconds(RCON_REFILL_MS);
+        assert!(limiter.check(addr, later));
+        assert!(!limiter.check(addr, later));
+    }
+
+    #[test]
+    fn test_split_output() {
+        assert_eq!(split_output("abcdef", 4), vec!["abcd", "ef"]);
+        assert_eq!(split_output("", 4), Vec::<String>::new());
+
+        // multi-byte characters are not split
+        assert_eq!(split_output("aé", 2), vec!["a", "é"]);
+    }
+
+    #[test]
+    fn test_rcon_loopback() {
+        let names = Rc::new(RefCell::new(Vec::new()));
+        let cmds = Rc::new(RefCell::new(CmdRegistry::new(names.clone())));
+        let cvars = Rc::new(RefCell::new(CvarRegistry::new(names)));
+        let console = Console::new(cmds, cvars);
+
+        let listener = ConnectListener::bind("127.0.0.1:0").unwrap();
+        let client = RconClient::new(listener.local_addr().unwrap()).unwrap();
+        let mut server = RconServer::new();
+
+        let mut exchange = |rcon_password: &str, password: &str, command: &str| {
+            client.send(password, command).unwrap();
+            let (packet, remote) = match listener.recv_packet().unwrap() {
+                (ListenerPacket::Rcon(p), r) => (p, r),
+                p => panic!("expected rcon packet, got {:?}", p),
+            };
+            server
+                .handle(&listener, &console, rcon_password, packet, remote)
+                .unwrap();
+
+            // the reply is sent before handle returns, but give it a moment to arrive
+            for _ in 0..100 {
+                if let Some(text) = client.poll().unwrap() {
+                    return text;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(5));
+            }
+            panic!("no rcon reply");
+        };
+
+        assert_eq!(
+            exchange("", "secret", "echo hi"),
+            "rcon is disabled on this server.\n"
+        );
+        assert_eq!(
+            exchange("secret", "wrong", "echo hi"),
+            "Bad rcon_password.\n"
+        );
+        assert_eq!(exchange("secret", "secret", "echo hi"), "hi\n");
+    }
+}
@@ -0,0 +1,233 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Server discovery.
+//!
+//! Servers are found by sending a `Request::ServerInfo` to each candidate
+//! address (usually the broadcast address of the local subnet) and collecting
+//! the `Response::ServerInfo` replies until a timeout expires.
+
+use std::{
+    net::{Ipv4Addr, SocketAddr, ToSocketAddrs},
+    ops::RangeInclusive,
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use crate::common::net::{
+    connect::{ConnectSocket, Request, Response, ResponseServerInfo},
+    master, NetError, GAME_NAME,
+};
+
+use chrono::{Duration, Utc};
+
+/// The port a server listens on unless configured otherwise.
+pub const DEFAULT_PORT: u16 = 26000;
+
+/// How long to wait for replies, in milliseconds.
+///
+/// This matches the original engine's `slist` (see `Slist_Poll` in
+/// `WinQuake/net_main.c`).
+pub const DEFAULT_TIMEOUT_MS: i64 = 1500;
+
+/// The pending result of a query started with [`spawn_query`].
+pub type ServerQuery = Receiver<Result<Vec<ServerListEntry>, NetError>>;
+
+/// A server that replied to a server info query.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerListEntry {
+    /// The address the reply came from.
+    pub address: SocketAddr,
+    pub hostname: String,
+    pub map: String,
+    pub client_count: u8,
+    pub client_max: u8,
+    pub protocol_version: u8,
+}
+
+impl ServerListEntry {
+    fn from_response(address: SocketAddr, info: ResponseServerInfo) -> ServerListEntry {
+        ServerListEntry {
+            address,
+            hostname: info.hostname,
+            map: info.levelname,
+            client_count: info.client_count,
+            client_max: info.client_max,
+            protocol_version: info.protocol_version,
+        }
+    }
+}
+
+/// Query each of `targets` for server info, returning the servers that reply
+/// within `timeout`.
+///
+/// Targets may be broadcast addresses. Each server is listed once, in the order
+/// its first reply arrived.
+pub fn query_servers<I>(targets: I, timeout: Duration) -> Result<Vec<ServerListEntry>, NetError>
+where
+    I: IntoIterator<Item = SocketAddr>,
+{
+    let mut socket = ConnectSocket::bind("0.0.0.0:0")?;
+    socket.set_broadcast(true)?;
+
+    for target in targets {
+        socket.send_request(Request::server_info(GAME_NAME), target)?;
+    }
+
+    let deadline = Utc::now() + timeout;
+    let mut servers: Vec<ServerListEntry> = Vec::new();
+
+    loop {
+        let remaining = deadline.signed_duration_since(Utc::now());
+        if remaining <= Duration::zero() {
+            break;
+        }
+
+        match socket.recv_response(Some(remaining)) {
+            Ok(Some((Response::ServerInfo(info), remote))) => {
+                if servers.iter().all(|s| s.address != remote) {
+                    servers.push(ServerListEntry::from_response(remote, info));
+                }
+            }
+
+            Ok(Some((response, remote))) => {
+                debug!(
+                    "Ignoring unexpected response from {}: {:?}",
+                    remote, response
+                )
+            }
+
+            // timed out
+            Ok(None) => break,
+
+            // a malformed reply from one host shouldn't end the search
+            Err(NetError::InvalidData(msg)) | Err(NetError::Other(msg)) => {
+                warn!("Invalid server info reply: {}", msg)
+            }
+
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(servers)
+}
+
+/// Broadcast a server info query on the local subnet to each port in `ports`.
+pub fn query_lan(
+    ports: RangeInclusive<u16>,
+    timeout: Duration,
+) -> Result<Vec<ServerListEntry>, NetError> {
+    query_servers(
+        ports.map(|port| SocketAddr::from((Ipv4Addr::BROADCAST, port))),
+        timeout,
+    )
+}
+
+/// Fetch the server list from a master server, then query each listed server.
+///
+/// `timeout` applies to each of the two steps.
+pub fn query_internet<A>(master: A, timeout: Duration) -> Result<Vec<ServerListEntry>, NetError>
+where
+    A: ToSocketAddrs,
+{
+    let addrs = master::query_master(master, timeout)?;
+    if addrs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    query_servers(addrs, timeout)
+}
+
+/// Run a query on a background thread.
+///
+/// The result is sent on the returned channel once the query completes.
+pub fn spawn_query<F>(query: F) -> ServerQuery
+where
+    F: FnOnce() -> Result<Vec<ServerListEntry>, NetError> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        // if the receiver was dropped, nobody wants the result anyway
+        let _ = sender.send(query());
+    });
+
+    receiver
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::common::net::connect::ConnectListener;
+
+    #[test]
+    fn test_query_servers_loopback() {
+        let listener = ConnectListener::bind("127.0.0.1:0").unwrap();
+        let listener_addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (request, remote) = listener.recv_request().unwrap();
+            match request {
+                Request::ServerInfo(r) => assert_eq!(r.game_name, GAME_NAME),
+                r => panic!("unexpected request: {:?}", r),
+            }
+
+            listener
+                .send_response(
+                    Response::ServerInfo(ResponseServerInfo {
+                        address: listener_addr.to_string(),
+                        hostname: String::from("test server"),
+                        levelname: String::from("e1m1"),
+                        client_count: 2,
+                        client_max: 8,
+                        protocol_version: 3,
+                    }),
+                    remote,
+                )
+                .unwrap();
+        });
+
+        let servers = query_servers(vec![listener_addr], Duration::milliseconds(500)).unwrap();
+        server.join().unwrap();
+
+        assert_eq!(
+            servers,
+            vec![ServerListEntry {
+                address: listener_addr,
+                hostname: String::from("test server"),
+                map: String::from("e1m1"),
+                client_count: 2,
+                client_max: 8,
+                protocol_version: 3,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_query_servers_no_reply() {
+        // bind a socket that never answers so the query has somewhere to go
+        let listener = ConnectListener::bind("127.0.0.1:0").unwrap();
+        let listener_addr = listener.local_addr().unwrap();
+
+        let servers = query_servers(vec![listener_addr], Duration::milliseconds(100)).unwrap();
+        assert!(servers.is_empty());
+    }
+}
@@ -118,6 +118,16 @@ impl Vfs {
         Ok(())
     }
 
+    /// Returns the directory that files written by the game should be placed in.
+    ///
+    /// This is the first directory added to the filesystem, usually `id1/`.
+    pub fn game_dir(&self) -> Option<&Path> {
+        self.components.iter().find_map(|c| match c {
+            VfsComponent::Directory(path) => Some(path.as_path()),
+            _ => None,
+        })
+    }
+
     pub fn open<S>(&self, virtual_path: S) -> Result<VirtualFile, VfsError>
     where
         S: AsRef<str>,
@@ -0,0 +1,501 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Server-side bots.
+//!
+//! Bots occupy ordinary client slots and produce a [`ClientCmd::Move`] every
+//! frame, just as a remote client would send. They roam the level along a
+//! [`WaypointGraph`](waypoint::WaypointGraph) and, when an enemy is in sight,
+//! chase it and fire once their aim is close enough.
+//!
+//! Bot skill ranges from 0.0 to 1.0 and controls how quickly a bot turns and
+//! how far off target its aim drifts.
+
+pub mod waypoint;
+
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+use crate::{
+    common::{
+        bsp::{BspCollisionHull, BspError},
+        net::{ButtonFlags, ClientCmd},
+    },
+    server::Session,
+};
+
+use self::waypoint::WaypointGraph;
+
+use cgmath::{Angle as _, Deg, InnerSpace as _, Vector3, Zero as _};
+use chrono::Duration;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+/// The skill of bots added without one.
+pub const DEFAULT_BOT_SKILL: f32 = 0.5;
+
+/// Names given to bots added without one, in order of preference.
+pub const BOT_NAMES: &[&str] = &[
+    "Grunt", "Enforcer", "Ogre", "Knight", "Fiend", "Scrag", "Vore", "Shambler",
+];
+
+// the speed at which bots run, same as sv_maxspeed
+const BOT_MOVE_SPEED: f32 = 320.0;
+
+// turn rates at skill 0 and 1, in degrees per second
+const MIN_TURN_RATE: f32 = 90.0;
+const MAX_TURN_RATE: f32 = 720.0;
+
+// the largest aim error, at skill 0
+const MAX_AIM_ERROR: f32 = 15.0;
+const AIM_ERROR_INTERVAL_MS: i64 = 500;
+
+// bots fire when their aim is within this many degrees of the target
+const FIRE_CONE: f32 = 5.0;
+
+// the height of the eyes above the player origin
+const EYE_HEIGHT: f32 = 22.0;
+
+// a waypoint counts as reached when the bot is this close to it horizontally
+const ARRIVE_DISTANCE: f32 = 24.0;
+
+// a bot that moves less than STUCK_DISTANCE in STUCK_INTERVAL_MS jumps and
+// picks a new path
+const STUCK_DISTANCE: f32 = 16.0;
+const STUCK_INTERVAL_MS: i64 = 1000;
+
+/// What a bot can perceive at the start of a frame.
+#[derive(Clone, Copy, Debug)]
+pub struct BotView {
+    /// The current server time.
+    pub time: Duration,
+
+    /// The origin of the bot's player entity.
+    pub origin: Vector3<f32>,
+
+    /// The origin of the nearest visible enemy, if any.
+    pub enemy: Option<Vector3<f32>>,
+}
+
+#[derive(Debug)]
+pub struct Bot {
+    skill: f32,
+    rng: SmallRng,
+
+    // current view angles
+    angles: Vector3<Deg<f32>>,
+
+    // aim offset from the target as (pitch, yaw), changed periodically
+    aim_error: (Deg<f32>, Deg<f32>),
+    aim_error_time: Duration,
+
+    // remaining waypoints on the current path, next first
+    path: VecDeque<usize>,
+
+    // used to detect when the bot is stuck
+    stuck_origin: Vector3<f32>,
+    stuck_time: Duration,
+}
+
+impl Bot {
+    /// Creates a new bot with the given skill, clamped to [0.0, 1.0].
+    pub fn new(skill: f32) -> Bot {
+        Bot {
+            skill: skill.clamp(0.0, 1.0),
+            rng: SmallRng::from_entropy(),
+            angles: Vector3::new(Deg::zero(), Deg::zero(), Deg::zero()),
+            aim_error: (Deg::zero(), Deg::zero()),
+            aim_error_time: Duration::zero(),
+            path: VecDeque::new(),
+            stuck_origin: Vector3::zero(),
+            stuck_time: Duration::zero(),
+        }
+    }
+
+    pub fn skill(&self) -> f32 {
+        self.skill
+    }
+
+    /// Decides what to do this frame and returns the resulting move command.
+    pub fn think(
+        &mut self,
+        view: &BotView,
+        waypoints: &WaypointGraph,
+        frame_time: Duration,
+    ) -> ClientCmd {
+        let mut button_flags = ButtonFlags::empty();
+
+        if view.time - self.stuck_time >= Duration::milliseconds(STUCK_INTERVAL_MS) {
+            if (view.origin - self.stuck_origin).magnitude() < STUCK_DISTANCE {
+                self.path.clear();
+                button_flags |= ButtonFlags::JUMP;
+            }
+
+            self.stuck_origin = view.origin;
+            self.stuck_time = view.time;
+        }
+
+        self.navigate(view, waypoints);
+
+        let move_target = match self.path.front() {
+            Some(&id) => waypoints.get(id).map(|wp| wp.origin()),
+            None => view.enemy,
+        };
+
+        // face the enemy if there is one, otherwise the direction of travel
+        let desired = match (view.enemy, move_target) {
+            (Some(enemy), _) => {
+                if view.time >= self.aim_error_time {
+                    let max_error = MAX_AIM_ERROR * (1.0 - self.skill);
+                    self.aim_error = (
+                        Deg(self.rng.gen_range(-max_error..=max_error)),
+                        Deg(self.rng.gen_range(-max_error..=max_error)),
+                    );
+                    self.aim_error_time = view.time + Duration::milliseconds(AIM_ERROR_INTERVAL_MS);
+                }
+
+                let eye = view.origin + Vector3::new(0.0, 0.0, EYE_HEIGHT);
+                let angles = angles_toward(eye, enemy + Vector3::new(0.0, 0.0, EYE_HEIGHT));
+                Some(Vector3::new(
+                    angles.x + self.aim_error.0,
+                    angles.y + self.aim_error.1,
+                    Deg::zero(),
+                ))
+            }
+
+            (None, Some(target)) => {
+                let angles = angles_toward(view.origin, target);
+                Some(Vector3::new(Deg::zero(), angles.y, Deg::zero()))
+            }
+
+            (None, None) => None,
+        };
+
+        if let Some(desired) = desired {
+            self.angles = aim(self.angles, desired, self.skill, frame_time);
+        }
+
+        if let Some(enemy) = view.enemy {
+            let eye = view.origin + Vector3::new(0.0, 0.0, EYE_HEIGHT);
+            let exact = angles_toward(eye, enemy + Vector3::new(0.0, 0.0, EYE_HEIGHT));
+            if angle_between(self.angles, exact) <= FIRE_CONE {
+                button_flags |= ButtonFlags::ATTACK;
+            }
+        }
+
+        let (fwd_move, side_move) = match move_target {
+            Some(target) => relative_move(self.angles.y, target - view.origin, BOT_MOVE_SPEED),
+            None => (0, 0),
+        };
+
+        ClientCmd::Move {
+            send_time: view.time,
+            angles: self.angles,
+            fwd_move,
+            side_move,
+            up_move: 0,
+            button_flags,
+            impulse: 0,
+        }
+    }
+
+    // find a new path if needed and drop waypoints that have been reached
+    fn navigate(&mut self, view: &BotView, waypoints: &WaypointGraph) {
+        // discard paths from an old graph
+        if self.path.iter().any(|&id| waypoints.get(id).is_none()) {
+            self.path.clear();
+        }
+
+        if self.path.is_empty() {
+            // chase the enemy or wander somewhere new
+            let start = waypoints.nearest(view.origin);
+            let goal = match view.enemy {
+                Some(enemy) => waypoints.nearest(enemy),
+                None if !waypoints.is_empty() => Some(self.rng.gen_range(0..waypoints.len())),
+                None => None,
+            };
+
+            if let (Some(start), Some(goal)) = (start, goal) {
+                if let Some(path) = waypoints.path(start, goal) {
+                    self.path = path.into();
+                }
+            }
+        }
+
+        while let Some(&id) = self.path.front() {
+            let mut offset = waypoints.get(id).unwrap().origin() - view.origin;
+            offset.z = 0.0;
+            if offset.magnitude() > ARRIVE_DISTANCE {
+                break;
+            }
+
+            self.path.pop_front();
+        }
+    }
+}
+
+/// Returns `true` if a player at `from` can see a player at `to`.
+///
+/// `hull` should be the point-sized hull (hull 0) of the world.
+pub fn can_see(
+    hull: &BspCollisionHull,
+    from: Vector3<f32>,
+    to: Vector3<f32>,
+) -> Result<bool, BspError> {
+    let eye = Vector3::new(0.0, 0.0, EYE_HEIGHT);
+    waypoint::line_clear(hull, from + eye, to + eye)
+}
+
+/// Returns the view angles (pitch, yaw, roll) that look from `from` to `to`.
+///
+/// As with all view angles, positive pitch looks down.
+pub fn angles_toward(from: Vector3<f32>, to: Vector3<f32>) -> Vector3<Deg<f32>> {
+    let d = to - from;
+    let flat = (d.x * d.x + d.y * d.y).sqrt();
+    Vector3::new(
+        -Deg::atan2(d.z, flat),
+        Deg::atan2(d.y, d.x).normalize(),
+        Deg::zero(),
+    )
+}
+
+/// Turns from `current` toward `desired` as far as `skill` allows in
+/// `frame_time`.
+pub fn aim(
+    current: Vector3<Deg<f32>>,
+    desired: Vector3<Deg<f32>>,
+    skill: f32,
+    frame_time: Duration,
+) -> Vector3<Deg<f32>> {
+    let rate = MIN_TURN_RATE + (MAX_TURN_RATE - MIN_TURN_RATE) * skill.clamp(0.0, 1.0);
+    let max_turn = Deg(rate * frame_time.num_milliseconds() as f32 / 1000.0);
+
+    let turn = |from: Deg<f32>, to: Deg<f32>| {
+        let delta = (to - from).normalize_signed();
+        if delta.0.abs() <= max_turn.0 {
+            to.normalize()
+        } else {
+            (from + max_turn * delta.0.signum()).normalize()
+        }
+    };
+
+    Vector3::new(
+        turn(current.x, desired.x).normalize_signed(),
+        turn(current.y, desired.y),
+        Deg::zero(),
+    )
+}
+
+// the largest of the pitch and yaw differences between two sets of angles
+fn angle_between(a: Vector3<Deg<f32>>, b: Vector3<Deg<f32>>) -> f32 {
+    let pitch = (a.x - b.x).normalize_signed().0.abs();
+    let yaw = (a.y - b.y).normalize_signed().0.abs();
+    pitch.max(yaw)
+}
+
+// convert a world-space direction into forward and side speeds for a player
+// facing `yaw`
+fn relative_move(yaw: Deg<f32>, direction: Vector3<f32>, speed: f32) -> (i16, i16) {
+    let flat = Vector3::new(direction.x, direction.y, 0.0);
+    if flat.magnitude2() == 0.0 {
+        return (0, 0);
+    }
+
+    let dir = flat.normalize();
+    let (sin, cos) = yaw.sin_cos();
+    let forward = Vector3::new(cos, sin, 0.0);
+    let right = Vector3::new(sin, -cos, 0.0);
+
+    (
+        (dir.dot(forward) * speed).round() as i16,
+        (dir.dot(right) * speed).round() as i16,
+    )
+}
+
+pub fn cmd_addbot(session: Rc<RefCell<Option<Session>>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        if args.len() > 2 {
+            return "usage: addbot [name] [skill]".to_owned();
+        }
+
+        let skill = match args.get(1) {
+            Some(s) => match s.parse::<f32>() {
+                Ok(skill) => skill,
+                Err(_) => return format!("Invalid skill: {}", s),
+            },
+            None => DEFAULT_BOT_SKILL,
+        };
+
+        match *session.borrow_mut() {
+            Some(ref mut session) => match session.add_bot(args.first().copied(), skill) {
+                Ok(slot) => format!(
+                    "Added bot {} in slot {}",
+                    session.client(slot).and_then(|c| c.name()).unwrap_or(""),
+                    slot
+                ),
+                Err(e) => format!("Couldn't add bot: {}", e),
+            },
+            None => "No server running".to_owned(),
+        }
+    })
+}
+
+pub fn cmd_removebot(session: Rc<RefCell<Option<Session>>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        if args.len() != 1 {
+            return "usage: removebot <name | all>".to_owned();
+        }
+
+        match *session.borrow_mut() {
+            Some(ref mut session) => {
+                if args[0] == "all" {
+                    match session.remove_all_bots() {
+                        Ok(count) => format!("Removed {} bots", count),
+                        Err(e) => format!("Couldn't remove bots: {}", e),
+                    }
+                } else {
+                    match session.remove_bot(args[0]) {
+                        Ok(true) => String::new(),
+                        Ok(false) => format!("No bot named {}", args[0]),
+                        Err(e) => format!("Couldn't remove bot: {}", e),
+                    }
+                }
+            }
+            None => "No server running".to_owned(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{common::console::CmdRegistry, server};
+
+    fn assert_deg_eq(a: Deg<f32>, b: Deg<f32>) {
+        assert!(
+            (a - b).normalize_signed().0.abs() < 0.01,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn test_angles_toward() {
+        let angles = angles_toward(Vector3::zero(), Vector3::new(0.0, 10.0, -10.0));
+        assert_deg_eq(angles.x, Deg(45.0));
+        assert_deg_eq(angles.y, Deg(90.0));
+    }
+
+    #[test]
+    fn test_aim_turn_rate() {
+        let current = Vector3::new(Deg(0.0), Deg(350.0), Deg(0.0));
+        let desired = Vector3::new(Deg(0.0), Deg(100.0), Deg(0.0));
+
+        // the shortest turn is counterclockwise, through 0
+        let fast = aim(current, desired, 1.0, Duration::milliseconds(100));
+        assert_deg_eq(fast.y, Deg(62.0));
+
+        let slow = aim(current, desired, 0.0, Duration::milliseconds(100));
+        assert_deg_eq(slow.y, Deg(359.0));
+
+        // close enough to snap
+        let done = aim(fast, desired, 1.0, Duration::milliseconds(100));
+        assert_deg_eq(done.y, Deg(100.0));
+    }
+
+    #[test]
+    fn test_relative_move() {
+        // facing east, moving north is moving left
+        assert_eq!(
+            relative_move(Deg(0.0), Vector3::new(0.0, 50.0, 0.0), 320.0),
+            (0, -320)
+        );
+        assert_eq!(
+            relative_move(Deg(90.0), Vector3::new(0.0, 50.0, 0.0), 320.0),
+            (320, 0)
+        );
+    }
+
+    #[test]
+    fn test_bot_attacks_visible_enemy() {
+        let hull = BspCollisionHull::for_bounds(
+            Vector3::new(-256.0, -256.0, -64.0),
+            Vector3::new(256.0, 256.0, 0.0),
+        )
+        .unwrap();
+        let waypoints = WaypointGraph::generate(
+            &hull,
+            Vector3::new(-224.0, -224.0, -128.0),
+            Vector3::new(224.0, 224.0, 128.0),
+            64.0,
+        )
+        .unwrap();
+
+        let mut bot = Bot::new(1.0);
+        let view = BotView {
+            time: Duration::zero(),
+            origin: Vector3::new(-224.0, 32.0, 1.0),
+            enemy: Some(Vector3::new(224.0, 32.0, 1.0)),
+        };
+
+        // already facing the enemy, so fire and run toward it
+        match bot.think(&view, &waypoints, Duration::milliseconds(10)) {
+            ClientCmd::Move {
+                angles,
+                fwd_move,
+                button_flags,
+                ..
+            } => {
+                assert_deg_eq(angles.y, Deg(0.0));
+                assert!(fwd_move > 0);
+                assert!(button_flags.contains(ButtonFlags::ATTACK));
+            }
+            cmd => panic!("expected Move, got {:?}", cmd),
+        }
+
+        // facing away, the bot must turn before it can fire
+        let view = BotView {
+            enemy: Some(Vector3::new(-224.0, -224.0, 1.0)),
+            origin: Vector3::new(-224.0, 224.0, 1.0),
+            ..view
+        };
+        let mut bot = Bot::new(0.0);
+        match bot.think(&view, &waypoints, Duration::milliseconds(10)) {
+            ClientCmd::Move { button_flags, .. } => {
+                assert!(!button_flags.contains(ButtonFlags::ATTACK))
+            }
+            cmd => panic!("expected Move, got {:?}", cmd),
+        }
+    }
+
+    #[test]
+    fn test_commands_without_session() {
+        let mut cmds = CmdRegistry::new(Rc::new(RefCell::new(Vec::new())));
+        server::register_commands(&mut cmds, Rc::new(RefCell::new(None)));
+
+        assert_eq!(cmds.exec("addbot", &[]).unwrap(), "No server running");
+        assert_eq!(
+            cmds.exec("removebot", &["all"]).unwrap(),
+            "No server running"
+        );
+        assert_eq!(
+            cmds.exec("removebot", &[]).unwrap(),
+            "usage: removebot <name | all>"
+        );
+        assert_eq!(cmds.exec("ping", &[]).unwrap(), "No server running");
+    }
+}
@@ -0,0 +1,448 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Waypoint graphs for bot navigation.
+//!
+//! Waypoints are generated by sampling the player collision hull (hull 1) of
+//! the world model on a regular grid. The hull is already expanded by the
+//! player's bounding box, so a point is a valid player origin exactly when its
+//! contents are not solid. Each grid column is scanned downward for transitions
+//! into solid space, and every floor found becomes a waypoint.
+//!
+//! Waypoints in neighboring columns are linked if a player could walk from one
+//! to the other: the path must be clear at step height, there must be ground
+//! underneath it, and it may only climb as high as a single stair step. Links
+//! that drop off a ledge are one-way.
+
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+};
+
+use crate::common::bsp::{BspCollisionHull, BspError, BspLeafContents};
+
+use cgmath::{InnerSpace as _, Vector3};
+
+pub use crate::server::world::phys::STEP_HEIGHT;
+
+/// The furthest bots will drop off a ledge.
+pub const MAX_DROP: f32 = 256.0;
+
+/// The default distance between grid columns.
+pub const DEFAULT_WAYPOINT_SPACING: f32 = 64.0;
+
+// vertical distance between samples when scanning a column for floors. this is
+// smaller than the player hull is tall, so no floor can be skipped.
+const SCAN_STEP: f32 = 16.0;
+
+// horizontal distance between samples when checking a link
+const LINK_STEP: f32 = 8.0;
+
+// waypoints are placed slightly above the floor so they aren't inside it
+const FLOOR_OFFSET: f32 = 1.0;
+
+#[derive(Clone, Debug)]
+pub struct Waypoint {
+    origin: Vector3<f32>,
+    links: Vec<usize>,
+}
+
+impl Waypoint {
+    /// Returns the player origin at this waypoint.
+    pub fn origin(&self) -> Vector3<f32> {
+        self.origin
+    }
+
+    /// Returns the IDs of the waypoints reachable directly from this one.
+    pub fn links(&self) -> &[usize] {
+        &self.links
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct WaypointGraph {
+    waypoints: Vec<Waypoint>,
+}
+
+impl WaypointGraph {
+    /// Generates a waypoint graph from a player collision hull.
+    ///
+    /// Grid columns are placed every `spacing` units between `mins` and `maxs`
+    /// (usually the bounds of the world model).
+    pub fn generate(
+        hull: &BspCollisionHull,
+        mins: Vector3<f32>,
+        maxs: Vector3<f32>,
+        spacing: f32,
+    ) -> Result<WaypointGraph, BspError> {
+        if spacing <= 0.0 {
+            return Err(BspError::Other(
+                "waypoint spacing must be positive".to_owned(),
+            ));
+        }
+
+        let cols_x = ((maxs.x - mins.x) / spacing).floor() as i32 + 1;
+        let cols_y = ((maxs.y - mins.y) / spacing).floor() as i32 + 1;
+
+        let mut waypoints = Vec::new();
+        let mut columns: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+
+        for i in 0..cols_x {
+            for j in 0..cols_y {
+                let x = mins.x + i as f32 * spacing;
+                let y = mins.y + j as f32 * spacing;
+
+                for z in floors(hull, x, y, mins.z, maxs.z)? {
+                    columns.entry((i, j)).or_default().push(waypoints.len());
+                    waypoints.push(Waypoint {
+                        origin: Vector3::new(x, y, z),
+                        links: Vec::new(),
+                    });
+                }
+            }
+        }
+
+        for (&(i, j), ids) in columns.iter() {
+            for &(di, dj) in &[
+                (-1, -1),
+                (-1, 0),
+                (-1, 1),
+                (0, -1),
+                (0, 1),
+                (1, -1),
+                (1, 0),
+                (1, 1),
+            ] {
+                let neighbors = match columns.get(&(i + di, j + dj)) {
+                    Some(n) => n,
+                    None => continue,
+                };
+
+                for &a in ids {
+                    for &b in neighbors {
+                        if can_move(hull, waypoints[a].origin, waypoints[b].origin)? {
+                            waypoints[a].links.push(b);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(WaypointGraph { waypoints })
+    }
+
+    pub fn len(&self) -> usize {
+        self.waypoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waypoints.is_empty()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Waypoint> {
+        self.waypoints.get(id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Waypoint> {
+        self.waypoints.iter()
+    }
+
+    /// Returns the ID of the waypoint closest to `point`.
+    pub fn nearest(&self, point: Vector3<f32>) -> Option<usize> {
+        self.waypoints
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                (a.origin - point)
+                    .magnitude2()
+                    .total_cmp(&(b.origin - point).magnitude2())
+            })
+            .map(|(id, _)| id)
+    }
+
+    /// Finds the shortest path between two waypoints.
+    ///
+    /// The returned path includes both `from` and `to`.
+    pub fn path(&self, from: usize, to: usize) -> Option<Vec<usize>> {
+        let goal = self.waypoints.get(to)?.origin;
+        self.waypoints.get(from)?;
+
+        let mut cost = vec![f32::INFINITY; self.waypoints.len()];
+        let mut prev = vec![None; self.waypoints.len()];
+        let mut open = BinaryHeap::new();
+
+        cost[from] = 0.0;
+        open.push(OpenWaypoint {
+            id: from,
+            estimate: (self.waypoints[from].origin - goal).magnitude(),
+        });
+
+        while let Some(OpenWaypoint { id, .. }) = open.pop() {
+            if id == to {
+                let mut path = vec![to];
+                let mut current = to;
+                while let Some(p) = prev[current] {
+                    path.push(p);
+                    current = p;
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            let origin = self.waypoints[id].origin;
+            for &next in self.waypoints[id].links.iter() {
+                let next_origin = self.waypoints[next].origin;
+                let next_cost = cost[id] + (next_origin - origin).magnitude();
+                if next_cost < cost[next] {
+                    cost[next] = next_cost;
+                    prev[next] = Some(id);
+                    open.push(OpenWaypoint {
+                        id: next,
+                        estimate: next_cost + (next_origin - goal).magnitude(),
+                    });
+                }
+            }
+        }
+
+        None
+    }
+}
+
+// a waypoint in the A* open set, ordered so that BinaryHeap pops the lowest
+// estimate first
+struct OpenWaypoint {
+    id: usize,
+    estimate: f32,
+}
+
+impl PartialEq for OpenWaypoint {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for OpenWaypoint {}
+
+impl PartialOrd for OpenWaypoint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OpenWaypoint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+/// Returns `true` if the line between two points doesn't pass through solid
+/// space in `hull`.
+pub fn line_clear(
+    hull: &BspCollisionHull,
+    start: Vector3<f32>,
+    end: Vector3<f32>,
+) -> Result<bool, BspError> {
+    let steps = ((end - start).magnitude() / LINK_STEP).ceil().max(1.0) as usize;
+    for s in 0..=steps {
+        let p = start + (end - start) * (s as f32 / steps as f32);
+        if hull.contents_at_point(p)? == BspLeafContents::Solid {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+// find the heights of all the floors in a column, top to bottom
+fn floors(
+    hull: &BspCollisionHull,
+    x: f32,
+    y: f32,
+    min_z: f32,
+    max_z: f32,
+) -> Result<Vec<f32>, BspError> {
+    let contents = |z| hull.contents_at_point(Vector3::new(x, y, z));
+
+    let mut floors = Vec::new();
+    let mut z = max_z;
+    let mut above = contents(z)?;
+
+    while z > min_z {
+        let below_z = (z - SCAN_STEP).max(min_z);
+        let below = contents(below_z)?;
+
+        if above != BspLeafContents::Solid && below == BspLeafContents::Solid {
+            // narrow down the surface between the two samples
+            let (mut hi, mut lo) = (z, below_z);
+            for _ in 0..8 {
+                let mid = (hi + lo) / 2.0;
+                if contents(mid)? == BspLeafContents::Solid {
+                    lo = mid;
+                } else {
+                    hi = mid;
+                }
+            }
+
+            // only stand on dry land
+            let floor = hi + FLOOR_OFFSET;
+            if contents(floor)? == BspLeafContents::Empty {
+                floors.push(floor);
+            }
+        }
+
+        above = below;
+        z = below_z;
+    }
+
+    Ok(floors)
+}
+
+// check whether a player could walk (or drop) from one waypoint to another
+fn can_move(
+    hull: &BspCollisionHull,
+    from: Vector3<f32>,
+    to: Vector3<f32>,
+) -> Result<bool, BspError> {
+    let rise = to.z - from.z;
+    if rise > STEP_HEIGHT || -rise > MAX_DROP {
+        return Ok(false);
+    }
+
+    // move across at step height, so small obstacles can be stepped over
+    let top = from.z.max(to.z) + STEP_HEIGHT;
+    if !line_clear(hull, from, Vector3::new(from.x, from.y, top))?
+        || !line_clear(
+            hull,
+            Vector3::new(from.x, from.y, top),
+            Vector3::new(to.x, to.y, top),
+        )?
+        || !line_clear(hull, Vector3::new(to.x, to.y, top), to)?
+    {
+        return Ok(false);
+    }
+
+    // dropping off a ledge doesn't need ground in between
+    if rise < -STEP_HEIGHT {
+        return Ok(true);
+    }
+
+    let delta = to - from;
+    let steps = (delta.magnitude() / LINK_STEP).ceil().max(1.0) as usize;
+    for s in 0..=steps {
+        let p = from + delta * (s as f32 / steps as f32);
+        if !ground_below(hull, Vector3::new(p.x, p.y, top), p.z - STEP_HEIGHT)? {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+// scan down from `start` to `min_z`, returning true if solid ground is found
+// before any liquid
+fn ground_below(
+    hull: &BspCollisionHull,
+    start: Vector3<f32>,
+    min_z: f32,
+) -> Result<bool, BspError> {
+    let mut z = start.z;
+    while z >= min_z {
+        match hull.contents_at_point(Vector3::new(start.x, start.y, z))? {
+            BspLeafContents::Solid => return Ok(true),
+            BspLeafContents::Empty => (),
+            _ => return Ok(false),
+        }
+        z -= SCAN_STEP / 4.0;
+    }
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn platform() -> BspCollisionHull {
+        BspCollisionHull::for_bounds(
+            Vector3::new(-256.0, -256.0, -64.0),
+            Vector3::new(256.0, 256.0, 0.0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_generate_platform() {
+        let graph = WaypointGraph::generate(
+            &platform(),
+            Vector3::new(-224.0, -224.0, -128.0),
+            Vector3::new(224.0, 224.0, 128.0),
+            64.0,
+        )
+        .unwrap();
+
+        // one waypoint per column, on top of the platform
+        assert_eq!(graph.len(), 64);
+        for wp in graph.iter() {
+            assert!(wp.origin().z > 0.0 && wp.origin().z < 2.0, "{:?}", wp);
+        }
+
+        let corner = graph.nearest(Vector3::new(-224.0, -224.0, 0.0)).unwrap();
+        let center = graph.nearest(Vector3::new(-32.0, -32.0, 0.0)).unwrap();
+        assert_eq!(graph.get(corner).unwrap().links().len(), 3);
+        assert_eq!(graph.get(center).unwrap().links().len(), 8);
+    }
+
+    #[test]
+    fn test_generate_skips_open_space() {
+        // columns beyond the platform edge have no floor
+        let graph = WaypointGraph::generate(
+            &platform(),
+            Vector3::new(192.0, 0.0, -128.0),
+            Vector3::new(448.0, 0.0, 128.0),
+            64.0,
+        )
+        .unwrap();
+
+        assert_eq!(graph.len(), 1);
+        assert!(graph.get(0).unwrap().links().is_empty());
+    }
+
+    #[test]
+    fn test_path_diagonal() {
+        let graph = WaypointGraph::generate(
+            &platform(),
+            Vector3::new(-224.0, -224.0, -128.0),
+            Vector3::new(224.0, 224.0, 128.0),
+            64.0,
+        )
+        .unwrap();
+
+        let from = graph.nearest(Vector3::new(-224.0, -224.0, 0.0)).unwrap();
+        let to = graph.nearest(Vector3::new(224.0, 224.0, 0.0)).unwrap();
+        let path = graph.path(from, to).unwrap();
+
+        // straight along the diagonal
+        assert_eq!(path.len(), 8);
+        assert_eq!(path[0], from);
+        assert_eq!(path[7], to);
+        for pair in path.windows(2) {
+            let a = graph.get(pair[0]).unwrap().origin();
+            let b = graph.get(pair[1]).unwrap().origin();
+            assert!(((b - a).magnitude() - 64.0 * 2.0f32.sqrt()).abs() < 0.01);
+        }
+    }
+}
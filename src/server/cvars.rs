@@ -0,0 +1,44 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use crate::common::console::{ConsoleError, CvarRegistry};
+
+pub fn register_cvars(cvars: &CvarRegistry) -> Result<(), ConsoleError> {
+    cvars.register_archive("hostname", "UNNAMED")?;
+    cvars.register("sv_public", "0")?;
+    cvars.register_notify("teamplay", "0")?;
+
+    // player movement
+    cvars.register("sv_maxvelocity", "2000")?;
+    cvars.register("sv_maxspeed", "320")?;
+    cvars.register("sv_friction", "4")?;
+    cvars.register("sv_stopspeed", "100")?;
+    cvars.register("sv_accelerate", "10")?;
+
+    // the client predicts with the server's gravity, so it registers this too
+    let _ = cvars.register_notify("sv_gravity", "800");
+
+    // the client uses the same master server to find games, and will already
+    // have registered it if the server is running in the same process
+    let _ = cvars.register_archive("net_master", "dpmaster.deathmask.net");
+
+    // an empty password disables remote commands. the client registers this
+    // too, to authenticate its own rcon commands
+    let _ = cvars.register("rcon_password", "");
+
+    Ok(())
+}
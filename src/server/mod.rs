@@ -15,6 +15,9 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+pub mod bot;
+pub mod cvars;
+pub mod net;
 pub mod precache;
 pub mod progs;
 pub mod world;
@@ -27,14 +30,20 @@ use std::{
 
 use crate::{
     common::{
-        console::CvarRegistry,
+        bsp::BspCollisionHull,
+        console::{CmdRegistry, CvarRegistry},
         engine::{duration_from_f32, duration_to_f32},
         math::Hyperplane,
-        model::Model,
+        model::{Model, ModelKind},
+        net::{ButtonFlags, ClientCmd, PlayerColor},
         parse,
         vfs::Vfs,
     },
     server::{
+        bot::{
+            waypoint::{WaypointGraph, DEFAULT_WAYPOINT_SPACING},
+            Bot, BotView,
+        },
         progs::{functions::FunctionKind, GlobalAddrFunction},
         world::{FieldAddrEntityId, FieldAddrVector, MoveKind},
     },
@@ -57,12 +66,53 @@ use self::{
 };
 
 use arrayvec::ArrayVec;
-use cgmath::{InnerSpace, Vector3, Zero};
+use cgmath::{Angle as _, InnerSpace, Vector3, Zero};
 use chrono::Duration;
 
 const MAX_DATAGRAM: usize = 1024;
 const MAX_LIGHTSTYLES: usize = 64;
 
+// the number of move round trips averaged into a client's ping
+const NUM_PING_TIMES: usize = 16;
+
+// the most speed a player can gain from steering in the air
+const AIR_WISH_SPEED: f32 = 30.0;
+
+// bottom colors of the red and blue teams
+const BOT_TEAM_RED: u8 = 4;
+const BOT_TEAM_BLUE: u8 = 13;
+
+/// Registers the server's console commands.
+///
+/// The commands act on whichever session is in `session` when they run.
+pub fn register_commands(cmds: &mut CmdRegistry, session: Rc<RefCell<Option<Session>>>) {
+    cmds.insert_or_replace("addbot", bot::cmd_addbot(session.clone()))
+        .unwrap();
+    cmds.insert_or_replace("removebot", bot::cmd_removebot(session.clone()))
+        .unwrap();
+    cmds.insert_or_replace("ping", cmd_ping(session)).unwrap();
+}
+
+// Lists the ping of every active client.
+//
+// See `Host_Ping_f` in `WinQuake/host_cmd.c`.
+fn cmd_ping(session: Rc<RefCell<Option<Session>>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |_| match *session.borrow() {
+        Some(ref session) => {
+            let mut out = String::from("Client ping times:");
+            let slots = &session.persist.client_slots;
+            for slot in 0..slots.limit() {
+                if let Some(ClientState::Active(c)) = slots.get(slot) {
+                    out.push_str(&format!("\n{:4} {}", c.ping().num_milliseconds(), c.name()));
+                }
+            }
+
+            out
+        }
+        None => "No server running".to_owned(),
+    })
+}
+
 /// The state of a client's connection to the server.
 pub enum ClientState {
     /// The client is still connecting.
@@ -72,12 +122,89 @@ pub enum ClientState {
     Active(ClientActive),
 }
 
+impl ClientState {
+    /// Returns the client's name, if it's active.
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            ClientState::Connecting => None,
+            ClientState::Active(active) => Some(active.name()),
+        }
+    }
+}
+
 pub struct ClientActive {
     /// If true, client may execute any command.
     privileged: bool,
 
     /// ID of the entity controlled by this client.
     entity_id: EntityId,
+
+    /// The player's name.
+    name: String,
+
+    /// The player's shirt and pants colors.
+    color: PlayerColor,
+
+    /// The most recent move command from this client.
+    last_move: Option<ClientCmd>,
+
+    /// Round-trip times of the most recent moves, used as a ring buffer.
+    ping_times: [Duration; NUM_PING_TIMES],
+
+    /// The number of round-trip times recorded so far.
+    num_pings: usize,
+
+    /// If `Some`, this client is a bot controlled by the server.
+    bot: Option<Box<Bot>>,
+}
+
+impl ClientActive {
+    pub fn entity_id(&self) -> EntityId {
+        self.entity_id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn color(&self) -> PlayerColor {
+        self.color
+    }
+
+    pub fn last_move(&self) -> Option<&ClientCmd> {
+        self.last_move.as_ref()
+    }
+
+    pub fn is_bot(&self) -> bool {
+        self.bot.is_some()
+    }
+
+    /// Returns the client's average round-trip time over its recent moves.
+    pub fn ping(&self) -> Duration {
+        let count = self.num_pings.min(NUM_PING_TIMES);
+        if count == 0 {
+            return Duration::zero();
+        }
+
+        self.ping_times[..count]
+            .iter()
+            .fold(Duration::zero(), |sum, &t| sum + t)
+            / count as i32
+    }
+
+    // Stores a move command received at level time `time`.
+    //
+    // The client stamps each move with the server time of the last message it
+    // received, so the difference is the round-trip time. See
+    // `SV_ReadClientMove` in `WinQuake/sv_user.c`.
+    fn receive_move(&mut self, cmd: ClientCmd, time: Duration) {
+        if let ClientCmd::Move { send_time, .. } = cmd {
+            self.ping_times[self.num_pings % NUM_PING_TIMES] = time - send_time;
+            self.num_pings += 1;
+        }
+
+        self.last_move = Some(cmd);
+    }
 }
 
 bitflags! {
@@ -115,11 +242,47 @@ impl ClientSlots {
         self.slots.get(id)?.as_ref()
     }
 
+    /// Returns a mutable reference to the client in a slot.
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut ClientState> {
+        self.slots.get_mut(id)?.as_mut()
+    }
+
     /// Returns the maximum number of simultaneous clients.
     pub fn limit(&self) -> usize {
         self.slots.len()
     }
 
+    /// Returns the slot of the active client with the given name.
+    pub fn find_by_name(&self, name: &str) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|s| s.as_ref().and_then(|c| c.name()) == Some(name))
+    }
+
+    /// Puts a bot in the first available slot and returns the slot.
+    ///
+    /// Bots skip the connection process and are active immediately.
+    pub fn add_bot(&mut self, name: String, color: PlayerColor, bot: Bot) -> Option<usize> {
+        let slot = self.slots.iter().position(|s| s.is_none())?;
+        self.slots[slot] = Some(ClientState::Active(ClientActive {
+            privileged: false,
+            entity_id: EntityId(slot + 1),
+            name,
+            color,
+            last_move: None,
+            ping_times: [Duration::zero(); NUM_PING_TIMES],
+            num_pings: 0,
+            bot: Some(Box::new(bot)),
+        }));
+
+        Some(slot)
+    }
+
+    /// Empties a slot, returning the client that occupied it.
+    pub fn remove(&mut self, id: usize) -> Option<ClientState> {
+        self.slots.get_mut(id)?.take()
+    }
+
     /// Finds an available connection slot for a new client.
     pub fn find_available(&mut self) -> Option<&mut ClientState> {
         let slot = self.slots.iter_mut().find(|s| s.is_none())?;
@@ -165,15 +328,16 @@ pub struct SessionLoading {
 
 impl SessionLoading {
     pub fn new(
+        max_clients: usize,
         vfs: Rc<Vfs>,
         cvars: Rc<RefCell<CvarRegistry>>,
         progs: LoadProgs,
         models: Vec<Model>,
         entmap: String,
-    ) -> SessionLoading {
-        SessionLoading {
-            level: LevelState::new(vfs, cvars, progs, models, entmap),
-        }
+    ) -> Result<SessionLoading, ProgsError> {
+        Ok(SessionLoading {
+            level: LevelState::new(max_clients, vfs, cvars, progs, models, entmap)?,
+        })
     }
 
     /// Adds a name to the sound precache.
@@ -219,13 +383,18 @@ impl Session {
         progs: LoadProgs,
         models: Vec<Model>,
         entmap: String,
-    ) -> Session {
-        Session {
+    ) -> Result<Session, ProgsError> {
+        Ok(Session {
             persist: SessionPersistent::new(max_clients),
-            state: SessionState::Loading(SessionLoading {
-                level: LevelState::new(vfs, cvars, progs, models, entmap),
-            }),
-        }
+            state: SessionState::Loading(SessionLoading::new(
+                max_clients,
+                vfs,
+                cvars,
+                progs,
+                models,
+                entmap,
+            )?),
+        })
     }
 
     /// Returns the maximum number of clients allowed on the server.
@@ -293,6 +462,213 @@ impl Session {
             SessionState::Active(ref active) => Some(active.level.time),
         }
     }
+
+    /// Adds a bot with the given skill and returns its slot.
+    ///
+    /// If `name` is `None`, the bot is named from [`bot::BOT_NAMES`]. Bots are
+    /// put on whichever of the red and blue teams has fewer players.
+    pub fn add_bot(&mut self, name: Option<&str>, skill: f32) -> Result<usize, ProgsError> {
+        let slots = &self.persist.client_slots;
+
+        let name = match name {
+            Some(n) => n.to_owned(),
+            None => bot::BOT_NAMES
+                .iter()
+                .find(|n| slots.find_by_name(n).is_none())
+                .map(|n| (*n).to_owned())
+                .unwrap_or_else(|| format!("bot{}", slots.limit())),
+        };
+
+        if slots.find_by_name(&name).is_some() {
+            return Err(ProgsError::with_msg(format!("Name in use: {}", name)));
+        }
+
+        let team_size = |team: u8| {
+            (0..slots.limit())
+                .filter_map(|i| match slots.get(i) {
+                    Some(ClientState::Active(c)) => Some(c.color.bits() & 0x0F),
+                    _ => None,
+                })
+                .filter(|&c| c == team)
+                .count()
+        };
+        let team = if team_size(BOT_TEAM_RED) <= team_size(BOT_TEAM_BLUE) {
+            BOT_TEAM_RED
+        } else {
+            BOT_TEAM_BLUE
+        };
+        let color = PlayerColor::new(team, team);
+
+        let slot = self
+            .persist
+            .client_slots
+            .add_bot(name.clone(), color, Bot::new(skill))
+            .ok_or_else(|| ProgsError::with_msg("Server is full"))?;
+
+        let level = self.level_mut();
+        let ent_id = EntityId(slot + 1);
+        let name_id = level.string_table.borrow_mut().find_or_insert(&name);
+        level.world.clear_entity(ent_id)?;
+        let ent = level.world.entity_mut(ent_id)?;
+        ent.put_string_id(name_id, FieldAddrStringId::NetName as i16)?;
+        ent.store(FieldAddrFloat::Colormap, (slot + 1) as f32)?;
+        ent.store(FieldAddrFloat::Team, (team + 1) as f32)?;
+
+        // bots skip the signon, so they're connected and spawned at once
+        level.execute_client_function(GlobalAddrFunction::SetNewArgs, ent_id)?;
+        level.execute_client_function(GlobalAddrFunction::ClientConnect, ent_id)?;
+        level.execute_client_function(GlobalAddrFunction::PutClientInServer, ent_id)?;
+
+        Ok(slot)
+    }
+
+    /// Removes the bot with the given name.
+    ///
+    /// Returns `false` if there is no bot with that name.
+    pub fn remove_bot(&mut self, name: &str) -> Result<bool, ProgsError> {
+        let slots = &self.persist.client_slots;
+        match slots.find_by_name(name) {
+            Some(slot) => match slots.get(slot) {
+                Some(ClientState::Active(c)) if c.is_bot() => {
+                    self.drop_client(slot)?;
+                    Ok(true)
+                }
+                _ => Ok(false),
+            },
+            None => Ok(false),
+        }
+    }
+
+    /// Removes all bots and returns how many there were.
+    pub fn remove_all_bots(&mut self) -> Result<usize, ProgsError> {
+        let mut count = 0;
+        for slot in 0..self.persist.client_slots.limit() {
+            if let Some(ClientState::Active(c)) = self.persist.client_slots.get(slot) {
+                if c.is_bot() {
+                    self.drop_client(slot)?;
+                    count += 1;
+                }
+            }
+        }
+
+        Ok(count)
+    }
+
+    // Run the QuakeC disconnect handler for the client in `slot`, then empty
+    // the slot and clear its player entity.
+    fn drop_client(&mut self, slot: usize) -> Result<(), ProgsError> {
+        let ent_id = EntityId(slot + 1);
+        let level = self.level_mut();
+        level.execute_client_function(GlobalAddrFunction::ClientDisconnect, ent_id)?;
+        level.world.clear_entity(ent_id)?;
+        self.persist.client_slots.remove(slot);
+
+        Ok(())
+    }
+
+    /// Stores a move command received from the client in `slot`.
+    ///
+    /// The move is simulated in the next frame, and its `send_time` is used to
+    /// measure the client's ping.
+    pub fn receive_move(&mut self, slot: usize, cmd: ClientCmd) -> Result<(), ProgsError> {
+        let time = self
+            .time()
+            .ok_or_else(|| ProgsError::with_msg("Received move while loading"))?;
+
+        match self.persist.client_slots.get_mut(slot) {
+            Some(ClientState::Active(c)) => {
+                c.receive_move(cmd, time);
+                Ok(())
+            }
+            _ => Err(ProgsError::with_msg(format!(
+                "No active client in slot {}",
+                slot
+            ))),
+        }
+    }
+
+    /// Lets every bot decide on its move for this frame.
+    ///
+    /// Each move is stored as the bot's last move, the same as a move received
+    /// from a remote client.
+    pub fn run_bots(&mut self, frame_time: Duration) -> Result<(), ProgsError> {
+        let level = match self.state {
+            SessionState::Loading(_) => return Ok(()),
+            SessionState::Active(ref mut active) => &mut active.level,
+        };
+        let clients = &mut self.persist.client_slots;
+
+        let waypoints = level.waypoints()?;
+        let sight_hull = level.world_hull(0)?;
+        let teamplay = level.cvars.borrow().get_value("teamplay").unwrap_or(0.0) != 0.0;
+
+        // origins and teams of all players in the game
+        let players: Vec<Option<(Vector3<f32>, u8)>> = (0..clients.limit())
+            .map(|slot| match clients.get(slot) {
+                Some(ClientState::Active(c)) => {
+                    let ent = level.world.try_entity(c.entity_id).ok()?;
+                    Some((ent.origin().ok()?, c.color.bits() & 0x0F))
+                }
+                _ => None,
+            })
+            .collect();
+
+        for slot in 0..clients.limit() {
+            let (origin, team) = match players[slot] {
+                Some(p) => p,
+                None => continue,
+            };
+
+            let client = match clients.get_mut(slot) {
+                Some(ClientState::Active(c)) if c.is_bot() => c,
+                _ => continue,
+            };
+
+            let enemy = players
+                .iter()
+                .enumerate()
+                .filter(|&(other, _)| other != slot)
+                .filter_map(|(_, p)| *p)
+                .filter(|&(_, other_team)| !teamplay || other_team != team)
+                .map(|(o, _)| o)
+                .filter(|&o| bot::can_see(&sight_hull, origin, o).unwrap_or(false))
+                .min_by(|a, b| {
+                    (a - origin)
+                        .magnitude2()
+                        .total_cmp(&(b - origin).magnitude2())
+                });
+
+            let view = BotView {
+                time: level.time,
+                origin,
+                enemy,
+            };
+
+            let cmd = client
+                .bot
+                .as_mut()
+                .unwrap()
+                .think(&view, &waypoints, frame_time);
+            client.receive_move(cmd, level.time);
+        }
+
+        Ok(())
+    }
+
+    /// Runs a server frame.
+    ///
+    /// Bots choose their moves first, so that they're simulated alongside the
+    /// moves received from remote clients.
+    pub fn frame(&mut self, frame_time: Duration) -> Result<(), ProgsError> {
+        self.run_bots(frame_time)?;
+
+        match self.state {
+            SessionState::Loading(_) => Ok(()),
+            SessionState::Active(ref mut active) => {
+                active.level.physics(&self.persist.client_slots, frame_time)
+            }
+        }
+    }
 }
 
 /// Server-side level state.
@@ -323,16 +699,20 @@ pub struct LevelState {
     world: World,
 
     datagram: ArrayVec<u8, MAX_DATAGRAM>,
+
+    /// Bot navigation graph, generated when the first bot needs it.
+    waypoints: Option<Rc<WaypointGraph>>,
 }
 
 impl LevelState {
     pub fn new(
+        max_clients: usize,
         vfs: Rc<Vfs>,
         cvars: Rc<RefCell<CvarRegistry>>,
         progs: LoadProgs,
         models: Vec<Model>,
         entmap: String,
-    ) -> LevelState {
+    ) -> Result<LevelState, ProgsError> {
         let LoadProgs {
             cx,
             globals,
@@ -351,8 +731,9 @@ impl LevelState {
             model_precache.precache(string_table.borrow().get(model_name).unwrap());
         }
 
-        let world = World::create(models, entity_def.clone(), string_table.clone()).unwrap();
-        let entity_list = parse::entities(&entmap).unwrap();
+        let world = World::create(models, entity_def.clone(), string_table.clone())?;
+        let entity_list = parse::entities(&entmap)
+            .map_err(|e| ProgsError::with_msg(format!("Invalid entity map: {}", e)))?;
 
         let mut level = LevelState {
             vfs,
@@ -368,13 +749,19 @@ impl LevelState {
             world,
 
             datagram: ArrayVec::new(),
+            waypoints: None,
         };
 
+        // player entities directly follow the world entity, one per client slot
+        for _ in 0..max_clients {
+            level.world.alloc_uninitialized()?;
+        }
+
         for entity in entity_list {
-            level.spawn_entity_from_map(entity).unwrap();
+            level.spawn_entity_from_map(entity)?;
         }
 
-        level
+        Ok(level)
     }
 
     #[inline]
@@ -414,6 +801,36 @@ impl LevelState {
         self.lightstyles[index] = val;
     }
 
+    /// Returns a collision hull of the world model.
+    pub fn world_hull(&self, index: usize) -> Result<BspCollisionHull, ProgsError> {
+        match self.world.world_model().kind() {
+            ModelKind::Brush(ref bmodel) => bmodel
+                .hull(index)
+                .map_err(|e| ProgsError::with_msg(format!("{}", e))),
+            _ => Err(ProgsError::with_msg("World model is not a brush model")),
+        }
+    }
+
+    /// Returns the bot navigation graph for this level, generating it if
+    /// necessary.
+    pub fn waypoints(&mut self) -> Result<Rc<WaypointGraph>, ProgsError> {
+        if let Some(ref waypoints) = self.waypoints {
+            return Ok(waypoints.clone());
+        }
+
+        // hull 1 is the player-sized hull
+        let hull = self.world_hull(1)?;
+        let model = self.world.world_model();
+        let waypoints = Rc::new(
+            WaypointGraph::generate(&hull, model.min(), model.max(), DEFAULT_WAYPOINT_SPACING)
+                .map_err(|e| ProgsError::with_msg(format!("{}", e)))?,
+        );
+        debug!("Generated {} waypoints", waypoints.len());
+
+        self.waypoints = Some(waypoints.clone());
+        Ok(waypoints)
+    }
+
     /// Execute a QuakeC function in the VM.
     pub fn execute_program(&mut self, f: FunctionId) -> Result<(), ProgsError> {
         let mut runaway = 100000;
@@ -636,6 +1053,22 @@ impl LevelState {
         Ok(())
     }
 
+    /// Executes one of the QuakeC client callbacks with `self` set to the
+    /// client's player entity.
+    pub fn execute_client_function(
+        &mut self,
+        func: GlobalAddrFunction,
+        ent_id: EntityId,
+    ) -> Result<(), ProgsError> {
+        self.globals
+            .store(GlobalAddrFloat::Time, duration_to_f32(self.time))?;
+        self.globals.store(GlobalAddrEntity::Self_, ent_id)?;
+        self.globals.store(GlobalAddrEntity::Other, EntityId(0))?;
+        let func_id = self.globals.function_id(func as i16)?;
+        self.execute_program(func_id)?;
+        Ok(())
+    }
+
     /// Link an entity into the `World`.
     ///
     /// If `touch_triggers` is `true`, this will invoke the touch function of
@@ -787,8 +1220,8 @@ impl LevelState {
             }
 
             let max_clients = clients.limit();
-            if ent_id.0 != 0 && ent_id.0 < max_clients {
-                self.physics_player(clients, ent_id)?;
+            if ent_id.0 != 0 && ent_id.0 <= max_clients {
+                self.physics_player(clients, ent_id, frame_time)?;
             } else {
                 match self.world.entity(ent_id).move_kind()? {
                     MoveKind::Walk => {
@@ -812,28 +1245,178 @@ impl LevelState {
             }
         }
 
-        // TODO: increase sv.time by host_frametime
-        unimplemented!();
+        self.time = self.time + frame_time;
+
+        Ok(())
     }
 
-    // TODO: rename arguments when implementing
+    /// Runs physics for a player entity.
+    ///
+    /// The client's last move is applied first. Bots store their moves in the
+    /// same place, so they share this path with remote clients.
     pub fn physics_player(
         &mut self,
         clients: &ClientSlots,
         ent_id: EntityId,
+        frame_time: Duration,
     ) -> Result<(), ProgsError> {
         let client_id = ent_id.0.checked_sub(1).ok_or_else(|| {
             ProgsError::with_msg(format!("Invalid client entity ID: {:?}", ent_id))
         })?;
 
-        if clients.get(client_id).is_none() {
-            // No client in this slot.
-            return Ok(());
+        let client = match clients.get(client_id) {
+            Some(ClientState::Active(c)) => c,
+
+            // No client in this slot, or it hasn't spawned yet.
+            _ => return Ok(()),
+        };
+
+        let sv_maxvelocity = self.cvars.borrow().get_value("sv_maxvelocity").unwrap();
+        let ent = self.world.entity_mut(ent_id)?;
+        ent.limit_velocity(sv_maxvelocity)?;
+
+        if let Some(cmd) = client.last_move() {
+            self.client_think(ent_id, cmd, frame_time)?;
+        }
+
+        self.execute_client_function(GlobalAddrFunction::PlayerPreThink, ent_id)?;
+
+        match self.world.entity(ent_id).move_kind()? {
+            MoveKind::None => self.think(ent_id, frame_time)?,
+
+            MoveKind::Walk => {
+                self.think(ent_id, frame_time)?;
+
+                let sv_gravity = self.cvars.borrow().get_value("sv_gravity").unwrap();
+                self.world
+                    .entity_mut(ent_id)?
+                    .apply_gravity(sv_gravity, frame_time)?;
+
+                self.move_walk(frame_time, ent_id)?;
+            }
+
+            MoveKind::NoClip => self.physics_noclip(ent_id, frame_time)?,
+
+            k => {
+                return Err(ProgsError::with_msg(format!(
+                    "Unsupported player move kind: {:?}",
+                    k
+                )))
+            }
         }
 
+        self.link_entity(ent_id, true)?;
+        self.execute_client_function(GlobalAddrFunction::PlayerPostThink, ent_id)?;
+
+        Ok(())
+    }
+
+    /// Applies a client's move command to its player entity.
+    ///
+    /// Buttons and view angles are copied into the entity for QuakeC to use,
+    /// then the player accelerates toward the requested movement.
+    pub fn client_think(
+        &mut self,
+        ent_id: EntityId,
+        cmd: &ClientCmd,
+        frame_time: Duration,
+    ) -> Result<(), ProgsError> {
+        let (angles, fwd_move, side_move, up_move, button_flags, impulse) = match *cmd {
+            ClientCmd::Move {
+                angles,
+                fwd_move,
+                side_move,
+                up_move,
+                button_flags,
+                impulse,
+                ..
+            } => (angles, fwd_move, side_move, up_move, button_flags, impulse),
+            _ => return Ok(()),
+        };
+
+        let cvars = self.cvars.borrow();
+        let sv_maxspeed = cvars.get_value("sv_maxspeed").unwrap();
+        let sv_friction = cvars.get_value("sv_friction").unwrap();
+        let sv_stopspeed = cvars.get_value("sv_stopspeed").unwrap();
+        let sv_accelerate = cvars.get_value("sv_accelerate").unwrap();
+        drop(cvars);
+
         let ent = self.world.entity_mut(ent_id)?;
-        ent.limit_velocity(self.cvars.borrow().get_value("sv_maxvelocity").unwrap())?;
-        unimplemented!();
+
+        let button = |flag| {
+            if button_flags.contains(flag) {
+                1.0
+            } else {
+                0.0
+            }
+        };
+        ent.store(FieldAddrFloat::Button0, button(ButtonFlags::ATTACK))?;
+        ent.store(FieldAddrFloat::Button2, button(ButtonFlags::JUMP))?;
+        if impulse != 0 {
+            ent.store(FieldAddrFloat::Impulse, impulse as f32)?;
+        }
+
+        // QuakeC sets fixangle to force the view angles, e.g. when teleporting
+        if ent.load(FieldAddrFloat::FixAngle)? == 0.0 {
+            ent.store(
+                FieldAddrVector::ViewAngle,
+                [angles.x.0, angles.y.0, angles.z.0],
+            )?;
+
+            // the player model only pitches a third as far as the view
+            ent.store(
+                FieldAddrVector::Angles,
+                [-angles.x.0 / 3.0, angles.y.0, 0.0],
+            )?;
+        }
+
+        let move_kind = ent.move_kind()?;
+        if move_kind == MoveKind::None || ent.load(FieldAddrFloat::Health)? <= 0.0 {
+            return Ok(());
+        }
+
+        let (sin_yaw, cos_yaw) = angles.y.sin_cos();
+        let forward = Vector3::new(cos_yaw, sin_yaw, 0.0);
+        let right = Vector3::new(sin_yaw, -cos_yaw, 0.0);
+        let mut wish_vel = fwd_move as f32 * forward + side_move as f32 * right;
+        if move_kind != MoveKind::Walk {
+            wish_vel.z = up_move as f32;
+        }
+
+        let mut wish_speed = wish_vel.magnitude();
+        let wish_dir = if wish_speed > 0.0 {
+            wish_vel / wish_speed
+        } else {
+            Vector3::zero()
+        };
+        wish_speed = wish_speed.min(sv_maxspeed);
+
+        let frame_time_f = duration_to_f32(frame_time);
+        let vel = ent.velocity()?;
+        let new_vel = if move_kind == MoveKind::NoClip {
+            wish_dir * wish_speed
+        } else if ent.flags()?.contains(EntityFlags::ON_GROUND) {
+            let vel = phys::velocity_after_friction(vel, sv_friction, sv_stopspeed, frame_time_f);
+            phys::velocity_after_acceleration(
+                vel,
+                wish_dir,
+                wish_speed,
+                sv_accelerate,
+                frame_time_f,
+            )
+        } else {
+            // very little control in the air
+            phys::velocity_after_acceleration(
+                vel,
+                wish_dir,
+                wish_speed.min(AIR_WISH_SPEED),
+                sv_accelerate,
+                frame_time_f,
+            )
+        };
+        ent.store(FieldAddrVector::Velocity, new_vel.into())?;
+
+        Ok(())
     }
 
     pub fn physics_push(
@@ -1114,6 +1697,112 @@ impl LevelState {
         Ok((flags, out_trace))
     }
 
+    /// Movement function for walking players.
+    ///
+    /// This is an ordinary ballistic move, except that a player who walks into
+    /// a wall while on the ground tries the move again from `STEP_HEIGHT`
+    /// higher and then drops back down. This lets players climb stairs. See
+    /// `SV_WalkMove` in `WinQuake/sv_phys.c`.
+    pub fn move_walk(&mut self, sim_time: Duration, ent_id: EntityId) -> Result<(), ProgsError> {
+        let was_on_ground = self
+            .world
+            .entity(ent_id)
+            .flags()?
+            .contains(EntityFlags::ON_GROUND);
+
+        // the move sets the flag again if the player lands on something
+        self.world
+            .entity_mut(ent_id)?
+            .remove_flags(EntityFlags::ON_GROUND)?;
+
+        let old_origin = self.world.entity(ent_id).origin()?;
+        let old_velocity = self.world.entity(ent_id).velocity()?;
+
+        let (flags, _) = self.move_ballistic(sim_time, ent_id)?;
+
+        // only steps block a move, and players can't climb them while jumping
+        if !flags.contains(CollisionFlags::VERTICAL) || !was_on_ground {
+            return Ok(());
+        }
+
+        // a touch function may have removed the player or changed how it moves
+        if !self.world.entity_exists(ent_id)
+            || self.world.entity(ent_id).move_kind()? != MoveKind::Walk
+        {
+            return Ok(());
+        }
+
+        let no_step_origin = self.world.entity(ent_id).origin()?;
+        let no_step_velocity = self.world.entity(ent_id).velocity()?;
+
+        // move up from the start, then forward
+        self.world
+            .entity_mut(ent_id)?
+            .store(FieldAddrVector::Origin, old_origin.into())?;
+        self.push_entity(ent_id, Vector3::new(0.0, 0.0, phys::STEP_HEIGHT))?;
+        self.world.entity_mut(ent_id)?.store(
+            FieldAddrVector::Velocity,
+            [old_velocity.x, old_velocity.y, 0.0],
+        )?;
+        self.move_ballistic(sim_time, ent_id)?;
+
+        // then back down onto the step
+        let down = -phys::STEP_HEIGHT + old_velocity.z * duration_to_f32(sim_time);
+        let (trace, ground) = self.push_entity(ent_id, Vector3::new(0.0, 0.0, down))?;
+
+        let on_floor = match trace.end().kind() {
+            TraceEndKind::Boundary(b) => b.plane.normal().z > 0.7,
+            TraceEndKind::Terminal => false,
+        };
+
+        if on_floor {
+            if let Some(ground) = ground {
+                if self.world.entity(ground).solid()? == EntitySolid::Bsp {
+                    let ent = self.world.entity_mut(ent_id)?;
+                    ent.add_flags(EntityFlags::ON_GROUND)?;
+                    ent.store(FieldAddrEntityId::Ground, ground)?;
+                }
+            }
+        } else {
+            // Stepping up didn't land on solid ground. This happens near
+            // slopes too steep to climb, so keep the move without the step.
+            let ent = self.world.entity_mut(ent_id)?;
+            ent.store(FieldAddrVector::Origin, no_step_origin.into())?;
+            ent.store(FieldAddrVector::Velocity, no_step_velocity.into())?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves an entity by `push`, stopping at the first thing it hits.
+    ///
+    /// Unlike the other movement functions, this ignores the entity's
+    /// velocity. Returns the trace of the move and the entity it hit, if any.
+    pub fn push_entity(
+        &mut self,
+        ent_id: EntityId,
+        push: Vector3<f32>,
+    ) -> Result<(Trace, Option<EntityId>), ProgsError> {
+        let orig = self.world.entity(ent_id).origin()?;
+        let min = self.world.entity(ent_id).min()?;
+        let max = self.world.entity(ent_id).max()?;
+
+        let (trace, hit_entity) =
+            self.world
+                .move_entity(ent_id, orig, min, max, orig + push, CollideKind::Normal)?;
+
+        self.world
+            .entity_mut(ent_id)?
+            .store(FieldAddrVector::Origin, trace.end_point().into())?;
+        self.link_entity(ent_id, true)?;
+
+        if let Some(hit_entity) = hit_entity {
+            self.impact_entities(ent_id, hit_entity)?;
+        }
+
+        Ok((trace, hit_entity))
+    }
+
     const DROP_TO_FLOOR_DIST: f32 = 256.0;
 
     /// Moves an entity straight down until it collides with a solid surface.
@@ -1606,3 +2295,292 @@ impl LevelState {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{common::bsp::BspModel, server::world::STATIC_ADDRESS_COUNT};
+
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use cgmath::Deg;
+    use std::io::Cursor;
+
+    const FRAME_TIME_MS: i64 = 50;
+
+    // Builds a progs.dat with a single function that returns immediately.
+    // Every QuakeC callback refers to it, so the engine runs without any game
+    // logic.
+    fn empty_progs() -> LoadProgs {
+        const HEADER_SIZE: i32 = 60;
+        const STATEMENT_SIZE: i32 = 8;
+        const FUNCTION_SIZE: i32 = 36;
+
+        // the callbacks are the last globals the engine uses
+        let global_count = GlobalAddrFunction::SetChangeArgs as usize + 1;
+
+        let statements = HEADER_SIZE;
+        let functions = statements + STATEMENT_SIZE;
+        let strings = functions + FUNCTION_SIZE;
+        let globals = strings + 1;
+
+        let mut data = Vec::new();
+        data.write_i32::<LittleEndian>(6).unwrap(); // version
+        data.write_i32::<LittleEndian>(5927).unwrap(); // CRC
+
+        // statements, global defs, field defs, functions, strings, globals
+        for &(offset, count) in &[
+            (statements, 1),
+            (globals, 0),
+            (globals, 0),
+            (functions, 1),
+            (strings, 1),
+            (globals, global_count as i32),
+        ] {
+            data.write_i32::<LittleEndian>(offset).unwrap();
+            data.write_i32::<LittleEndian>(count).unwrap();
+        }
+        data.write_i32::<LittleEndian>(STATIC_ADDRESS_COUNT as i32)
+            .unwrap();
+
+        // DONE
+        data.extend_from_slice(&[0; STATEMENT_SIZE as usize]);
+
+        // starts at statement 0, everything else (name, arguments) empty
+        data.extend_from_slice(&[0; FUNCTION_SIZE as usize]);
+
+        // the empty string
+        data.push(0);
+
+        data.resize(data.len() + global_count * 4, 0);
+
+        progs::load(Cursor::new(data)).unwrap()
+    }
+
+    // A level with a floor at height 0 and a step of `step_height` at x = 128.
+    fn test_level(max_clients: usize, step_height: f32) -> Result<LevelState, ProgsError> {
+        let cvars = Rc::new(RefCell::new(CvarRegistry::new(Rc::new(RefCell::new(
+            Vec::new(),
+        )))));
+        cvars::register_cvars(&cvars.borrow()).unwrap();
+
+        let world = BspModel::with_step(
+            128.0,
+            step_height,
+            Vector3::new(-256.0, -256.0, -64.0),
+            Vector3::new(256.0, 256.0, 128.0),
+        );
+
+        LevelState::new(
+            max_clients,
+            Rc::new(Vfs::new()),
+            cvars,
+            empty_progs(),
+            vec![Model::from_brush_model("maps/test.bsp", world)],
+            String::new(),
+        )
+    }
+
+    // Sets up a player entity the way PutClientInServer would.
+    fn spawn_player(level: &mut LevelState, ent_id: EntityId, origin: Vector3<f32>) {
+        let ent = level.world.entity_mut(ent_id).unwrap();
+        ent.store(FieldAddrFloat::MoveKind, MoveKind::Walk as u32 as f32)
+            .unwrap();
+        ent.store(FieldAddrFloat::Solid, EntitySolid::SlideBox as u32 as f32)
+            .unwrap();
+        ent.store(FieldAddrFloat::Health, 100.0).unwrap();
+        ent.store(FieldAddrVector::Mins, [-16.0, -16.0, -24.0])
+            .unwrap();
+        ent.store(FieldAddrVector::Maxs, [16.0, 16.0, 32.0])
+            .unwrap();
+        ent.store(FieldAddrVector::Size, [32.0, 32.0, 56.0])
+            .unwrap();
+        level.set_entity_origin(ent_id, origin).unwrap();
+    }
+
+    // Runs forward at full speed, facing `yaw`.
+    fn run(yaw: f32) -> ClientCmd {
+        ClientCmd::Move {
+            send_time: Duration::zero(),
+            angles: Vector3::new(Deg(0.0), Deg(yaw), Deg(0.0)),
+            fwd_move: 320,
+            side_move: 0,
+            up_move: 0,
+            button_flags: ButtonFlags::empty(),
+            impulse: 0,
+        }
+    }
+
+    fn assert_vec_eq(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 0.01, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_level_too_many_clients() {
+        // there's one entity per client, and there aren't enough of them
+        assert!(test_level(1000, 16.0).is_err());
+    }
+
+    #[test]
+    fn test_client_think() {
+        let mut level = test_level(1, 16.0).unwrap();
+        let player = EntityId(1);
+        spawn_player(&mut level, player, Vector3::new(0.0, 0.0, 24.0));
+        level
+            .world
+            .entity_mut(player)
+            .unwrap()
+            .add_flags(EntityFlags::ON_GROUND)
+            .unwrap();
+
+        // sv_accelerate 10 reaches sv_maxspeed in a tenth of a second
+        let frame_time = Duration::milliseconds(10);
+        level.client_think(player, &run(90.0), frame_time).unwrap();
+        let ent = level.world.entity(player);
+        assert_vec_eq(ent.velocity().unwrap(), Vector3::new(0.0, 32.0, 0.0));
+        let view_angles: Vector3<f32> = ent.load(FieldAddrVector::ViewAngle).unwrap().into();
+        assert_vec_eq(view_angles, Vector3::new(0.0, 90.0, 0.0));
+
+        // in the air, there's very little control
+        let ent = level.world.entity_mut(player).unwrap();
+        ent.remove_flags(EntityFlags::ON_GROUND).unwrap();
+        level.client_think(player, &run(0.0), frame_time).unwrap();
+        let ent = level.world.entity(player);
+        assert_vec_eq(ent.velocity().unwrap(), Vector3::new(3.0, 32.0, 0.0));
+    }
+
+    // Runs east for a second from x = 0, toward the step.
+    fn run_toward_step(step_height: f32) -> (Vector3<f32>, Vector3<f32>, EntityFlags) {
+        let mut level = test_level(1, step_height).unwrap();
+        let player = EntityId(1);
+        spawn_player(&mut level, player, Vector3::new(0.0, 0.0, 25.0));
+
+        let mut clients = ClientSlots::new(1);
+        clients.add_bot("player".to_owned(), PlayerColor::new(0, 0), Bot::new(0.0));
+        if let Some(ClientState::Active(c)) = clients.get_mut(0) {
+            c.last_move = Some(run(0.0));
+        }
+
+        for _ in 0..1000 / FRAME_TIME_MS {
+            level
+                .physics(&clients, Duration::milliseconds(FRAME_TIME_MS))
+                .unwrap();
+        }
+
+        let ent = level.world.entity(player);
+        (
+            ent.origin().unwrap(),
+            ent.velocity().unwrap(),
+            ent.flags().unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_physics_player_step_up() {
+        let (origin, velocity, flags) = run_toward_step(16.0);
+
+        // climbed the step and kept running
+        assert!(origin.x > 128.0, "{:?}", origin);
+        assert!((origin.z - 40.0).abs() < 0.1, "{:?}", origin);
+        assert_vec_eq(velocity, Vector3::new(320.0, 0.0, 0.0));
+        assert!(flags.contains(EntityFlags::ON_GROUND));
+    }
+
+    #[test]
+    fn test_physics_player_blocked_by_wall() {
+        // too high to step up, so the player stops against it
+        let (origin, _, _) = run_toward_step(32.0);
+
+        assert!((origin.x - 112.0).abs() < 0.1, "{:?}", origin);
+        assert!((origin.z - 24.0).abs() < 0.1, "{:?}", origin);
+    }
+
+    #[test]
+    fn test_session_bot_roams() {
+        let mut session = Session {
+            persist: SessionPersistent::new(2),
+            state: SessionState::Active(SessionActive {
+                level: test_level(2, 16.0).unwrap(),
+            }),
+        };
+
+        let slot = session.add_bot(None, 1.0).unwrap();
+        let bot = EntityId(slot + 1);
+        let start = Vector3::new(0.0, 0.0, 25.0);
+        spawn_player(session.level_mut(), bot, start);
+
+        let mut top_speed: f32 = 0.0;
+        for _ in 0..3000 / FRAME_TIME_MS {
+            session
+                .frame(Duration::milliseconds(FRAME_TIME_MS))
+                .unwrap();
+
+            let ent = session.level().world.entity(bot);
+            let origin = ent.origin().unwrap();
+            let velocity = ent.velocity().unwrap();
+
+            // never leaves the floor or the step
+            assert!(origin.z > 23.9 && origin.z < 40.1, "{:?}", origin);
+            if ent.flags().unwrap().contains(EntityFlags::ON_GROUND) {
+                let on_floor = (origin.z - 24.0).abs() < 0.1 || (origin.z - 40.0).abs() < 0.1;
+                assert!(on_floor, "{:?}", origin);
+                assert_eq!(velocity.z, 0.0);
+            }
+
+            top_speed = top_speed.max(velocity.truncate().magnitude());
+        }
+
+        // the bot got up to running speed and went somewhere
+
+        let origin = session.level().world.entity(bot).origin().unwrap();
+        assert!(
+            (origin - start).truncate().magnitude() > 32.0,
+            "{:?}",
+            origin
+        );
+        assert!(top_speed > 250.0, "{}", top_speed);
+    }
+
+    #[test]
+    fn test_session_ping() {
+        let mut session = Session {
+            persist: SessionPersistent::new(2),
+            state: SessionState::Active(SessionActive {
+                level: test_level(2, 16.0).unwrap(),
+            }),
+        };
+        let slot = session.add_bot(Some("alice"), 1.0).unwrap();
+
+        // round trips alternate between 80 and 120 ms
+        for i in 0..NUM_PING_TIMES as i64 + 3 {
+            let time = Duration::milliseconds(1000 + i * FRAME_TIME_MS);
+            session.level_mut().time = time;
+
+            let latency = Duration::milliseconds(80 + (i % 2) * 40);
+            let cmd = ClientCmd::Move {
+                send_time: time - latency,
+                angles: Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+                fwd_move: 0,
+                side_move: 0,
+                up_move: 0,
+                button_flags: ButtonFlags::empty(),
+                impulse: 0,
+            };
+            session.receive_move(slot, cmd).unwrap();
+        }
+
+        match session.persist.client(slot) {
+            Some(ClientState::Active(c)) => assert_eq!(c.ping(), Duration::milliseconds(100)),
+            _ => panic!("bot is not active"),
+        }
+        assert!(session.receive_move(1, run(0.0)).is_err());
+
+        let session = Rc::new(RefCell::new(Some(session)));
+        let mut cmds = CmdRegistry::new(Rc::new(RefCell::new(Vec::new())));
+        register_commands(&mut cmds, session);
+        assert_eq!(
+            cmds.exec("ping", &[]).unwrap(),
+            "Client ping times:\n 100 alice"
+        );
+    }
+}
@@ -0,0 +1,284 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! The server's connection port.
+//!
+//! Out-of-band queries and remote console commands arrive on the same port
+//! that clients connect to, so they are all handled here once per frame.
+
+use std::{
+    cell::RefCell,
+    io::ErrorKind,
+    net::{SocketAddr, ToSocketAddrs},
+};
+
+use crate::common::{
+    console::{Console, CvarRegistry},
+    net::{
+        connect::{
+            ConnectListener, ListenerPacket, Request, Response, ResponseServerInfo,
+            CONNECT_PROTOCOL_VERSION,
+        },
+        master::{MasterPacket, ServerDetails, DEFAULT_MASTER_PORT, HEARTBEAT_INTERVAL_SECS},
+        rcon::RconServer,
+        NetError, GAME_NAME,
+    },
+};
+
+use chrono::{DateTime, Duration, Utc};
+
+/// Listens for out-of-band packets on the server's connection port.
+pub struct ServerListener {
+    listener: ConnectListener,
+    rcon: RconServer,
+    last_heartbeat: Option<DateTime<Utc>>,
+}
+
+impl ServerListener {
+    /// Binds the connection port without blocking on reads.
+    pub fn bind<A>(addr: A) -> Result<ServerListener, NetError>
+    where
+        A: ToSocketAddrs,
+    {
+        let listener = ConnectListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(ServerListener {
+            listener,
+            rcon: RconServer::new(),
+            last_heartbeat: None,
+        })
+    }
+
+    /// Returns the local address of the connection port.
+    pub fn local_addr(&self) -> Result<SocketAddr, NetError> {
+        self.listener.local_addr()
+    }
+
+    /// Sends a heartbeat if one is due and handles every packet that has arrived.
+    ///
+    /// Heartbeats are only sent while `sv_public` is set, once at startup and
+    /// every `HEARTBEAT_INTERVAL_SECS` afterwards. `details` is reported to any
+    /// master server or client server list that asks for it, and remote
+    /// console commands are run on `console` if they carry the right
+    /// `rcon_password`.
+    ///
+    /// `cvars` must not be borrowed by the caller, since remote commands may
+    /// set cvars.
+    pub fn frame(
+        &mut self,
+        cvars: &RefCell<CvarRegistry>,
+        console: &Console,
+        details: &ServerDetails,
+    ) -> Result<(), NetError> {
+        let sv_public = cvars.borrow().get_value("sv_public").unwrap();
+        if sv_public != 0.0 {
+            let now = Utc::now();
+            let due = match self.last_heartbeat {
+                Some(last) => {
+                    now.signed_duration_since(last) >= Duration::seconds(HEARTBEAT_INTERVAL_SECS)
+                }
+                None => true,
+            };
+
+            if due {
+                let master = cvars.borrow().get("net_master").unwrap();
+                self.send_heartbeat(&master);
+                self.last_heartbeat = Some(now);
+            }
+        }
+
+        loop {
+            let (packet, remote) = match self.listener.recv_packet() {
+                Ok(p) => p,
+                Err(NetError::Io(e)) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(NetError::Io(e)) => return Err(NetError::Io(e)),
+
+                // a malformed packet shouldn't hold up the rest
+                Err(e) => {
+                    debug!("Ignoring invalid packet: {}", e);
+                    continue;
+                }
+            };
+
+            match packet {
+                ListenerPacket::Master(MasterPacket::GetInfo { challenge }) => {
+                    self.listener.send_master_packet(
+                        &MasterPacket::info_response(challenge, details),
+                        remote,
+                    )?;
+                }
+
+                // answered as in `_Datagram_CheckNewConnections` in `WinQuake/net_dgrm.c`
+                ListenerPacket::Request(Request::ServerInfo(request)) => {
+                    if request.game_name != GAME_NAME {
+                        debug!("Ignoring server info request for {}", request.game_name);
+                        continue;
+                    }
+
+                    self.listener.send_response(
+                        Response::ServerInfo(ResponseServerInfo {
+                            address: self.listener.local_addr()?.to_string(),
+                            hostname: details.hostname.clone(),
+                            levelname: details.map.clone(),
+                            client_count: details.client_count,
+                            client_max: details.client_max,
+                            protocol_version: CONNECT_PROTOCOL_VERSION,
+                        }),
+                        remote,
+                    )?;
+                }
+
+                ListenerPacket::Rcon(packet) => {
+                    let rcon_password = cvars.borrow().get("rcon_password").unwrap();
+                    self.rcon
+                        .handle(&self.listener, console, &rcon_password, packet, remote)?;
+                }
+
+                p => debug!("Ignoring packet from {}: {:?}", remote, p),
+            }
+        }
+    }
+
+    // Send a heartbeat to the master server at `master`, using the default
+    // port if none is given.
+    fn send_heartbeat(&self, master: &str) {
+        let master = if master.contains(':') {
+            master.to_owned()
+        } else {
+            format!("{}:{}", master, DEFAULT_MASTER_PORT)
+        };
+
+        let addr = match master.to_socket_addrs().map(|mut a| a.next()) {
+            Ok(Some(a)) => a,
+            _ => {
+                warn!("Couldn't resolve master server {}", master);
+                return;
+            }
+        };
+
+        debug!("Sending heartbeat to {}", addr);
+        if let Err(e) = self
+            .listener
+            .send_master_packet(&MasterPacket::heartbeat(), addr)
+        {
+            warn!("Couldn't send heartbeat to {}: {}", addr, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::rc::Rc;
+
+    use crate::{
+        common::{
+            console::CmdRegistry,
+            net::{
+                rcon::RconClient,
+                slist::{self, ServerListEntry},
+            },
+        },
+        server,
+    };
+
+    fn test_console() -> (Rc<RefCell<CvarRegistry>>, Console) {
+        let names = Rc::new(RefCell::new(Vec::new()));
+        let cmds = Rc::new(RefCell::new(CmdRegistry::new(names.clone())));
+        let cvars = Rc::new(RefCell::new(CvarRegistry::new(names)));
+        server::cvars::register_cvars(&cvars.borrow()).unwrap();
+        let console = Console::new(cmds, cvars.clone());
+        (cvars, console)
+    }
+
+    #[test]
+    fn test_server_info() {
+        let (cvars, console) = test_console();
+        let mut listener = ServerListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let details = ServerDetails {
+            hostname: String::from("richter"),
+            map: String::from("start"),
+            client_count: 1,
+            client_max: 8,
+        };
+
+        let query = slist::spawn_query(move || {
+            slist::query_servers(vec![addr], Duration::milliseconds(500))
+        });
+        let servers = loop {
+            listener.frame(&cvars, &console, &details).unwrap();
+            if let Ok(result) = query.try_recv() {
+                break result.unwrap();
+            }
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        };
+
+        assert_eq!(
+            servers,
+            vec![ServerListEntry {
+                address: addr,
+                hostname: String::from("richter"),
+                map: String::from("start"),
+                client_count: 1,
+                client_max: 8,
+                protocol_version: CONNECT_PROTOCOL_VERSION,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_rcon() {
+        let (cvars, console) = test_console();
+        cvars.borrow().set("rcon_password", "secret").unwrap();
+
+        let mut listener = ServerListener::bind("127.0.0.1:0").unwrap();
+        let client = RconClient::new(listener.local_addr().unwrap()).unwrap();
+        let details = ServerDetails {
+            hostname: String::from("richter"),
+            map: String::from("start"),
+            client_count: 0,
+            client_max: 8,
+        };
+
+        let mut exchange = |password: &str, command: &str| {
+            client.send(password, command).unwrap();
+            for _ in 0..100 {
+                listener.frame(&cvars, &console, &details).unwrap();
+                if let Some(text) = client.poll().unwrap() {
+                    return text;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(5));
+            }
+            panic!("no rcon reply");
+        };
+
+        assert_eq!(exchange("wrong", "hostname"), "Bad rcon_password.\n");
+
+        // commands run on the server's console, including cvar changes
+        assert_eq!(
+            exchange("secret", "hostname"),
+            "\"hostname\" is \"UNNAMED\"\n"
+        );
+        assert_eq!(
+            exchange("secret", "hostname frag.example.com; hostname"),
+            "\"hostname\" is \"frag.example.com\"\n"
+        );
+    }
+}
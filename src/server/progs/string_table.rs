@@ -38,17 +38,12 @@ impl StringTable {
         S: AsRef<str>,
     {
         let target = target.as_ref();
-        for (ofs, _) in target.char_indices() {
-            let sub = &self.data[ofs..];
-            if !sub.starts_with(target) {
-                continue;
-            }
-
+        for (ofs, _) in self.data.match_indices(target) {
             // Make sure the string is NUL-terminated. Otherwise, this could
             // erroneously return the StringId of a String whose first
             // `target.len()` bytes were equal to `target`, but which had
             // additional bytes.
-            if sub.as_bytes().get(target.len()) != Some(&0) {
+            if self.data.as_bytes().get(ofs + target.len()) != Some(&0) {
                 continue;
             }
 
@@ -31,7 +31,7 @@ use self::{
 pub use self::{
     entity::{
         EntityError, EntityFlags, EntitySolid, EntityTypeDef, FieldAddrEntityId, FieldAddrFloat,
-        FieldAddrFunctionId, FieldAddrStringId, FieldAddrVector,
+        FieldAddrFunctionId, FieldAddrStringId, FieldAddrVector, STATIC_ADDRESS_COUNT,
     },
     phys::{MoveKind, Trace, TraceEnd, TraceEndKind, TraceStart},
 };
@@ -266,6 +266,11 @@ impl World {
         })
     }
 
+    /// Returns the model of the world entity.
+    pub fn world_model(&self) -> &Model {
+        &self.models[1]
+    }
+
     pub fn add_model(&mut self, vfs: &Vfs, name_id: StringId) -> Result<(), ProgsError> {
         let strs = self.string_table.borrow();
         let name = strs.get(name_id).unwrap();
@@ -347,18 +352,18 @@ impl World {
         }
     }
 
-    fn find_vacant_slot(&self) -> Result<usize, ()> {
+    fn find_vacant_slot(&self) -> Result<usize, ProgsError> {
         for (i, slot) in self.slots.iter().enumerate() {
             if let &AreaEntitySlot::Vacant = slot {
                 return Ok(i);
             }
         }
 
-        panic!("no vacant slots");
+        Err(ProgsError::with_msg("No vacant entity slots"))
     }
 
     pub fn alloc_uninitialized(&mut self) -> Result<EntityId, ProgsError> {
-        let slot_id = self.find_vacant_slot().unwrap();
+        let slot_id = self.find_vacant_slot()?;
 
         self.slots[slot_id] = AreaEntitySlot::Occupied(AreaEntity {
             entity: Entity::new(self.string_table.clone(), self.type_def.clone()),
@@ -447,7 +452,7 @@ impl World {
             }
         }
 
-        let entry_id = self.find_vacant_slot().unwrap();
+        let entry_id = self.find_vacant_slot()?;
 
         self.slots[entry_id] = AreaEntitySlot::Occupied(AreaEntity {
             entity: ent,
@@ -475,6 +480,17 @@ impl World {
         Ok(())
     }
 
+    /// Unlinks an entity and resets all of its fields to zero.
+    ///
+    /// Unlike [`World::free`], the slot stays occupied. Player entities are
+    /// cleared instead of freed, since their IDs are tied to client slots.
+    pub fn clear_entity(&mut self, entity_id: EntityId) -> Result<(), ProgsError> {
+        self.unlink_entity(entity_id)?;
+        *self.entity_mut(entity_id)? =
+            Entity::new(self.string_table.clone(), self.type_def.clone());
+        Ok(())
+    }
+
     /// Returns a reference to an entity.
     ///
     /// # Panics
@@ -31,6 +31,9 @@ use cgmath::{InnerSpace, Vector3, Zero};
 /// This prevents objects from sliding indefinitely at low velocity.
 const STOP_THRESHOLD: f32 = 0.1;
 
+/// The height of the tallest step a player can climb without jumping.
+pub const STEP_HEIGHT: f32 = 18.0;
+
 #[derive(Copy, Clone, Debug, Eq, FromPrimitive, PartialEq)]
 pub enum MoveKind {
     /// Does not move.
@@ -120,6 +123,46 @@ pub fn velocity_after_collision(
     (out, flags)
 }
 
+/// Applies ground friction to a walking player's velocity.
+///
+/// Players moving slower than `stop_speed` lose speed as if they were moving at
+/// `stop_speed`, so they come to a full stop instead of sliding indefinitely.
+pub fn velocity_after_friction(
+    initial: Vector3<f32>,
+    friction: f32,
+    stop_speed: f32,
+    frame_time: f32,
+) -> Vector3<f32> {
+    let speed = initial.truncate().magnitude();
+    if speed == 0.0 {
+        return initial;
+    }
+
+    let control = speed.max(stop_speed);
+    let new_speed = (speed - frame_time * control * friction).max(0.0);
+    initial * (new_speed / speed)
+}
+
+/// Accelerates a player toward `wish_speed` in the direction `wish_dir`.
+///
+/// Only the component of `initial` along `wish_dir` is limited to
+/// `wish_speed`, so the total speed may exceed it.
+pub fn velocity_after_acceleration(
+    initial: Vector3<f32>,
+    wish_dir: Vector3<f32>,
+    wish_speed: f32,
+    accel: f32,
+    frame_time: f32,
+) -> Vector3<f32> {
+    let add_speed = wish_speed - initial.dot(wish_dir);
+    if add_speed <= 0.0 {
+        return initial;
+    }
+
+    let accel_speed = (accel * frame_time * wish_speed).min(add_speed);
+    initial + accel_speed * wish_dir
+}
+
 /// Calculates a new velocity after collision with multiple surfaces.
 pub fn velocity_after_multi_collision(
     initial: Vector3<f32>,
@@ -389,3 +432,58 @@ pub fn bounds_for_move(
 
     (box_min, box_max)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_velocity_after_friction() {
+        // 4 friction for 0.1 seconds takes away 40% of the speed
+        let vel = velocity_after_friction(Vector3::new(300.0, 400.0, 0.0), 4.0, 100.0, 0.1);
+        assert!((vel - Vector3::new(180.0, 240.0, 0.0)).magnitude() < 0.001);
+
+        // slow players lose speed as if moving at the stop speed
+        let vel = velocity_after_friction(Vector3::new(50.0, 0.0, 0.0), 4.0, 100.0, 0.1);
+        assert!((vel - Vector3::new(10.0, 0.0, 0.0)).magnitude() < 0.001);
+
+        // and come to a full stop instead of reversing
+        let vel = velocity_after_friction(Vector3::new(0.0, 30.0, 0.0), 4.0, 100.0, 0.1);
+        assert_eq!(vel, Vector3::zero());
+
+        // falling alone doesn't cause friction
+        let vel = velocity_after_friction(Vector3::new(0.0, 0.0, -100.0), 4.0, 100.0, 0.1);
+        assert_eq!(vel, Vector3::new(0.0, 0.0, -100.0));
+    }
+
+    #[test]
+    fn test_velocity_after_acceleration() {
+        let east = Vector3::unit_x();
+
+        // from a standstill, accelerate by accel * wish_speed per second
+        let vel = velocity_after_acceleration(Vector3::zero(), east, 320.0, 10.0, 0.01);
+        assert!((vel - Vector3::new(32.0, 0.0, 0.0)).magnitude() < 0.001);
+
+        // but never past the wish speed
+        let vel =
+            velocity_after_acceleration(Vector3::new(300.0, 0.0, 0.0), east, 320.0, 10.0, 0.1);
+        assert!((vel - Vector3::new(320.0, 0.0, 0.0)).magnitude() < 0.001);
+
+        // players already moving faster keep their speed
+        let vel =
+            velocity_after_acceleration(Vector3::new(400.0, 0.0, 0.0), east, 320.0, 10.0, 0.1);
+        assert_eq!(vel, Vector3::new(400.0, 0.0, 0.0));
+
+        // only the speed along the wish direction is limited, so steering
+        // sideways adds speed
+        let vel = velocity_after_acceleration(
+            Vector3::new(320.0, 0.0, 0.0),
+            Vector3::unit_y(),
+            30.0,
+            10.0,
+            0.1,
+        );
+        assert!((vel - Vector3::new(320.0, 30.0, 0.0)).magnitude() < 0.001);
+        assert!(vel.magnitude() > 320.0);
+    }
+}